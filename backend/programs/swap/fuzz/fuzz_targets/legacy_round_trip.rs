@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solana_nft_swap::instruction::SwapInstruction;
+
+// Any instruction that decodes must survive a pack_legacy -> unpack round trip.
+// Instructions without a legacy tag are packed in the versioned format instead.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(instruction) = SwapInstruction::unpack(data) {
+        let repacked = SwapInstruction::unpack(&instruction.pack_legacy())
+            .expect("pack_legacy output must unpack");
+        assert_eq!(repacked, instruction);
+    }
+});
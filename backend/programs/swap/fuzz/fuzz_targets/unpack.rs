@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solana_nft_swap::instruction::SwapInstruction;
+
+// Arbitrary instruction data must decode or fail with an error, never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = SwapInstruction::unpack(data);
+});
@@ -3,6 +3,7 @@ use thiserror::Error;
 
 /// Errors that may be returned by the NFT Swap Program
 #[derive(Debug, Error, Copy, Clone, PartialEq)]
+#[repr(u32)]
 pub enum SwapError {
     /// Invalid instruction data passed
     #[error("Invalid instruction data")]
@@ -67,11 +68,406 @@ pub enum SwapError {
     /// Cancellation denied - trade already in progress
     #[error("Cancellation denied - trade already in progress")]
     CancellationDenied,
+    
+    /// Trade loop has been paused by its authority
+    #[error("Trade loop is paused")]
+    TradeLoopPaused,
+    
+    /// Trade loop template does not exist
+    #[error("Trade loop template not found")]
+    TemplateNotFound,
+    
+    /// Trade loop has not expired yet
+    #[error("Trade loop has not expired")]
+    TradeLoopNotExpired,
+    
+    /// Estimated compute units exceed the per-loop limit
+    #[error("Estimated compute units exceed the per-loop limit")]
+    EstimatedComputeExceedsLimit,
+    
+    /// Pending authority transfer was not accepted in time
+    #[error("Authority transfer proposal has expired")]
+    AuthorityTransferExpired,
+    
+    /// No authority transfer has been proposed
+    #[error("No pending authority transfer")]
+    NoPendingAuthorityTransfer,
+    
+    /// Trade step has been marked as failed
+    #[error("Trade step has failed")]
+    StepFailed,
+    
+    /// Royalty payment to a creator failed
+    #[error("Royalty transfer failed")]
+    RoyaltyTransferFailed,
+    
+    /// Metadata lists no verified creator to receive royalties
+    #[error("Metadata creator not verified")]
+    MetadataCreatorNotVerified,
+    
+    /// Instruction nonce does not match the trade loop nonce
+    #[error("Trade loop nonce mismatch")]
+    NonceMismatch,
+    
+    /// The trade loop witness did not co-sign execution
+    #[error("Witness signature required")]
+    WitnessSignatureRequired,
+    
+    /// Account data is too small to hold the serialized state
+    #[error("Account too small")]
+    AccountTooSmall,
+    
+    /// The authority transfer time-lock has not elapsed yet
+    #[error("Authority transfer time-lock active")]
+    AuthorityTransferTimelocked,
+    
+    /// The linked trade loop is missing or not fully approved
+    #[error("Linked trade loop not ready")]
+    LinkedLoopNotReady,
+    
+    /// The linked trade loop has expired
+    #[error("Linked trade loop expired")]
+    LinkedLoopExpired,
+    
+    /// A step would hold more NFTs than allowed
+    #[error("Too many NFTs in step")]
+    TooManyNftsInStep,
+    
+    /// Voting on the governance proposal has closed
+    #[error("Governance voting closed")]
+    GovernanceVotingClosed,
+    
+    /// Voting on the governance proposal is still open
+    #[error("Governance voting still open")]
+    GovernanceVotingOpen,
+    
+    /// The voter has already voted on this proposal
+    #[error("Already voted")]
+    AlreadyVoted,
+    
+    /// The governance proposal has already been finalized
+    #[error("Proposal already finalized")]
+    ProposalAlreadyFinalized,
+    
+    /// The counter-offer has expired
+    #[error("Counter-offer expired")]
+    CounterOfferExpired,
+    
+    /// The wallet is blocked from joining new trade loops
+    #[error("Participant blocked")]
+    ParticipantBlocked,
+    
+    /// One or more NFTs failed bulk verification; bit `step_index * MAX_NFTS_PER_STEP + nft_index` is set for each failure
+    #[error("Bulk NFT verification failed (mask {0:#x})")]
+    BulkVerificationFailed(u64),
+    
+    /// The trade loop cannot be cancelled during the lockout after full approval
+    #[error("Cancellation time-locked")]
+    CancellationTimelocked,
+    
+    /// The approval delegation has expired
+    #[error("Delegate approval expired")]
+    DelegateExpired,
+    
+    /// The signer is neither the step sender nor its approval delegate
+    #[error("Unauthorized delegate")]
+    UnauthorizedDelegate,
+    
+    /// The trade loop state does not match the snapshot
+    #[error("Snapshot mismatch")]
+    SnapshotMismatch,
+    
+    /// The executor is not in the trade loop's execution whitelist or, without one,
+    /// is neither a participant nor an authorized relayer
+    #[error("Executor not authorized")]
+    UnauthorizedExecutor,
+    
+    /// The maximum number of authorized relayers has been reached
+    #[error("Too many authorized relayers")]
+    TooManyRelayers,
+    
+    /// More tags were provided than a trade loop can hold
+    #[error("Too many tags")]
+    TooManyTags,
+    
+    /// The same wallet appears more than once among a trade loop's participants
+    #[error("Duplicate participant address")]
+    DuplicateParticipantAddress,
+    
+    /// Both parties of a two-party swap must sign
+    #[error("Both parties must sign")]
+    BothPartiesMustSign,
+    
+    /// The NFT is not a verified member of the required collection
+    #[error("NFT not in collection")]
+    NftNotInCollection,
+    
+    /// The open offer has expired
+    #[error("Open offer expired")]
+    OpenOfferExpired,
+    
+    /// The upgraded program failed its self-test instruction
+    #[error("Upgrade self-test failed")]
+    UpgradeSelfTestFailed,
+    
+    /// Every entry of a bulk or batch operation failed; the bitmask marks skipped entries
+    #[error("Bulk operation partial failure")]
+    BulkOperationPartialFailure(u8),
+    
+    /// The completion badge mint has already been created
+    #[error("Badge mint already initialized")]
+    BadgeMintAlreadyInitialized,
+    
+    /// The step's NFTs are locked until the trade loop executes, expires or fails
+    #[error("Step NFTs locked")]
+    StepNftsLocked,
+    
+    /// The NFT mint is on the mint blocklist
+    #[error("NFT mint blocked")]
+    NftMintBlocked,
+    
+    /// The same execution was already processed in this slot
+    #[error("Duplicate instruction")]
+    DuplicateInstruction,
+    
+    /// The trade loop has already been tokenized
+    #[error("Trade loop already tokenized")]
+    AlreadyTokenized,
+    
+    /// No escrowed step could be released during cancellation; the bitmask marks failed step indices
+    #[error("Escrow release partial failure (mask {0:#x})")]
+    EscrowReleasePartialFailure(u16),
+    
+    /// The step's own deadline has passed
+    #[error("Step expired")]
+    StepExpired,
+    
+    /// The NFT is not a verified member of a whitelisted collection
+    #[error("Unverified collection member")]
+    UnverifiedCollectionMember,
+    
+    /// The Token-2022 mint has no usable token metadata extension
+    #[error("Token-2022 metadata extension missing")]
+    Token2022MetadataExtensionMissing,
+    
+    /// The wallet initialized trade loops too quickly
+    #[error("Rate limit exceeded")]
+    RateLimitExceeded,
+    
+    /// The discount coupon is not signed by the configured coupon issuer
+    #[error("Invalid coupon signature")]
+    InvalidCouponSignature,
+    
+    /// The discount coupon is no longer valid
+    #[error("Coupon expired")]
+    CouponExpired,
+    
+    /// Governance can only be replaced through the timelocked proposal flow
+    #[error("Governance timelock active")]
+    GovernanceTimelockActive,
+    
+    /// The proposed governance cannot be finalized before its timelock ends
+    #[error("Governance timelock not expired")]
+    GovernanceTimelockNotExpired,
+    
+    /// The NFT's collection floor price is below the configured minimum
+    #[error("NFT below floor price")]
+    NftBelowFloorPrice,
+    
+    /// The oracle price is too old to be used
+    #[error("Oracle price stale")]
+    OraclePriceStale,
+    
+    /// The fee vault holds no lamports beyond its rent exemption
+    #[error("Fee vault insufficient")]
+    FeeVaultInsufficient,
+    
+    /// The trade loop account could not be grown to hold its steps
+    #[error("Insufficient account space")]
+    InsufficientAccountSpace,
+    
+    /// No Ed25519 program instruction verified the signature
+    #[error("Signature verification failed")]
+    SignatureVerificationFailed,
+    
+    /// The signer of an approval does not send the step
+    #[error("Signer not step owner")]
+    SignerNotStepOwner,
+    
+    /// The trade loop's execution condition does not hold
+    #[error("Execution condition not met")]
+    ExecutionConditionNotMet,
+    
+    /// A sender's token account holds less than a step's fungible token leg
+    #[error("Insufficient token balance")]
+    InsufficientTokenBalance,
+    
+    /// The program is paused; carries the `PauseReason` code
+    #[error("Program paused (reason {0})")]
+    ProgramPaused(u8),
+    
+    /// A revealed NFT does not match its sealed-bid commitment
+    #[error("NFT commitment mismatch")]
+    CommitmentMismatch,
+    
+    /// A sealed-bid step has not revealed its NFTs
+    #[error("NFTs not revealed")]
+    NftsNotRevealed,
+    
+    /// The execution bond can neither be claimed nor refunded yet
+    #[error("Execution bond locked")]
+    ExecutionBondLocked,
+    
+    /// A transfer of a hooked Token-2022 mint is not part of an active, approved trade loop
+    #[error("Transfer not authorized")]
+    TransferNotAuthorized,
+    
+    /// Two NFT offers cannot be matched against each other
+    #[error("Offers not matchable")]
+    OffersNotMatchable,
+    
+    /// The step's NFTs cannot be transferred before its `not_before` time
+    #[error("Step not yet active")]
+    StepNotYetActive,
+    
+    /// A participant has no valid KYC entry while KYC is required
+    #[error("Participant not KYC verified")]
+    ParticipantNotKYCVerified,
+    
+    /// A partial fill takes fewer NFTs than the offer's minimum fill
+    #[error("Below minimum fill")]
+    BelowMinimumFill,
+    
+    /// An NFT has no agreed value while royalty enforcement is enabled
+    #[error("NFT value not agreed")]
+    NftValueNotAgreed,
+    
+    /// Error code this version of the program does not define
+    /// New variants go above this one, which is pinned so it never shifts their codes
+    #[error("Unknown swap error code {0}")]
+    Unknown(u32) = u32::MAX,
+}
+
+/// Every known error, indexed by its code; payloads of data-carrying variants are not
+/// part of the code and decode as 0
+const KNOWN_ERRORS: &[SwapError] = &[
+    SwapError::InvalidInstructionData,
+    SwapError::NotRentExempt,
+    SwapError::InvalidAccountOwner,
+    SwapError::UninitializedAccount,
+    SwapError::IncorrectProgramId,
+    SwapError::InvalidAccountData,
+    SwapError::TradeLoopVerificationFailed,
+    SwapError::MissingApprovals,
+    SwapError::StepAlreadyExecuted,
+    SwapError::UpgradeAuthorityMismatch,
+    SwapError::InvalidProgramVersion,
+    SwapError::InsufficientFunds,
+    SwapError::InvalidMetadataAccount,
+    SwapError::TradeTimeoutExceeded,
+    SwapError::TooManyParticipants,
+    SwapError::CancellationDenied,
+    SwapError::TradeLoopPaused,
+    SwapError::TemplateNotFound,
+    SwapError::TradeLoopNotExpired,
+    SwapError::EstimatedComputeExceedsLimit,
+    SwapError::AuthorityTransferExpired,
+    SwapError::NoPendingAuthorityTransfer,
+    SwapError::StepFailed,
+    SwapError::RoyaltyTransferFailed,
+    SwapError::MetadataCreatorNotVerified,
+    SwapError::NonceMismatch,
+    SwapError::WitnessSignatureRequired,
+    SwapError::AccountTooSmall,
+    SwapError::AuthorityTransferTimelocked,
+    SwapError::LinkedLoopNotReady,
+    SwapError::LinkedLoopExpired,
+    SwapError::TooManyNftsInStep,
+    SwapError::GovernanceVotingClosed,
+    SwapError::GovernanceVotingOpen,
+    SwapError::AlreadyVoted,
+    SwapError::ProposalAlreadyFinalized,
+    SwapError::CounterOfferExpired,
+    SwapError::ParticipantBlocked,
+    SwapError::BulkVerificationFailed(0),
+    SwapError::CancellationTimelocked,
+    SwapError::DelegateExpired,
+    SwapError::UnauthorizedDelegate,
+    SwapError::SnapshotMismatch,
+    SwapError::UnauthorizedExecutor,
+    SwapError::TooManyRelayers,
+    SwapError::TooManyTags,
+    SwapError::DuplicateParticipantAddress,
+    SwapError::BothPartiesMustSign,
+    SwapError::NftNotInCollection,
+    SwapError::OpenOfferExpired,
+    SwapError::UpgradeSelfTestFailed,
+    SwapError::BulkOperationPartialFailure(0),
+    SwapError::BadgeMintAlreadyInitialized,
+    SwapError::StepNftsLocked,
+    SwapError::NftMintBlocked,
+    SwapError::DuplicateInstruction,
+    SwapError::AlreadyTokenized,
+    SwapError::EscrowReleasePartialFailure(0),
+    SwapError::StepExpired,
+    SwapError::UnverifiedCollectionMember,
+    SwapError::Token2022MetadataExtensionMissing,
+    SwapError::RateLimitExceeded,
+    SwapError::InvalidCouponSignature,
+    SwapError::CouponExpired,
+    SwapError::GovernanceTimelockActive,
+    SwapError::GovernanceTimelockNotExpired,
+    SwapError::NftBelowFloorPrice,
+    SwapError::OraclePriceStale,
+    SwapError::FeeVaultInsufficient,
+    SwapError::InsufficientAccountSpace,
+    SwapError::SignatureVerificationFailed,
+    SwapError::SignerNotStepOwner,
+    SwapError::ExecutionConditionNotMet,
+    SwapError::InsufficientTokenBalance,
+    SwapError::ProgramPaused(0),
+    SwapError::CommitmentMismatch,
+    SwapError::NftsNotRevealed,
+    SwapError::ExecutionBondLocked,
+    SwapError::TransferNotAuthorized,
+    SwapError::OffersNotMatchable,
+    SwapError::StepNotYetActive,
+    SwapError::ParticipantNotKYCVerified,
+    SwapError::BelowMinimumFill,
+    SwapError::NftValueNotAgreed,
+];
+
+impl SwapError {
+    /// Custom program error code of this error (the variant's position in the enum)
+    pub fn code(&self) -> u32 {
+        if let SwapError::Unknown(code) = self {
+            return *code;
+        }
+        
+        // SAFETY: `#[repr(u32)]` puts the discriminant as a u32 at the start of the enum
+        unsafe { *(self as *const Self as *const u32) }
+    }
+    
+    /// Decode a `ProgramError::Custom` returned by this program, if the code is known
+    pub fn from_program_error(e: &ProgramError) -> Option<Self> {
+        match e {
+            ProgramError::Custom(code) => Self::try_from(*code).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u32> for SwapError {
+    type Error = ProgramError;
+    
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        KNOWN_ERRORS.get(code as usize).copied().ok_or(ProgramError::InvalidArgument)
+    }
 }
 
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
-        ProgramError::Custom(e as u32)
+        ProgramError::Custom(e.code())
     }
 }
 
@@ -0,0 +1,343 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+/// Structured trade lifecycle events emitted with `sol_log_data` for off-chain indexers
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub enum SwapEvent {
+    /// A new trade loop was created
+    TradeLoopCreated {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Authority that created the trade loop
+        authority: Pubkey,
+        /// Unix timestamp when the trade loop expires
+        expires_at: u64,
+    },
+
+    /// A step was added to a trade loop
+    StepAdded {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the added step
+        step_index: u8,
+        /// Sender of the step's NFTs
+        from: Pubkey,
+        /// Recipient of the step's NFTs
+        to: Pubkey,
+    },
+
+    /// A step was approved by its sender
+    StepApproved {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the approved step
+        step_index: u8,
+    },
+
+    /// A step's NFTs were transferred
+    StepExecuted {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the executed step
+        step_index: u8,
+        /// NFTs transferred in the step
+        nft_mints: Vec<Pubkey>,
+    },
+
+    /// A trade loop was cancelled or closed before completing
+    TradeLoopCancelled {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+    },
+
+    /// Every step of a trade loop has been executed
+    TradeLoopComplete {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+    },
+
+    /// Two wallets swapped one NFT each without a trade loop account
+    TwoPartySwapExecuted {
+        /// The first party
+        alice: Pubkey,
+        /// The second party
+        bob: Pubkey,
+        /// NFT sent by the first party
+        alice_nft_mint: Pubkey,
+        /// NFT sent by the second party
+        bob_nft_mint: Pubkey,
+    },
+
+    /// An NFT was escrowed in an open offer
+    OpenOfferCreated {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+        /// Account that created the offer
+        offerer: Pubkey,
+        /// NFT held in escrow
+        offered_nft_mint: Pubkey,
+        /// Collection the offerer wants an NFT from
+        wanted_collection: Pubkey,
+        /// Unix timestamp when the offer expires
+        expires_at: i64,
+    },
+
+    /// An open offer was fulfilled and closed
+    OpenOfferFulfilled {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+        /// Account that fulfilled the offer
+        fulfiller: Pubkey,
+        /// NFT the fulfiller sent to the offerer
+        fulfiller_nft_mint: Pubkey,
+    },
+
+    /// An open offer was cancelled and its NFT returned
+    OpenOfferCancelled {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+    },
+
+    /// A metadata URI was attached to a step
+    StepMetadataSet {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the step
+        step_index: u8,
+        /// The zero-padded metadata URI
+        metadata_uri: [u8; 128],
+    },
+
+    /// A step's metadata URI was removed
+    StepMetadataCleared {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the step
+        step_index: u8,
+    },
+
+    /// The steps of a trade loop were reordered
+    StepsReordered {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Previous index of the step now at each position
+        new_order: Vec<u8>,
+    },
+
+    /// A trade loop's tags were replaced (an empty list means they were cleared)
+    LoopTagsUpdated {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// The zero-padded tags now attached to the loop
+        tags: Vec<[u8; 32]>,
+    },
+
+    /// The sender's token accounts for a step were frozen until execution
+    StepNftsLocked {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the locked step
+        step_index: u8,
+    },
+
+    /// The sender's token accounts for a step were thawed
+    StepNftsUnlocked {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the unlocked step
+        step_index: u8,
+    },
+
+    /// A participant handed their position in a trade loop to another wallet
+    StepOwnershipTransferred {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the transferred step
+        step_index: u8,
+        /// The previous sender of the step
+        old_owner: Pubkey,
+        /// The new sender of the step
+        new_owner: Pubkey,
+    },
+
+    /// A trade loop was moved from its legacy PDA to the creator-scoped PDA
+    LoopMigrated {
+        /// The legacy trade loop address
+        old_address: Pubkey,
+        /// The creator-scoped trade loop address
+        new_address: Pubkey,
+    },
+
+    /// The authority set the advisory deadline shown to participants
+    DeadlineMessageSet {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Advisory deadline (Unix timestamp)
+        display_deadline: i64,
+        /// Timezone to show the deadline in, as minutes from UTC
+        timezone_offset: i16,
+        /// The zero-padded deadline message
+        message: [u8; 64],
+    },
+
+    /// A trade loop executed with some steps skipped because of frozen token accounts
+    LoopPartiallyExecuted {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Bit `i` is set if step `i` was skipped and marked failed
+        failed_steps_bitmask: u16,
+    },
+
+    /// Empty escrow token accounts of a trade loop were closed by BulkCloseEscrows
+    EscrowsClosed {
+        /// Number of escrow token accounts closed
+        count: u8,
+        /// Lamports of rent paid to the caller
+        rent_reclaimed: u64,
+    },
+
+    /// The recipient of a step was changed before approval
+    StepRecipientUpdated {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the updated step
+        step_index: u8,
+        /// Previous recipient of the step's NFTs
+        old_recipient: Pubkey,
+        /// New recipient of the step's NFTs
+        new_recipient: Pubkey,
+    },
+
+    /// An NFT was escrowed and listed in a collection's offer book
+    NftOfferListed {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+        /// Account that listed the offer
+        offerer: Pubkey,
+        /// NFT held in escrow
+        offered_nft: Pubkey,
+        /// Collection the offerer wants an NFT from
+        wanted_collection: Pubkey,
+        /// Premium over the wanted collection's floor, in basis points
+        floor_premium_bps: i16,
+    },
+
+    /// An offer was removed from its offer book and its NFT returned
+    NftOfferDelisted {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+    },
+
+    /// Two complementary offers were matched and their NFTs swapped
+    NftOffersMatched {
+        /// Unique identifier of the first offer
+        offer_id_a: [u8; 16],
+        /// Unique identifier of the second offer
+        offer_id_b: [u8; 16],
+    },
+
+    /// A step was timelocked until a future time
+    StepTimelockSet {
+        /// Unique identifier of the trade loop
+        trade_id: [u8; 32],
+        /// Index of the timelocked step
+        step_index: u8,
+        /// Unix timestamp before which the step cannot execute
+        not_before: u64,
+    },
+
+    /// An NFT bundle was escrowed in a partial fill offer
+    PartialFillOfferCreated {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+        /// Account that created the offer
+        offerer: Pubkey,
+        /// NFTs held in escrow
+        offered_nfts: Vec<Pubkey>,
+        /// Collections owed for each NFT taken
+        wanted_nfts_per_unit: Vec<Pubkey>,
+        /// Minimum number of NFTs a fill must take
+        minimum_fill: u8,
+    },
+
+    /// Part of a partial fill offer was taken
+    PartialFillOfferFilled {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+        /// Account that filled the offer
+        filler: Pubkey,
+        /// Offered NFTs sent to the filler
+        taken_nfts: Vec<Pubkey>,
+        /// NFTs the filler sent to the offerer
+        given_nfts: Vec<Pubkey>,
+        /// Number of NFTs left in the offer (the offer is closed at 0)
+        remaining: u8,
+    },
+
+    /// A partial fill offer was cancelled and its remaining NFTs returned
+    PartialFillOfferCancelled {
+        /// Unique identifier of the offer
+        offer_id: [u8; 16],
+    },
+}
+
+/// Program health snapshot, emitted by HealthCheck
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct HealthStatus {
+    /// Program version recorded in the config
+    pub version: u32,
+    /// Whether the program is paused
+    pub paused: bool,
+    /// Current Unix timestamp of the cluster clock
+    pub clock: i64,
+    /// The program upgrade authority
+    pub authority: Pubkey,
+}
+
+/// Ownership check result for one NFT, emitted by DiagnoseTradeLoop
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct DiagnosticResult {
+    /// Index of the step the NFT belongs to
+    pub step_index: u8,
+    /// The NFT mint
+    pub nft_mint: Pubkey,
+    /// Whether the provided token account is held by the expected owner (sender or escrow)
+    pub owner_match: bool,
+    /// Token amount in the provided token account
+    pub amount: u64,
+}
+
+/// Timeline of one participant's step, emitted by GetParticipantStats
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct ParticipantStats {
+    /// The step's sender
+    pub participant: Pubkey,
+    /// Index of the step
+    pub step_index: u8,
+    /// Slot at which the step was added (0 if not yet added)
+    pub added_at_slot: u64,
+    /// Slot of the step's current approval
+    pub approved_at_slot: Option<u64>,
+    /// Slot at which the step was executed
+    pub executed_at_slot: Option<u64>,
+    /// Number of NFTs the step sends
+    pub nft_count: u8,
+}
+
+/// Account of a trade loop holding less than its rent-exempt minimum, emitted by CheckRentExemption
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct RentDeficit {
+    /// The underfunded participant wallet or token account
+    pub account: Pubkey,
+    /// Lamports missing to reach the rent-exempt minimum
+    pub deficit_lamports: u64,
+}
+
+/// Outcome of a rent exemption check, emitted last by CheckRentExemption
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct RentSummary {
+    /// Whether every checked account is rent exempt
+    pub all_exempt: bool,
+    /// Sum of all deficits, in lamports
+    pub total_deficit: u64,
+}
@@ -1,135 +1,2423 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
-    instruction::{AccountMeta, Instruction},
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
 };
-use crate::error::SwapError;
+use crate::{error::SwapError, state::{FailReason, FeeRoute, FeeSchedule, GovernedChange, PauseReason, SplTokenLeg}};
+
+/// Instruction tags (Borsh variant indexes) still allowed during scheduled maintenance
+///
+/// Every other instruction takes the program config account (PDA, seeds: "config"; need
+/// not exist yet) as its last account, after the accounts listed for the instruction.
+pub const PAUSE_EXEMPT_TAGS: &[u8] = &[
+    7,   // UpdateProgramConfig (lifts the pause)
+    31,  // DiagnoseTradeLoop
+    73,  // GetProgramStats
+    81,  // HealthCheck
+    114, // PauseForMaintenance
+    125, // GetParticipantStats
+    135, // CheckRentExemption
+];
 
 /// Instructions supported by the NFT Swap program
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub enum SwapInstruction {
     /// Initializes a new trade loop
     ///
+    /// One step is reserved per participant, sending to the next participant in the
+    /// ring (the last sends to the first). `AddTradeStep` then supplies each step's NFTs.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account initializing the trade loop (payer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    /// 4. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 5. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    /// 6. `[writable]` (Required while rate limiting is enabled) The payer's cooldown account
+    ///    (PDA, seeds: "cooldown", payer)
+    InitializeTradeLoop {
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// The participant ring, in trade order; each wallet may appear only once
+        participants: Vec<Pubkey>,
+        /// Timeout in seconds from initialization
+        timeout_seconds: u64,
+        /// Optional number of multisig signers per step, used to reserve space for approval configs
+        step_signer_counts: Option<Vec<u8>>,
+        /// Seconds after every step is approved during which the loop cannot be cancelled
+        cancel_lockout_seconds: u64,
+        /// Execute the loop in the same instruction as its last approval (see ApproveTradeStep).
+        /// Only ApproveTradeStep triggers it; a loop completed by BatchApproveSteps,
+        /// BatchSignedApprovals or ApplyOffchainApproval is executed separately
+        auto_execute: bool,
+        /// Optional maximum NFT count of each step (one entry per participant), at most MAX_NFTS_PER_STEP
+        per_step_nft_limits: Option<Vec<u8>>,
+    },
+
+    /// Adds a step to an existing trade loop
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account adding the step (must match the 'from' address)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Token program
+    /// 3. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 4. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 5. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 6. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 7+ Token accounts for verification (for each NFT mint):
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT (must own the NFT)
+    ///
+    /// In a bonded loop the sender (writable) deposits the execution bond, so the
+    /// step's bond PDA (writable) and the system program must be included as well.
+    AddTradeStep {
+        /// The index of this step in the trade loop (0-based)
+        step_index: u8,
+        /// The recipient of the NFT(s) in this step
+        to: Pubkey,
+        /// The mint addresses of NFTs being transferred
+        nft_mints: Vec<Pubkey>,
+    },
+
+    /// Approves a trade step (as the sender or its approval delegate)
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender approving the trade, or the delegate set by DelegateApproval
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Clock sysvar
+    /// 3. `[]` (Required when this approval completes an auto-execute loop) This program
+    /// 4. ..  (Required when this approval completes an auto-execute loop) The ExecuteFullTradeLoop
+    ///    accounts after the trade loop; the approver is the executor
+    ApproveTradeStep {
+        /// The index of the step to approve
+        step_index: u8,
+    },
+
+    /// Executes a single trade step (transfers NFTs)
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account executing the trade (can be anyone once approved)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The sender's wallet (`[signer, writable]` if the step has a SOL amount)
+    /// 3. `[]` The recipient's wallet (`[writable]` if the step has a SOL amount)
+    /// 4. `[]` Token program
+    /// 5. `[]` Associated token program
+    /// 6. `[]` System program
+    /// 7. `[]` Rent sysvar
+    /// 8. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 9+ NFT accounts and token accounts (varies based on step) in triples:
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT
+    ///    - Recipient's token account for this NFT (will be created if needed)
+    ///
+    /// Escrowed NFTs pass the escrow token account as the source and are
+    /// delivered to the recipient afterwards via `ReleaseEscrow`
+    ///
+    /// Fungible token legs follow the NFT accounts, each as its mint, the sender's
+    /// token account and the recipient's token account (created if needed)
+    ///
+    /// When royalty enforcement is enabled, each NFT's accounts are followed by its Metaplex metadata account
+    /// and one writable account per verified creator, in metadata order. Royalties
+    /// are paid by the sender from the step's agreed NFT values, and a step
+    /// without agreed values (see `SetStepNftValues`) is rejected.
+    ///
+    /// Compute budget hint: steps with several NFTs or new recipient token accounts
+    /// can exceed the default 200k CU limit. Use `utils::estimate_compute_units`
+    /// and prepend a compute budget instruction when needed.
+    ExecuteTradeStep {
+        /// The index of the step to execute
+        step_index: u8,
+    },
+
+    /// Executes an atomic multi-step trade (executes multiple steps at once)
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account executing the trade (a participant or an authorized relayer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Token program
+    /// 3. `[]` Associated token program
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    /// 6. `[]` Clock sysvar
+    /// 7. `[writable]` The dedup cache account (PDA, seeds: "dedup_cache"; need not exist yet)
+    /// 8. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// Many accounts required for each step - specific structure varies based on trade loop composition
+    ///
+    /// Escrowed NFTs pass the escrow token account as the source and are
+    /// delivered to the recipient afterwards via `ReleaseEscrow`
+    ///
+    /// If the trade loop has a witness, it must also be included as a signer.
+    /// If the trade loop was split, its linked loop must also be included.
+    /// Steps with a SOL amount need their sender to sign and both wallets writable.
+    /// Steps with fungible token legs follow their NFT accounts with the leg's mint,
+    /// sender's token account and recipient's token account (created if needed).
+    /// Token-2022 NFTs use Token-2022 associated token accounts and need the Token-2022
+    /// program included; a mint hooked by RegisterTransferHook must be writable and also
+    /// needs its hook registry (writable) and the hook authority PDA, and its hook is released.
+    /// When royalty enforcement is enabled, the step accounts are followed by each
+    /// executed NFT's Metaplex metadata account and one writable account per verified
+    /// creator, in step and metadata order; senders must then be writable. Royalties
+    /// are paid from the steps' agreed NFT values (see `SetStepNftValues`).
+    /// A relayer must also include the fee vault account and is reimbursed
+    /// `RELAYER_REIMBURSEMENT_LAMPORTS` from the fee vault.
+    /// A recurring loop with renewals left must also include its successor trade
+    /// loop PDA (writable), which the executor pays for.
+    /// The dedup cache rejects a resubmission by the same executor in the same slot.
+    /// A bonded loop must include each step's bond PDA (writable) with the senders
+    /// writable; every bond is refunded to its sender.
+    /// If a completion badge mint is configured, including the badge mint and each
+    /// sender's badge token account mints them a badge; participants whose badge
+    /// token account is missing are skipped.
+    ///
+    /// Compute budget hint: most loops exceed the default 200k CU limit. Use
+    /// `utils::estimate_compute_units` and prepend a compute budget instruction.
+    ExecuteFullTradeLoop {
+        /// The trade loop's current nonce, guarding against replay if the loop is recreated
+        expected_nonce: u64,
+    },
+
+    /// Cancels a trade loop, returning any escrowed NFTs to their senders
+    ///
+    /// Once a step has been marked as failed, any participant may cancel
+    /// regardless of the approval status of the other steps.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Any participant in the trade loop
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Token program (only required if any NFTs are escrowed)
+    ///
+    /// 3+ For each escrowed NFT (in step order):
+    ///    - Escrow authority PDA
+    ///    - Escrow token account
+    ///    - Sender's token account for this NFT
+    ///
+    /// A bonded loop must also include the system program and each step's bond PDA
+    /// (writable) and sender (writable); every bond is refunded to its sender.
+    CancelTradeLoop {},
+
+    /// Initializes the program configuration
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority (payer)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    InitializeProgramConfig {
+        /// Optional: multisig governance address for decentralized upgrades
+        governance: Option<Pubkey>,
+    },
+
+    /// Updates the program configuration
+    ///
+    /// Once a governance key is set, it can only be replaced through
+    /// `ProposeGovernanceReplacement` and `FinalizeGovernanceReplacement`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The current upgrade authority
+    /// 1. `[writable]` The program config account 
+    UpdateProgramConfig {
+        /// New upgrade authority (None to keep the same)
+        new_upgrade_authority: Option<Pubkey>,
+        /// New governance address (None to keep the same)
+        new_governance: Option<Pubkey>,
+        /// New pause state (None to keep the same)
+        new_paused_state: Option<bool>,
+    },
+
+    /// Updates the program to a new implementation
+    ///
+    /// The upgrade is recorded in the program config as unverified until
+    /// `VerifyProgramUpgrade` runs its self-test in a later slot.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays for any config account growth)
+    /// 1. `[writable]` The program data account
+    /// 2. `[]` The program account
+    /// 3. `[]` The buffer containing the new program
+    /// 4. `[]` Rent sysvar
+    /// 5. `[]` Clock sysvar
+    /// 6. `[]` BPF Loader Upgradeable program
+    /// 7. `[writable]` The program config account
+    /// 8. `[]` System program
+    UpgradeProgram {
+        /// New program version
+        new_program_version: u32,
+    },
+
+    /// Locks an NFT from a trade step into a program-controlled escrow account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender (must match the step's 'from' address)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` NFT mint address
+    /// 3. `[writable]` Sender's token account for this NFT
+    /// 4. `[]` Escrow authority PDA (seeds: "escrow", trade loop, NFT mint)
+    /// 5. `[writable]` Escrow token account (associated token account of the escrow PDA)
+    /// 6. `[]` Token program
+    /// 7. `[]` Associated token program
+    /// 8. `[]` System program
+    /// 9. `[]` Rent sysvar
+    EscrowNft {
+        /// The index of the step the NFT belongs to
+        step_index: u8,
+        /// The mint address of the NFT to escrow
+        nft_mint: Pubkey,
+    },
+
+    /// Releases an escrowed NFT to the step recipient once the step has been executed,
+    /// or back to the sender if the step has not been approved yet
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account releasing the NFT (pays for the destination token account if needed)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` NFT mint address
+    /// 3. `[]` Escrow authority PDA (seeds: "escrow", trade loop, NFT mint)
+    /// 4. `[writable]` Escrow token account
+    /// 5. `[]` Destination wallet (step recipient if executed, step sender otherwise)
+    /// 6. `[writable]` Destination token account (will be created if needed)
+    /// 7. `[]` Token program
+    /// 8. `[]` Associated token program
+    /// 9. `[]` System program
+    /// 10. `[]` Rent sysvar
+    ReleaseEscrow {
+        /// The index of the step the NFT belongs to
+        step_index: u8,
+        /// The mint address of the escrowed NFT
+        nft_mint: Pubkey,
+    },
+
+    /// Pauses a single trade loop without affecting the rest of the program
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    PauseTradeLoop {},
+
+    /// Resumes a trade loop previously paused with `PauseTradeLoop`
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    ResumeTradeLoop {},
+
+    /// Creates a reusable trade loop template describing a participant ring
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account creating the template (payer)
+    /// 1. `[writable]` The template account (PDA: "template", creator, template_id)
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    CreateTemplate {
+        /// Unique identifier for the template
+        template_id: [u8; 16],
+        /// Participants in ring order (participant i sends to participant i + 1)
+        participant_ring: Vec<Pubkey>,
+        /// The total number of steps in trade loops spawned from this template
+        step_count: u8,
+    },
+
+    /// Initializes a new trade loop with its steps pre-populated from a template
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account initializing the trade loop (payer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[writable]` The template account (PDA: "template", template creator, template_id)
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    InitializeFromTemplate {
+        /// Identifier of the template to spawn from, among its creator's templates
+        template_id: [u8; 16],
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// Timeout in seconds from initialization
+        timeout_seconds: u64,
+    },
+
+    /// Closes expired trade loop accounts and returns their rent to the loops' authority
+    ///
+    /// Loops that cannot be closed (not expired, paused, holding escrowed NFTs, or
+    /// belonging to another authority) are logged and skipped without failing the batch.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Any account triggering the cleanup
+    /// 1. `[writable]` The authority of the trade loops (receives the reclaimed rent)
+    ///
+    /// 2+ Up to 10 `[writable]` trade loop state accounts
+    BatchCancelExpired {
+        /// The trade loop authority receiving the reclaimed rent
+        recipient: Pubkey,
+    },
+
+    /// Logs the estimated compute units needed to execute a trade loop (read-only)
+    ///
+    /// Accounts expected:
+    /// None
+    EstimateComputeUnits {
+        /// The total number of steps in the trade loop
+        step_count: u8,
+        /// Number of NFTs transferred in each step
+        nft_counts: Vec<u8>,
+        /// Non-zero if the recipient token accounts of a step must be created
+        ata_creates: Vec<u8>,
+    },
+
+    /// Proposes a new upgrade authority, which must accept within the grace period
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The current upgrade authority
+    /// 1. `[writable]` The program config account
+    ProposeAuthorityTransfer {
+        /// The proposed new upgrade authority
+        new_authority: Pubkey,
+    },
+
+    /// Accepts a pending upgrade authority transfer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The pending upgrade authority
+    /// 1. `[writable]` The program config account
+    AcceptAuthorityTransfer {},
+
+    /// Marks a step as failed (e.g. its NFT was frozen after approval), allowing
+    /// any participant to cancel the trade loop
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The governance account
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The program config account
+    MarkStepFailed {
+        /// The index of the failed step
+        step_index: u8,
+        /// Why the step can no longer be executed
+        reason: FailReason,
+    },
+
+    /// Sets the agreed value of each NFT in a step, used as the royalty baseline
+    ///
+    /// The sender pays the royalties, so the recipient co-signs the values. While
+    /// royalty enforcement is enabled, a step whose NFTs have no agreed value
+    /// cannot execute. Changing the step's recipient clears the values.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[signer]` The step recipient
+    SetStepNftValues {
+        /// The index of the step
+        step_index: u8,
+        /// Value in lamports of each NFT in the step (same order as the step's NFT mints)
+        nft_values_lamports: Vec<u64>,
+    },
+
+    /// Updates the creator royalty enforcement settings
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority or governance
+    /// 1. `[writable]` The program config account
+    UpdateRoyaltyEnforcement {
+        /// Whether royalties are paid to creators when NFTs are transferred
+        enabled: bool,
+        /// Maximum number of creators paid per NFT
+        max_creators: u8,
+    },
+
+    /// Dry-run of `ExecuteFullTradeLoop`: runs the same account and balance
+    /// validation but performs no CPIs and writes no state
+    ///
+    /// Accounts expected: same as `ExecuteFullTradeLoop`
+    ///
+    /// Intended as an on-chain pre-check CPI target; off-chain callers should use
+    /// `simulateTransaction` instead. The result is only valid at the time of the
+    /// check and a later real execution can still fail (e.g. if an NFT moves or the
+    /// loop expires in between). Missing recipient token accounts are accepted since
+    /// the real execution creates them.
+    SimulateExecuteFullTradeLoop {},
+
+    /// Replaces the NFTs in a step before it is approved, invalidating all existing approvals
+    ///
+    /// While the collection whitelist is not empty or a minimum floor price is set, each
+    /// new NFT's Metaplex metadata account must be passed as an extra account, and with a
+    /// minimum floor price also its collection's Pyth price feed account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The token program
+    /// 3. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 4. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 5. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 6. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 7+. For each new NFT:
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT (must own the NFT)
+    UpdateStepNfts {
+        /// The index of the step to amend
+        step_index: u8,
+        /// The replacement NFT mints
+        new_nft_mints: Vec<Pubkey>,
+    },
+
+    /// Initializes a trade loop, adds the initiator's step and escrows its NFTs atomically
+    ///
+    /// The initiator's step is approved immediately, so no separate `AddTradeStep`
+    /// or `ApproveTradeStep` is needed for the creator.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The initiator paying for the trade loop account
+    /// 1. `[writable]` The trade loop state account (PDA)
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    /// 4. `[]` Token program
+    /// 5. `[]` Associated token program
+    /// 6. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 7. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 8. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 9. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 10+. For each initiator NFT:
+    ///    - NFT mint address
+    ///    - Initiator's token account for this NFT (must own the NFT)
+    ///    - Escrow authority PDA (seeds: "escrow", trade loop, NFT mint)
+    ///    - Escrow token account (associated token account of the escrow PDA)
+    ///
+    /// Followed by the initiator's cooldown account (writable) while rate limiting is enabled,
+    /// and by each NFT's metadata and price feed accounts while collection or floor price
+    /// checks apply
+    InitializeTradeLoopWithEscrow {
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// The participant ring, in trade order, starting with the initiator
+        participants: Vec<Pubkey>,
+        /// Timeout in seconds from initialization
+        timeout_seconds: u64,
+        /// Index of the initiator's step (must be 0, the initiator starts the ring)
+        initiator_step_index: u8,
+        /// Recipient of the initiator's NFTs
+        initiator_to: Pubkey,
+        /// NFTs the initiator is trading
+        initiator_nft_mints: Vec<Pubkey>,
+    },
+    /// Executes the contiguous steps `[from_step, to_step)` of a fully approved trade loop
+    ///
+    /// Used when the full loop needs more accounts than fit in one transaction.
+    /// Each step can only be executed once, so overlapping ranges are rejected.
+    ///
+    /// Accounts expected: accounts 0-6 of `ExecuteFullTradeLoop`, then the program
+    /// config account (PDA, seeds: "config"; need not exist yet), then per-step and
+    /// royalty accounts as for `ExecuteFullTradeLoop`, only for the steps in the range
+    ExecutePartialLoop {
+        /// The first step to execute
+        from_step: u8,
+        /// One past the last step to execute
+        to_step: u8,
+    },
+    /// Requires a third party to co-sign execution of the trade loop
+    ///
+    /// When set, the witness must be included as a signer in `ExecuteFullTradeLoop`
+    /// and `ExecutePartialLoop`. The zero pubkey clears the requirement.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    SetWitness {
+        /// The account that must co-sign execution
+        witness: Pubkey,
+    },
+
+    /// Removes the witness co-signing requirement from a trade loop
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    ClearWitness {},
+    /// Executes a trade step and closes each source token account left empty,
+    /// returning its rent to the sender
+    ///
+    /// Accounts expected: same as `ExecuteTradeStep`, with a writable rent
+    /// destination (the sender) after each NFT's mint, source and destination accounts.
+    ///
+    /// Closing only happens when enabled in the program config. Token accounts the token program would refuse to close (non-empty,
+    /// native, or with another close authority) are skipped with a warning rather
+    /// than aborting the trade.
+    ExecuteTradeStepAndCloseEmpty {
+        /// The index of the step to execute
+        step_index: u8,
+    },
+
+    /// Enables or disables closing emptied source token accounts after transfer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority or governance
+    /// 1. `[writable]` The program config account
+    SetCloseEmptyTokenAccounts {
+        /// Whether ExecuteTradeStepAndCloseEmpty closes emptied token accounts
+        enabled: bool,
+    },
+    /// Initializes the program config with all operator settings in one transaction
+    ///
+    /// If a version 1 config already exists, it is migrated to the current layout
+    /// (the account is extended with `realloc`) by its upgrade authority.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority (payer)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    InitializeProgramConfigV2 {
+        /// Optional: multisig governance address for decentralized upgrades
+        governance: Option<Pubkey>,
+        /// Maximum number of steps allowed in a trade loop
+        max_participants: u8,
+        /// Protocol fee in basis points
+        fee_basis_points: u16,
+        /// Account receiving protocol fees
+        fee_recipient: Pubkey,
+        /// Whether creator royalties are enforced on transfers
+        royalty_enforcement_enabled: bool,
+    },
+    /// Logs per-step diagnostics of a trade loop, including whether each NFT is still held
+    ///
+    /// Emits one `msg!` line per step and a Borsh-serialized `Vec<DiagnosticResult>`
+    /// via `sol_log_data`. Read-only, and allowed while the program is paused.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    ///
+    /// 1+. For each NFT of each step, in order:
+    ///    - Token account holding the NFT (sender's account, or the escrow token account if escrowed)
+    DiagnoseTradeLoop {},
+    /// Requests a transfer of the trade loop authority, confirmable after a time-lock
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The current trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    TransferTradeAuthority {
+        /// The proposed new authority
+        new_authority: Pubkey,
+    },
+
+    /// Completes a pending trade loop authority transfer once the time-lock has elapsed
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The new authority named in the pending request
+    /// 1. `[writable]` The trade loop state account
+    ConfirmTradeAuthorityTransfer {},
+    /// Splits a trade loop into two linked loops that together form the original cycle
+    ///
+    /// Steps `[0, split_at_step)` stay in the original loop and the rest move to a new
+    /// loop. Executing either half requires the other half to be fully approved and
+    /// included in the accounts; put both `ExecuteFullTradeLoop` instructions in the
+    /// same transaction so they succeed or fail together. Only allowed before any step
+    /// is approved.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority (pays for the new loop)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[writable]` The new trade loop state account (PDA for `new_trade_id`)
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    SplitTradeLoop {
+        /// Index of the first step moved to the new loop
+        split_at_step: u8,
+        /// Unique identifier for the new trade loop
+        new_trade_id: [u8; 32],
+    },
+    /// Logs the deterministic trade ID and trade loop address for the given participants
+    ///
+    /// Read-only; see `utils::derive_trade_id`.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The creator of the trade loop
+    ComputeTradeId {
+        /// Participants of the trade loop, in step order
+        participants: Vec<Pubkey>,
+        /// Distinguishes loops between the same participants
+        nonce: u64,
+    },
+    /// Merges two unapproved steps with the same sender and recipient into one
+    ///
+    /// Step B's NFTs are appended to step A and step B is removed, shifting later steps down.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender of both steps
+    /// 1. `[writable]` The trade loop state account
+    MergeSteps {
+        /// The step that receives the NFTs
+        step_index_a: u8,
+        /// The step that is merged into step A and removed
+        step_index_b: u8,
+    },
+    /// Sets the mint of the token used to vote on governance proposals
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority or governance
+    /// 1. `[writable]` The program config account
+    SetGovernanceTokenMint {
+        /// The governance token mint (None disables proposals)
+        governance_token_mint: Option<Pubkey>,
+    },
+
+    /// Creates a governance proposal; voting stays open for the governance voting period
+    ///
+    /// The proposed change must be within the limits the config accepts.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The proposer (must hold governance tokens, pays for the account)
+    /// 1. `[writable]` The proposal account (PDA, seeds: "proposal", proposal_id)
+    /// 2. `[]` The program config account
+    /// 3. `[]` The proposer's governance token account
+    /// 4. `[]` System program
+    ProposeGovernanceChange {
+        /// Unique identifier for the proposal
+        proposal_id: [u8; 16],
+        /// The change applied if the proposal passes
+        proposed_change: GovernedChange,
+    },
+
+    /// Votes on a governance proposal, weighted by the voter's governance token balance
+    ///
+    /// The voter's whole balance is locked in the vote record's token account until the
+    /// proposal is finalized, so the same tokens cannot vote again from another wallet.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The voter (pays for the vote record and its token account)
+    /// 1. `[writable]` The proposal account
+    /// 2. `[writable]` The vote record account (PDA, seeds: "vote", proposal, voter)
+    /// 3. `[]` The program config account
+    /// 4. `[writable]` The voter's governance token account
+    /// 5. `[]` System program
+    /// 6. `[]` The governance token mint
+    /// 7. `[writable]` The vote escrow token account (associated token account of the vote record)
+    /// 8. `[]` Token program
+    /// 9. `[]` Associated token program
+    /// 10. `[]` Rent sysvar
+    VoteOnProposal {
+        /// Identifier of the proposal
+        proposal_id: [u8; 16],
+        /// Whether the vote is in favor of the proposal
+        in_favor: bool,
+    },
+
+    /// Applies a governance proposal after its deadline if more weight voted for than against
+    ///
+    /// The proposal is always finalized; a passed change outside the limits the config
+    /// accepts (possible for proposals created before changes were validated) is rejected.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Any account
+    /// 1. `[writable]` The proposal account
+    /// 2. `[writable]` The program config account
+    FinalizeProposal {
+        /// Identifier of the proposal
+        proposal_id: [u8; 16],
+    },
+    /// Proposes an alternative NFT list for a step on behalf of the step's recipient
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The step's recipient (pays for the counter-offer account)
+    /// 1. `[]` The trade loop account
+    /// 2. `[writable]` The counter-offer account (PDA, seeds: "counter", trade_loop, step_index)
+    /// 3. `[]` System program
+    ProposeCounterOffer {
+        /// The step being countered
+        step_index: u8,
+        /// The NFTs the step's sender is asked to send instead
+        alternative_nft_mints: Vec<Pubkey>,
+    },
+
+    /// Accepts a counter-offer, replacing the step's NFTs and invalidating existing approvals
+    ///
+    /// While the collection whitelist is not empty or a minimum floor price is set, each
+    /// new NFT's Metaplex metadata account must be passed as an extra account, and with a
+    /// minimum floor price also its collection's Pyth price feed account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step's sender
+    /// 1. `[writable]` The trade loop account
+    /// 2. `[writable]` The counter-offer account
+    /// 3. `[writable]` The counter-offer proposer (receives the account rent)
+    /// 4. `[]` Token program
+    /// 5. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 6. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 7. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 8. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 9+. For each counter-offer NFT:
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT (must own the NFT)
+    AcceptCounterOffer {
+        /// The step whose counter-offer is accepted
+        step_index: u8,
+    },
+
+    /// Rejects a counter-offer and deletes the counter-offer account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step's sender
+    /// 1. `[]` The trade loop account
+    /// 2. `[writable]` The counter-offer account
+    /// 3. `[writable]` The counter-offer proposer (receives the account rent)
+    RejectCounterOffer {
+        /// The step whose counter-offer is rejected
+        step_index: u8,
+    },
+    /// Blocks a wallet from creating or joining new trade loops
+    ///
+    /// Loops the wallet already participates in can still complete or be cancelled.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for the blocklist account)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The blocklist account (PDA, seeds: "blocklist")
+    /// 3. `[]` System program
+    BlockParticipant {
+        /// The wallet to block
+        wallet: Pubkey,
+    },
+
+    /// Removes a wallet from the blocklist
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The blocklist account (PDA, seeds: "blocklist")
+    /// 3. `[]` System program
+    UnblockParticipant {
+        /// The wallet to unblock
+        wallet: Pubkey,
+    },
+    /// Attaches an off-chain description or terms document URI to a step
+    ///
+    /// Allowed before the step is approved; afterwards governance must co-sign.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` (Optional) The program config account, required after approval
+    /// 3. `[signer]` (Optional) The governance account, required after approval
+    SetStepMetadata {
+        /// The step to annotate
+        step_index: u8,
+        /// The metadata URI, zero-padded to 128 bytes
+        metadata_uri: [u8; 128],
+    },
+
+    /// Removes the metadata URI from a step
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` (Optional) The program config account, required after approval
+    /// 3. `[signer]` (Optional) The governance account, required after approval
+    ClearStepMetadata {
+        /// The step to clear
+        step_index: u8,
+    },
+    /// Verifies the metadata and current ownership of every NFT in a trade loop (read-only)
+    ///
+    /// Fails with `BulkVerificationFailed` carrying a bitmask of failing NFTs, where bit
+    /// `step_index * MAX_NFTS_PER_STEP + nft_index` is set for each failure. The mask is
+    /// also logged with `sol_log_data`, since the program error code cannot carry it.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    ///
+    /// 1+. For each NFT of each step, in order:
+    ///    - NFT mint address
+    ///    - Token account currently holding the NFT (sender's, or the escrow's if escrowed)
+    ///    - Metaplex metadata account for the mint
+    VerifyAllNfts {},
+
+    /// Re-initializes a cancelled (zeroed) trade loop account in place, reusing its rent
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The original creator of the trade loop
+    /// 1. `[writable]` The cancelled trade loop account (PDA of trade_id and creator)
+    /// 2. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 3. `[]` (Optional) The program config account
+    ReopenCancelledLoop {
+        /// The trade_id the account was originally created with (PDA seed)
+        trade_id: [u8; 32],
+        /// The total number of steps in the reopened trade loop
+        step_count: u8,
+        /// Timeout in seconds from reopening
+        timeout_seconds: u64,
+    },
+
+    /// Allows another wallet to approve a step on behalf of its sender, once, until the expiry
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    DelegateApproval {
+        /// The step whose approval is delegated
+        step_index: u8,
+        /// The wallet allowed to approve the step
+        delegate: Pubkey,
+        /// Unix timestamp after which the delegation can no longer be used
+        expiry: i64,
+    },
+
+    /// Revokes an approval delegation
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    RevokeDelegateApproval {
+        /// The step whose delegation is revoked
+        step_index: u8,
+    },
+
+    /// Adds up to `MAX_BATCH_ADD_STEPS` steps sent by the same wallet in one instruction
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender of every step in the batch
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Token program
+    /// 3. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 4. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 5. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 6. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 7+. For each NFT of each batch step, in order:
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT (must own the NFT)
+    BatchAddSteps {
+        /// The steps to add
+        steps: Vec<BatchStepData>,
+    },
+
+    /// Records a hash of the trade loop's current state for dispute resolution
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The trade loop authority or a step participant (pays for the snapshot)
+    /// 1. `[]` The trade loop state account
+    /// 2. `[writable]` The snapshot account (PDA, seeds: "snapshot", trade_loop, snapshot_id)
+    /// 3. `[]` System program
+    SnapshotTradeLoop {
+        /// Identifier of the snapshot within the trade loop
+        snapshot_id: u8,
+    },
+
+    /// Succeeds only if the trade loop's current state matches the snapshot (read-only)
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    /// 1. `[]` The snapshot account
+    VerifySnapshot {
+        /// Identifier of the snapshot within the trade loop
+        snapshot_id: u8,
+    },
+    /// Resizes a trade loop account created before the fixed-size packed layout
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Any account (pays rent for the extra space)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` System program
+    MigrateTradeLoopAccount {},
+    /// Authorizes a relayer to execute trade loops on behalf of participants
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for any account growth)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    AddRelayer {
+        /// The relayer wallet
+        relayer: Pubkey,
+    },
+    /// Removes a relayer from the authorized relayers
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    RemoveRelayer {
+        /// The relayer wallet
+        relayer: Pubkey,
+    },
+    /// Deposits lamports into the fee vault used to reimburse relayers
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The funding account
+    /// 1. `[writable]` The fee vault account (PDA, seeds: "fee_vault")
+    /// 2. `[]` System program
+    FundFeeVault {
+        /// Lamports to deposit
+        amount: u64,
+    },
+    /// Replaces the searchable tags of a trade loop (only before it is fully approved)
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    SetLoopTags {
+        /// Up to `MAX_LOOP_TAGS` raw UTF-8 tags, zero-padded to 32 bytes
+        tags: Vec<[u8; 32]>,
+    },
+    /// Removes every tag from a trade loop (only before it is fully approved)
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    ClearLoopTags {},
+    /// Checks that a trade loop forms a valid cycle (read-only)
+    ///
+    /// Fails with `TradeLoopVerificationFailed` and logs the Borsh-encoded
+    /// `LoopIntegrityError` with `sol_log_data` so clients can see why.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    VerifyLoopIntegrity {},
+    /// Reorders the steps of a trade loop before any step is approved
+    ///
+    /// The reordered steps must still form a valid cycle.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    ReorderSteps {
+        /// Permutation of the step indexes: position `i` takes the step currently at `new_order[i]`
+        new_order: Vec<u8>,
+    },
+    /// Swaps one NFT from Alice for one NFT from Bob without creating a trade loop account
+    ///
+    /// Both NFTs are moved into escrow PDAs (seeds: "swap_escrow", depositor, NFT mint)
+    /// and then forwarded to the other party. The escrow token accounts are closed
+    /// and their rent returned to the depositors within the same instruction.
+    ///
+    /// While KYC is required, the KYC entries of both parties must be passed as extra accounts.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Alice
+    /// 1. `[signer, writable]` Bob
+    /// 2. `[]` Alice's NFT mint
+    /// 3. `[writable]` Alice's token account holding her NFT
+    /// 4. `[]` Alice's escrow authority PDA
+    /// 5. `[writable]` Alice's escrow token account (associated token account of the PDA)
+    /// 6. `[writable]` Bob's token account receiving Alice's NFT (created if needed)
+    /// 7. `[]` Bob's NFT mint
+    /// 8. `[writable]` Bob's token account holding his NFT
+    /// 9. `[]` Bob's escrow authority PDA
+    /// 10. `[writable]` Bob's escrow token account (associated token account of the PDA)
+    /// 11. `[writable]` Alice's token account receiving Bob's NFT (created if needed)
+    /// 12. `[]` Token program
+    /// 13. `[]` Associated token program
+    /// 14. `[]` System program
+    /// 15. `[]` Rent sysvar
+    /// 16. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 17. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    AtomicSwapTwoParty {
+        /// The NFT Alice gives
+        alice_nft_mint: Pubkey,
+        /// The NFT Bob gives
+        bob_nft_mint: Pubkey,
+    },
+    /// Publishes an NFT for trade against any NFT from a collection, escrowing it in the offer account
+    ///
+    /// While KYC is required, the offerer's KYC entry must be passed as an extra account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer (pays for the accounts)
+    /// 1. `[writable]` The open offer account (PDA, seeds: "open_offer", offer_id)
+    /// 2. `[]` The offered NFT mint
+    /// 3. `[writable]` The offerer's token account holding the NFT
+    /// 4. `[writable]` The escrow token account (associated token account of the offer account)
+    /// 5. `[]` Token program
+    /// 6. `[]` Associated token program
+    /// 7. `[]` System program
+    /// 8. `[]` Rent sysvar
+    /// 9. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 10. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    CreateOpenOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+        /// The NFT being offered
+        offered_nft_mint: Pubkey,
+        /// Verified Metaplex collection wanted in return
+        wanted_collection: Pubkey,
+        /// Seconds until the offer expires
+        timeout_seconds: u64,
+    },
+    /// Fulfills an open offer with an NFT from the wanted collection and closes the offer
+    ///
+    /// While KYC is required, the KYC entries of both parties must be passed as extra accounts.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The fulfiller (pays for any missing token accounts)
+    /// 1. `[writable]` The offerer (receives the fulfiller's NFT and the offer rent)
+    /// 2. `[writable]` The open offer account
+    /// 3. `[]` The offered NFT mint
+    /// 4. `[writable]` The escrow token account
+    /// 5. `[writable]` The fulfiller's token account receiving the offered NFT (created if needed)
+    /// 6. `[]` The fulfiller's NFT mint
+    /// 7. `[]` Metaplex metadata account of the fulfiller's NFT
+    /// 8. `[writable]` The fulfiller's token account holding their NFT
+    /// 9. `[writable]` The offerer's token account receiving the fulfiller's NFT (created if needed)
+    /// 10. `[]` Token program
+    /// 11. `[]` Associated token program
+    /// 12. `[]` System program
+    /// 13. `[]` Rent sysvar
+    /// 14. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 15. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    FulfillOpenOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+        /// The NFT the fulfiller sends to the offerer
+        fulfiller_nft_mint: Pubkey,
+    },
+    /// Cancels an open offer, returning the escrowed NFT and rent to the offerer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer
+    /// 1. `[writable]` The open offer account
+    /// 2. `[]` The offered NFT mint
+    /// 3. `[writable]` The escrow token account
+    /// 4. `[writable]` The offerer's token account receiving the NFT (created if needed)
+    /// 5. `[]` Token program
+    /// 6. `[]` Associated token program
+    /// 7. `[]` System program
+    /// 8. `[]` Rent sysvar
+    CancelOpenOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+    },
+    /// Sets the lamports a step's sender pays its recipient alongside the NFTs
+    ///
+    /// Only allowed before the step is approved. The amount is capped by the
+    /// program config's `max_sol_per_step`. The sender must sign execution.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step sender
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The program config account
+    SetStepSolAmount {
+        /// The step to update
+        step_index: u8,
+        /// Lamports paid to the recipient (0 removes the SOL leg)
+        sol_amount: u64,
+    },
+    /// Sets the maximum lamports a step may pay alongside its NFTs
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for any account growth)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetMaxSolPerStep {
+        /// Maximum lamports per step (0 disables SOL legs)
+        max_sol_per_step: u64,
+    },
+    /// Creates up to MAX_BULK_INITIALIZE_LOOPS trade loops in one transaction
+    ///
+    /// Entries whose trade loop account already exists are skipped and marked
+    /// in a bitmask that is logged; the instruction only fails if every entry
+    /// was skipped. A zero trade ID is replaced with one derived from the
+    /// payer, the participants and the entry's index.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The payer and creator of the trade loops
+    /// 1. `[]` Rent sysvar
+    /// 2. `[]` System program
+    /// 3. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 4. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    /// 5. `[writable]` One trade loop PDA per entry, in order
+    ///
+    /// N+. `[writable]` (Required while rate limiting is enabled) The payer's cooldown account
+    ///    (PDA, seeds: "cooldown", payer)
+    BulkInitializeLoops {
+        /// The trade loops to create
+        loops: Vec<BulkLoopParams>,
+    },
+    /// Creates the completion badge mint, with the program config as mint authority
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for the accounts)
+    /// 1. `[writable]` The program config account
+    /// 2. `[writable]` The badge mint PDA
+    /// 3. `[writable]` The Metaplex metadata account of the badge mint
+    /// 4. `[]` Token program
+    /// 5. `[]` Metaplex Token Metadata program
+    /// 6. `[]` System program
+    /// 7. `[]` Rent sysvar
+    InitializeBadgeMint {
+        /// Metadata URI of the badge, zero-padded
+        metadata_uri: [u8; 128],
+    },
+    /// Freezes the sender's token accounts of an approved step until execution
+    ///
+    /// The program config must be the freeze authority of every NFT mint in the
+    /// step. Escrowed NFTs are already held by the program and are skipped.
+    /// Execution thaws the accounts before transferring; executing a locked step
+    /// also needs the program config account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` A participant of the trade loop
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The program config account
+    /// 3. `[]` Token program
+    ///
+    /// 4+. For each non-escrowed NFT in the step: `[]` the mint, then
+    ///     `[writable]` the sender's token account
+    LockStepNfts {
+        /// The step whose NFTs to lock
+        step_index: u8,
+    },
+    /// Thaws the sender's token accounts of a locked step
+    ///
+    /// Only allowed once the step is no longer approved, the trade loop has
+    /// expired or a step has failed. Locked steps must be unlocked before the
+    /// trade loop can be cancelled or closed.
+    ///
+    /// Accounts expected: as for `LockStepNfts`
+    UnlockStepNfts {
+        /// The step whose NFTs to unlock
+        step_index: u8,
+    },
+    /// Logs the program stats with `sol_log_data` (Borsh-encoded `ProgramStats`)
+    ///
+    /// Trade loop instructions update the stats when the stats account is
+    /// included as an extra writable account.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The program stats account
+    GetProgramStats {},
+    /// Resets every program stats counter to zero, creating the account if needed
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays for the account)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The program stats account
+    /// 3. `[]` System program
+    ResetProgramStats {},
+    /// Blocks an NFT mint from being added to trade steps (e.g. stolen or sanctioned NFTs)
+    ///
+    /// Steps that already hold the mint are not affected.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays for the mint blocklist account)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The mint blocklist account (PDA, seeds: "mint_blocklist")
+    /// 3. `[]` System program
+    BlockNftMint {
+        /// The mint to block
+        mint: Pubkey,
+    },
+    /// Removes an NFT mint from the mint blocklist
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The mint blocklist account (PDA, seeds: "mint_blocklist")
+    /// 3. `[]` System program
+    UnblockNftMint {
+        /// The mint to unblock
+        mint: Pubkey,
+    },
+    /// Hands a participant's position in a trade loop to another wallet
+    ///
+    /// The new owner becomes the sender of the step and the recipient of the
+    /// step that paid the old owner, and must hold the step's NFTs. Only allowed
+    /// before the step is approved and before any step has executed; all
+    /// existing approvals are invalidated.
+    ///
+    /// While KYC is required, the new owner's KYC entry must be passed as an extra account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The current sender of the step
+    /// 1. `[signer]` The new owner
+    /// 2. `[writable]` The trade loop state account
+    /// 3. `[]` Token program
+    /// 4. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 5. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 6+. For each NFT in the step:
+    ///    - NFT mint address
+    ///    - New owner's token account for this NFT (must own the NFT)
+    TransferStepOwnership {
+        /// The step to hand over
+        step_index: u8,
+        /// The wallet taking over the position
+        new_owner: Pubkey,
+    },
+    /// Makes a trade loop renew itself after each full execution
+    ///
+    /// When `ExecuteFullTradeLoop` completes, a successor loop with the same
+    /// participants is created, expiring `interval_seconds` later. Its steps start
+    /// without NFTs, so participants add new ones with `AddTradeStep`. The successor
+    /// inherits the settings, so renewal continues until `max_recurrences` successors
+    /// exist. Calling this again updates the settings without resetting the count.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    EnableRecurring {
+        /// Number of successor loops to create in total
+        max_recurrences: u8,
+        /// Lifetime of each successor loop in seconds
+        interval_seconds: u64,
+    },
+    /// Creates the dedup cache that ExecuteFullTradeLoop records executions in
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for the account)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The dedup cache account (PDA, seeds: "dedup_cache")
+    /// 3. `[]` System program
+    InitializeDedupCache {},
+    /// Checks a proposed participant ring for topology errors without touching any account
+    ///
+    /// Step i sends from participant i to participant i+1 and the last step closes
+    /// the ring. Every problem found (participant count out of range, self-sends,
+    /// wallets appearing more than once, or a ring that is not one strongly connected
+    /// cycle) is logged before failing with `TradeLoopVerificationFailed`. Intended
+    /// for simulation while a trade is proposed, before anyone signs.
+    ///
+    /// Accounts expected: none
+    ValidateTopology {
+        /// The participant ring, in trade order
+        participants: Vec<Pubkey>,
+    },
+    /// Lightweight canary for monitoring: checks the program config and logs a
+    /// Borsh-encoded `HealthStatus` with `sol_log_data`
+    ///
+    /// Fails with `InvalidProgramVersion` if the config version differs from
+    /// `expected_version`. Succeeds with a warning if the config account has not been
+    /// created. Never mutates state and is allowed while the program is paused.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The program config account (PDA, seeds: "program_config")
+    HealthCheck {
+        /// The program version the monitor expects to be deployed
+        expected_version: u32,
+    },
+    /// Moves a trade loop from the legacy PDA (seeds: "trade_loop", trade_id) to the
+    /// creator-scoped PDA (seeds: "trade_loop", trade_id, authority)
+    ///
+    /// Only loops with no executed steps can be migrated. The legacy account is
+    /// zeroed and its rent returned to the authority.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The trade loop authority (pays for the new account)
+    /// 1. `[writable]` The legacy trade loop account
+    /// 2. `[writable]` The new trade loop account
+    /// 3. `[]` System program
+    MigrateLegacyLoop {
+        /// Trade ID the legacy trade loop was created with
+        original_trade_id: [u8; 32],
+    },
+    /// Approves up to MAX_BATCH_APPROVALS steps, possibly across several trade loops
+    ///
+    /// The signer must be the sender of every step (delegated approvals are not
+    /// accepted here). An entry that fails is skipped and marked in a bitmask that
+    /// is logged; the instruction only fails if every entry failed.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender approving the steps
+    /// 1. `[]` Clock sysvar
+    /// 2. `[writable]` One trade loop account per entry, in order
+    ///
+    /// N+. `[]` (Optional) The program config account
+    BatchApproveSteps {
+        /// The steps to approve
+        approvals: Vec<BatchApprovalEntry>,
+    },
+    /// Unpacks the given instruction data and logs its debug representation
+    ///
+    /// Only processed by builds with the `debug` feature; other builds reject it
+    /// as invalid. The variant itself is always present so instruction encodings
+    /// do not depend on the build.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority
+    /// 1. `[]` The program config account
+    DebugDumpInstruction {
+        /// Packed instruction data to inspect
+        instruction_bytes: Vec<u8>,
+    },
+    /// Mints an NFT representing the trade loop's authority to the current authority
+    ///
+    /// The loop's authority moves to the program config until the NFT is redeemed,
+    /// so whoever holds the NFT controls the loop. The Metaplex metadata URI is the
+    /// trade loop address.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The trade loop authority (payer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The program config account (mint and metadata update authority)
+    /// 3. `[writable]` The position mint (PDA, seeds: "position_mint", trade loop)
+    /// 4. `[writable]` The position mint's Metaplex metadata account
+    /// 5. `[writable]` The authority's token account for the position mint (ATA, created if needed)
+    /// 6. `[]` Token program
+    /// 7. `[]` Associated token program
+    /// 8. `[]` Token metadata program
+    /// 9. `[]` System program
+    /// 10. `[]` Rent sysvar
+    TokenizeTradeLoop {},
+    /// Burns a trade loop position NFT and makes its holder the trade loop authority
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The holder of the position NFT
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[writable]` The holder's token account for the position mint
+    /// 3. `[writable]` The position mint
+    /// 4. `[]` Token program
+    RedeemTokenizedPosition {
+        /// The position NFT mint recorded in the trade loop
+        tokenized_nft_mint: Pubkey,
+    },
+    /// Cancels a trade loop like `CancelTradeLoop`, returning escrowed NFTs step by step
+    ///
+    /// Every step is marked `Cancelled`. A step whose escrowed NFTs cannot be
+    /// returned is skipped and keeps its escrow flags; the trade loop then stays
+    /// open so any participant can retry. Once every NFT is returned the account
+    /// is zeroed. Fails with `EscrowReleasePartialFailure` only if no escrowed
+    /// step could be released.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The participant cancelling the trade
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Token program (only if any NFT is escrowed)
+    ///
+    /// For each escrowed NFT, in step order:
+    ///    - Escrow authority PDA
+    ///    - `[writable]` Escrow token account
+    ///    - `[writable]` Sender's token account
+    ///
+    /// Bonded loops pass their bond accounts as for `CancelTradeLoop`; bonds are
+    /// refunded once the loop is wiped.
+    CancelTradeLoopV2 {},
+    /// Hands the Metaplex update authority of an NFT held by the program config to another account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority or governance
+    /// 1. `[]` The program config account (current update authority)
+    /// 2. `[writable]` The NFT's Metaplex metadata account
+    /// 3. `[]` Token metadata program
+    SetMetadataUpdateAuthority {
+        /// The NFT mint whose metadata to update
+        mint: Pubkey,
+        /// The new update authority
+        new_authority: Pubkey,
+    },
+    /// Enables or disables recording trade provenance in NFT metadata on full execution
+    ///
+    /// Only metadata whose update authority is the program config is updated. The
+    /// metadata accounts and the token metadata program are passed as extra
+    /// accounts to `ExecuteFullTradeLoop`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetAppendProvenanceMetadata {
+        /// Whether executed trade loops append provenance to NFT metadata
+        enabled: bool,
+    },
+    /// Sets a deadline for one step, after which it can no longer be approved or executed
+    ///
+    /// The deadline cannot be later than the trade loop's own expiry, which is
+    /// still enforced independently.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender of the step
+    /// 1. `[writable]` The trade loop state account
+    SetStepExpiry {
+        /// The step to set the deadline for
+        step_index: u8,
+        /// Unix timestamp after which the step expires
+        step_expires_at: u64,
+    },
+    /// Adds a Metaplex collection to the collection whitelist
+    ///
+    /// While the whitelist holds any collection, steps can only be added with NFTs
+    /// that are verified members of a whitelisted collection. Each NFT's metadata
+    /// account is then passed as an extra account when adding steps.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays for the collection whitelist account)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The collection whitelist account (PDA, seeds: "collection_whitelist")
+    /// 3. `[]` System program
+    WhitelistCollection {
+        /// The collection mint to whitelist
+        collection: Pubkey,
+    },
+    /// Removes a Metaplex collection from the collection whitelist
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The collection whitelist account (PDA, seeds: "collection_whitelist")
+    /// 3. `[]` System program
+    UnwhitelistCollection {
+        /// The collection mint to remove
+        collection: Pubkey,
+    },
+    /// Sets the advisory deadline and message shown to participants
+    ///
+    /// Purely informational: `expires_at` remains the enforced deadline.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    SetDeadlineMessage {
+        /// Raw UTF-8 message, zero-padded to 64 bytes
+        message: [u8; 64],
+        /// Advisory deadline (Unix timestamp), no later than the loop's `expires_at`
+        display_deadline: i64,
+        /// Timezone to show the deadline in, as minutes from UTC (at most 840 either way)
+        timezone_offset: i16,
+    },
+    /// Enables or disables the per-wallet cooldown between trade loop initializations
+    ///
+    /// While enabled, each initializing wallet must wait `COOLDOWN_SLOTS` slots between
+    /// trade loops and can create at most `MAX_LOOPS_PER_EPOCH` per epoch. Its cooldown
+    /// account is then passed as an extra account when initializing trade loops.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetRateLimitingEnabled {
+        /// Whether trade loop initialization is rate limited
+        enabled: bool,
+    },
+    /// Initializes a trade loop from a participant list, building the ring topology
+    ///
+    /// Shorthand for `InitializeTradeLoop` without multisig steps or a cancel lockout:
+    /// participant i owns step i and sends to participant (i + 1) % n.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account initializing the trade loop (payer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    /// 4. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 5. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    /// 6. `[writable]` (Required while rate limiting is enabled) The payer's cooldown account
+    ///    (PDA, seeds: "cooldown", payer)
+    InitializeRingFromParticipants {
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// The participant ring, in trade order; each wallet may appear only once
+        participants: Vec<Pubkey>,
+        /// Timeout in seconds from initialization
+        timeout_seconds: u64,
+    },
+    /// Sets the key that signs fee discount coupons
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetCouponIssuer {
+        /// The coupon issuer, or None to stop accepting coupons
+        coupon_issuer: Option<Pubkey>,
+    },
+    /// Applies a fee discount coupon to a trade loop, replacing any earlier coupon
+    ///
+    /// The instruction immediately before this one must be an Ed25519 program
+    /// instruction verifying the coupon issuer's signature over `DiscountCoupon::message`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` The program config account
+    /// 3. `[]` Instructions sysvar
+    ApplyCoupon {
+        /// The signed coupon
+        coupon: DiscountCoupon,
+    },
+    /// Proposes a new governance key, which takes effect after a 48 hour timelock
+    ///
+    /// A new proposal replaces any pending one and restarts the timelock.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The current governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    ProposeGovernanceReplacement {
+        /// The proposed governance key
+        new_governance: Pubkey,
+    },
+    /// Replaces the governance key with the proposed one once the timelock has passed
+    ///
+    /// Can be called by anyone.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` The program config account
+    FinalizeGovernanceReplacement {},
+    /// Sets the minimum collection floor price of NFTs added to trade steps
+    ///
+    /// While set, steps can only be added with NFTs of a verified, whitelisted collection
+    /// that has a Pyth floor price feed. Each NFT's metadata account and each collection's
+    /// Pyth price feed account are then passed as extra accounts when adding steps.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetMinNftFloorPrice {
+        /// Minimum floor price in USD cents, or None to disable the check
+        min_usd_cents: Option<u64>,
+    },
+    /// Sets or clears the Pyth floor price feed of a whitelisted collection
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays to grow the collection whitelist)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The collection whitelist account (PDA, seeds: "collection_whitelist")
+    /// 3. `[]` System program
+    SetCollectionPriceFeed {
+        /// The whitelisted collection mint
+        collection: Pubkey,
+        /// Pyth feed ID of the collection's floor price, or None to remove it
+        feed_id: Option<[u8; 32]>,
+    },
+    /// Funds the fee vault up to its rent exemption so it can hold and pay out lamports
+    ///
+    /// The vault is a data-less system account, so the program pays out of it by
+    /// signing with its seeds. Does nothing if the vault is already rent exempt.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The payer
+    /// 1. `[writable]` The fee vault account (PDA, seeds: "fee_vault")
+    /// 2. `[]` System program
+    InitializeFeeVault {},
+    /// Withdraws up to `amount_lamports` from the fee vault, keeping it rent exempt
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The upgrade authority
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The fee vault account (PDA, seeds: "fee_vault")
+    /// 3. `[writable]` The recipient
+    /// 4. `[]` System program
+    DrainFeeVault {
+        /// Maximum lamports to withdraw
+        amount_lamports: u64,
+        /// The account receiving the lamports
+        recipient: Pubkey,
+    },
+    /// Logs the fee vault's balance and spendable lamports (read-only)
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The fee vault account (PDA, seeds: "fee_vault")
+    QueryFeeVaultBalance {},
+    /// Applies approvals signed offline by the step senders, all in one transaction
+    ///
+    /// Each signature must be verified by an Ed25519 program instruction earlier in the
+    /// same transaction, over `SignedApproval::message` for the loop's current nonce and
+    /// the step's current hash.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The submitter (any wallet)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Instructions sysvar
+    /// 3. `[]` (Optional) The program config account
+    BatchSignedApprovals {
+        /// The approvals to apply, at most one per step
+        approvals: Vec<SignedApproval>,
+    },
+    /// Offers to take over a step, sending the proposer's NFTs in place of the step's NFTs
+    ///
+    /// Only one proposal per step can be pending at a time.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The proposer (pays for the proposal account)
+    /// 1. `[]` The trade loop account
+    /// 2. `[writable]` The proposal account (PDA, seeds: "step_replace", trade_loop, step_index)
+    /// 3. `[]` System program
+    /// 4. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    ProposeStepReplacement {
+        /// The step to take over
+        step_index: u8,
+        /// The NFTs the proposer would send instead
+        replacement_nft_mints: Vec<Pubkey>,
+    },
+    /// Accepts a replacement proposal, handing the step over to the proposer
+    ///
+    /// The proposer takes over both sending this step and receiving from the previous one,
+    /// and every existing approval is invalidated.
+    ///
+    /// While KYC is required, the proposer's KYC entry must be passed as an extra account,
+    /// and while the collection whitelist is not empty or a minimum floor price is set, so
+    /// must each replacement NFT's Metaplex metadata account (plus its collection's Pyth
+    /// price feed account with a minimum floor price).
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step's sender
+    /// 1. `[writable]` The trade loop account
+    /// 2. `[writable]` The proposal account
+    /// 3. `[writable]` The proposer (receives the account rent)
+    /// 4. `[]` Token program
+    /// 5. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 6. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 7. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 8. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// 9+. For each replacement NFT:
+    ///    - NFT mint address
+    ///    - Proposer's token account for this NFT (must own the NFT)
+    AcceptStepReplacement {
+        /// The step whose replacement proposal is accepted
+        step_index: u8,
+    },
+    /// Rejects (or, signed by the proposer, withdraws) a replacement proposal and deletes its account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The step's sender or the proposer
+    /// 1. `[]` The trade loop account
+    /// 2. `[writable]` The proposal account
+    /// 3. `[writable]` The proposer (receives the account rent)
+    RejectStepReplacement {
+        /// The step whose replacement proposal is rejected
+        step_index: u8,
+    },
+    /// Makes execution of the trade loop conditional on one byte of an external account
+    ///
+    /// When set, the condition account must be passed to `ExecuteFullTradeLoop` and
+    /// `ExecutePartialLoop`, and the byte at `byte_offset` must equal `expected_value`.
+    /// Can only be set before any step is approved.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    SetExecutionCondition {
+        /// The account holding the condition
+        account: Pubkey,
+        /// Offset of the byte within the account data
+        byte_offset: u32,
+        /// Value the byte must have
+        expected_value: u8,
+    },
+    /// Logs the recorded hashes of a step's terms, oldest first (read-only)
+    ///
+    /// A hash is recorded each time the step is added or its NFTs or parties change.
+    /// Emitted via `sol_log_data` as [step_index, concatenated 8-byte hashes].
+    ///
     /// Accounts expected:
-    /// 0. `[signer]` The account initializing the trade loop (payer)
+    /// 0. `[]` The trade loop state account
+    QueryStepHistory {
+        /// The step whose history is logged
+        step_index: u8,
+    },
+    /// Restricts execution of the trade loop to the given executors
+    ///
+    /// Applies to `ExecuteFullTradeLoop` and `ExecutePartialLoop` in place of the
+    /// participant/relayer check. An empty list blocks execution of the loop.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
     /// 1. `[writable]` The trade loop state account
-    /// 2. `[]` Rent sysvar
-    /// 3. `[]` System program
-    InitializeTradeLoop {
+    SetExecutionWhitelist {
+        /// The executors allowed to execute the loop, at most MAX_WHITELISTED_EXECUTORS
+        executors: Vec<Pubkey>,
+    },
+    /// Removes the execution whitelist from a trade loop
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The trade loop authority
+    /// 1. `[writable]` The trade loop state account
+    ClearExecutionWhitelist {},
+    /// Adds a step that sends fungible token amounts alongside its NFTs
+    ///
+    /// Accounts expected: same as `AddTradeStep`, with the NFT accounts followed by,
+    /// for each fungible leg:
+    ///    - Token mint address
+    ///    - Sender's token account for this mint (must hold at least the amount)
+    ///
+    /// Executing the step needs the leg's (mint, sender's ATA, recipient's ATA) accounts
+    /// after the step's NFT accounts; the recipient's ATA is created if needed.
+    AddTradeStepWithFungibles {
+        /// The index of this step in the trade loop (0-based)
+        step_index: u8,
+        /// The recipient of the NFT(s) and tokens in this step
+        to: Pubkey,
+        /// The mint addresses of NFTs being transferred
+        nft_mints: Vec<Pubkey>,
+        /// The fungible token amounts being transferred, at most MAX_SPL_LEGS_PER_STEP
+        spl_legs: Vec<SplTokenLeg>,
+    },
+    /// Pauses the program with a reason
+    ///
+    /// `ScheduledMaintenance` rejects every instruction outside `PAUSE_EXEMPT_TAGS`, all of
+    /// which end with the program config account; other reasons behave like the emergency pause.
+    /// `UpdateProgramConfig` with `new_paused_state: Some(false)` lifts the pause.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    PauseForMaintenance {
+        /// Why the program is paused
+        reason: PauseReason,
+    },
+    /// Creates a sealed-bid trade loop, whose steps commit to hashed NFTs
+    ///
+    /// Each participant commits with CommitTradeStep and approves the committed step; the
+    /// NFTs are only revealed when the loop executes with ExecuteSealedBidLoop.
+    ///
+    /// Accounts expected: same as `InitializeTradeLoop`
+    CreateSealedBidLoop {
         /// Unique identifier for the trade loop
         trade_id: [u8; 32],
-        /// The total number of steps in this trade loop
-        step_count: u8,
+        /// The participant ring, in trade order; each wallet may appear only once
+        participants: Vec<Pubkey>,
         /// Timeout in seconds from initialization
         timeout_seconds: u64,
     },
-
-    /// Adds a step to an existing trade loop
+    /// Commits a sealed-bid step to NFTs without revealing them
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The account adding the step (must match the 'from' address)
+    /// 0. `[signer]` The step's sender
+    /// 1. `[writable]` The trade loop state account
+    CommitTradeStep {
+        /// The index of the step in the trade loop
+        step_index: u8,
+        /// SHA-256 of each NFT's mint || nonce
+        nft_mint_commitments: Vec<[u8; 32]>,
+    },
+    /// Reveals the NFTs of every step of a fully approved sealed-bid loop and executes it
+    ///
+    /// Each step's revealed mints and nonces must hash to its commitments. The revealed
+    /// NFTs pass the mint blocklist, collection whitelist and floor price checks of
+    /// `AddTradeStep`, and the loop then executes like `ExecuteFullTradeLoop`.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 1. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    ///
+    /// 2+ The accounts of `ExecuteFullTradeLoop` for the revealed NFTs, followed by each
+    ///    revealed NFT's Metaplex metadata account (and its collection's Pyth price feed
+    ///    account) while the collection whitelist or a minimum floor price applies
+    ExecuteSealedBidLoop {
+        /// The trade loop's current nonce, guarding against replay if the loop is recreated
+        expected_nonce: u64,
+        /// The revealed NFTs of each step, in step order
+        reveals: Vec<StepReveal>,
+    },
+    /// Executes a trade loop like `ExecuteFullTradeLoop`, skipping steps that touch a frozen token account
+    ///
+    /// Skipped steps are marked `Failed { reason: NftFrozen }` and every other step is executed;
+    /// a `LoopPartiallyExecuted` event lists the skipped steps. Completion badges, provenance and
+    /// recurring renewal only apply when no step was skipped. Escrowed NFTs of a skipped step are
+    /// returned to its sender with `ReleaseFailedStepEscrow`.
+    ///
+    /// Accounts expected: same as `ExecuteFullTradeLoop`
+    ExecuteWithFallback {
+        /// The trade loop nonce the transaction was built against
+        expected_nonce: u64,
+    },
+    /// Returns the escrowed NFTs of a failed step to its sender
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Any participant of the trade loop
     /// 1. `[writable]` The trade loop state account
     /// 2. `[]` Token program
-    /// 3+ Token accounts for verification (for each NFT mint):
-    ///     - NFT mint address
-    ///     - Sender's token account for this NFT (must own the NFT)
-    AddTradeStep {
-        /// The index of this step in the trade loop (0-based)
+    ///
+    /// 3+ For each escrowed NFT of the step:
+    ///    - Escrow authority (PDA)
+    ///    - `[writable]` Escrow token account
+    ///    - `[writable]` Sender's token account
+    ReleaseFailedStepEscrow {
+        /// The failed step
         step_index: u8,
-        /// The recipient of the NFT(s) in this step
-        to: Pubkey,
-        /// The mint addresses of NFTs being transferred
-        nft_mints: Vec<Pubkey>,
     },
-
-    /// Approves a trade step (as the sender)
+    /// Returns one escrowed NFT to its sender after the trade loop expired unexecuted
+    ///
+    /// Needs neither the authority nor a cancellation; the rest of the loop is left as is.
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The sender approving the trade
+    /// 0. `[signer]` The step's sender
     /// 1. `[writable]` The trade loop state account
-    /// 2. `[]` Clock sysvar
-    ApproveTradeStep {
-        /// The index of the step to approve
+    /// 2. `[]` Token program
+    /// 3. `[]` Escrow authority (PDA)
+    /// 4. `[writable]` Escrow token account
+    /// 5. `[writable]` Sender's token account
+    ClaimExpiredEscrow {
+        /// The step holding the NFT
         step_index: u8,
+        /// Index of the NFT in the step's `nft_mints`
+        nft_mint_index: u8,
     },
-
-    /// Executes a single trade step (transfers NFTs)
+    /// Creates a trade loop whose senders each post an execution bond when adding their step
+    ///
+    /// Bonds are refunded when the loop executes or is cancelled. Once every step is
+    /// approved, the loop has the program config's execution window to execute; after
+    /// that any party can claim the bonds with ClaimExecutionBond.
+    ///
+    /// Accounts expected: same as `InitializeTradeLoop`
+    CreateTradeLoopWithGuaranteedExecution {
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// The participant ring, in trade order; each wallet may appear only once
+        participants: Vec<Pubkey>,
+        /// Timeout in seconds from initialization
+        timeout_seconds: u64,
+        /// Lamports each sender deposits, at least the rent-exempt minimum of an empty account
+        execution_bond_lamports: u64,
+    },
+    /// Claims or refunds the execution bond of a step
+    ///
+    /// The bond goes to the claimer once the fully approved loop has missed its execution
+    /// window. It is refunded to the step's sender once the loop has executed, or if it
+    /// expired without missing the window.
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The account executing the trade (can be anyone once approved)
+    /// 0. `[signer, writable]` Any party claiming the bond
+    /// 1. `[]` The trade loop state account
+    /// 2. `[writable]` The step's bond PDA
+    /// 3. `[]` System program
+    /// 4. `[]` The program config account (the default window applies if it is uninitialized)
+    /// 5. `[writable]` The step's sender (refunds only)
+    ClaimExecutionBond {
+        /// The step whose bond is claimed
+        step_index: u8,
+    },
+    /// Sets how long a fully approved bonded trade loop has to execute before its bonds can be claimed
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    SetExecutionWindow {
+        /// The window in seconds; None restores DEFAULT_EXECUTION_WINDOW_SECONDS
+        execution_window_seconds: Option<u64>,
+    },
+    /// Applies one approval the step's sender signed offline, until a slot it chose
+    ///
+    /// The signature must be verified by an Ed25519 program instruction earlier in the
+    /// same transaction, over `offchain_approval_message`. Like `BatchSignedApprovals`, the
+    /// signed message covers the loop's nonce and the step's hash, and it also carries its
+    /// own expiry.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The submitter (any wallet)
     /// 1. `[writable]` The trade loop state account
-    /// 2. `[]` The sender's wallet
-    /// 3. `[]` The recipient's wallet
-    /// 4. `[]` Token program
-    /// 5. `[]` Associated token program
-    /// 6+ NFT accounts and token accounts (varies based on step) in pairs:
-    ///     - NFT mint address
-    ///     - Sender's token account for this NFT
-    ///     - Recipient's token account for this NFT (will be created if needed)
-    ExecuteTradeStep {
-        /// The index of the step to execute
+    /// 2. `[]` Instructions sysvar
+    ApplyOffchainApproval {
+        /// The step being approved
         step_index: u8,
+        /// Ed25519 signature of the step's sender over `message`
+        signature: [u8; 64],
+        /// The signed `offchain_approval_message`
+        message: [u8; OFFCHAIN_APPROVAL_MESSAGE_SIZE],
     },
-
-    /// Executes an atomic multi-step trade (executes multiple steps at once)
+    /// Logs when each step of a trade loop was added, approved and executed
+    ///
+    /// Emits a Borsh-serialized `Vec<ParticipantStats>` via `sol_log_data`, one entry per
+    /// step. Read-only, and allowed while the program is paused.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    GetParticipantStats {},
+    /// Initializes the program configuration with tiered protocol fees
+    ///
+    /// Accounts expected: same as `InitializeProgramConfig`
+    InitializeProgramConfigWithFeeSchedule {
+        /// Optional: multisig governance address for decentralized upgrades
+        governance: Option<Pubkey>,
+        /// Protocol fee tiers by loop size; each tier is at most MAX_SCHEDULED_FEE_BASIS_POINTS
+        fee_schedule: FeeSchedule,
+    },
+    /// Replaces the protocol fee tiers
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    UpdateFeeSchedule {
+        /// Protocol fee tiers by loop size; each tier is at most MAX_SCHEDULED_FEE_BASIS_POINTS
+        new_schedule: FeeSchedule,
+    },
+    /// Closes empty escrow token accounts of a trade loop, paying their rent to the caller
+    ///
+    /// Anyone may call this as a cleanup-for-reward operation. Accounts that are not an
+    /// empty escrow token account of the loop, or whose NFT the loop still counts as
+    /// escrowed, are skipped. Emits `EscrowsClosed`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The caller, receiving the reclaimed rent
+    /// 1. `[]` The trade loop account (may already be closed)
+    /// 2. `[]` Token program
+    ///
+    /// 3+ Up to MAX_PARTICIPANTS_PER_TRANSACTION * MAX_NFTS_PER_STEP pairs of:
+    ///    - Escrow authority PDA (seeds: "escrow", trade loop, NFT mint)
+    ///    - `[writable]` Escrow token account
+    BulkCloseEscrows {},
+    /// Changes the recipient of a step before it is approved, invalidating all existing approvals
+    ///
+    /// Once every step is added, the loop must still form a valid cycle.
+    ///
+    /// While KYC is required, the new recipient's KYC entry must be passed as an extra account.
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The account executing the trade (can be anyone once all approved)
+    /// 0. `[signer]` The step sender
     /// 1. `[writable]` The trade loop state account
-    /// Many accounts required for each step - specific structure varies based on trade loop composition
-    ExecuteFullTradeLoop {},
-
-    /// Cancels a trade loop
+    /// 2. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 3. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    UpdateStepRecipient {
+        /// The step to update
+        step_index: u8,
+        /// The new recipient of the step's NFTs
+        new_recipient: Pubkey,
+    },
+    /// Makes this program the transfer hook of a Token-2022 mint, so while the given trade
+    /// loop is live the mint only moves from the sender to the recipient of an approved step
+    ///
+    /// The mint's transfer hook authority is handed to the hook authority PDA. Executing the
+    /// step restores the original hook program and authority before moving the NFT, as does
+    /// DeregisterTransferHook. The restriction also lapses once the loop is closed or expires.
+    /// Mints that already have a transfer hook program are rejected.
     ///
     /// Accounts expected:
-    /// 0. `[signer]` Any participant in the trade loop
+    /// 0. `[signer, writable]` The mint's transfer hook authority (pays for the new accounts)
+    /// 1. `[writable]` The Token-2022 mint
+    /// 2. `[writable]` The hook registry account (PDA seeds: "hook_registry", mint)
+    /// 3. `[writable]` The extra account metas account (PDA seeds: "extra-account-metas", mint)
+    /// 4. `[]` The trade loop trading the mint
+    /// 5. `[]` Token-2022 program
+    /// 6. `[]` System program
+    RegisterTransferHook {
+        /// The Token-2022 mint to hook
+        mint: Pubkey,
+    },
+    /// Restores the transfer hook program and authority a mint had before RegisterTransferHook,
+    /// if execution has not already, and closes the registry and extra account metas accounts
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The original transfer hook authority, receiving the reclaimed rent
+    /// 1. `[writable]` The Token-2022 mint
+    /// 2. `[writable]` The hook registry account
+    /// 3. `[writable]` The extra account metas account
+    /// 4. `[]` The hook authority PDA (seeds: "hook_authority")
+    /// 5. `[]` Token-2022 program
+    DeregisterTransferHook {},
+    /// Lists an NFT in the offer book of the wanted collection, escrowing it in the book
+    ///
+    /// Books are kept sorted by floor premium so the cheapest offers for a collection come first.
+    /// The book is created on first use and grown by the offerer.
+    ///
+    /// While KYC is required, the offerer's KYC entry must be passed as an extra account.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer (pays for the book's growth and the escrow)
+    /// 1. `[writable]` The offer book account (PDA, seeds: "offer_book", wanted collection)
+    /// 2. `[]` The offered NFT mint
+    /// 3. `[writable]` The offerer's token account holding the NFT
+    /// 4. `[writable]` The escrow token account (associated token account of the offer book)
+    /// 5. `[]` Token program
+    /// 6. `[]` Associated token program
+    /// 7. `[]` System program
+    /// 8. `[]` Rent sysvar
+    /// 9. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 10. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ListNftOffer {
+        /// Unique identifier for the offer within the book
+        offer_id: [u8; 16],
+        /// The NFT being offered
+        offered_nft: Pubkey,
+        /// Verified Metaplex collection wanted in return
+        wanted_collection: Pubkey,
+        /// Premium over the wanted collection's floor, between -10000 and 10000 basis points
+        floor_premium_bps: i16,
+    },
+    /// Removes an offer from its offer book, returning the escrowed NFT to the offerer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer
+    /// 1. `[writable]` The offer book account
+    /// 2. `[]` The offered NFT mint
+    /// 3. `[writable]` The escrow token account
+    /// 4. `[writable]` The offerer's token account receiving the NFT (created if needed)
+    /// 5. `[]` Token program
+    /// 6. `[]` Associated token program
+    /// 7. `[]` System program
+    /// 8. `[]` Rent sysvar
+    DelistNftOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+    },
+    /// Swaps the escrowed NFTs of two offers that each want the other's collection
+    ///
+    /// Anyone may match offers. Their floor premiums must sum to at most zero.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The matcher (pays for any missing token accounts)
+    /// 1. `[writable]` The offer book holding offer A
+    /// 2. `[writable]` The offer book holding offer B
+    /// 3. `[writable]` Offerer A (receives the escrow rent of A's NFT)
+    /// 4. `[writable]` Offerer B (receives the escrow rent of B's NFT)
+    /// 5. `[]` The NFT mint offered by A
+    /// 6. `[]` Metaplex metadata account of A's NFT
+    /// 7. `[writable]` The escrow token account of A's NFT
+    /// 8. `[writable]` Offerer B's token account receiving A's NFT (created if needed)
+    /// 9. `[]` The NFT mint offered by B
+    /// 10. `[]` Metaplex metadata account of B's NFT
+    /// 11. `[writable]` The escrow token account of B's NFT
+    /// 12. `[writable]` Offerer A's token account receiving B's NFT (created if needed)
+    /// 13. `[]` Token program
+    /// 14. `[]` Associated token program
+    /// 15. `[]` System program
+    /// 16. `[]` Rent sysvar
+    MatchOffers {
+        /// Unique identifier of offer A
+        offer_id_a: [u8; 16],
+        /// Unique identifier of offer B
+        offer_id_b: [u8; 16],
+    },
+    /// Reports participant wallets and token accounts of a trade loop that are not rent exempt
+    ///
+    /// Emits a Borsh-serialized `RentDeficit` via `sol_log_data` for each underfunded account,
+    /// followed by a `RentSummary`. Advisory only: succeeds even when deficits exist.
+    /// Read-only, and allowed while the program is paused.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The trade loop state account
+    ///
+    /// 1+ Participant wallets and their associated token accounts for the loop's NFTs.
+    ///    Accounts that do not exist yet or are unrelated to the loop are skipped.
+    CheckRentExemption {},
+    /// Sets or clears the swap route converting protocol fees before they reach the treasury
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority (pays to grow old configs)
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` System program
+    UpdateFeeRoute {
+        /// The new route, or None to keep fees in SOL
+        route: Option<FeeRoute>,
+        /// Deposit fees as SOL when the route cannot be used
+        fallback_to_sol: bool,
+    },
+    /// Timelocks a step so its NFTs cannot be transferred before a future time
+    ///
+    /// Only allowed before the step is approved. The step must still be able to execute
+    /// before it or its trade loop expires. A sender replacing their own step keeps the timelock.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The sender of the step
     /// 1. `[writable]` The trade loop state account
-    CancelTradeLoop {},
-
-    /// Initializes the program configuration
+    SetStepNotBefore {
+        /// The step to timelock
+        step_index: u8,
+        /// Unix timestamp before which the step cannot execute
+        not_before: u64,
+    },
+    /// Enables or disables the KYC requirement for step participants
+    ///
+    /// While enabled, every instruction that admits a wallet into a trade or offer
+    /// (adding a step, taking over or redirecting a step, and the direct swap and offer
+    /// instructions) requires the KYC entries of the wallets it admits as extra accounts.
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The upgrade authority (payer)
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays to grow old configs)
     /// 1. `[writable]` The program config account
-    /// 2. `[]` Rent sysvar
+    /// 2. `[]` System program
+    SetKycRequired {
+        /// Whether step participants must be KYC verified
+        required: bool,
+    },
+    /// Marks a wallet as KYC verified until `expires_at`, creating or renewing its KYC entry
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The upgrade authority or governance (pays for the entry)
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The wallet's KYC entry account (PDA, seeds: "kyc", wallet)
     /// 3. `[]` System program
-    InitializeProgramConfig {
-        /// Optional: multisig governance address for decentralized upgrades
-        governance: Option<Pubkey>,
+    RegisterKycEntry {
+        /// The verified wallet
+        wallet: Pubkey,
+        /// Unix timestamp when the verification lapses
+        expires_at: i64,
     },
-
-    /// Updates the program configuration
+    /// Deletes a wallet's KYC entry, returning its rent to the authority
     ///
     /// Accounts expected:
-    /// 0. `[signer]` The current upgrade authority
-    /// 1. `[writable]` The program config account 
-    UpdateProgramConfig {
-        /// New upgrade authority (None to keep the same)
-        new_upgrade_authority: Option<Pubkey>,
-        /// New governance address (None to keep the same)
-        new_governance: Option<Pubkey>,
-        /// New pause state (None to keep the same)
-        new_paused_state: Option<bool>,
+    /// 0. `[signer, writable]` The upgrade authority or governance
+    /// 1. `[]` The program config account
+    /// 2. `[writable]` The wallet's KYC entry account
+    RevokeKycEntry {
+        /// The wallet to delist
+        wallet: Pubkey,
     },
-
-    /// Updates the program to a new implementation
+    /// Publishes a bundle of NFTs that fillers can take in any subset, escrowing them in the offer account
+    ///
+    /// For each offered NFT taken, the filler owes one NFT from each collection in
+    /// `wanted_nfts_per_unit`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer (pays for the accounts)
+    /// 1. `[writable]` The partial fill offer account (PDA, seeds: "partial_fill_offer", offerer, offer_id)
+    /// 2. `[]` Token program
+    /// 3. `[]` Associated token program
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    /// 6. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    ///
+    /// For each offered NFT, in order:
+    /// - `[]` The NFT mint
+    /// - `[writable]` The offerer's token account holding the NFT
+    /// - `[writable]` The escrow token account (associated token account of the offer account)
+    CreatePartialFillOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+        /// The NFTs being offered
+        offered_nfts: Vec<Pubkey>,
+        /// Verified Metaplex collections owed for each NFT taken, one NFT per collection
+        wanted_nfts_per_unit: Vec<Pubkey>,
+        /// Minimum number of NFTs a single fill must take
+        minimum_fill: u8,
+    },
+    /// Takes a subset of a partial fill offer's NFTs in exchange for the NFTs owed per unit
+    ///
+    /// `give_nfts` holds `take_indices.len()` groups of `wanted_nfts_per_unit.len()` NFTs,
+    /// each group ordered like `wanted_nfts_per_unit`. The offer is closed once empty.
+    ///
+    /// While KYC is required, the KYC entries of both parties must be passed as extra accounts.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The filler (pays for any missing token accounts)
+    /// 1. `[writable]` The offerer (receives the filler's NFTs and the escrow rent)
+    /// 2. `[writable]` The partial fill offer account
+    /// 3. `[]` Token program
+    /// 4. `[]` Associated token program
+    /// 5. `[]` System program
+    /// 6. `[]` Rent sysvar
+    /// 7. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 8. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    ///
+    /// For each taken NFT, in `take_indices` order:
+    /// - `[]` The offered NFT mint
+    /// - `[writable]` The escrow token account
+    /// - `[writable]` The filler's token account receiving the NFT (created if needed)
+    ///
+    /// For each given NFT, in `give_nfts` order:
+    /// - `[]` The NFT mint
+    /// - `[]` Metaplex metadata account of the NFT
+    /// - `[writable]` The filler's token account holding the NFT
+    /// - `[writable]` The offerer's token account receiving the NFT (created if needed)
+    FillPartialOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+        /// Indexes into the offer's current `offered_nfts` to take
+        take_indices: Vec<u8>,
+        /// The NFTs the filler sends to the offerer
+        give_nfts: Vec<Pubkey>,
+    },
+    /// Cancels a partial fill offer, returning the remaining NFTs and rent to the offerer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The offerer
+    /// 1. `[writable]` The partial fill offer account
+    /// 2. `[]` Token program
+    /// 3. `[]` Associated token program
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    ///
+    /// For each remaining offered NFT, in order:
+    /// - `[]` The NFT mint
+    /// - `[writable]` The escrow token account
+    /// - `[writable]` The offerer's token account receiving the NFT (created if needed)
+    CancelPartialFillOffer {
+        /// Unique identifier for the offer
+        offer_id: [u8; 16],
+    },
+    /// Returns the governance tokens locked by a vote once its proposal is finalized,
+    /// closing the vote record and its token account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The voter (receives the rent)
+    /// 1. `[]` The proposal account
+    /// 2. `[writable]` The vote record account
+    /// 3. `[writable]` The vote escrow token account
+    /// 4. `[writable]` The voter's governance token account
+    /// 5. `[]` Token program
+    WithdrawVoteTokens {
+        /// Identifier of the proposal
+        proposal_id: [u8; 16],
+    },
+    /// Verifies the last upgrade by invoking the upgraded program with a self-test instruction
+    ///
+    /// A redeployed program is only visible from the slot after its upgrade, so this must
+    /// be sent in a later transaction than `UpgradeProgram`. If the self-test call fails,
+    /// the instruction fails with `UpgradeSelfTestFailed` and the upgrade stays unverified.
+    /// The test instruction runs against live state, so callers must supply a safe no-op,
+    /// e.g. a read-only instruction such as `VerifyLoopIntegrity`.
     ///
     /// Accounts expected:
     /// 0. `[signer]` The upgrade authority
-    /// 1. `[writable]` The program data account
-    /// 2. `[]` The program account
-    /// 3. `[]` The buffer containing the new program
-    /// 4. `[]` Rent sysvar
-    /// 5. `[]` Clock sysvar
-    /// 6. `[]` BPF Loader Upgradeable program
-    UpgradeProgram {
-        /// New program version
-        new_program_version: u32,
+    /// 1. `[writable]` The program config account
+    /// 2. `[]` This program's account
+    ///
+    /// 3+. Accounts for the self-test instruction
+    VerifyProgramUpgrade {
+        /// Instruction data of the self-test call made to the upgraded program
+        test_instruction_data: Vec<u8>,
+    },
+    /// Creates a trade loop with every step's NFTs and applies approvals signed offline
+    /// by the step senders, all in one instruction
+    ///
+    /// Step `i` sends `nft_mints[i]` from `participants[i]` to the next participant in the
+    /// ring. Every step needs an approval from its sender, verified by an Ed25519 program
+    /// instruction earlier in the same transaction over `SignedApproval::creation_message`.
+    /// The loop expires at `expires_at`, which the signatures also bind.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account creating the trade loop (payer)
+    /// 1. `[writable]` The trade loop state account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    /// 4. `[]` The blocklist account (PDA, seeds: "blocklist"; need not exist yet)
+    /// 5. `[]` The program config account (PDA, seeds: "config"; need not exist yet)
+    /// 6. `[]` The mint blocklist account (PDA, seeds: "mint_blocklist"; need not exist yet)
+    /// 7. `[]` The collection whitelist account (PDA, seeds: "collection_whitelist"; need not exist yet)
+    /// 8. `[]` Instructions sysvar
+    ///
+    /// 9+ Token accounts for verification (for each NFT mint, in step order):
+    ///    - NFT mint address
+    ///    - Sender's token account for this NFT (must own the NFT)
+    CreateTradeLoopWithSignedApprovals {
+        /// Unique identifier for the trade loop
+        trade_id: [u8; 32],
+        /// The participant ring, in trade order; each wallet may appear only once
+        participants: Vec<Pubkey>,
+        /// Unix timestamp at which the trade loop expires
+        expires_at: u64,
+        /// The NFTs each participant sends, in participant order
+        nft_mints: Vec<Vec<Pubkey>>,
+        /// One approval per step, signed by its sender
+        approvals: Vec<SignedApproval>,
     },
 }
 
+/// A step added by BatchAddSteps; the sender is the instruction signer
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct BatchStepData {
+    /// The index of this step in the trade loop (0-based)
+    pub step_index: u8,
+    /// The recipient of the NFTs
+    pub to: Pubkey,
+    /// The NFT mints to transfer
+    pub nft_mints: Vec<Pubkey>,
+}
+
+/// The NFTs of a sealed-bid step revealed by ExecuteSealedBidLoop
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct StepReveal {
+    /// The committed NFT mints, in commitment order
+    pub nft_mints: Vec<Pubkey>,
+    /// The nonce of each commitment, in commitment order
+    pub nonces: Vec<[u8; 16]>,
+}
+
+/// A trade loop created by BulkInitializeLoops
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct BulkLoopParams {
+    /// Unique identifier for the trade loop (all zeros to derive one)
+    pub trade_id: [u8; 32],
+    /// The participant ring; step i sends from participant i to participant i+1
+    pub participants: Vec<Pubkey>,
+    /// Timeout in seconds
+    pub timeout_seconds: u64,
+}
+
+/// A step approved by BatchApproveSteps
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct BatchApprovalEntry {
+    /// The trade loop holding the step
+    pub trade_loop_key: Pubkey,
+    /// The index of the step to approve
+    pub step_index: u8,
+}
+
+/// Protocol fee discount for one trade loop, signed by the configured coupon issuer
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct DiscountCoupon {
+    /// The trade loop the discount applies to
+    pub loop_key: Pubkey,
+    /// Discount in basis points of the protocol fee
+    pub discount_basis_points: u16,
+    /// Unix timestamp after which the coupon can no longer be applied
+    pub valid_until: i64,
+    /// Ed25519 signature of the coupon issuer over `message()`
+    pub issuer_signature: [u8; 64],
+}
+
+impl DiscountCoupon {
+    /// The signed bytes: loop_key || discount_basis_points (LE) || valid_until (LE)
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(32 + 2 + 8);
+        message.extend_from_slice(self.loop_key.as_ref());
+        message.extend_from_slice(&self.discount_basis_points.to_le_bytes());
+        message.extend_from_slice(&self.valid_until.to_le_bytes());
+        message
+    }
+}
+
+/// Approval of a step signed offline by its sender
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct SignedApproval {
+    /// The step being approved
+    pub step_index: u8,
+    /// The step's sender
+    pub signer: Pubkey,
+    /// Ed25519 signature of the sender over `SignedApproval::message`
+    pub signature: [u8; 64],
+}
+
+impl SignedApproval {
+    /// Domain separator of signed approvals
+    pub const DOMAIN: &'static [u8] = b"SWAPS_APPROVE_V1";
+    
+    /// The signed bytes: sha256(trade_loop || step_index || nonce (LE) || step_hash || "SWAPS_APPROVE_V1"),
+    /// where `step_hash` is `TradeStep::hash8` of the approved step
+    /// The nonce changes when a loop executes, is recreated or has its approvals reset, so
+    /// signatures cannot be replayed
+    pub fn message(trade_loop: &Pubkey, step_index: u8, nonce: u64, step_hash: &[u8; 8]) -> [u8; 32] {
+        solana_program::hash::hashv(&[trade_loop.as_ref(), &[step_index], &nonce.to_le_bytes(), step_hash, Self::DOMAIN]).to_bytes()
+    }
+    
+    /// Domain separator of approvals signed for `CreateTradeLoopWithSignedApprovals`
+    pub const CREATION_DOMAIN: &'static [u8] = b"SWAPS_CREATE_APPROVE_V1";
+    
+    /// The signed bytes for a loop created with its approvals:
+    /// sha256(trade_loop || step_index || expires_at (LE) || step_hash || "SWAPS_CREATE_APPROVE_V1")
+    /// The loop's nonce is not known before it is created, so its expiry bounds the signature instead
+    pub fn creation_message(trade_loop: &Pubkey, step_index: u8, expires_at: u64, step_hash: &[u8; 8]) -> [u8; 32] {
+        solana_program::hash::hashv(&[trade_loop.as_ref(), &[step_index], &expires_at.to_le_bytes(), step_hash, Self::CREATION_DOMAIN]).to_bytes()
+    }
+}
+
+/// Size of the message signed for `ApplyOffchainApproval`
+pub const OFFCHAIN_APPROVAL_MESSAGE_SIZE: usize = 96;
+
+/// The message signed for `ApplyOffchainApproval`: trade_loop || step_index || expires_slot (LE)
+/// || nonce (LE) || step_hash, zero-padded to OFFCHAIN_APPROVAL_MESSAGE_SIZE bytes
+///
+/// `nonce` is the loop's current nonce and `step_hash` is `TradeStep::hash8` of the step, so the
+/// approval lapses when the loop executes, is recreated or has its approvals reset
+pub fn offchain_approval_message(
+    trade_loop: &Pubkey,
+    step_index: u8,
+    expires_slot: u64,
+    nonce: u64,
+    step_hash: &[u8; 8],
+) -> [u8; OFFCHAIN_APPROVAL_MESSAGE_SIZE] {
+    let mut message = [0u8; OFFCHAIN_APPROVAL_MESSAGE_SIZE];
+    message[..32].copy_from_slice(trade_loop.as_ref());
+    message[32] = step_index;
+    message[33..41].copy_from_slice(&expires_slot.to_le_bytes());
+    message[41..49].copy_from_slice(&nonce.to_le_bytes());
+    message[49..57].copy_from_slice(step_hash);
+    message
+}
+
 /// Instruction format version identifier
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub enum InstructionVersion {
@@ -137,6 +2425,8 @@ pub enum InstructionVersion {
     Legacy = 0,
     /// Modern Borsh-based parsing with full schema validation
     V1 = 1,
+    /// Adds the replay protection nonce to ExecuteFullTradeLoop
+    V2 = 2,
 }
 
 /// Modern versioned instruction wrapper for future extensibility
@@ -185,6 +2475,7 @@ impl SwapInstruction {
     /// 
     /// WARNING: This parsing method is error-prone and maintained only for
     /// backward compatibility. New clients should use versioned instructions.
+    /// Truncated input returns `InvalidInstructionData`; all reads are bounds-checked.
     fn unpack_legacy(input: &[u8]) -> Result<Self, ProgramError> {
         let (&tag, rest) = input.split_first().ok_or(SwapError::InvalidInstructionData)?;
         
@@ -192,38 +2483,56 @@ impl SwapInstruction {
         
         Ok(match tag {
             0 => {
-                let trade_id: [u8; 32] = rest[..32].try_into().map_err(|_| SwapError::InvalidInstructionData)?;
-                let step_count = rest[32];
-                let timeout_seconds = u64::from_le_bytes(rest[33..41].try_into().map_err(|_| SwapError::InvalidInstructionData)?);
+                let trade_id: [u8; 32] = Self::unpack_bytes(rest, 0)?;
+                let participants = Self::unpack_pubkey_vector(rest.get(32..).ok_or(SwapError::InvalidInstructionData)?)?;
+                let timeout_offset = 32 + 1 + participants.len() * 32;
+                let timeout_seconds = u64::from_le_bytes(Self::unpack_bytes(rest, timeout_offset)?);
+                
+                // Per-step NFT limits, if present, follow the timeout as a length-prefixed byte array
+                let limits_offset = timeout_offset + 8;
+                let per_step_nft_limits = match rest.get(limits_offset) {
+                    Some(&limit_count) => Some(
+                        rest.get(limits_offset + 1..limits_offset + 1 + limit_count as usize)
+                            .ok_or(SwapError::InvalidInstructionData)?
+                            .to_vec(),
+                    ),
+                    None => None,
+                };
                 
                 Self::InitializeTradeLoop {
                     trade_id,
-                    step_count,
+                    participants,
                     timeout_seconds,
+                    step_signer_counts: None,
+                    cancel_lockout_seconds: 0,
+                    auto_execute: false,
+                    per_step_nft_limits,
                 }
             },
             1 => Self::AddTradeStep {
-                step_index: rest[0],
-                to: Pubkey::new(&rest[1..33]),
-                nft_mints: Self::unpack_pubkey_vector(&rest[33..])?,
+                step_index: Self::unpack_u8(rest, 0)?,
+                to: Self::unpack_pubkey(rest, 1)?,
+                nft_mints: Self::unpack_pubkey_vector(rest.get(33..).ok_or(SwapError::InvalidInstructionData)?)?,
             },
             2 => Self::ApproveTradeStep {
-                step_index: rest[0],
+                step_index: Self::unpack_u8(rest, 0)?,
             },
             3 => Self::ExecuteTradeStep {
-                step_index: rest[0],
+                step_index: Self::unpack_u8(rest, 0)?,
+            },
+            4 => Self::ExecuteFullTradeLoop {
+                expected_nonce: u64::from_le_bytes(Self::unpack_bytes(rest, 0)?),
             },
-            4 => Self::ExecuteFullTradeLoop {},
             5 => Self::CancelTradeLoop {},
             6 => Self::UpgradeProgram {
-                new_program_version: u32::from_le_bytes(rest[0..4].try_into().map_err(|_| SwapError::InvalidInstructionData)?),
+                new_program_version: u32::from_le_bytes(Self::unpack_bytes(rest, 0)?),
             },
             7 => {
-                let has_governance = rest[0] != 0;
+                let has_governance = Self::unpack_u8(rest, 0)? != 0;
                 
                 if has_governance {
                     Self::InitializeProgramConfig {
-                        governance: Some(Pubkey::new(&rest[1..33])),
+                        governance: Some(Self::unpack_pubkey(rest, 1)?),
                     }
                 } else {
                     Self::InitializeProgramConfig {
@@ -234,33 +2543,33 @@ impl SwapInstruction {
             8 => {
                 let mut offset = 0;
                 
-                let has_new_authority = rest[offset] != 0;
+                let has_new_authority = Self::unpack_u8(rest, offset)? != 0;
                 offset += 1;
                 
                 let new_upgrade_authority = if has_new_authority {
-                    let pubkey = Pubkey::new(&rest[offset..offset+32]);
+                    let pubkey = Self::unpack_pubkey(rest, offset)?;
                     offset += 32;
                     Some(pubkey)
                 } else {
                     None
                 };
                 
-                let has_new_governance = rest[offset] != 0;
+                let has_new_governance = Self::unpack_u8(rest, offset)? != 0;
                 offset += 1;
                 
                 let new_governance = if has_new_governance {
-                    let pubkey = Pubkey::new(&rest[offset..offset+32]);
+                    let pubkey = Self::unpack_pubkey(rest, offset)?;
                     offset += 32;
                     Some(pubkey)
                 } else {
                     None
                 };
                 
-                let has_new_paused_state = rest[offset] != 0;
+                let has_new_paused_state = Self::unpack_u8(rest, offset)? != 0;
                 offset += 1;
                 
                 let new_paused_state = if has_new_paused_state {
-                    Some(rest[offset] != 0)
+                    Some(Self::unpack_u8(rest, offset)? != 0)
                 } else {
                     None
                 };
@@ -275,20 +2584,177 @@ impl SwapInstruction {
         })
     }
 
+    /// Unpack instruction data and pretty-print it for debugging
+    #[cfg(feature = "debug")]
+    pub fn inspect_instruction(input: &[u8]) -> Result<String, ProgramError> {
+        Ok(format!("{:#?}", Self::unpack(input)?))
+    }
+
+    /// Borsh variant index of the instruction, as listed in `PAUSE_EXEMPT_TAGS`
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::InitializeTradeLoop { .. } => 0,
+            Self::AddTradeStep { .. } => 1,
+            Self::ApproveTradeStep { .. } => 2,
+            Self::ExecuteTradeStep { .. } => 3,
+            Self::ExecuteFullTradeLoop { .. } => 4,
+            Self::CancelTradeLoop { .. } => 5,
+            Self::InitializeProgramConfig { .. } => 6,
+            Self::UpdateProgramConfig { .. } => 7,
+            Self::UpgradeProgram { .. } => 8,
+            Self::EscrowNft { .. } => 9,
+            Self::ReleaseEscrow { .. } => 10,
+            Self::PauseTradeLoop { .. } => 11,
+            Self::ResumeTradeLoop { .. } => 12,
+            Self::CreateTemplate { .. } => 13,
+            Self::InitializeFromTemplate { .. } => 14,
+            Self::BatchCancelExpired { .. } => 15,
+            Self::EstimateComputeUnits { .. } => 16,
+            Self::ProposeAuthorityTransfer { .. } => 17,
+            Self::AcceptAuthorityTransfer { .. } => 18,
+            Self::MarkStepFailed { .. } => 19,
+            Self::SetStepNftValues { .. } => 20,
+            Self::UpdateRoyaltyEnforcement { .. } => 21,
+            Self::SimulateExecuteFullTradeLoop { .. } => 22,
+            Self::UpdateStepNfts { .. } => 23,
+            Self::InitializeTradeLoopWithEscrow { .. } => 24,
+            Self::ExecutePartialLoop { .. } => 25,
+            Self::SetWitness { .. } => 26,
+            Self::ClearWitness { .. } => 27,
+            Self::ExecuteTradeStepAndCloseEmpty { .. } => 28,
+            Self::SetCloseEmptyTokenAccounts { .. } => 29,
+            Self::InitializeProgramConfigV2 { .. } => 30,
+            Self::DiagnoseTradeLoop { .. } => 31,
+            Self::TransferTradeAuthority { .. } => 32,
+            Self::ConfirmTradeAuthorityTransfer { .. } => 33,
+            Self::SplitTradeLoop { .. } => 34,
+            Self::ComputeTradeId { .. } => 35,
+            Self::MergeSteps { .. } => 36,
+            Self::SetGovernanceTokenMint { .. } => 37,
+            Self::ProposeGovernanceChange { .. } => 38,
+            Self::VoteOnProposal { .. } => 39,
+            Self::FinalizeProposal { .. } => 40,
+            Self::ProposeCounterOffer { .. } => 41,
+            Self::AcceptCounterOffer { .. } => 42,
+            Self::RejectCounterOffer { .. } => 43,
+            Self::BlockParticipant { .. } => 44,
+            Self::UnblockParticipant { .. } => 45,
+            Self::SetStepMetadata { .. } => 46,
+            Self::ClearStepMetadata { .. } => 47,
+            Self::VerifyAllNfts { .. } => 48,
+            Self::ReopenCancelledLoop { .. } => 49,
+            Self::DelegateApproval { .. } => 50,
+            Self::RevokeDelegateApproval { .. } => 51,
+            Self::BatchAddSteps { .. } => 52,
+            Self::SnapshotTradeLoop { .. } => 53,
+            Self::VerifySnapshot { .. } => 54,
+            Self::MigrateTradeLoopAccount { .. } => 55,
+            Self::AddRelayer { .. } => 56,
+            Self::RemoveRelayer { .. } => 57,
+            Self::FundFeeVault { .. } => 58,
+            Self::SetLoopTags { .. } => 59,
+            Self::ClearLoopTags { .. } => 60,
+            Self::VerifyLoopIntegrity { .. } => 61,
+            Self::ReorderSteps { .. } => 62,
+            Self::AtomicSwapTwoParty { .. } => 63,
+            Self::CreateOpenOffer { .. } => 64,
+            Self::FulfillOpenOffer { .. } => 65,
+            Self::CancelOpenOffer { .. } => 66,
+            Self::SetStepSolAmount { .. } => 67,
+            Self::SetMaxSolPerStep { .. } => 68,
+            Self::BulkInitializeLoops { .. } => 69,
+            Self::InitializeBadgeMint { .. } => 70,
+            Self::LockStepNfts { .. } => 71,
+            Self::UnlockStepNfts { .. } => 72,
+            Self::GetProgramStats { .. } => 73,
+            Self::ResetProgramStats { .. } => 74,
+            Self::BlockNftMint { .. } => 75,
+            Self::UnblockNftMint { .. } => 76,
+            Self::TransferStepOwnership { .. } => 77,
+            Self::EnableRecurring { .. } => 78,
+            Self::InitializeDedupCache { .. } => 79,
+            Self::ValidateTopology { .. } => 80,
+            Self::HealthCheck { .. } => 81,
+            Self::MigrateLegacyLoop { .. } => 82,
+            Self::BatchApproveSteps { .. } => 83,
+            Self::DebugDumpInstruction { .. } => 84,
+            Self::TokenizeTradeLoop { .. } => 85,
+            Self::RedeemTokenizedPosition { .. } => 86,
+            Self::CancelTradeLoopV2 { .. } => 87,
+            Self::SetMetadataUpdateAuthority { .. } => 88,
+            Self::SetAppendProvenanceMetadata { .. } => 89,
+            Self::SetStepExpiry { .. } => 90,
+            Self::WhitelistCollection { .. } => 91,
+            Self::UnwhitelistCollection { .. } => 92,
+            Self::SetDeadlineMessage { .. } => 93,
+            Self::SetRateLimitingEnabled { .. } => 94,
+            Self::InitializeRingFromParticipants { .. } => 95,
+            Self::SetCouponIssuer { .. } => 96,
+            Self::ApplyCoupon { .. } => 97,
+            Self::ProposeGovernanceReplacement { .. } => 98,
+            Self::FinalizeGovernanceReplacement { .. } => 99,
+            Self::SetMinNftFloorPrice { .. } => 100,
+            Self::SetCollectionPriceFeed { .. } => 101,
+            Self::InitializeFeeVault { .. } => 102,
+            Self::DrainFeeVault { .. } => 103,
+            Self::QueryFeeVaultBalance { .. } => 104,
+            Self::BatchSignedApprovals { .. } => 105,
+            Self::ProposeStepReplacement { .. } => 106,
+            Self::AcceptStepReplacement { .. } => 107,
+            Self::RejectStepReplacement { .. } => 108,
+            Self::SetExecutionCondition { .. } => 109,
+            Self::QueryStepHistory { .. } => 110,
+            Self::SetExecutionWhitelist { .. } => 111,
+            Self::ClearExecutionWhitelist { .. } => 112,
+            Self::AddTradeStepWithFungibles { .. } => 113,
+            Self::PauseForMaintenance { .. } => 114,
+            Self::CreateSealedBidLoop { .. } => 115,
+            Self::CommitTradeStep { .. } => 116,
+            Self::ExecuteSealedBidLoop { .. } => 117,
+            Self::ExecuteWithFallback { .. } => 118,
+            Self::ReleaseFailedStepEscrow { .. } => 119,
+            Self::ClaimExpiredEscrow { .. } => 120,
+            Self::CreateTradeLoopWithGuaranteedExecution { .. } => 121,
+            Self::ClaimExecutionBond { .. } => 122,
+            Self::SetExecutionWindow { .. } => 123,
+            Self::ApplyOffchainApproval { .. } => 124,
+            Self::GetParticipantStats { .. } => 125,
+            Self::InitializeProgramConfigWithFeeSchedule { .. } => 126,
+            Self::UpdateFeeSchedule { .. } => 127,
+            Self::BulkCloseEscrows { .. } => 128,
+            Self::UpdateStepRecipient { .. } => 129,
+            Self::RegisterTransferHook { .. } => 130,
+            Self::DeregisterTransferHook { .. } => 131,
+            Self::ListNftOffer { .. } => 132,
+            Self::DelistNftOffer { .. } => 133,
+            Self::MatchOffers { .. } => 134,
+            Self::CheckRentExemption { .. } => 135,
+            Self::UpdateFeeRoute { .. } => 136,
+            Self::SetStepNotBefore { .. } => 137,
+            Self::SetKycRequired { .. } => 138,
+            Self::RegisterKycEntry { .. } => 139,
+            Self::RevokeKycEntry { .. } => 140,
+            Self::CreatePartialFillOffer { .. } => 141,
+            Self::FillPartialOffer { .. } => 142,
+            Self::CancelPartialFillOffer { .. } => 143,
+            Self::WithdrawVoteTokens { .. } => 144,
+            Self::VerifyProgramUpgrade { .. } => 145,
+            Self::CreateTradeLoopWithSignedApprovals { .. } => 146,
+        }
+    }
+
     /// Pack instruction into bytes using modern versioned format
     /// 
     /// This creates a versioned instruction that can be safely evolved
     /// without breaking existing functionality.
     pub fn pack_versioned(&self) -> Vec<u8> {
         let versioned = VersionedInstruction {
-            version: InstructionVersion::V1,
+            version: InstructionVersion::V2,
             instruction: self.clone(),
         };
         
         let mut packed = vec![255]; // Version marker
         packed.extend_from_slice(&versioned.try_to_vec().unwrap());
-        
-        msg!("MODERN: Packed versioned instruction v{:?}", versioned.version);
         packed
     }
 
@@ -297,17 +2763,26 @@ impl SwapInstruction {
     /// WARNING: Use pack_versioned() for new code. This is maintained only
     /// for compatibility with existing clients.
     pub fn pack_legacy(&self) -> Vec<u8> {
-        msg!("LEGACY: Using deprecated manual packing");
-        
         match self {
-            Self::InitializeTradeLoop { trade_id, step_count, timeout_seconds } => {
+            // The legacy format stores the participant and limit counts in a single byte
+            Self::InitializeTradeLoop { trade_id, participants, timeout_seconds, step_signer_counts: None, cancel_lockout_seconds: 0, auto_execute: false, per_step_nft_limits }
+                if participants.len() <= u8::MAX as usize && per_step_nft_limits.as_ref().map_or(0, Vec::len) <= u8::MAX as usize =>
+            {
                 let mut packed = vec![0]; // Tag 0
                 packed.extend_from_slice(trade_id);
-                packed.push(*step_count);
+                packed.push(participants.len() as u8);
+                for participant in participants {
+                    packed.extend_from_slice(participant.as_ref());
+                }
                 packed.extend_from_slice(&timeout_seconds.to_le_bytes());
+                if let Some(per_step_nft_limits) = per_step_nft_limits {
+                    packed.push(per_step_nft_limits.len() as u8);
+                    packed.extend_from_slice(per_step_nft_limits);
+                }
                 packed
             },
-            Self::AddTradeStep { step_index, to, nft_mints } => {
+            // The legacy format stores the NFT count in a single byte
+            Self::AddTradeStep { step_index, to, nft_mints } if nft_mints.len() <= u8::MAX as usize => {
                 let mut packed = vec![1]; // Tag 1
                 packed.push(*step_index);
                 packed.extend_from_slice(to.as_ref());
@@ -323,8 +2798,10 @@ impl SwapInstruction {
             Self::ExecuteTradeStep { step_index } => {
                 vec![3, *step_index] // Tag 3
             },
-            Self::ExecuteFullTradeLoop {} => {
-                vec![4] // Tag 4
+            Self::ExecuteFullTradeLoop { expected_nonce } => {
+                let mut packed = vec![4]; // Tag 4
+                packed.extend_from_slice(&expected_nonce.to_le_bytes());
+                packed
             },
             Self::CancelTradeLoop {} => {
                 vec![5] // Tag 5
@@ -373,21 +2850,40 @@ impl SwapInstruction {
                 
                 packed
             },
+            // Instructions added after the legacy format was frozen have no legacy tag
+            _ => self.pack_versioned(),
         }
     }
 
+    /// Helper function to unpack a single byte at the given offset
+    fn unpack_u8(input: &[u8], offset: usize) -> Result<u8, ProgramError> {
+        input.get(offset).copied().ok_or_else(|| SwapError::InvalidInstructionData.into())
+    }
+
+    /// Helper function to unpack a fixed-size byte array at the given offset
+    fn unpack_bytes<const N: usize>(input: &[u8], offset: usize) -> Result<[u8; N], ProgramError> {
+        input
+            .get(offset..)
+            .and_then(|tail| tail.get(..N))
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| SwapError::InvalidInstructionData.into())
+    }
+
+    /// Helper function to unpack a Pubkey at the given offset
+    fn unpack_pubkey(input: &[u8], offset: usize) -> Result<Pubkey, ProgramError> {
+        Ok(Pubkey::new_from_array(Self::unpack_bytes(input, offset)?))
+    }
+
     /// Helper function to unpack a vector of Pubkeys
     fn unpack_pubkey_vector(input: &[u8]) -> Result<Vec<Pubkey>, ProgramError> {
-        let count = input[0] as usize;
+        let count = Self::unpack_u8(input, 0)? as usize;
         if input.len() < 1 + (count * 32) {
             return Err(SwapError::InvalidInstructionData.into());
         }
         
         let mut pubkeys = Vec::with_capacity(count);
         for i in 0..count {
-            let start = 1 + (i * 32);
-            let end = start + 32;
-            pubkeys.push(Pubkey::new(&input[start..end]));
+            pubkeys.push(Self::unpack_pubkey(input, 1 + (i * 32))?);
         }
         
         Ok(pubkeys)
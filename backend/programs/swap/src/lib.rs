@@ -1,17 +1,13 @@
 use solana_program::{
-    account_info::{next_account_info, AccountInfo},
-    entrypoint,
+    account_info::AccountInfo,
     entrypoint::ProgramResult,
     msg,
-    program_error::ProgramError,
     pubkey::Pubkey,
-    program_pack::{IsInitialized, Pack},
-    sysvar::{rent::Rent, Sysvar},
 };
-use borsh::{BorshDeserialize, BorshSerialize};
 
 // Local modules
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
 pub mod state;
@@ -21,7 +17,8 @@ pub mod utils;
 pub use error::SwapError;
 
 // Program entrypoint's implementation
-entrypoint!(process_instruction);
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
 
 // Program entrypoint
 pub fn process_instruction(
@@ -31,6 +28,11 @@ pub fn process_instruction(
 ) -> ProgramResult {
     msg!("NFT Swap Program Entrypoint");
     
+    // Token-2022 calls registered mints' transfer hook with the interface's own instruction layout
+    if let Some(hook_data) = instruction_data.strip_prefix(&utils::TRANSFER_HOOK_EXECUTE_DISCRIMINATOR) {
+        return processor::Processor::process_transfer_hook_execute(program_id, accounts, hook_data);
+    }
+    
     // Decode instruction data
     let instruction = instruction::SwapInstruction::unpack(instruction_data)?;
     
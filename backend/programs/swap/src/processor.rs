@@ -3,9 +3,14 @@ use solana_program::{
     account_info::{next_account_info, AccountInfo},
     clock::Clock,
     entrypoint::ProgramResult,
+    epoch_schedule::EpochSchedule,
+    hash::hash,
+    log::sol_log_data,
     msg,
     program::{invoke, invoke_signed},
+    instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
+    program_option::COption,
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
@@ -15,9 +20,10 @@ use solana_program::{
 
 use crate::{
     error::SwapError,
-    instruction::SwapInstruction,
-    state::{ProgramConfig, StepStatus, TradeLoop, TradeStep, PROGRAM_VERSION, MAX_PARTICIPANTS_PER_TRANSACTION, MAX_TIMEOUT_SECONDS},
-    utils,
+    event::{DiagnosticResult, HealthStatus, ParticipantStats, RentDeficit, RentSummary, SwapEvent},
+    instruction::{PAUSE_EXEMPT_TAGS, BatchApprovalEntry, BatchStepData, BulkLoopParams, DiscountCoupon, SignedApproval, StepReveal, SwapInstruction, OFFCHAIN_APPROVAL_MESSAGE_SIZE, offchain_approval_message},
+    state::{BlockedAccounts, KycEntry, PartialFillOffer, FeeRoute, FeeSchedule, HookRegistry, NftOfferEntry, OfferBook, CollectionWhitelist, PauseReason, SplTokenLeg, CounterOffer, ExecutionCondition, StepReplacementProposal, MintBlocklist, FailReason, OpenOffer, ParticipantCooldown, GovernanceProposal, GovernedChange, LoopStatus, ProcessedInstructions, ProgramConfig, ProgramStats, RecurringConfig, VoteRecord, GOVERNANCE_REPLACEMENT_DELAY_SECONDS, GOVERNANCE_VOTING_PERIOD_SECONDS, PROGRAM_CONFIG_VERSION, RoyaltyEnforcement, StepStatus, TradeLoop, TradeLoopTemplate, TradeSnapshot, TradeStep, PROGRAM_VERSION, AUTHORITY_TRANSFER_GRACE_PERIOD_SECONDS, COMPLETION_BADGE_NAME, COMPLETION_BADGE_SYMBOL, COOLDOWN_SLOTS, MAX_LOOPS_PER_EPOCH, MAX_ORACLE_PRICE_AGE_SECONDS, TRADE_POSITION_NAME, TRADE_POSITION_SYMBOL, MAX_AUTHORIZED_RELAYERS, MAX_BATCH_ADD_STEPS, MAX_BATCH_APPROVALS, MAX_BATCH_CANCEL_ACCOUNTS, MAX_BULK_INITIALIZE_LOOPS, DEDUP_CACHE_SIZE, DEFAULT_EXECUTION_WINDOW_SECONDS, MAX_SCHEDULED_FEE_BASIS_POINTS, MAX_COMPUTE_UNITS_PER_LOOP, DEADLINE_MESSAGE_SIZE, MAX_FEE_ROUTE_HOPS, MAX_LOOP_TAGS, MAX_OFFER_BOOK_ENTRIES, MAX_PARTIAL_FILL_OFFER_NFTS, MAX_SPL_LEGS_PER_STEP, MAX_WHITELISTED_EXECUTORS, STEP_HASH_HISTORY_DEPTH, MAX_TIMEZONE_OFFSET_MINUTES, MAX_METADATA_CREATORS, MAX_NFTS_PER_STEP, MAX_PARTICIPANTS_PER_TRANSACTION, MAX_TIMEOUT_SECONDS, RELAYER_REIMBURSEMENT_LAMPORTS, TRADE_AUTHORITY_TRANSFER_DELAY_SECONDS},
+    utils::{self, NftVerificationMode},
 };
 
 /// Program state processor
@@ -25,32 +31,45 @@ pub struct Processor {}
 
 impl Processor {
     /// Process InitializeTradeLoop instruction
+    #[allow(clippy::too_many_arguments)]
     pub fn process_initialize_trade_loop(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         trade_id: [u8; 32],
-        step_count: u8,
+        participants: Vec<Pubkey>,
         timeout_seconds: u64,
+        step_signer_counts: Option<Vec<u8>>,
+        cancel_lockout_seconds: u64,
+        auto_execute: bool,
+        per_step_nft_limits: Option<Vec<u8>>,
     ) -> ProgramResult {
         // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Each participant sends in exactly one step
+        let step_count = u8::try_from(participants.len()).map_err(|_| SwapError::TooManyParticipants)?;
         
-        // Enforce the maximum step count limit
-        if step_count == 0 {
-            msg!("Trade loop must have at least 1 step");
+        // Validate the step count and timeout against program and operator limits
+        validate_trade_loop_parameters(program_id, accounts, step_count, timeout_seconds)?;
+        
+        // A ring needs at least 2 participants
+        if step_count < 2 {
+            msg!("Trade loop must have at least 2 participants");
             return Err(SwapError::InvalidInstructionData.into());
         }
         
-        if step_count > MAX_PARTICIPANTS_PER_TRANSACTION {
-            msg!("Trade loop exceeds the maximum allowed steps ({}). Requested: {}", 
-                 MAX_PARTICIPANTS_PER_TRANSACTION, step_count);
-            return Err(SwapError::TooManyParticipants.into());
+        // Reject duplicates now rather than when the loop is verified at execution
+        let mut unique_participants = std::collections::HashSet::new();
+        for participant in &participants {
+            if !unique_participants.insert(*participant) {
+                msg!("Duplicate participant found: {}", participant);
+                return Err(SwapError::DuplicateParticipantAddress.into());
+            }
         }
         
-        // Validate timeout to prevent excessively long timeouts
-        if timeout_seconds > MAX_TIMEOUT_SECONDS {
-            msg!("Timeout exceeds maximum allowed ({}). Requested: {}", 
-                 MAX_TIMEOUT_SECONDS, timeout_seconds);
+        if cancel_lockout_seconds > MAX_TIMEOUT_SECONDS {
+            msg!("Cancel lockout exceeds maximum allowed ({}). Requested: {}", 
+                 MAX_TIMEOUT_SECONDS, cancel_lockout_seconds);
             return Err(SwapError::InvalidInstructionData.into());
         }
         
@@ -61,10 +80,12 @@ impl Processor {
         let trade_loop_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
         let system_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
         
         // SECURITY: Verify the trade loop account is the correct PDA for this creator and trade_id
         // This prevents replay attacks where someone reuses an old trade_id
-        let (expected_trade_loop_address, _bump) = utils::get_trade_loop_address(
+        let (expected_trade_loop_address, bump_seed) = utils::get_trade_loop_address(
             &trade_id,
             payer_info.key,
             program_id,
@@ -86,18 +107,55 @@ impl Processor {
             return Err(ProgramError::MissingRequiredSignature);
         }
         
-        // Create space for trade loop with default max of 4 NFTs per step
-        let space = TradeLoop::get_space(step_count, 4);
+        // Throttle wallets that initialize trade loops too quickly
+        let config = load_optional_program_config(program_id, config_info)?;
+        enforce_participant_cooldown(program_id, config.as_ref(), accounts, payer_info, system_program_info)?;
         
-        // Create the trade loop account
-        utils::create_and_initialize_account(
-            payer_info,
-            trade_loop_info,
-            space,
-            program_id,
-            system_program_info,
-            &Rent::from_account_info(rent_info)?,
-            program_id,
+        // Blocked wallets cannot start or join new trade loops
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            for wallet in std::iter::once(payer_info.key).chain(participants.iter()) {
+                if blocklist.is_blocked(wallet) {
+                    msg!("Wallet {} is blocked from joining trade loops", wallet);
+                    return Err(SwapError::ParticipantBlocked.into());
+                }
+            }
+        }
+        
+        if let Some(step_signer_counts) = &step_signer_counts {
+            if step_signer_counts.len() != step_count as usize {
+                msg!("Expected {} step signer counts, got {}", step_count, step_signer_counts.len());
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        // Every step needs room for at least one NFT, within the packed layout's limit
+        if let Some(per_step_nft_limits) = &per_step_nft_limits {
+            if per_step_nft_limits.len() != step_count as usize {
+                msg!("Expected {} per-step NFT limits, got {}", step_count, per_step_nft_limits.len());
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            if per_step_nft_limits.iter().any(|limit| *limit == 0 || *limit > MAX_NFTS_PER_STEP) {
+                msg!("Per-step NFT limits must be between 1 and {}", MAX_NFTS_PER_STEP);
+                return Err(SwapError::TooManyNftsInStep.into());
+            }
+        }
+        
+        // Create the trade loop account as a PDA at the fixed packed size
+        let space = TradeLoop::LEN;
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                trade_loop_info.key,
+                Rent::from_account_info(rent_info)?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                payer_info.clone(),
+                trade_loop_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"trade_loop".as_ref(), trade_id.as_ref(), payer_info.key.as_ref(), &[bump_seed]]],
         )?;
         
         // Get current timestamp
@@ -108,41 +166,77 @@ impl Processor {
         let expires_at = current_time.checked_add(timeout_seconds)
             .ok_or(SwapError::InvalidInstructionData)?;
         
-        // Initialize the trade loop data
+        // Initialize the trade loop with the participant ring, leaving NFTs to AddTradeStep
         let trade_loop = TradeLoop {
             is_initialized: true,
             trade_id,
             created_at: current_time,
             expires_at,
-            steps: Vec::with_capacity(step_count as usize),
+            steps: TradeStep::build_ring(&participants),
             authority: *payer_info.key,
+            is_paused: false,
+            nonce: TradeLoop::initial_nonce(&trade_id, current_time, payer_info.key),
+            witness: None,
+            authority_transfer_request: None,
+            linked_loop: None,
+            cancel_lockout_seconds,
+            all_approved_at: None,
+            loop_tags: [None; MAX_LOOP_TAGS],
+            recurring: None,
+            successor_loop: None,
+            tokenized_as: None,
+            offered_until_display: 0,
+            display_timezone_offset: 0,
+            deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+            applied_discount_bps: 0,
+            allocated_step_count: step_count,
+            execution_condition: None,
+            step_hash_history: Vec::new(),
+            execution_whitelist: None,
+            auto_execute,
+            commitment_mode: false,
+            per_step_nft_limits: per_step_nft_limits.unwrap_or_default(),
+            execution_bond_lamports: 0,
+            applied_fee_bps: 0,
         };
         
         // Serialize and store the trade loop data
-        trade_loop.serialize(&mut *trade_loop_info.data.borrow_mut())?;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::TradeLoopCreated {
+            trade_id,
+            authority: *payer_info.key,
+            expires_at,
+        })?;
+        
+        record_program_stats(program_id, accounts, |stats| {
+            stats.total_loops_initialized = stats.total_loops_initialized.saturating_add(1);
+        })?;
         
         msg!("Trade loop initialized with ID {:?}", trade_id);
         
         Ok(())
     }
     
-    /// Process AddTradeStep instruction
+    /// Process AddTradeStep and AddTradeStepWithFungibles instructions
     pub fn process_add_trade_step(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         step_index: u8,
         to: Pubkey,
         nft_mints: Vec<Pubkey>,
+        spl_legs: Vec<SplTokenLeg>,
     ) -> ProgramResult {
-        // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
-        
         let account_info_iter = &mut accounts.iter();
         
         // Get accounts
         let from_info = next_account_info(account_info_iter)?;
         let trade_loop_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let guards = StepGuards::load(program_id, account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
         
         // Verify signers
         if !from_info.is_signer {
@@ -157,100 +251,45 @@ impl Processor {
         // Verify the trade loop account is owned by this program
         utils::verify_account_owner(trade_loop_info, program_id)?;
         
-        // Deserialize the trade loop data
-        let mut trade_loop = TradeLoop::try_from_slice(&trade_loop_info.data.borrow())?;
+        // Deserialize the trade loop data, which may sit in an account smaller than the packed size
+        let mut trade_loop = TradeLoop::unpack_from_slice(&trade_loop_info.data.borrow())?;
         
         // Ensure the trade loop is initialized
         if !trade_loop.is_initialized {
             return Err(SwapError::UninitializedAccount.into());
         }
         
-        // Ensure the step index is valid
-        if step_index as usize >= trade_loop.steps.capacity() {
-            return Err(SwapError::InvalidInstructionData.into());
-        }
-        
-        // Ensure there is at least one NFT to transfer
-        if nft_mints.is_empty() {
-            return Err(SwapError::InvalidInstructionData.into());
-        }
-        
-        // Check for duplicate NFTs in the list
-        let mut unique_nfts = std::collections::HashSet::new();
-        for nft_mint in &nft_mints {
-            if !unique_nfts.insert(*nft_mint) {
-                msg!("Duplicate NFT mint found: {}", nft_mint);
-                return Err(SwapError::InvalidInstructionData.into());
-            }
-        }
+        // Validate the step and add it to the trade loop
+        add_step_to_loop(program_id, accounts, &guards, &mut trade_loop, from_info.key, step_index, to, nft_mints, |nft_mints| {
+            verify_sender_owns_nfts(account_info_iter, from_info.key, nft_mints)
+        })?;
+        deposit_execution_bond(program_id, accounts, &trade_loop, trade_loop_info.key, from_info, step_index)?;
         
-        // Verify that the sender owns all the NFTs they're committing to trade
-        for nft_mint in &nft_mints {
-            // Get accounts for this specific NFT
-            let mint_info = next_account_info(account_info_iter)?;
-            let source_token_account_info = next_account_info(account_info_iter)?;
-            
-            // Verify the mint account matches the expected mint
-            if mint_info.key != nft_mint {
-                return Err(SwapError::InvalidAccountData.into());
-            }
-            
-            // Verify this is actually an NFT (metadata check)
-            utils::verify_nft_metadata(mint_info)?;
-            
-            // Verify the token account is owned by the token program
-            utils::verify_token_account_owner(source_token_account_info)?;
-            
-            // Verify the token account is the expected ATA for this wallet/mint
-            utils::verify_token_account_address(source_token_account_info, from_info.key, mint_info.key)?;
-            
-            // Verify the token account belongs to the sender and contains the NFT
-            let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
-            
-            if source_token_account.owner != *from_info.key {
-                msg!("Token account {} is not owned by sender {}", source_token_account_info.key, from_info.key);
-                return Err(SwapError::InvalidAccountOwner.into());
-            }
-            
-            if source_token_account.mint != *mint_info.key {
-                msg!("Token account {} does not match mint {}", source_token_account_info.key, mint_info.key);
-                return Err(SwapError::InvalidAccountData.into());
-            }
-            
-            // Verify the sender has the NFT (amount should be 1 for NFTs)
-            if source_token_account.amount < 1 {
-                msg!("Token account {} has insufficient balance for NFT {}", source_token_account_info.key, mint_info.key);
-                return Err(SwapError::InsufficientFunds.into());
-            }
+        // Verify the sender can cover each fungible leg, then attach the legs to the step
+        if !spl_legs.is_empty() {
+            verify_sender_holds_spl_legs(account_info_iter, from_info.key, &spl_legs)?;
+            trade_loop.steps[step_index as usize].spl_token_legs = spl_legs;
         }
         
-        // Create the new trade step
-        let new_step = TradeStep {
-            from: *from_info.key,
-            to,
-            nft_mints,
-            status: StepStatus::Created,
-        };
-        
-        // Add or replace the step at the specified index
-        if step_index as usize >= trade_loop.steps.len() {
-            trade_loop.steps.push(new_step);
-        } else {
-            trade_loop.steps[step_index as usize] = new_step;
-        }
+        // Grow an undersized account so the added step fits
+        grow_trade_loop_account(&trade_loop, trade_loop_info, accounts)?;
         
         // If we have added all expected steps, verify the loop forms a valid cycle
-        if trade_loop.steps.len() == trade_loop.steps.capacity() {
+        if trade_loop.all_steps_added() {
             // Perform loop validation
-            if !trade_loop.verify_loop() {
-                msg!("Trade loop validation failed - not a valid cycle");
-                return Err(SwapError::TradeLoopVerificationFailed.into());
-            }
+            verify_trade_loop_cycle(&trade_loop)?;
             msg!("All steps added, trade loop forms a valid cycle");
         }
         
         // Serialize and store the updated trade loop data
-        trade_loop.serialize(&mut *trade_loop_info.data.borrow_mut())?;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepAdded {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            from: *from_info.key,
+            to,
+        })?;
         
         msg!("Added trade step {} from {} to {}", step_index, from_info.key, to);
         
@@ -263,9 +302,6 @@ impl Processor {
         accounts: &[AccountInfo],
         step_index: u8,
     ) -> ProgramResult {
-        // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
-        
         let account_info_iter = &mut accounts.iter();
         
         // Get accounts
@@ -273,6 +309,9 @@ impl Processor {
         let trade_loop_info = next_account_info(account_info_iter)?;
         let clock_info = next_account_info(account_info_iter)?;
         
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
         // Verify signers
         if !sender_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
@@ -282,7 +321,7 @@ impl Processor {
         utils::verify_account_owner(trade_loop_info, program_id)?;
         
         // Deserialize the trade loop data
-        let mut trade_loop = TradeLoop::try_from_slice(&trade_loop_info.data.borrow())?;
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
         
         // Ensure the trade loop is initialized
         if !trade_loop.is_initialized {
@@ -295,39 +334,28 @@ impl Processor {
             return Err(SwapError::TradeTimeoutExceeded.into());
         }
         
-        // Ensure the step index is valid
-        if step_index as usize >= trade_loop.steps.len() {
-            return Err(SwapError::InvalidInstructionData.into());
-        }
-        
-        // Get the step
-        let step = &mut trade_loop.steps[step_index as usize];
-        
-        // Ensure the sender is the owner of this step
-        if step.from != *sender_info.key {
-            return Err(SwapError::InvalidAccountOwner.into());
-        }
-        
-        // If already approved, just return success (idempotent)
-        if step.status == StepStatus::Approved {
+        // Approve the step, or succeed without changes if it is already approved
+        if !approve_step_in_loop(&mut trade_loop, step_index, sender_info.key, &clock)? {
             msg!("Step {} already approved by {}", step_index, sender_info.key);
             return Ok(());
         }
         
-        // Verify the step isn't already executed
-        if step.status == StepStatus::Executed {
-            return Err(SwapError::StepAlreadyExecuted.into());
-        }
-        
-        // Update the step status to Approved
-        step.status = StepStatus::Approved;
-        
         // Serialize and store the updated trade loop data
-        trade_loop.serialize(&mut *trade_loop_info.data.borrow_mut())?;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepApproved {
+            trade_id: trade_loop.trade_id,
+            step_index,
+        })?;
         
         msg!("FINAL APPROVAL: Step {} approved by {}. This approval cannot be revoked.", 
              step_index, sender_info.key);
         
+        // Execute in this instruction, leaving no window between the last approval and execution
+        if trade_loop.auto_execute && trade_loop.is_ready_for_execution() {
+            auto_execute_trade_loop(program_id, sender_info, trade_loop_info, &trade_loop, account_info_iter.as_slice())?;
+        }
+        
         Ok(())
     }
     
@@ -336,10 +364,8 @@ impl Processor {
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         step_index: u8,
+        close_empty_source: bool,
     ) -> ProgramResult {
-        // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
-        
         let account_info_iter = &mut accounts.iter();
         
         // Get base accounts
@@ -351,6 +377,10 @@ impl Processor {
         let associated_token_program_info = next_account_info(account_info_iter)?;
         let system_program_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
         
         // Verify signers
         if !executor_info.is_signer {
@@ -375,8 +405,11 @@ impl Processor {
             return Err(SwapError::IncorrectProgramId.into());
         }
         
+        // The config decides royalty enforcement, so it is read from its fixed position
+        let program_config = load_optional_program_config(program_id, config_info)?;
+        
         // Deserialize the trade loop data
-        let mut trade_loop = TradeLoop::try_from_slice(&trade_loop_info.data.borrow())?;
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
         
         // Ensure the trade loop is initialized
         if !trade_loop.is_initialized {
@@ -389,6 +422,12 @@ impl Processor {
             return Err(SwapError::TradeTimeoutExceeded.into());
         }
         
+        // Sealed-bid loops only execute with ExecuteFullTradeLoop
+        if trade_loop.commitment_mode {
+            msg!("Sealed-bid trade loops must be executed in full");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
         // Ensure the step index is valid
         if step_index as usize >= trade_loop.steps.len() {
             return Err(SwapError::InvalidInstructionData.into());
@@ -408,6 +447,17 @@ impl Processor {
                 return Err(SwapError::StepAlreadyExecuted.into());
             }
             
+            // The step's own deadline applies on top of the loop's
+            if step.is_expired(clock.unix_timestamp as u64) {
+                return Err(SwapError::StepExpired.into());
+            }
+            
+            // Timelocked steps wait for their not_before time
+            if step.is_timelocked(clock.unix_timestamp as u64) {
+                msg!("Step {} cannot execute before {}", step_index, step.not_before.unwrap_or_default());
+                return Err(SwapError::StepNotYetActive.into());
+            }
+            
             // Ensure the sender and recipient match the step
             if step.from != *sender_info.key {
                 return Err(SwapError::InvalidAccountData.into());
@@ -418,27 +468,59 @@ impl Processor {
             }
         }
         
+        // Locked NFTs are thawed right before their transfers
+        let lock_authority_info = trade_loop.steps[step_index as usize].nfts_locked
+            .then(|| find_lock_authority(program_id, accounts))
+            .transpose()?;
+        
         // CRITICAL REENTRANCY FIX: Mark the step as executed BEFORE doing any transfers
         // This prevents reentrancy attacks via malicious CPI callbacks during NFT transfers
         trade_loop.steps[step_index as usize].status = StepStatus::Executed;
+        trade_loop.steps[step_index as usize].executed_at_slot = Some(clock.slot);
+        trade_loop.steps[step_index as usize].nfts_locked = false;
         
         // Immediately persist the status change to prevent reentrancy
-        trade_loop.serialize(&mut *trade_loop_info.data.borrow_mut())?;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
         
         msg!("REENTRANCY PROTECTION: Step {} marked as executed before transfers", step_index);
         
         // Get the rent to check for rent exemption
-        let _rent = Rent::from_account_info(rent_info)?;
+        let rent = Rent::from_account_info(rent_info)?;
+        
+        // Pay the step's SOL leg before its NFTs
+        transfer_step_sol(
+            &trade_loop.steps[step_index as usize],
+            sender_info,
+            recipient_info,
+            system_program_info,
+            &rent,
+        )?;
         
         // Get a reference to the step for processing NFTs
         let step_nft_mints = trade_loop.steps[step_index as usize].nft_mints.clone();
+        let step_escrowed = trade_loop.steps[step_index as usize].escrowed.clone();
+        let step_nft_values = trade_loop.steps[step_index as usize].nft_values_lamports.clone();
+        
+        // Royalties are only collected when enabled in the program config
+        let royalty_enforcement = program_config.as_ref()
+            .map(|config| config.royalty_enforcement)
+            .filter(|royalty_enforcement| royalty_enforcement.enabled);
+        
+        // Emptied source token accounts are only closed when enabled in the program config
+        let close_empty_token_accounts = close_empty_source
+            && program_config.as_ref().is_some_and(|config| config.close_empty_token_accounts);
         
         // Process each NFT in the step
-        for (_i, nft_mint) in step_nft_mints.iter().enumerate() {
+        for (nft_index, nft_mint) in step_nft_mints.iter().enumerate() {
             // Get the accounts for this specific NFT
             let mint_info = next_account_info(account_info_iter)?;
             let source_token_account_info = next_account_info(account_info_iter)?;
             let destination_token_account_info = next_account_info(account_info_iter)?;
+            let rent_destination_info = if close_empty_source {
+                Some(next_account_info(account_info_iter)?)
+            } else {
+                None
+            };
             
             // Verify that the mint account matches the expected mint
             if mint_info.key != nft_mint {
@@ -448,12 +530,46 @@ impl Processor {
             // Verify this is actually an NFT (metadata check)
             utils::verify_nft_metadata(mint_info)?;
             
+            // Reclaimed rent can only go back to the sender
+            if let Some(rent_destination_info) = rent_destination_info {
+                if rent_destination_info.key != sender_info.key {
+                    msg!("Rent destination {} does not match sender {}", rent_destination_info.key, sender_info.key);
+                    return Err(SwapError::InvalidAccountData.into());
+                }
+            }
+            
+            // Pay creator royalties before completing the transfer
+            if let Some(royalty_enforcement) = royalty_enforcement {
+                let metadata_info = next_account_info(account_info_iter)?;
+                pay_creator_royalties(
+                    account_info_iter,
+                    mint_info,
+                    metadata_info,
+                    sender_info,
+                    system_program_info,
+                    step_nft_values.get(nft_index).copied().unwrap_or(0),
+                    &royalty_enforcement,
+                )?;
+            }
+            
+            // Escrowed NFTs stay with the escrow PDA until released to the recipient
+            if step_escrowed.get(nft_index).copied().unwrap_or(false) {
+                verify_escrow_holds_nft(program_id, trade_loop_info.key, mint_info.key, source_token_account_info)?;
+                msg!("NFT {} is held in escrow, release it to {} with ReleaseEscrow", mint_info.key, recipient_info.key);
+                continue;
+            }
+            
             // Verify the token accounts are owned by the token program
             utils::verify_token_account_owner(source_token_account_info)?;
             
             // Verify the source token account is the expected ATA for this wallet/mint
             utils::verify_token_account_address(source_token_account_info, sender_info.key, mint_info.key)?;
             
+            // Locked NFTs stay frozen until execution
+            if let Some(lock_authority_info) = lock_authority_info {
+                set_nft_frozen(program_id, lock_authority_info, mint_info, source_token_account_info, token_program_info, false)?;
+            }
+            
             // For destination, we only verify if it exists
             if destination_token_account_info.data_len() > 0 {
                 utils::verify_token_account_address(destination_token_account_info, recipient_info.key, mint_info.key)?;
@@ -498,21 +614,57 @@ impl Processor {
                 sender_info,
                 token_program_info,
             )?;
+            
+            // Return the rent of the now empty source token account to the sender
+            if let (true, Some(rent_destination_info)) = (close_empty_token_accounts, rent_destination_info) {
+                close_empty_token_account(
+                    source_token_account_info,
+                    rent_destination_info,
+                    sender_info,
+                    token_program_info,
+                )?;
+            }
+        }
+        
+        // Pay the step's fungible token legs after its NFTs
+        transfer_step_spl_legs(
+            account_info_iter,
+            &trade_loop.steps[step_index as usize],
+            sender_info,
+            recipient_info,
+            executor_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        
+        utils::emit_event(&SwapEvent::StepExecuted {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            nft_mints: step_nft_mints,
+        })?;
+        
+        if trade_loop.overall_status() == LoopStatus::Executed {
+            utils::emit_event(&SwapEvent::TradeLoopComplete { trade_id: trade_loop.trade_id })?;
         }
         
+        record_program_stats(program_id, accounts, |stats| {
+            stats.record_steps_executed([&trade_loop.steps[step_index as usize]]);
+        })?;
+        
         msg!("Successfully executed trade step {} with reentrancy protection", step_index);
         
         Ok(())
     }
     
-    /// Process ExecuteFullTradeLoop instruction
+    /// Process ExecuteFullTradeLoop and ExecuteWithFallback instructions
     pub fn process_execute_full_trade_loop(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        expected_nonce: u64,
+        fallback: bool,
     ) -> ProgramResult {
-        // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
-        
         let account_info_iter = &mut accounts.iter();
         
         // Get base accounts
@@ -523,6 +675,11 @@ impl Processor {
         let system_program_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
         let clock_info = next_account_info(account_info_iter)?;
+        let dedup_cache_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
         
         // Verify signers
         if !executor_info.is_signer {
@@ -548,22 +705,51 @@ impl Processor {
         }
         
         // Deserialize the trade loop data
-        let mut trade_loop = TradeLoop::try_from_slice(&trade_loop_info.data.borrow())?;
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
         
         // Ensure the trade loop is initialized
         if !trade_loop.is_initialized {
             return Err(SwapError::UninitializedAccount.into());
         }
         
+        // Relayers, fees and royalties come from the program config at its fixed position
+        let config = load_optional_program_config(program_id, config_info)?;
+        
+        // The executor must be whitelisted, or without a whitelist a participant
+        // or a relayer authorized in the program config
+        let is_participant = trade_loop.steps.iter()
+            .any(|step| step.from == *executor_info.key || step.to == *executor_info.key);
+        let is_relayer = !is_participant && config.as_ref()
+            .is_some_and(|config| config.is_authorized_relayer(executor_info.key));
+        if trade_loop.execution_whitelist.is_some() {
+            verify_executor_whitelisted(&trade_loop, executor_info.key)?;
+        } else if !is_participant && !is_relayer {
+            msg!("Executor {} is neither a participant nor an authorized relayer", executor_info.key);
+            return Err(SwapError::UnauthorizedExecutor.into());
+        }
+        
+        // Reject transactions signed against a different incarnation of this trade loop
+        if trade_loop.nonce != expected_nonce {
+            msg!("Trade loop nonce mismatch. Expected: {}, Found: {}", expected_nonce, trade_loop.nonce);
+            return Err(SwapError::NonceMismatch.into());
+        }
+        
         // Check if the trade loop has expired
         let clock = Clock::from_account_info(clock_info)?;
+        
+        // Defense in depth against resubmitted transactions; the step statuses already
+        // prevent a second execution
+        record_execution(program_id, dedup_cache_info, trade_loop_info.key, clock.slot, executor_info.key)?;
         if trade_loop.is_expired(clock.unix_timestamp as u64) {
             return Err(SwapError::TradeTimeoutExceeded.into());
         }
         
         // Verify the trade loop forms a valid cycle
-        if !trade_loop.verify_loop() {
-            return Err(SwapError::TradeLoopVerificationFailed.into());
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Sealed-bid loops reveal their NFTs with ExecuteSealedBidLoop
+        if trade_loop.has_unrevealed_commitments() {
+            return Err(SwapError::NftsNotRevealed.into());
         }
         
         // Ensure all steps are approved
@@ -571,6 +757,27 @@ impl Processor {
             return Err(SwapError::MissingApprovals.into());
         }
         
+        // Every step's own deadline applies on top of the loop's
+        if let Some(step_index) = trade_loop.steps.iter().position(|step| step.is_expired(clock.unix_timestamp as u64)) {
+            msg!("Step {} has expired", step_index);
+            return Err(SwapError::StepExpired.into());
+        }
+        
+        // Timelocked steps hold back the whole loop until their not_before time
+        if let Some(step_index) = trade_loop.steps.iter().position(|step| step.is_timelocked(clock.unix_timestamp as u64)) {
+            msg!("Step {} cannot execute before {}", step_index, trade_loop.steps[step_index].not_before.unwrap_or_default());
+            return Err(SwapError::StepNotYetActive.into());
+        }
+        
+        // Ensure the witness co-signed, if one is required
+        verify_witness_signature(&trade_loop, accounts)?;
+        
+        // Ensure the external execution condition holds, if one is set
+        verify_execution_condition(&trade_loop, accounts)?;
+        
+        // A split loop can only execute when its other half is also ready
+        verify_linked_loop_ready(program_id, &trade_loop, trade_loop_info.key, accounts, clock.unix_timestamp as u64)?;
+        
         // Verify the number of participants doesn't exceed the maximum
         if trade_loop.steps.len() > MAX_PARTICIPANTS_PER_TRANSACTION as usize {
             msg!("Trade loop exceeds the maximum allowed participants ({}). Actual: {}", 
@@ -581,6 +788,17 @@ impl Processor {
         // Get the rent for creating token accounts if needed
         let rent = Rent::from_account_info(rent_info)?;
         
+        // Locked steps are thawed right before their transfers
+        let locked_steps: Vec<bool> = trade_loop.steps.iter().map(|step| step.nfts_locked).collect();
+        
+        // With fallback, steps touching a frozen token account are skipped rather than failing
+        // the loop. A failed transfer aborts the whole transaction, so they are found up front
+        let skipped_steps = if fallback {
+            find_steps_with_frozen_accounts(&trade_loop, &accounts[9..])?
+        } else {
+            vec![false; trade_loop.steps.len()]
+        };
+        
         // CRITICAL REENTRANCY FIX: Mark ALL steps as executed BEFORE doing ANY transfers
         // This prevents reentrancy attacks via malicious CPI callbacks during NFT transfers
         for (step_index, step) in trade_loop.steps.iter_mut().enumerate() {
@@ -589,13 +807,29 @@ impl Processor {
                 return Err(SwapError::StepAlreadyExecuted.into());
             }
             
+            if skipped_steps[step_index] {
+                step.status = StepStatus::Failed { reason: FailReason::NftFrozen };
+                msg!("Step {} has a frozen token account and is skipped", step_index);
+                continue;
+            }
+            
             // Mark each step as executed before any transfers begin
             step.status = StepStatus::Executed;
+            step.executed_at_slot = Some(clock.slot);
+            step.nfts_locked = false;
             msg!("REENTRANCY PROTECTION: Step {} marked as executed before transfers", step_index);
         }
         
+        // Record the protocol fee of this loop's size tier, after any coupon discount
+        let fee_basis_points = config.as_ref()
+            .map_or(0, |config| config.fee_basis_points_for(trade_loop.steps.len()));
+        trade_loop.applied_fee_bps = trade_loop.discounted_fee_basis_points(fee_basis_points);
+        
+        // Advance the nonce so this execution cannot be replayed
+        trade_loop.nonce = trade_loop.nonce.wrapping_add(1);
+        
         // Immediately persist all status changes to prevent reentrancy
-        trade_loop.serialize(&mut *trade_loop_info.data.borrow_mut())?;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
         msg!("REENTRANCY PROTECTION: All {} steps marked as executed and persisted", trade_loop.steps.len());
         
         // Reset the account iterator for the actual processing
@@ -608,91 +842,101 @@ impl Processor {
         let _system_program_info = next_account_info(account_info_iter)?;
         let _rent_info = next_account_info(account_info_iter)?;
         let _clock_info = next_account_info(account_info_iter)?;
+        let _dedup_cache_info = next_account_info(account_info_iter)?;
+        let _config_info = next_account_info(account_info_iter)?;
+        
+        // Royalty accounts of the executed steps follow the accounts of every step
+        let royalty_enforcement = config.as_ref()
+            .map(|config| config.royalty_enforcement)
+            .filter(|royalty_enforcement| royalty_enforcement.enabled);
+        let royalty_accounts_start = 9 + trade_loop.steps.iter().map(step_execution_account_count).sum::<usize>();
+        let royalty_account_iter = &mut accounts.get(royalty_accounts_start..).unwrap_or_default().iter();
         
         // Now process each step in the trade loop (status already updated)
-        for (_step_index, step) in trade_loop.steps.iter().enumerate() {
+        for (step_index, step) in trade_loop.steps.iter().enumerate() {
+            // Skipped steps keep their accounts in the list; step past them
+            if skipped_steps[step_index] {
+                for _ in 0..step_execution_account_count(step) {
+                    next_account_info(account_info_iter)?;
+                }
+                continue;
+            }
             
-            // Get participant accounts for this step
-            let sender_info = next_account_info(account_info_iter)?;
-            let recipient_info = next_account_info(account_info_iter)?;
+            let lock_authority_info = locked_steps[step_index]
+                .then(|| find_lock_authority(program_id, accounts))
+                .transpose()?;
+            execute_step_transfers(
+                account_info_iter,
+                royalty_account_iter,
+                accounts,
+                program_id,
+                step,
+                trade_loop_info,
+                executor_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+                lock_authority_info,
+                royalty_enforcement.as_ref(),
+            )?;
             
-            // Verify the participants match the expected step
-            if step.from != *sender_info.key {
-                return Err(SwapError::InvalidAccountData.into());
-            }
+            utils::emit_event(&SwapEvent::StepExecuted {
+                trade_id: trade_loop.trade_id,
+                step_index: step_index as u8,
+                nft_mints: step.nft_mints.clone(),
+            })?;
+        }
+        
+        let failed_steps_bitmask = skipped_steps.iter().enumerate()
+            .filter(|(_, skipped)| **skipped)
+            .fold(0u16, |bitmask, (step_index, _)| bitmask | 1 << step_index);
+        if failed_steps_bitmask != 0 {
+            utils::emit_event(&SwapEvent::LoopPartiallyExecuted { trade_id: trade_loop.trade_id, failed_steps_bitmask })?;
+            msg!("Trade loop partially executed; skipped steps bitmask {:#b}", failed_steps_bitmask);
+        } else {
+            utils::emit_event(&SwapEvent::TradeLoopComplete { trade_id: trade_loop.trade_id })?;
+        }
+        
+        // The protocol fee is settled off-chain, so log the rate after any coupon discount
+        if fee_basis_points > 0 {
+            msg!("Protocol fee: {} basis points ({} basis point discount applied)", 
+                 trade_loop.applied_fee_bps, trade_loop.applied_discount_bps);
             
-            if step.to != *recipient_info.key {
-                return Err(SwapError::InvalidAccountData.into());
+            // Settlement converts the fee through the configured route before depositing it
+            if let Some(route) = config.as_ref().and_then(|config| config.fee_route.as_ref()) {
+                msg!("Protocol fee route: program {} through {} intermediate mints, minimum output {} (fallback to SOL: {})", 
+                     route.route_program, route.intermediate_mints.len(), route.min_out_amount, 
+                     config.as_ref().is_some_and(|config| config.fee_route_fallback_to_sol));
             }
+        }
+        
+        // Badges, provenance and renewal only follow a complete trade
+        if failed_steps_bitmask == 0 {
+            // Give each participant a completion badge, if configured
+            mint_completion_badges(program_id, accounts, &trade_loop, token_program_info)?;
             
-            // Process each NFT in this step
-            for nft_mint in &step.nft_mints {
-                // Get accounts for this specific NFT
-                let mint_info = next_account_info(account_info_iter)?;
-                let source_token_account_info = next_account_info(account_info_iter)?;
-                let destination_token_account_info = next_account_info(account_info_iter)?;
-                
-                // Verify that the mint account matches the expected mint
-                if mint_info.key != nft_mint {
-                    return Err(SwapError::InvalidAccountData.into());
-                }
-                
-                // Verify this is actually an NFT (metadata check)
-                utils::verify_nft_metadata(mint_info)?;
-                
-                // Verify the token accounts are owned by the token program
-                utils::verify_token_account_owner(source_token_account_info)?;
-                
-                // Verify the source token account is the expected ATA for this wallet/mint
-                utils::verify_token_account_address(source_token_account_info, sender_info.key, mint_info.key)?;
-                
-                // For destination, we only verify if it exists
-                if destination_token_account_info.data_len() > 0 {
-                    utils::verify_token_account_address(destination_token_account_info, recipient_info.key, mint_info.key)?;
-                }
-                
-                // Create the destination token account if it doesn't exist
-                if destination_token_account_info.data_len() == 0 {
-                    msg!("Creating token account for recipient");
-                    utils::create_associated_token_account_if_needed(
-                        executor_info,
-                        recipient_info,
-                        mint_info,
-                        destination_token_account_info,
-                        token_program_info,
-                        associated_token_program_info,
-                        system_program_info,
-                        rent_info,
-                    )?;
-                }
-                
-                // Verify the token accounts are correctly associated with the sender and recipient
-                let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
-                
-                if source_token_account.owner != *sender_info.key {
-                    return Err(SwapError::InvalidAccountOwner.into());
-                }
-                
-                if source_token_account.mint != *mint_info.key {
-                    return Err(SwapError::InvalidAccountData.into());
-                }
-                
-                // Verify the sender has the NFT (amount should be 1 for NFTs)
-                if source_token_account.amount < 1 {
-                    return Err(SwapError::InsufficientFunds.into());
-                }
-                
-                // Transfer the NFT to the recipient
-                msg!("Transferring NFT {} from {} to {}", mint_info.key, sender_info.key, recipient_info.key);
-                utils::transfer_nft(
-                    source_token_account_info,
-                    destination_token_account_info,
-                    sender_info,
-                    token_program_info,
-                )?;
+            // Record the trade in the metadata of NFTs the program can update, if configured
+            append_trade_provenance(program_id, accounts, &trade_loop, clock.slot)?;
+            
+            // Renew a recurring loop with a fresh successor
+            if create_successor_loop(program_id, accounts, &mut trade_loop, trade_loop_info, executor_info, system_program_info, &rent)? {
+                serialize_trade_loop(&trade_loop, trade_loop_info)?;
             }
         }
         
+        // Return every sender's execution bond
+        refund_execution_bonds(program_id, trade_loop_info.key, &trade_loop, accounts)?;
+        
+        // Reimburse the relayer's transaction fee from the fee vault
+        if is_relayer {
+            reimburse_relayer(program_id, accounts, executor_info, system_program_info, &rent)?;
+        }
+        
+        record_program_stats(program_id, accounts, |stats| {
+            stats.record_steps_executed(trade_loop.steps.iter().filter(|step| step.status == StepStatus::Executed))
+        })?;
+        
         msg!("Successfully executed full trade loop with {} steps using reentrancy protection", trade_loop.steps.len());
         
         Ok(())
@@ -703,15 +947,15 @@ impl Processor {
         program_id: &Pubkey,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        // Check if the program is paused
-        check_program_not_paused(program_id, accounts)?;
-        
         let account_info_iter = &mut accounts.iter();
         
         // Get accounts
         let canceller_info = next_account_info(account_info_iter)?;
         let trade_loop_info = next_account_info(account_info_iter)?;
         
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
         // Verify signers
         if !canceller_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
@@ -721,44 +965,66 @@ impl Processor {
         utils::verify_account_owner(trade_loop_info, program_id)?;
         
         // Deserialize the trade loop data
-        let trade_loop = TradeLoop::try_from_slice(&trade_loop_info.data.borrow())?;
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
         
         // Ensure the trade loop is initialized
         if !trade_loop.is_initialized {
             return Err(SwapError::UninitializedAccount.into());
         }
         
-        // Check if the canceller is a participant
-        let user_step_index = trade_loop.steps.iter().position(|step| step.from == *canceller_info.key);
-        
-        if user_step_index.is_none() {
-            msg!("Canceller is not a participant in this trade loop");
-            return Err(SwapError::InvalidAccountOwner.into());
-        }
-        
-        // Get the user's step 
-        let user_step = &trade_loop.steps[user_step_index.unwrap()];
+        // Verify the canceller may cancel the trade loop in its current state
+        verify_cancellation_allowed(&trade_loop, canceller_info.key)?;
         
-        // CRITICAL: Only allow cancellation if the user's step is not yet approved
-        // This prevents users from backing out after committing
-        if user_step.status != StepStatus::Created {
-            msg!("Cannot cancel trade after approving. Your step status: {:?}", user_step.status);
-            return Err(SwapError::CancellationDenied.into());
+        // Return any escrowed NFTs to their senders before the loop state is wiped
+        if trade_loop.has_escrowed_nfts() {
+            let token_program_info = next_account_info(account_info_iter)?;
+            
+            // Verify the token program is actually the token program
+            if token_program_info.key != &spl_token::id() {
+                return Err(SwapError::IncorrectProgramId.into());
+            }
+            
+            for step in &trade_loop.steps {
+                for (nft_mint, escrowed) in step.nft_mints.iter().zip(step.escrowed.iter()) {
+                    if !*escrowed {
+                        continue;
+                    }
+                    
+                    let escrow_authority_info = next_account_info(account_info_iter)?;
+                    let escrow_token_account_info = next_account_info(account_info_iter)?;
+                    let sender_token_account_info = next_account_info(account_info_iter)?;
+                    
+                    verify_escrow_holds_nft(program_id, trade_loop_info.key, nft_mint, escrow_token_account_info)?;
+                    utils::verify_token_account_address(sender_token_account_info, &step.from, nft_mint)?;
+                    
+                    utils::transfer_nft_from_escrow(
+                        escrow_token_account_info,
+                        sender_token_account_info,
+                        escrow_authority_info,
+                        token_program_info,
+                        trade_loop_info.key,
+                        nft_mint,
+                        program_id,
+                    )?;
+                    
+                    msg!("Returned escrowed NFT {} to {}", nft_mint, step.from);
+                }
+            }
         }
         
-        // Check if any other steps are already approved
-        let any_approved_steps = trade_loop.steps.iter()
-            .any(|step| step.status == StepStatus::Approved);
-        
-        if any_approved_steps {
-            msg!("Cannot cancel trade when other participants have already approved");
-            return Err(SwapError::CancellationDenied.into());
-        }
+        // Return every sender's execution bond before the loop state is wiped
+        refund_execution_bonds(program_id, trade_loop_info.key, &trade_loop, accounts)?;
         
         // All checks passed - allow cancellation
         // Zero out the account data to mark it as cancelled
         trade_loop_info.data.borrow_mut().fill(0);
         
+        utils::emit_event(&SwapEvent::TradeLoopCancelled { trade_id: trade_loop.trade_id })?;
+        
+        record_program_stats(program_id, accounts, |stats| {
+            stats.total_loops_cancelled = stats.total_loops_cancelled.saturating_add(1);
+        })?;
+        
         msg!("Cancelled trade loop");
         
         Ok(())
@@ -774,21 +1040,32 @@ impl Processor {
         
         // Get accounts
         let upgrade_authority_info = next_account_info(account_info_iter)?;
-        let program_data_info = next_account_info(account_info_iter)?;
+        let _program_data_info = next_account_info(account_info_iter)?;
         let program_info = next_account_info(account_info_iter)?;
         let buffer_info = next_account_info(account_info_iter)?;
         let rent_info = next_account_info(account_info_iter)?;
         let clock_info = next_account_info(account_info_iter)?;
         let bpf_loader_upgradeable_info = next_account_info(account_info_iter)?;
         let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
         
         // Verify signers
         if !upgrade_authority_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
         
+        // The config only tracks upgrades of this program
+        if program_info.key != program_id {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
         // Get the program config
-        let (config_pubkey, bump_seed) = utils::get_program_config_address(program_id);
+        let (config_pubkey, _bump_seed) = utils::get_program_config_address(program_id);
         
         // Verify the config account is the correct PDA
         if config_info.key != &config_pubkey {
@@ -799,7 +1076,7 @@ impl Processor {
         utils::verify_account_owner(config_info, program_id)?;
         
         // Deserialize the config
-        let config = ProgramConfig::try_from_slice(&config_info.data.borrow())?;
+        let config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
         
         // Ensure the config is initialized
         if !config.is_initialized {
@@ -849,14 +1126,90 @@ impl Processor {
             ],
         )?;
         
-        // Update the program version in the config
+        // Update the program version; the new program is unverified until its self-test passes
         let mut updated_config = config;
         updated_config.version = new_program_version;
+        updated_config.last_upgrade_test_passed = false;
+        updated_config.last_upgrade_timestamp = Clock::from_account_info(clock_info)?.unix_timestamp;
+        
+        // Config accounts created before the audit fields existed are too small to hold them
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                upgrade_authority_info,
+                system_program_info,
+                &Rent::from_account_info(rent_info)?,
+                ProgramConfig::get_space(),
+            )?;
+        }
         
         // Serialize and store the updated config
         updated_config.serialize(&mut *config_info.data.borrow_mut())?;
         
-        msg!("Upgraded program to version {}", new_program_version);
+        msg!("Upgraded program to version {}; run VerifyProgramUpgrade in a later slot", new_program_version);
+        
+        Ok(())
+    }
+    
+    /// Process VerifyProgramUpgrade instruction
+    pub fn process_verify_program_upgrade(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        test_instruction_data: Vec<u8>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let upgrade_authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let program_info = next_account_info(account_info_iter)?;
+        
+        // The remaining accounts are passed to the self-test instruction
+        let test_account_infos: Vec<AccountInfo> = account_info_iter.cloned().collect();
+        
+        // Verify signers
+        if !upgrade_authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // The self-test calls this program
+        if program_info.key != program_id {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let mut config = load_program_config(program_id, config_info)?;
+        
+        // Only the authority that can upgrade the program may verify the upgrade
+        if config.upgrade_authority != *upgrade_authority_info.key
+            && config.governance != Some(*upgrade_authority_info.key)
+        {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Run the self-test against the deployed program
+        let test_instruction = Instruction {
+            program_id: *program_id,
+            accounts: test_account_infos.iter()
+                .map(|account_info| AccountMeta {
+                    pubkey: *account_info.key,
+                    is_signer: account_info.is_signer,
+                    is_writable: account_info.is_writable,
+                })
+                .collect(),
+            data: test_instruction_data,
+        };
+        let mut test_invoke_infos = test_account_infos;
+        test_invoke_infos.push(program_info.clone());
+        invoke(&test_instruction, &test_invoke_infos).map_err(|err| {
+            msg!("Upgrade self-test failed: {}", err);
+            SwapError::UpgradeSelfTestFailed
+        })?;
+        
+        // Record the passed self-test in the upgrade audit trail
+        config.last_upgrade_test_passed = true;
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Verified program version {} (self-test passed)", config.version);
         
         Ok(())
     }
@@ -901,8 +1254,8 @@ impl Processor {
         // Get the rent
         let rent = Rent::from_account_info(rent_info)?;
         
-        // Size of the config account - base struct is about 64 bytes with option fields
-        let config_size = 96;
+        // Size of the config account
+        let config_size = ProgramConfig::get_space();
         
         // Create the config account as a PDA
         let seeds = &[b"config".as_ref(), &[bump_seed]];
@@ -926,11 +1279,36 @@ impl Processor {
         
         // Initialize the config data
         let config = ProgramConfig {
+            config_version: PROGRAM_CONFIG_VERSION,
             is_initialized: true,
             version: PROGRAM_VERSION,
             upgrade_authority: *authority_info.key,
             governance,
             paused: false,
+            pending_upgrade_authority: None,
+            authority_transfer_expiry: 0,
+            royalty_enforcement: RoyaltyEnforcement::default(),
+            close_empty_token_accounts: false,
+            max_participants: MAX_PARTICIPANTS_PER_TRANSACTION,
+            fee_basis_points: 0,
+            fee_recipient: *authority_info.key,
+            governance_token_mint: None,
+            authorized_relayers: Vec::new(),
+            max_sol_per_step: 0,
+            last_upgrade_test_passed: false,
+            last_upgrade_timestamp: 0,
+            badge_mint: None,
+            append_provenance_metadata: false,
+            rate_limiting_enabled: false,
+            coupon_issuer: None,
+            proposed_governance: None,
+            min_nft_floor_price_usd_cents: None,
+            paused_reason: None,
+            execution_window_seconds: None,
+            fee_schedule: None,
+            fee_route: None,
+            fee_route_fallback_to_sol: false,
+            kyc_required: false,
         };
         
         // Serialize and store the config data
@@ -941,6 +1319,33 @@ impl Processor {
         Ok(())
     }
 
+    /// Process InitializeProgramConfigWithFeeSchedule instruction
+    pub fn process_initialize_program_config_with_fee_schedule(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        governance: Option<Pubkey>,
+        fee_schedule: FeeSchedule,
+    ) -> ProgramResult {
+        if !fee_schedule.is_valid() {
+            msg!("Fee tiers cannot exceed {} basis points. Requested: {:?}", MAX_SCHEDULED_FEE_BASIS_POINTS, fee_schedule);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        Self::process_initialize_program_config(program_id, accounts, governance)?;
+        
+        // The config account follows the authority
+        let config_info = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut config = load_program_config(program_id, config_info)?;
+        config.fee_schedule = Some(fee_schedule);
+        
+        // Serialize and store the config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Fee schedule set: {:?}", fee_schedule);
+        
+        Ok(())
+    }
+
     /// Process UpdateProgramConfig instruction
     pub fn process_update_program_config(
         program_id: &Pubkey,
@@ -972,7 +1377,7 @@ impl Processor {
         }
         
         // Deserialize the config data
-        let mut config = ProgramConfig::try_from_slice(&config_info.data.borrow())?;
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
         
         // Ensure the config is initialized
         if !config.is_initialized {
@@ -1000,12 +1405,18 @@ impl Processor {
         }
         
         if let Some(new_gov) = new_governance {
+            // An existing governance key can only be replaced through the timelock
+            if config.governance.is_some_and(|governance| governance != new_gov) {
+                msg!("Governance is set; use ProposeGovernanceReplacement to replace it");
+                return Err(SwapError::GovernanceTimelockActive.into());
+            }
             config.governance = Some(new_gov);
             msg!("Updated governance to {}", new_gov);
         }
         
         if let Some(paused) = new_paused_state {
             config.paused = paused;
+            config.paused_reason = config.pause_reason();
             msg!("Updated paused state to {}", paused);
         }
         
@@ -1016,92 +1427,13115 @@ impl Processor {
         
         Ok(())
     }
-}
 
-/// Process an instruction
-pub fn process_instruction(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    instruction: SwapInstruction,
-) -> ProgramResult {
-    match instruction {
-        SwapInstruction::InitializeTradeLoop { trade_id, step_count, timeout_seconds } => {
-            Processor::process_initialize_trade_loop(program_id, accounts, trade_id, step_count, timeout_seconds)
+    /// Process EscrowNft instruction
+    pub fn process_escrow_nft(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        nft_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        let escrow_authority_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
         }
-        SwapInstruction::AddTradeStep { step_index, to, nft_mints } => {
-            Processor::process_add_trade_step(program_id, accounts, step_index, to, nft_mints)
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
         }
-        SwapInstruction::ApproveTradeStep { step_index } => {
-            Processor::process_approve_trade_step(program_id, accounts, step_index)
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
         }
-        SwapInstruction::ExecuteTradeStep { step_index } => {
-            Processor::process_execute_trade_step(program_id, accounts, step_index)
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
         }
-        SwapInstruction::ExecuteFullTradeLoop {} => {
-            Processor::process_execute_full_trade_loop(program_id, accounts)
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
         }
-        SwapInstruction::CancelTradeLoop {} => {
-            Processor::process_cancel_trade_loop(program_id, accounts)
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
         }
-        SwapInstruction::UpgradeProgram { new_program_version } => {
-            Processor::process_upgrade_program(program_id, accounts, new_program_version)
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
         }
-        SwapInstruction::InitializeProgramConfig { governance } => {
-            Processor::process_initialize_program_config(program_id, accounts, governance)
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Ensure the sender is the owner of this step
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
         }
-        SwapInstruction::UpdateProgramConfig { new_upgrade_authority, new_governance, new_paused_state } => {
-            Processor::process_update_program_config(program_id, accounts, new_upgrade_authority, new_governance, new_paused_state)
+        
+        // NFTs can no longer be escrowed once the step has been executed or has failed
+        if step.status == StepStatus::Executed {
+            return Err(SwapError::StepAlreadyExecuted.into());
         }
-    }
-}
-
-/// Helper function to check if the program is paused
-fn check_program_not_paused(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-    // Get the program configuration PDA
-    let (config_pubkey, _) = utils::get_program_config_address(program_id);
-    
-    // Try to find the config account
-    let mut config_found = false;
-    
-    for account_info in accounts {
-        if account_info.key == &config_pubkey {
-            // Verify the account is owned by this program
-            if account_info.owner != program_id {
-                msg!("Config account found but has incorrect owner");
-                continue;
-            }
-            
-            // Verify the account has data
-            if account_info.data_len() == 0 {
-                msg!("Config account found but has no data");
-                continue;
-            }
-            
-            config_found = true;
-            
-            // Try to deserialize - if it fails, the config might be corrupted
-            match ProgramConfig::try_from_slice(&account_info.data.borrow()) {
-                Ok(config) => {
-                    if config.paused {
-                        msg!("Program is currently paused");
-                        return Err(SwapError::InvalidInstructionData.into());
-                    }
-                },
-                Err(err) => {
-                    msg!("Error deserializing config account: {}", err);
-                    return Err(SwapError::InvalidAccountData.into());
-                }
-            }
-            
-            // Found valid config, stop searching
-            break;
+        
+        if matches!(step.status, StepStatus::Failed { .. }) {
+            return Err(SwapError::StepFailed.into());
         }
+        
+        // Ensure the NFT is part of this step
+        let nft_index = step.nft_mints.iter().position(|mint| *mint == nft_mint)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        if step.escrowed[nft_index] {
+            msg!("NFT {} is already escrowed", nft_mint);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the mint account matches the expected mint
+        if mint_info.key != &nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(mint_info)?;
+        
+        // Verify the source token account is the sender's ATA and holds the NFT
+        utils::verify_token_account_owner(source_token_account_info)?;
+        utils::verify_token_account_address(source_token_account_info, sender_info.key, mint_info.key)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+        
+        if source_token_account.owner != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // Verify the escrow authority is the expected PDA and the escrow account is its ATA
+        let (expected_escrow_authority, _) = utils::get_escrow_address(trade_loop_info.key, &nft_mint, program_id);
+        if escrow_authority_info.key != &expected_escrow_authority {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(escrow_token_account_info, escrow_authority_info.key, mint_info.key)?;
+        
+        // Create the escrow token account if it doesn't exist
+        utils::create_associated_token_account_if_needed(
+            sender_info,
+            escrow_authority_info,
+            mint_info,
+            escrow_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        
+        // Mark the NFT as escrowed before transferring it
+        step.escrowed[nft_index] = true;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        // Move the NFT from the sender into escrow
+        utils::transfer_nft(
+            source_token_account_info,
+            escrow_token_account_info,
+            sender_info,
+            token_program_info,
+        )?;
+        
+        msg!("Escrowed NFT {} for step {}", nft_mint, step_index);
+        
+        Ok(())
     }
-    
-    // If config account was not found, that's ok - it might not be initialized yet
-    if !config_found {
-        msg!("Config account not found, assuming program is not paused");
-    }
-    
-    Ok(())
-} 
\ No newline at end of file
+
+    /// Process ReleaseEscrow instruction
+    pub fn process_release_escrow(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        nft_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let caller_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let escrow_authority_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let destination_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !caller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Ensure the NFT is part of this step and currently escrowed
+        let nft_index = step.nft_mints.iter().position(|mint| *mint == nft_mint)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        if !step.escrowed[nft_index] {
+            msg!("NFT {} is not escrowed", nft_mint);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Executed steps deliver to the recipient; unapproved steps can be withdrawn by the sender
+        let expected_destination = match step.status {
+            StepStatus::Executed => step.to,
+            StepStatus::Created if step.from == *caller_info.key => step.from,
+            _ => {
+                msg!("Escrowed NFT can only be released after execution or withdrawn by the sender before approval");
+                return Err(SwapError::CancellationDenied.into());
+            }
+        };
+        
+        if destination_info.key != &expected_destination {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the mint account matches the expected mint
+        if mint_info.key != &nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the escrow token account actually holds the NFT
+        verify_escrow_holds_nft(program_id, trade_loop_info.key, &nft_mint, escrow_token_account_info)?;
+        
+        // For destination, we only verify if it exists
+        if destination_token_account_info.data_len() > 0 {
+            utils::verify_token_account_address(destination_token_account_info, destination_info.key, mint_info.key)?;
+        }
+        
+        // Create the destination token account if it doesn't exist
+        utils::create_associated_token_account_if_needed(
+            caller_info,
+            destination_info,
+            mint_info,
+            destination_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        
+        // Clear the escrow flag before transferring the NFT out
+        step.escrowed[nft_index] = false;
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::transfer_nft_from_escrow(
+            escrow_token_account_info,
+            destination_token_account_info,
+            escrow_authority_info,
+            token_program_info,
+            trade_loop_info.key,
+            &nft_mint,
+            program_id,
+        )?;
+        
+        msg!("Released escrowed NFT {} to {}", nft_mint, destination_info.key);
+        
+        Ok(())
+    }
+
+    /// Process PauseTradeLoop and ResumeTradeLoop instructions
+    pub fn process_set_trade_loop_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can pause or resume it
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        trade_loop.is_paused = paused;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop {} paused state set to {}", trade_loop_info.key, paused);
+        
+        Ok(())
+    }
+
+    /// Process CreateTemplate instruction
+    pub fn process_create_template(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        template_id: [u8; 16],
+        participant_ring: Vec<Pubkey>,
+        step_count: u8,
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // A ring needs at least 2 participants
+        if step_count < 2 {
+            msg!("Template must have at least 2 steps");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if step_count > MAX_PARTICIPANTS_PER_TRANSACTION {
+            msg!("Template exceeds the maximum allowed steps ({}). Requested: {}", 
+                 MAX_PARTICIPANTS_PER_TRANSACTION, step_count);
+            return Err(SwapError::TooManyParticipants.into());
+        }
+        
+        // Every step is described by exactly one participant in the ring
+        if participant_ring.len() != step_count as usize {
+            msg!("Participant ring length {} does not match step count {}", participant_ring.len(), step_count);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Check for duplicate participants in the ring
+        let mut unique_participants = std::collections::HashSet::new();
+        for participant in &participant_ring {
+            if !unique_participants.insert(*participant) {
+                msg!("Duplicate participant found in ring: {}", participant);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let template_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the template account is the creator's PDA for this template_id
+        let (expected_template_key, bump_seed) = utils::get_template_address(payer_info.key, &template_id, program_id);
+        if template_info.key != &expected_template_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the template account already exists
+        if template_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the template account as a PDA
+        let rent = Rent::from_account_info(rent_info)?;
+        let space = TradeLoopTemplate::get_space(step_count);
+        let seeds = &[b"template".as_ref(), payer_info.key.as_ref(), template_id.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                template_info.key,
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                payer_info.clone(),
+                template_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        // Initialize the template data
+        let template = TradeLoopTemplate {
+            is_initialized: true,
+            template_id,
+            creator: *payer_info.key,
+            participant_ring,
+            step_count,
+            usage_count: 0,
+        };
+        
+        // Serialize and store the template data
+        template.serialize(&mut *template_info.data.borrow_mut())?;
+        
+        msg!("Trade loop template created with ID {:?}", template_id);
+        
+        Ok(())
+    }
+
+    /// Process InitializeFromTemplate instruction
+    pub fn process_initialize_from_template(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        template_id: [u8; 16],
+        trade_id: [u8; 32],
+        timeout_seconds: u64,
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Validate timeout to prevent excessively long timeouts
+        if timeout_seconds > MAX_TIMEOUT_SECONDS {
+            msg!("Timeout exceeds maximum allowed ({}). Requested: {}", 
+                 MAX_TIMEOUT_SECONDS, timeout_seconds);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let template_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the template account has been created
+        if template_info.data_len() == 0 || template_info.owner != program_id {
+            msg!("Template {:?} has not been created", template_id);
+            return Err(SwapError::TemplateNotFound.into());
+        }
+        
+        // Deserialize the template data
+        let mut template = TradeLoopTemplate::try_from_slice(&template_info.data.borrow())?;
+        
+        // Verify the template account is its creator's PDA for this template_id
+        let (expected_template_key, _) = utils::get_template_address(&template.creator, &template_id, program_id);
+        if template_info.key != &expected_template_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if !template.is_initialized {
+            return Err(SwapError::TemplateNotFound.into());
+        }
+        
+        // SECURITY: Verify the trade loop account is the correct PDA for this creator and trade_id
+        let (expected_trade_loop_address, bump_seed) = utils::get_trade_loop_address(
+            &trade_id,
+            payer_info.key,
+            program_id,
+        );
+        
+        if trade_loop_info.key != &expected_trade_loop_address {
+            msg!("Trade loop account address mismatch. Expected: {}, Got: {}", 
+                 expected_trade_loop_address, trade_loop_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the trade loop account already exists
+        if trade_loop_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the trade loop account as a PDA at the fixed packed size
+        let rent = Rent::from_account_info(rent_info)?;
+        let space = TradeLoop::LEN;
+        let seeds = &[b"trade_loop".as_ref(), trade_id.as_ref(), payer_info.key.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                trade_loop_info.key,
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                payer_info.clone(),
+                trade_loop_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        // Get current timestamp
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp as u64;
+        
+        // Calculate expiration time with overflow protection
+        let expires_at = current_time.checked_add(timeout_seconds)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Initialize the trade loop with the template's ring, leaving NFTs to AddTradeStep
+        let trade_loop = TradeLoop {
+            is_initialized: true,
+            trade_id,
+            created_at: current_time,
+            expires_at,
+            steps: template.build_steps(),
+            authority: *payer_info.key,
+            is_paused: false,
+            nonce: TradeLoop::initial_nonce(&trade_id, current_time, payer_info.key),
+            witness: None,
+            authority_transfer_request: None,
+            linked_loop: None,
+            cancel_lockout_seconds: 0,
+            all_approved_at: None,
+            loop_tags: [None; MAX_LOOP_TAGS],
+            recurring: None,
+            successor_loop: None,
+            tokenized_as: None,
+            offered_until_display: 0,
+            display_timezone_offset: 0,
+            deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+            applied_discount_bps: 0,
+            allocated_step_count: template.step_count,
+            execution_condition: None,
+            step_hash_history: Vec::new(),
+            execution_whitelist: None,
+            auto_execute: false,
+            commitment_mode: false,
+            per_step_nft_limits: Vec::new(),
+            execution_bond_lamports: 0,
+            applied_fee_bps: 0,
+        };
+        
+        // Serialize and store the trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        // Record the template usage
+        template.usage_count = template.usage_count.saturating_add(1);
+        template.serialize(&mut *template_info.data.borrow_mut())?;
+        
+        utils::emit_event(&SwapEvent::TradeLoopCreated {
+            trade_id,
+            authority: *payer_info.key,
+            expires_at,
+        })?;
+        
+        msg!("Trade loop initialized with ID {:?} from template {:?} (usage {})", 
+             trade_id, template_id, template.usage_count);
+        
+        Ok(())
+    }
+
+    /// Process BatchCancelExpired instruction
+    pub fn process_batch_cancel_expired(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        recipient: Pubkey,
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let caller_info = next_account_info(account_info_iter)?;
+        let recipient_info = next_account_info(account_info_iter)?;
+        let trade_loop_infos: Vec<&AccountInfo> = account_info_iter.collect();
+        
+        // Verify signers
+        if !caller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the recipient account matches the requested recipient
+        if recipient_info.key != &recipient {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // The recipient cannot also be closed as one of the trade loops
+        if trade_loop_infos.iter().any(|trade_loop_info| trade_loop_info.key == recipient_info.key) {
+            msg!("Rent recipient {} is listed as a trade loop", recipient_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Bound the compute spent in a single call
+        if trade_loop_infos.is_empty() || trade_loop_infos.len() > MAX_BATCH_CANCEL_ACCOUNTS {
+            msg!("Batch must contain between 1 and {} trade loops. Provided: {}", 
+                 MAX_BATCH_CANCEL_ACCOUNTS, trade_loop_infos.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp as u64;
+        let mut closed_count = 0u8;
+        
+        for trade_loop_info in trade_loop_infos {
+            // Only accounts owned by this program can be trade loops
+            if trade_loop_info.owner != program_id {
+                msg!("Skipping {}: {}", trade_loop_info.key, SwapError::InvalidAccountOwner);
+                continue;
+            }
+            
+            let trade_loop = match TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow()) {
+                Ok(trade_loop) if trade_loop.is_initialized => trade_loop,
+                _ => {
+                    msg!("Skipping {}: {}", trade_loop_info.key, SwapError::UninitializedAccount);
+                    continue;
+                }
+            };
+            
+            if !trade_loop.is_expired(current_time) {
+                msg!("Skipping {}: {}", trade_loop_info.key, SwapError::TradeLoopNotExpired);
+                continue;
+            }
+            
+            // The rent belongs to the loop's authority, who paid for the account
+            if trade_loop.authority != *recipient_info.key {
+                msg!("Skipping {}: rent belongs to its authority {}", trade_loop_info.key, trade_loop.authority);
+                continue;
+            }
+            
+            if trade_loop.is_paused {
+                msg!("Skipping {}: {}", trade_loop_info.key, SwapError::TradeLoopPaused);
+                continue;
+            }
+            
+            // Closing the account would lose track of NFTs still held in escrow
+            if trade_loop.has_escrowed_nfts() {
+                msg!("Skipping {}: escrowed NFTs must be released first", trade_loop_info.key);
+                continue;
+            }
+            
+            // Frozen token accounts would be stranded as well
+            if trade_loop.has_locked_nfts() {
+                msg!("Skipping {}: {}", trade_loop_info.key, SwapError::StepNftsLocked);
+                continue;
+            }
+            
+            // And so would execution bonds, which are refunded by the loop's senders
+            if trade_loop.execution_bond_lamports > 0 {
+                msg!("Skipping {}: execution bonds must be refunded first", trade_loop_info.key);
+                continue;
+            }
+            
+            // Zero out the account data and move all lamports to the authority
+            trade_loop_info.data.borrow_mut().fill(0);
+            
+            let reclaimed_lamports = trade_loop_info.lamports();
+            **recipient_info.lamports.borrow_mut() = recipient_info.lamports()
+                .checked_add(reclaimed_lamports)
+                .ok_or(SwapError::InvalidAccountData)?;
+            **trade_loop_info.lamports.borrow_mut() = 0;
+            
+            closed_count += 1;
+            utils::emit_event(&SwapEvent::TradeLoopCancelled { trade_id: trade_loop.trade_id })?;
+            msg!("Closed expired trade loop {} ({} lamports reclaimed)", trade_loop_info.key, reclaimed_lamports);
+        }
+        
+        msg!("Batch cleanup closed {} expired trade loops", closed_count);
+        
+        Ok(())
+    }
+
+    /// Process EstimateComputeUnits instruction
+    pub fn process_estimate_compute_units(
+        step_count: u8,
+        nft_counts: Vec<u8>,
+        ata_creates: Vec<u8>,
+    ) -> ProgramResult {
+        if nft_counts.len() != step_count as usize || ata_creates.len() != step_count as usize {
+            msg!("Expected {} NFT counts and ATA flags, got {} and {}", 
+                 step_count, nft_counts.len(), ata_creates.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let creates_ata: Vec<bool> = ata_creates.iter().map(|flag| *flag != 0).collect();
+        let estimated_compute_units = utils::estimate_compute_units(step_count, &nft_counts, &creates_ata);
+        
+        msg!("Estimated compute units: {}", estimated_compute_units);
+        
+        if estimated_compute_units > utils::DEFAULT_COMPUTE_UNIT_LIMIT {
+            msg!("Estimate exceeds the default limit of {} CUs, request a higher compute budget", 
+                 utils::DEFAULT_COMPUTE_UNIT_LIMIT);
+        }
+        
+        if estimated_compute_units > MAX_COMPUTE_UNITS_PER_LOOP {
+            msg!("Estimate exceeds the per-loop limit of {} CUs, split the loop", MAX_COMPUTE_UNITS_PER_LOOP);
+        }
+        
+        Ok(())
+    }
+
+    /// Process ProposeAuthorityTransfer instruction
+    pub fn process_propose_authority_transfer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the config account is owned by this program
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Calculate the expected PDA for the config account
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        
+        // Verify that the provided config account matches the expected PDA
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the current upgrade authority can propose a transfer
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Record the proposal with its acceptance deadline
+        let clock = Clock::get()?;
+        config.pending_upgrade_authority = Some(new_authority);
+        config.authority_transfer_expiry = clock.unix_timestamp
+            .checked_add(AUTHORITY_TRANSFER_GRACE_PERIOD_SECONDS)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Proposed upgrade authority transfer to {}, must be accepted before {}", 
+             new_authority, config.authority_transfer_expiry);
+        
+        Ok(())
+    }
+
+    /// Process AcceptAuthorityTransfer instruction
+    pub fn process_accept_authority_transfer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let new_authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !new_authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the config account is owned by this program
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Calculate the expected PDA for the config account
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        
+        // Verify that the provided config account matches the expected PDA
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer is the proposed authority
+        let pending_authority = config.pending_upgrade_authority
+            .ok_or(SwapError::NoPendingAuthorityTransfer)?;
+        
+        if pending_authority != *new_authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Proposals lapse once the grace period has passed
+        let clock = Clock::get()?;
+        if clock.unix_timestamp > config.authority_transfer_expiry {
+            msg!("Authority transfer expired at {}", config.authority_transfer_expiry);
+            return Err(SwapError::AuthorityTransferExpired.into());
+        }
+        
+        // Complete the handoff
+        let previous_authority = config.upgrade_authority;
+        config.upgrade_authority = pending_authority;
+        config.pending_upgrade_authority = None;
+        config.authority_transfer_expiry = 0;
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Upgrade authority transferred from {} to {}", previous_authority, pending_authority);
+        
+        Ok(())
+    }
+
+    /// Process MarkStepFailed instruction
+    pub fn process_mark_step_failed(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        reason: FailReason,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let governance_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !governance_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the accounts are owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Verify that the provided config account matches the expected PDA
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only governance can mark steps as failed
+        if config.governance != Some(*governance_info.key) {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Executed steps have already transferred their NFTs
+        if step.status == StepStatus::Executed {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        step.status = StepStatus::Failed { reason };
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Step {} marked as failed ({:?}), trade loop is now eligible for cancellation", step_index, reason);
+        
+        Ok(())
+    }
+
+    /// Process SetStepNftValues instruction
+    pub fn process_set_step_nft_values(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        nft_values_lamports: Vec<u64>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let recipient_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers; the sender pays the royalties, so the recipient must agree to the values too
+        if !sender_info.is_signer || !recipient_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Ensure the sender is the owner of this step
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Ensure the recipient is the step's recipient
+        if step.to != *recipient_info.key {
+            msg!("Recipient {} does not match the step recipient {}", recipient_info.key, step.to);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Values are part of the trade terms and are fixed once approved
+        if step.status != StepStatus::Created {
+            msg!("NFT values can only be set before approval. Step status: {:?}", step.status);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // One value per NFT in the step
+        if nft_values_lamports.len() != step.nft_mints.len() {
+            msg!("Expected {} NFT values, got {}", step.nft_mints.len(), nft_values_lamports.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        step.nft_values_lamports = nft_values_lamports;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Updated NFT values for step {}", step_index);
+        
+        Ok(())
+    }
+
+    /// Process UpdateRoyaltyEnforcement instruction
+    pub fn process_update_royalty_enforcement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        enabled: bool,
+        max_creators: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the config account is owned by this program
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Verify that the provided config account matches the expected PDA
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the authority is authorized to update the config
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.royalty_enforcement = RoyaltyEnforcement { enabled, max_creators };
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Royalty enforcement set to {} (max {} creators)", enabled, max_creators);
+        
+        Ok(())
+    }
+
+    /// Process SimulateExecuteFullTradeLoop instruction
+    /// Mirrors the validation in process_execute_full_trade_loop without any CPI or state write
+    pub fn process_simulate_execute_full_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get base accounts
+        let executor_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let _dedup_cache_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !executor_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::from_account_info(clock_info)?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // Verify the trade loop forms a valid cycle
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Sealed-bid loops reveal their NFTs with ExecuteSealedBidLoop
+        if trade_loop.has_unrevealed_commitments() {
+            return Err(SwapError::NftsNotRevealed.into());
+        }
+        
+        // Ensure all steps are approved
+        if !trade_loop.is_ready_for_execution() {
+            return Err(SwapError::MissingApprovals.into());
+        }
+        
+        // Verify the number of participants doesn't exceed the maximum
+        if trade_loop.steps.len() > MAX_PARTICIPANTS_PER_TRANSACTION as usize {
+            msg!("Trade loop exceeds the maximum allowed participants ({}). Actual: {}", 
+                 MAX_PARTICIPANTS_PER_TRANSACTION, trade_loop.steps.len());
+            return Err(SwapError::TooManyParticipants.into());
+        }
+        
+        // Verify the rent sysvar is valid
+        let _rent = Rent::from_account_info(rent_info)?;
+        
+        // Enforced royalties need an agreed value for every NFT
+        let royalty_enforced = load_optional_program_config(program_id, config_info)?
+            .is_some_and(|config| config.royalty_enforcement.enabled);
+        
+        for step in trade_loop.steps.iter() {
+            // Ensure the step hasn't already been executed
+            if step.status == StepStatus::Executed {
+                return Err(SwapError::StepAlreadyExecuted.into());
+            }
+            
+            if royalty_enforced && (0..step.nft_mints.len())
+                .any(|nft_index| step.nft_values_lamports.get(nft_index).copied().unwrap_or(0) == 0) {
+                msg!("Step from {} has NFTs without an agreed value", step.from);
+                return Err(SwapError::NftValueNotAgreed.into());
+            }
+            
+            // Get participant accounts for this step
+            let sender_info = next_account_info(account_info_iter)?;
+            let recipient_info = next_account_info(account_info_iter)?;
+            
+            // Verify the participants match the expected step
+            if step.from != *sender_info.key {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            if step.to != *recipient_info.key {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Validate each NFT in this step
+            for (nft_index, nft_mint) in step.nft_mints.iter().enumerate() {
+                // Get accounts for this specific NFT
+                let mint_info = next_account_info(account_info_iter)?;
+                let source_token_account_info = next_account_info(account_info_iter)?;
+                let destination_token_account_info = next_account_info(account_info_iter)?;
+                
+                // Verify that the mint account matches the expected mint
+                if mint_info.key != nft_mint {
+                    return Err(SwapError::InvalidAccountData.into());
+                }
+                
+                // Verify this is actually an NFT (metadata check)
+                utils::verify_nft_metadata(mint_info)?;
+                
+                // Escrowed NFTs only need to be present in the escrow account
+                if step.escrowed.get(nft_index).copied().unwrap_or(false) {
+                    verify_escrow_holds_nft(program_id, trade_loop_info.key, mint_info.key, source_token_account_info)?;
+                    continue;
+                }
+                
+                // Verify the token accounts are owned by the mint's token program (SPL Token or Token-2022)
+                utils::verify_account_owner(source_token_account_info, mint_info.owner)?;
+                
+                // Verify the source token account is the expected ATA for this wallet/mint
+                utils::verify_token_account_address_for_program(source_token_account_info, sender_info.key, mint_info.key, mint_info.owner)?;
+                
+                // A missing destination would be created during execution
+                if destination_token_account_info.data_len() > 0 {
+                    utils::verify_token_account_address_for_program(destination_token_account_info, recipient_info.key, mint_info.key, mint_info.owner)?;
+                }
+                
+                // Verify the token accounts are correctly associated with the sender
+                let source_token_account = utils::unpack_token_account(source_token_account_info)?;
+                
+                if source_token_account.owner != *sender_info.key {
+                    return Err(SwapError::InvalidAccountOwner.into());
+                }
+                
+                if source_token_account.mint != *mint_info.key {
+                    return Err(SwapError::InvalidAccountData.into());
+                }
+                
+                // Verify the sender has the NFT (amount should be 1 for NFTs)
+                if source_token_account.amount < 1 {
+                    msg!("Sender {} does not hold NFT {}", sender_info.key, mint_info.key);
+                    return Err(SwapError::InsufficientFunds.into());
+                }
+            }
+        }
+        
+        msg!("Simulation passed: trade loop with {} steps is currently executable", trade_loop.steps.len());
+        
+        Ok(())
+    }
+
+    /// Process UpdateStepNfts instruction
+    pub fn process_update_step_nfts(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        new_nft_mints: Vec<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let guards = StepGuards::load(program_id, account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // Ensure there is at least one NFT to transfer
+        if new_nft_mints.is_empty() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Each step holds at most its NFT limit (MAX_NFTS_PER_STEP unless set at initialization)
+        if new_nft_mints.len() > trade_loop.step_nft_limit(step_index) as usize {
+            msg!("Step has {} NFTs, maximum for step {} is {}", new_nft_mints.len(), step_index, trade_loop.step_nft_limit(step_index));
+            return Err(SwapError::TooManyNftsInStep.into());
+        }
+        
+        {
+            let step = &trade_loop.steps[step_index as usize];
+            
+            // Ensure the signer is the sender of this step
+            if step.from != *from_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            // Only steps that have not been approved yet can be amended
+            if step.status != StepStatus::Created {
+                msg!("Step {} can only be amended before approval. Status: {:?}", step_index, step.status);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            // Refuse to replace NFTs that are still held in escrow
+            if step.escrowed.iter().any(|escrowed| *escrowed) {
+                msg!("Step {} has escrowed NFTs and cannot be amended", step_index);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Or whose token accounts are still frozen
+            if step.nfts_locked {
+                msg!("Step {} has locked NFTs and cannot be amended", step_index);
+                return Err(SwapError::StepNftsLocked.into());
+            }
+        }
+        
+        // Verify that the sender owns all the NFTs they're committing to trade
+        verify_sender_owns_nfts(account_info_iter, from_info.key, &new_nft_mints)?;
+        verify_mints_not_blocked(guards.mint_blocklist.as_ref(), &new_nft_mints)?;
+        verify_nft_collections_on_chain(guards.collection_whitelist.as_ref(), accounts, &new_nft_mints)?;
+        verify_nft_floor_prices(guards.config.as_ref(), guards.collection_whitelist.as_ref(), accounts, &new_nft_mints)?;
+        
+        // Replace the NFTs, resetting the per-NFT escrow flags and agreed values
+        let step = &mut trade_loop.steps[step_index as usize];
+        step.escrowed = vec![false; new_nft_mints.len()];
+        step.nft_values_lamports = vec![0; new_nft_mints.len()];
+        step.nft_mints = new_nft_mints;
+        trade_loop.record_step_hash(step_index);
+        
+        // The trade terms changed, so every existing approval is invalidated
+        invalidate_approvals(&mut trade_loop, "NFT update");
+        
+        // If we have all expected steps, verify the loop still forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Updated NFTs for trade step {} from {}", step_index, from_info.key);
+        
+        Ok(())
+    }
+
+    /// Process UpdateStepRecipient instruction
+    pub fn process_update_step_recipient(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        new_recipient: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        if new_recipient == Pubkey::default() {
+            msg!("New recipient cannot be the default pubkey");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let step = trade_loop.steps.get(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Ensure the signer is the sender of this step
+        if step.from != *from_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Only steps that have not been approved yet can be amended
+        if step.status != StepStatus::Created {
+            msg!("Step {} can only be amended before approval. Status: {:?}", step_index, step.status);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // A blocked wallet cannot join the loop as a new recipient
+        let already_participant = trade_loop.steps.iter()
+            .any(|step| step.from == new_recipient || step.to == new_recipient);
+        if !already_participant && load_blocklist(program_id, blocklist_info)?.is_some_and(|blocklist| blocklist.is_blocked(&new_recipient)) {
+            msg!("Wallet {} is blocked from joining trade loops", new_recipient);
+            return Err(SwapError::ParticipantBlocked.into());
+        }
+        
+        // The new recipient must pass KYC while it is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[&new_recipient])?;
+        
+        let old_recipient = step.to;
+        trade_loop.steps[step_index as usize].to = new_recipient;
+        trade_loop.record_step_hash(step_index);
+        
+        // The NFT values were agreed with the old recipient
+        let nft_count = trade_loop.steps[step_index as usize].nft_mints.len();
+        trade_loop.steps[step_index as usize].nft_values_lamports = vec![0; nft_count];
+        
+        // The trade terms changed, so every existing approval is invalidated
+        invalidate_approvals(&mut trade_loop, "recipient update");
+        
+        // If we have all expected steps, verify the loop still forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepRecipientUpdated {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            old_recipient,
+            new_recipient,
+        })?;
+        
+        msg!("Updated recipient of trade step {} from {} to {}", step_index, old_recipient, new_recipient);
+        
+        Ok(())
+    }
+
+    /// Process RegisterTransferHook instruction
+    pub fn process_register_transfer_hook(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let registry_info = next_account_info(account_info_iter)?;
+        let extra_metas_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the Token-2022 and system programs
+        if token_program_info.key != &utils::TOKEN_2022_PROGRAM_ID || system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        if mint_info.key != &mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Only the mint's current transfer hook authority can hand the hook over
+        let transfer_hook = utils::read_token_2022_transfer_hook(mint_info)?;
+        if transfer_hook.authority != Some(*authority_info.key) {
+            msg!("{} is not the transfer hook authority of mint {}", authority_info.key, mint);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Execution restores the original hook before moving the NFT and cannot supply the
+        // extra accounts of another hook program, so only mints without one are supported
+        if let Some(hook_program) = transfer_hook.program_id {
+            msg!("Mint {} already runs transfer hook program {}", mint, hook_program);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        if !trade_loop.steps.iter().any(|step| step.nft_mints.contains(&mint)) {
+            msg!("Mint {} is not traded in this trade loop", mint);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the registry and extra account metas accounts are the correct, unused PDAs
+        let (expected_registry_key, registry_bump) = utils::get_hook_registry_address(&mint, program_id);
+        let (expected_extra_metas_key, extra_metas_bump) = utils::get_extra_account_metas_address(&mint, program_id);
+        if registry_info.key != &expected_registry_key || extra_metas_info.key != &expected_extra_metas_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if registry_info.data_len() > 0 {
+            msg!("Mint {} already has a registered transfer hook", mint);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let registry = HookRegistry {
+            is_initialized: true,
+            mint,
+            active_loop: *trade_loop_info.key,
+            original_authority: *authority_info.key,
+            original_hook_program: transfer_hook.program_id,
+        };
+        let extra_metas_data = utils::transfer_hook_extra_account_metas(trade_loop_info.key);
+        
+        // Create the registry and the extra account metas accounts as PDAs
+        let rent = Rent::get()?;
+        let registry_space = HookRegistry::get_space();
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                registry_info.key,
+                rent.minimum_balance(registry_space),
+                registry_space as u64,
+                program_id,
+            ),
+            &[
+                authority_info.clone(),
+                registry_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"hook_registry".as_ref(), mint.as_ref(), &[registry_bump]]],
+        )?;
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                extra_metas_info.key,
+                rent.minimum_balance(extra_metas_data.len()),
+                extra_metas_data.len() as u64,
+                program_id,
+            ),
+            &[
+                authority_info.clone(),
+                extra_metas_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"extra-account-metas".as_ref(), mint.as_ref(), &[extra_metas_bump]]],
+        )?;
+        
+        registry.serialize(&mut *registry_info.data.borrow_mut())?;
+        extra_metas_info.data.borrow_mut().copy_from_slice(&extra_metas_data);
+        
+        // Point the mint's transfer hook at this program, then hand the hook authority to our PDA
+        let (hook_authority_key, _) = utils::get_hook_authority_address(program_id);
+        invoke(
+            &utils::token_2022_update_transfer_hook(&mint, authority_info.key, Some(*program_id)),
+            &[mint_info.clone(), authority_info.clone(), token_program_info.clone()],
+        )?;
+        invoke(
+            &utils::token_2022_set_transfer_hook_authority(&mint, authority_info.key, &hook_authority_key),
+            &[mint_info.clone(), authority_info.clone(), token_program_info.clone()],
+        )?;
+        
+        msg!("Registered transfer hook of mint {} for trade loop {:?}", mint, trade_loop.trade_id);
+        
+        Ok(())
+    }
+
+    /// Process DeregisterTransferHook instruction
+    pub fn process_deregister_transfer_hook(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let registry_info = next_account_info(account_info_iter)?;
+        let extra_metas_info = next_account_info(account_info_iter)?;
+        let hook_authority_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the Token-2022 program
+        if token_program_info.key != &utils::TOKEN_2022_PROGRAM_ID {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the registry account is owned by this program
+        utils::verify_account_owner(registry_info, program_id)?;
+        
+        // Deserialize the registry data
+        let mut registry = HookRegistry::try_from_slice(&registry_info.data.borrow())?;
+        
+        // Ensure the registry is initialized
+        if !registry.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the registry and extra account metas PDAs belong to the mint
+        let (expected_registry_key, _) = utils::get_hook_registry_address(mint_info.key, program_id);
+        let (expected_extra_metas_key, _) = utils::get_extra_account_metas_address(mint_info.key, program_id);
+        if registry_info.key != &expected_registry_key
+            || registry.mint != *mint_info.key
+            || extra_metas_info.key != &expected_extra_metas_key
+        {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Only the authority the hook was taken from can take it back
+        if registry.original_authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Execution already hands the hook back; otherwise restore it now
+        if !registry.is_released() {
+            release_transfer_hook(program_id, mint_info, registry_info, hook_authority_info, token_program_info, &mut registry)?;
+        }
+        
+        // Return the rent of both accounts to the original authority
+        close_program_account(registry_info, authority_info)?;
+        close_program_account(extra_metas_info, authority_info)?;
+        
+        msg!("Deregistered transfer hook of mint {}", mint_info.key);
+        
+        Ok(())
+    }
+
+    /// Process the transfer hook interface's Execute instruction, invoked by Token-2022 on
+    /// every transfer of a registered mint
+    ///
+    /// While the registry's trade loop is live, a transfer is authorized only from the sender
+    /// to the recipient of an approved step holding the mint. The restriction lapses once the
+    /// loop is closed, expired or has executed every step holding the mint.
+    pub fn process_transfer_hook_execute(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        hook_data: &[u8],
+    ) -> ProgramResult {
+        let amount = hook_data.get(..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts: the transfer's source, mint, destination and owner, then the
+        // extra account metas account and the extra accounts it lists
+        let source_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let _owner_info = next_account_info(account_info_iter)?;
+        let extra_metas_info = next_account_info(account_info_iter)?;
+        let registry_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the extra account metas and registry accounts belong to the mint
+        let (expected_extra_metas_key, _) = utils::get_extra_account_metas_address(mint_info.key, program_id);
+        let (expected_registry_key, _) = utils::get_hook_registry_address(mint_info.key, program_id);
+        if extra_metas_info.key != &expected_extra_metas_key || registry_info.key != &expected_registry_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the registry account is owned by this program
+        utils::verify_account_owner(registry_info, program_id)?;
+        
+        let registry = HookRegistry::try_from_slice(&registry_info.data.borrow())?;
+        if !registry.is_initialized || registry.mint != *mint_info.key {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        if trade_loop_info.key != &registry.active_loop {
+            msg!("Trade loop {} is not the active loop of mint {}", trade_loop_info.key, mint_info.key);
+            return Err(SwapError::TransferNotAuthorized.into());
+        }
+        
+        // Only a live trade loop restricts transfers; a closed or expired one releases the mint
+        let clock = Clock::get()?;
+        let trade_loop = if trade_loop_info.owner == program_id {
+            TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow()).ok()
+                .filter(|trade_loop| trade_loop.is_initialized && !trade_loop.is_expired(clock.unix_timestamp as u64))
+        } else {
+            None
+        };
+        
+        let authorized = match trade_loop {
+            None => true,
+            Some(trade_loop) => {
+                let source_owner = utils::unpack_token_account(source_info)?.owner;
+                let destination_owner = utils::unpack_token_account(destination_info)?.owner;
+                let mut mint_steps = trade_loop.steps.iter().filter(|step| step.nft_mints.contains(mint_info.key));
+                
+                // Once every step holding the mint has executed, the loop no longer restricts it
+                mint_steps.clone().all(|step| step.status == StepStatus::Executed)
+                    || mint_steps.any(|step| {
+                        step.status == StepStatus::Approved && step.from == source_owner && step.to == destination_owner
+                    })
+            }
+        };
+        
+        if !authorized {
+            msg!("Transfer of {} token(s) of mint {} is not part of an approved trade loop", amount, mint_info.key);
+            return Err(SwapError::TransferNotAuthorized.into());
+        }
+        
+        msg!("Transfer of mint {} authorized by trade loop {}", mint_info.key, trade_loop_info.key);
+        
+        Ok(())
+    }
+
+    /// Process TransferStepOwnership instruction
+    pub fn process_transfer_step_ownership(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        new_owner: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let old_owner_info = next_account_info(account_info_iter)?;
+        let new_owner_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Both sides of the handover must sign
+        if !old_owner_info.is_signer || !new_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        if new_owner_info.key != &new_owner {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        {
+            let step = &trade_loop.steps[step_index as usize];
+            
+            // Ensure the signer is the sender of this step
+            if step.from != *old_owner_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            // Only steps that have not been approved yet can change hands
+            if step.status != StepStatus::Created {
+                msg!("Step {} can only be transferred before approval. Status: {:?}", step_index, step.status);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            // Escrowed NFTs belong to the old owner and would go with the position
+            if step.escrowed.iter().any(|escrowed| *escrowed) {
+                msg!("Step {} has escrowed NFTs and cannot be transferred", step_index);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Or whose token accounts are still frozen
+            if step.nfts_locked {
+                msg!("Step {} has locked NFTs and cannot be transferred", step_index);
+                return Err(SwapError::StepNftsLocked.into());
+            }
+        }
+        
+        // The new owner must not already take part in the loop
+        if trade_loop.steps.iter().any(|step| step.from == new_owner || step.to == new_owner) {
+            msg!("{} already participates in this trade loop", new_owner);
+            return Err(SwapError::DuplicateParticipantAddress.into());
+        }
+        
+        // Blocked wallets cannot join the loop
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(&new_owner) {
+                msg!("Wallet {} is blocked from joining trade loops", new_owner);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // The new owner must pass KYC while it is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[&new_owner])?;
+        
+        // Verify that the new owner holds all the NFTs the step commits to trade
+        let nft_mints = trade_loop.steps[step_index as usize].nft_mints.clone();
+        verify_sender_owns_nfts(account_info_iter, &new_owner, &nft_mints)?;
+        
+        // The new owner takes over both sending this step and receiving from the previous one
+        let old_owner = *old_owner_info.key;
+        for step in trade_loop.steps.iter_mut() {
+            if step.from == old_owner {
+                step.from = new_owner;
+                // The delegate was chosen by the old owner
+                step.delegate_approval = None;
+            }
+            if step.to == old_owner {
+                step.to = new_owner;
+            }
+        }
+        for index in 0..trade_loop.steps.len() as u8 {
+            let step = &trade_loop.steps[index as usize];
+            if step.from == new_owner || step.to == new_owner {
+                trade_loop.record_step_hash(index);
+            }
+        }
+        
+        // The trade terms changed, so every existing approval is invalidated
+        invalidate_approvals(&mut trade_loop, "ownership transfer");
+        
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepOwnershipTransferred {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            old_owner,
+            new_owner,
+        })?;
+        
+        msg!("Transferred trade step {} from {} to {}", step_index, old_owner, new_owner);
+        
+        Ok(())
+    }
+
+    /// Process InitializeTradeLoopWithEscrow instruction
+    /// Runs the InitializeTradeLoop, AddTradeStep and EscrowNft paths in one instruction
+    /// and approves the initiator's step
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_initialize_trade_loop_with_escrow(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        timeout_seconds: u64,
+        initiator_step_index: u8,
+        initiator_to: Pubkey,
+        initiator_nft_mints: Vec<Pubkey>,
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get base accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let mint_blocklist_info = next_account_info(account_info_iter)?;
+        let collection_whitelist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // The initiator starts the participant ring, so their step is the first one
+        if initiator_step_index != 0 {
+            msg!("Initiator step index must be 0 for a new trade loop. Requested: {}", initiator_step_index);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Collect the per-NFT accounts: mint, source token account, escrow authority, escrow token account
+        let mut nft_accounts = Vec::with_capacity(initiator_nft_mints.len());
+        for _ in &initiator_nft_mints {
+            nft_accounts.push((
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+            ));
+        }
+        
+        // Initialize the trade loop; the accounts after the NFTs include the payer's cooldown account
+        let mut initialize_accounts = vec![
+            payer_info.clone(),
+            trade_loop_info.clone(),
+            rent_info.clone(),
+            system_program_info.clone(),
+            blocklist_info.clone(),
+            config_info.clone(),
+        ];
+        initialize_accounts.extend_from_slice(account_info_iter.as_slice());
+        Self::process_initialize_trade_loop(
+            program_id,
+            &initialize_accounts,
+            trade_id,
+            participants,
+            timeout_seconds,
+            None,
+            0,
+            false,
+            None,
+        )?;
+        
+        // Add the initiator's step, verifying ownership of each NFT and passing on any metadata and price feeds
+        let mut add_step_accounts = vec![
+            payer_info.clone(),
+            trade_loop_info.clone(),
+            token_program_info.clone(),
+            blocklist_info.clone(),
+            mint_blocklist_info.clone(),
+            collection_whitelist_info.clone(),
+            config_info.clone(),
+        ];
+        for (mint_info, source_token_account_info, _, _) in &nft_accounts {
+            add_step_accounts.push((*mint_info).clone());
+            add_step_accounts.push((*source_token_account_info).clone());
+        }
+        add_step_accounts.extend_from_slice(account_info_iter.as_slice());
+        Self::process_add_trade_step(
+            program_id,
+            &add_step_accounts,
+            initiator_step_index,
+            initiator_to,
+            initiator_nft_mints.clone(),
+            Vec::new(),
+        )?;
+        
+        // Move each of the initiator's NFTs into escrow
+        for (nft_mint, (mint_info, source_token_account_info, escrow_authority_info, escrow_token_account_info)) in
+            initiator_nft_mints.iter().zip(nft_accounts.iter())
+        {
+            Self::process_escrow_nft(
+                program_id,
+                &[
+                    payer_info.clone(),
+                    trade_loop_info.clone(),
+                    (*mint_info).clone(),
+                    (*source_token_account_info).clone(),
+                    (*escrow_authority_info).clone(),
+                    (*escrow_token_account_info).clone(),
+                    token_program_info.clone(),
+                    associated_token_program_info.clone(),
+                    system_program_info.clone(),
+                    rent_info.clone(),
+                ],
+                initiator_step_index,
+                *nft_mint,
+            )?;
+        }
+        
+        // The initiator signed and escrowed their NFTs, so their step is approved
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        trade_loop.steps[initiator_step_index as usize].status = StepStatus::Approved;
+        trade_loop.steps[initiator_step_index as usize].approved_at_slot = Some(Clock::get()?.slot);
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepApproved {
+            trade_id,
+            step_index: initiator_step_index,
+        })?;
+        
+        msg!("Trade loop initialized with {} escrowed NFTs from initiator {}", 
+             initiator_nft_mints.len(), payer_info.key);
+        
+        Ok(())
+    }
+
+    /// Process ExecutePartialLoop instruction
+    pub fn process_execute_partial_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        from_step: u8,
+        to_step: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get base accounts
+        let executor_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !executor_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Royalties are only collected when enabled in the program config
+        let royalty_enforcement = load_optional_program_config(program_id, config_info)?
+            .map(|config| config.royalty_enforcement)
+            .filter(|royalty_enforcement| royalty_enforcement.enabled);
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::from_account_info(clock_info)?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // Only whitelisted executors can execute a loop with an execution whitelist
+        verify_executor_whitelisted(&trade_loop, executor_info.key)?;
+        
+        // Ensure the range is non-empty and within the loop
+        if from_step >= to_step || to_step as usize > trade_loop.steps.len() {
+            msg!("Invalid step range [{}, {}) for a loop of {} steps", from_step, to_step, trade_loop.steps.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Sealed-bid loops only execute with ExecuteFullTradeLoop
+        if trade_loop.commitment_mode {
+            msg!("Sealed-bid trade loops must be executed in full");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the trade loop forms a valid cycle
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Partial execution only starts once every participant has approved, so no
+        // range can leave an unapproved step behind
+        if trade_loop.steps.iter().any(|step| !matches!(step.status, StepStatus::Approved | StepStatus::Executed)) {
+            return Err(SwapError::MissingApprovals.into());
+        }
+        
+        // Ensure the witness co-signed, if one is required
+        verify_witness_signature(&trade_loop, accounts)?;
+        
+        // Ensure the external execution condition holds, if one is set
+        verify_execution_condition(&trade_loop, accounts)?;
+        
+        // Locked steps are thawed right before their transfers
+        let locked_steps: Vec<bool> = trade_loop.steps.iter().map(|step| step.nfts_locked).collect();
+        
+        // Mark the range as executed BEFORE any transfers; a concurrent or replayed
+        // transaction covering any of these steps will then fail the check below
+        for step_index in from_step..to_step {
+            let step = &mut trade_loop.steps[step_index as usize];
+            if step.status == StepStatus::Executed {
+                msg!("Step {} was already executed by an overlapping range", step_index);
+                return Err(SwapError::StepAlreadyExecuted.into());
+            }
+            if step.is_expired(clock.unix_timestamp as u64) {
+                msg!("Step {} has expired", step_index);
+                return Err(SwapError::StepExpired.into());
+            }
+            if step.is_timelocked(clock.unix_timestamp as u64) {
+                msg!("Step {} cannot execute before {}", step_index, step.not_before.unwrap_or_default());
+                return Err(SwapError::StepNotYetActive.into());
+            }
+            step.status = StepStatus::Executed;
+            step.executed_at_slot = Some(clock.slot);
+            step.nfts_locked = false;
+        }
+        
+        // Immediately persist all status changes to prevent reentrancy
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        // Royalty accounts follow the accounts of every step in the range
+        let range_steps = &trade_loop.steps[from_step as usize..to_step as usize];
+        let royalty_accounts_start = 8 + range_steps.iter().map(step_execution_account_count).sum::<usize>();
+        let royalty_account_iter = &mut accounts.get(royalty_accounts_start..).unwrap_or_default().iter();
+        
+        // Process each step in the range (status already updated)
+        for step_index in from_step..to_step {
+            let step = &trade_loop.steps[step_index as usize];
+            let lock_authority_info = locked_steps[step_index as usize]
+                .then(|| find_lock_authority(program_id, accounts))
+                .transpose()?;
+            execute_step_transfers(
+                account_info_iter,
+                royalty_account_iter,
+                accounts,
+                program_id,
+                step,
+                trade_loop_info,
+                executor_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+                lock_authority_info,
+                royalty_enforcement.as_ref(),
+            )?;
+            
+            utils::emit_event(&SwapEvent::StepExecuted {
+                trade_id: trade_loop.trade_id,
+                step_index,
+                nft_mints: step.nft_mints.clone(),
+            })?;
+        }
+        
+        record_program_stats(program_id, accounts, |stats| {
+            stats.record_steps_executed(&trade_loop.steps[from_step as usize..to_step as usize]);
+        })?;
+        
+        msg!("Executed steps [{}, {}) of trade loop", from_step, to_step);
+        
+        if trade_loop.overall_status() == LoopStatus::Executed {
+            utils::emit_event(&SwapEvent::TradeLoopComplete { trade_id: trade_loop.trade_id })?;
+            msg!("Full loop execution complete.");
+        }
+        
+        Ok(())
+    }
+
+    /// Process SetWitness and ClearWitness instructions
+    pub fn process_set_witness(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        witness: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can change the witness
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // The witness can only change before execution starts
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // The zero pubkey means no witness is required
+        trade_loop.witness = witness.filter(|witness| *witness != Pubkey::default());
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        match trade_loop.witness {
+            Some(witness) => msg!("Trade loop {} now requires witness {}", trade_loop_info.key, witness),
+            None => msg!("Trade loop {} no longer requires a witness", trade_loop_info.key),
+        }
+        
+        Ok(())
+    }
+
+    /// Process SetExecutionWhitelist and ClearExecutionWhitelist instructions
+    pub fn process_set_execution_whitelist(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        executors: Option<Vec<Pubkey>>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can change the whitelist
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        trade_loop.execution_whitelist = match executors {
+            Some(executors) => {
+                if executors.len() > MAX_WHITELISTED_EXECUTORS {
+                    msg!("Execution whitelist has {} executors, maximum is {}", executors.len(), MAX_WHITELISTED_EXECUTORS);
+                    return Err(SwapError::InvalidInstructionData.into());
+                }
+                
+                // The zero pubkey marks unused slots
+                if executors.contains(&Pubkey::default()) {
+                    return Err(SwapError::InvalidInstructionData.into());
+                }
+                
+                let mut whitelist = [Pubkey::default(); MAX_WHITELISTED_EXECUTORS];
+                whitelist[..executors.len()].copy_from_slice(&executors);
+                
+                if executors.is_empty() {
+                    msg!("Execution of trade loop {} is blocked", trade_loop_info.key);
+                } else {
+                    msg!("Trade loop {} can only be executed by {:?}", trade_loop_info.key, executors);
+                }
+                Some(whitelist)
+            }
+            None => {
+                msg!("Trade loop {} no longer has an execution whitelist", trade_loop_info.key);
+                None
+            }
+        };
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        Ok(())
+    }
+
+    /// Process QueryStepHistory instruction
+    pub fn process_query_step_history(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let hashes = trade_loop.step_hashes(step_index);
+        sol_log_data(&[&[step_index], &hashes.concat()]);
+        
+        msg!("Step {} has {} recorded hashes", step_index, hashes.len());
+        
+        Ok(())
+    }
+
+    /// Process SetExecutionCondition instruction
+    pub fn process_set_execution_condition(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        condition: ExecutionCondition,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can set the condition
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Participants approve the loop knowing its condition, so it cannot change afterwards
+        if let Some(step_index) = trade_loop.steps.iter().position(|step| step.status != StepStatus::Created) {
+            msg!("Step {} is already {:?}, the execution condition can no longer change", 
+                 step_index, trade_loop.steps[step_index].status);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        trade_loop.execution_condition = Some(condition);
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop {} executes only when byte {} of {} is {}", 
+             trade_loop_info.key, condition.byte_offset, condition.account, condition.expected_value);
+        
+        Ok(())
+    }
+
+    /// Process SetCloseEmptyTokenAccounts instruction
+    pub fn process_set_close_empty_token_accounts(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        enabled: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the config account is owned by this program
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Verify that the provided config account matches the expected PDA
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the authority is authorized to update the config
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.close_empty_token_accounts = enabled;
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Closing empty token accounts after transfer set to {}", enabled);
+        
+        Ok(())
+    }
+
+    /// Process InitializeProgramConfigV2 instruction
+    /// Creates the config with all operator settings, or migrates an existing version 1 config
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_initialize_program_config_v2(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        governance: Option<Pubkey>,
+        max_participants: u8,
+        fee_basis_points: u16,
+        fee_recipient: Pubkey,
+        royalty_enforcement_enabled: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Validate the operator settings
+        if !(2..=MAX_PARTICIPANTS_PER_TRANSACTION).contains(&max_participants) {
+            msg!("Max participants must be between 2 and {}. Requested: {}", 
+                 MAX_PARTICIPANTS_PER_TRANSACTION, max_participants);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if fee_basis_points > 10_000 {
+            msg!("Fee cannot exceed 10000 basis points. Requested: {}", fee_basis_points);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Calculate the expected PDA for the config account
+        let (expected_config_key, bump_seed) = utils::get_program_config_address(program_id);
+        
+        // Verify that the provided config account matches the expected PDA
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Get the rent
+        let rent = Rent::from_account_info(rent_info)?;
+        
+        // Size of the config account
+        let config_size = ProgramConfig::get_space();
+        
+        let mut config = if config_info.data_len() == 0 {
+            // Create the config account as a PDA
+            let seeds = &[b"config".as_ref(), &[bump_seed]];
+            
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    config_info.key,
+                    rent.minimum_balance(config_size),
+                    config_size as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    config_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[seeds],
+            )?;
+            
+            ProgramConfig {
+                config_version: PROGRAM_CONFIG_VERSION,
+                is_initialized: true,
+                version: PROGRAM_VERSION,
+                upgrade_authority: *authority_info.key,
+                governance: None,
+                paused: false,
+                pending_upgrade_authority: None,
+                authority_transfer_expiry: 0,
+                royalty_enforcement: RoyaltyEnforcement::default(),
+                close_empty_token_accounts: false,
+                max_participants: MAX_PARTICIPANTS_PER_TRANSACTION,
+                fee_basis_points: 0,
+                fee_recipient: *authority_info.key,
+                governance_token_mint: None,
+                authorized_relayers: Vec::new(),
+                max_sol_per_step: 0,
+                last_upgrade_test_passed: false,
+                last_upgrade_timestamp: 0,
+                badge_mint: None,
+                append_provenance_metadata: false,
+                rate_limiting_enabled: false,
+                coupon_issuer: None,
+                proposed_governance: None,
+                min_nft_floor_price_usd_cents: None,
+                paused_reason: None,
+                execution_window_seconds: None,
+                fee_schedule: None,
+                fee_route: None,
+                fee_route_fallback_to_sol: false,
+                kyc_required: false,
+            }
+        } else {
+            // Only version 1 configs can be migrated, newer configs are already initialized
+            utils::verify_account_owner(config_info, program_id)?;
+            
+            let config_version = config_info.data.borrow()[0];
+            if config_version != 1 {
+                msg!("Program config is already at version {}", config_version);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            let config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+            
+            // Only the current upgrade authority can migrate the config
+            if config.upgrade_authority != *authority_info.key {
+                return Err(SwapError::UpgradeAuthorityMismatch.into());
+            }
+            
+            // Extend the account to the new layout, keeping it rent exempt
+            let required_lamports = rent.minimum_balance(config_size)
+                .saturating_sub(config_info.lamports());
+            if required_lamports > 0 {
+                invoke(
+                    &system_instruction::transfer(authority_info.key, config_info.key, required_lamports),
+                    &[authority_info.clone(), config_info.clone(), system_program_info.clone()],
+                )?;
+            }
+            config_info.realloc(config_size, true)?;
+            
+            msg!("Migrating program config from version 1 to version {}", PROGRAM_CONFIG_VERSION);
+            
+            config
+        };
+        
+        // Write all operator settings
+        config.governance = governance;
+        config.max_participants = max_participants;
+        config.fee_basis_points = fee_basis_points;
+        config.fee_recipient = fee_recipient;
+        config.royalty_enforcement = RoyaltyEnforcement {
+            enabled: royalty_enforcement_enabled,
+            max_creators: MAX_METADATA_CREATORS,
+        };
+        
+        // Serialize and store the config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Program config initialized with authority {} (max {} participants, fee {} bps)", 
+             config.upgrade_authority, max_participants, fee_basis_points);
+        
+        Ok(())
+    }
+
+    /// Process DiagnoseTradeLoop instruction
+    /// Read-only, so it runs even while the program or trade loop is paused
+    pub fn process_diagnose_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let mut results = Vec::new();
+        
+        for (step_index, step) in trade_loop.steps.iter().enumerate() {
+            let mut nft_statuses = Vec::with_capacity(step.nft_mints.len());
+            
+            for (nft_index, nft_mint) in step.nft_mints.iter().enumerate() {
+                let token_account_info = next_account_info(account_info_iter)?;
+                
+                // Escrowed NFTs are expected in the escrow PDA's token account
+                let expected_owner = if step.escrowed.get(nft_index).copied().unwrap_or(false) {
+                    utils::get_escrow_address(trade_loop_info.key, nft_mint, program_id).0
+                } else {
+                    step.from
+                };
+                
+                // Anything that is not a valid token account for this mint counts as missing
+                let (owner_match, amount) = match spl_token::state::Account::unpack(&token_account_info.data.borrow()) {
+                    Ok(token_account) if token_account_info.owner == &spl_token::id()
+                        && token_account.mint == *nft_mint =>
+                    {
+                        (token_account.owner == expected_owner, token_account.amount)
+                    }
+                    _ => (false, 0),
+                };
+                
+                let status = match (owner_match, amount > 0) {
+                    (true, true) => "owned",
+                    (true, false) => "EMPTY",
+                    (false, _) => "MISSING",
+                };
+                nft_statuses.push(format!("NFT_{}={}", nft_index, status));
+                
+                results.push(DiagnosticResult {
+                    step_index: step_index as u8,
+                    nft_mint: *nft_mint,
+                    owner_match,
+                    amount,
+                });
+            }
+            
+            msg!("STEP {}: from={} to={} status={:?} {}", 
+                 step_index, step.from, step.to, step.status, nft_statuses.join(" "));
+        }
+        
+        sol_log_data(&[&results.try_to_vec()?]);
+        
+        Ok(())
+    }
+
+    /// Process BulkCloseEscrows instruction
+    pub fn process_bulk_close_empty_escrow_accounts(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let executor_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let escrow_infos: Vec<&AccountInfo> = account_info_iter.collect();
+        
+        // Verify signers
+        if !executor_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Escrows come in (authority, token account) pairs, at most one per NFT of a full loop
+        let max_escrows = MAX_PARTICIPANTS_PER_TRANSACTION as usize * MAX_NFTS_PER_STEP as usize;
+        if escrow_infos.is_empty() || !escrow_infos.len().is_multiple_of(2) || escrow_infos.len() / 2 > max_escrows {
+            msg!("Expected between 1 and {} escrow account pairs. Provided {} accounts", max_escrows, escrow_infos.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // NFTs the loop still counts as escrowed keep their token accounts
+        let trade_loop = (trade_loop_info.owner == program_id && trade_loop_info.data_len() > 0)
+            .then(|| TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow()).ok())
+            .flatten()
+            .filter(|trade_loop| trade_loop.is_initialized);
+        
+        let mut count = 0u8;
+        let mut rent_reclaimed = 0u64;
+        for escrow_pair in escrow_infos.chunks(2) {
+            let (escrow_authority_info, escrow_token_account_info) = (escrow_pair[0], escrow_pair[1]);
+            
+            let token_account = match spl_token::state::Account::unpack(&escrow_token_account_info.data.borrow()) {
+                Ok(token_account) if escrow_token_account_info.owner == &spl_token::id() => token_account,
+                _ => {
+                    msg!("Skipping {}: not a token account", escrow_token_account_info.key);
+                    continue;
+                }
+            };
+            
+            let (expected_escrow_authority, _) = utils::get_escrow_address(trade_loop_info.key, &token_account.mint, program_id);
+            if escrow_authority_info.key != &expected_escrow_authority || token_account.owner != expected_escrow_authority {
+                msg!("Skipping {}: not an escrow of this trade loop", escrow_token_account_info.key);
+                continue;
+            }
+            
+            if token_account.amount != 0 {
+                msg!("Skipping {}: still holds {} tokens", escrow_token_account_info.key, token_account.amount);
+                continue;
+            }
+            
+            let still_escrowed = trade_loop.as_ref().is_some_and(|trade_loop| {
+                trade_loop.steps.iter().any(|step| {
+                    step.nft_mints.iter().zip(&step.escrowed)
+                        .any(|(nft_mint, escrowed)| *escrowed && *nft_mint == token_account.mint)
+                })
+            });
+            if still_escrowed {
+                msg!("Skipping {}: NFT {} is still escrowed by the trade loop", escrow_token_account_info.key, token_account.mint);
+                continue;
+            }
+            
+            let reclaimed_lamports = escrow_token_account_info.lamports();
+            utils::close_escrow_token_account(
+                escrow_token_account_info,
+                executor_info,
+                escrow_authority_info,
+                token_program_info,
+                trade_loop_info.key,
+                &token_account.mint,
+                program_id,
+            )?;
+            
+            count += 1;
+            rent_reclaimed = rent_reclaimed.saturating_add(reclaimed_lamports);
+            msg!("Closed escrow token account {} ({} lamports reclaimed)", escrow_token_account_info.key, reclaimed_lamports);
+        }
+        
+        utils::emit_event(&SwapEvent::EscrowsClosed { count, rent_reclaimed })?;
+        
+        msg!("Closed {} escrow token accounts of trade loop {}", count, trade_loop_info.key);
+        
+        Ok(())
+    }
+
+    /// Process GetParticipantStats instruction
+    pub fn process_get_loop_participant_stats(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let stats: Vec<ParticipantStats> = trade_loop.steps.iter().enumerate()
+            .map(|(step_index, step)| ParticipantStats {
+                participant: step.from,
+                step_index: step_index as u8,
+                added_at_slot: step.added_at_slot,
+                approved_at_slot: step.approved_at_slot,
+                executed_at_slot: step.executed_at_slot,
+                nft_count: step.nft_mints.len() as u8,
+            })
+            .collect();
+        
+        for entry in &stats {
+            msg!("STEP {}: participant={} added_at_slot={} approved_at_slot={:?} executed_at_slot={:?} nfts={}", 
+                 entry.step_index, entry.participant, entry.added_at_slot, entry.approved_at_slot, entry.executed_at_slot, entry.nft_count);
+        }
+        
+        sol_log_data(&[&stats.try_to_vec()?]);
+        
+        Ok(())
+    }
+
+    /// Process CheckRentExemption instruction
+    pub fn process_check_rent_exemption(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Participant wallets, and the associated token accounts their NFTs move between
+        let mut loop_accounts = Vec::new();
+        for step in &trade_loop.steps {
+            loop_accounts.extend([step.from, step.to]);
+            for nft_mint in &step.nft_mints {
+                loop_accounts.push(spl_associated_token_account::get_associated_token_address(&step.from, nft_mint));
+                loop_accounts.push(spl_associated_token_account::get_associated_token_address(&step.to, nft_mint));
+            }
+        }
+        
+        let rent = Rent::get()?;
+        let mut total_deficit: u64 = 0;
+        for account_info in account_info_iter {
+            if !loop_accounts.contains(account_info.key) {
+                msg!("Skipping {}: not a participant or token account of this trade loop", account_info.key);
+                continue;
+            }
+            
+            // Token accounts that do not exist yet are created during execution
+            if account_info.lamports() == 0 && account_info.data_is_empty() {
+                continue;
+            }
+            
+            let deficit_lamports = rent.minimum_balance(account_info.data_len())
+                .saturating_sub(account_info.lamports());
+            if deficit_lamports > 0 {
+                msg!("WARNING: {} is {} lamports below its rent-exempt minimum", account_info.key, deficit_lamports);
+                sol_log_data(&[&RentDeficit { account: *account_info.key, deficit_lamports }.try_to_vec()?]);
+                total_deficit = total_deficit.saturating_add(deficit_lamports);
+            }
+        }
+        
+        let summary = RentSummary { all_exempt: total_deficit == 0, total_deficit };
+        msg!("Rent exemption check of trade loop {:?}: all_exempt={} total_deficit={}", 
+             trade_loop.trade_id, summary.all_exempt, summary.total_deficit);
+        sol_log_data(&[&summary.try_to_vec()?]);
+        
+        Ok(())
+    }
+
+    /// Process TransferTradeAuthority instruction
+    pub fn process_transfer_trade_authority(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Prevent accidentally burning the authority
+        if new_authority == Pubkey::default() {
+            msg!("New authority cannot be the default pubkey");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the current trade loop authority can request a transfer
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Record the request with the earliest time it can be confirmed
+        let clock = Clock::get()?;
+        let confirmable_at = clock.unix_timestamp
+            .checked_add(TRADE_AUTHORITY_TRANSFER_DELAY_SECONDS)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        trade_loop.authority_transfer_request = Some((new_authority, confirmable_at));
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Requested trade loop authority transfer to {}, confirmable from {}", 
+             new_authority, confirmable_at);
+        
+        Ok(())
+    }
+
+    /// Process ConfirmTradeAuthorityTransfer instruction
+    pub fn process_confirm_trade_authority_transfer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let new_authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !new_authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let (new_authority, confirmable_at) = trade_loop.authority_transfer_request
+            .ok_or(SwapError::NoPendingAuthorityTransfer)?;
+        
+        // Only the requested authority can confirm the transfer
+        if new_authority != *new_authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Ensure the time-lock has elapsed
+        let clock = Clock::get()?;
+        if clock.unix_timestamp < confirmable_at {
+            msg!("Authority transfer can be confirmed from {}, current time {}", 
+                 confirmable_at, clock.unix_timestamp);
+            return Err(SwapError::AuthorityTransferTimelocked.into());
+        }
+        
+        let previous_authority = trade_loop.authority;
+        trade_loop.authority = new_authority;
+        trade_loop.authority_transfer_request = None;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop authority transferred from {} to {}", previous_authority, new_authority);
+        
+        Ok(())
+    }
+
+    /// Process SplitTradeLoop instruction
+    pub fn process_split_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        split_at_step: u8,
+        new_trade_id: [u8; 32],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let new_trade_loop_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can split it
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Bonds are held per step index of this loop
+        if trade_loop.execution_bond_lamports > 0 {
+            msg!("A bonded trade loop cannot be split");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // A loop can only be split once
+        if trade_loop.linked_loop.is_some() {
+            msg!("Trade loop is already linked to another loop");
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Both halves must contain at least one step
+        if split_at_step == 0 || split_at_step as usize >= trade_loop.steps.len() {
+            msg!("Split point {} must be within the {} steps of the loop", split_at_step, trade_loop.steps.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Splitting changes the trade terms, so it is only allowed before any approval
+        if trade_loop.steps.iter().any(|step| step.status != StepStatus::Created) {
+            msg!("Trade loop can only be split before any step is approved");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Escrow accounts are derived from the trade loop address, so moved steps cannot hold escrow
+        if trade_loop.steps[split_at_step as usize..].iter().any(|step| step.escrowed.iter().any(|escrowed| *escrowed)) {
+            msg!("Steps with escrowed NFTs cannot be moved to a new trade loop");
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the new trade loop account is the correct PDA for this authority and trade_id
+        let (expected_trade_loop_address, bump_seed) = utils::get_trade_loop_address(
+            &new_trade_id,
+            authority_info.key,
+            program_id,
+        );
+        
+        if new_trade_loop_info.key != &expected_trade_loop_address {
+            msg!("Trade loop account address mismatch. Expected: {}, Got: {}", 
+                 expected_trade_loop_address, new_trade_loop_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the new trade loop account already exists
+        if new_trade_loop_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let moved_steps = trade_loop.steps.split_off(split_at_step as usize);
+        let moved_step_count = moved_steps.len() as u8;
+        let history_split = (split_at_step as usize * STEP_HASH_HISTORY_DEPTH).min(trade_loop.step_hash_history.len());
+        let moved_hash_history = trade_loop.step_hash_history.split_off(history_split);
+        let limits_split = (split_at_step as usize).min(trade_loop.per_step_nft_limits.len());
+        let moved_nft_limits = trade_loop.per_step_nft_limits.split_off(limits_split);
+        trade_loop.allocated_step_count = split_at_step;
+        
+        // Create the new trade loop account as a PDA
+        let rent = Rent::from_account_info(rent_info)?;
+        let space = TradeLoop::LEN;
+        let seeds = &[b"trade_loop".as_ref(), new_trade_id.as_ref(), authority_info.key.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                new_trade_loop_info.key,
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                authority_info.clone(),
+                new_trade_loop_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        // Get current timestamp
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp as u64;
+        
+        // The new half shares the original loop's deadline and settings
+        let new_trade_loop = TradeLoop {
+            is_initialized: true,
+            trade_id: new_trade_id,
+            created_at: current_time,
+            expires_at: trade_loop.expires_at,
+            steps: moved_steps,
+            authority: *authority_info.key,
+            is_paused: false,
+            nonce: TradeLoop::initial_nonce(&new_trade_id, current_time, authority_info.key),
+            witness: trade_loop.witness,
+            authority_transfer_request: None,
+            linked_loop: Some(*trade_loop_info.key),
+            cancel_lockout_seconds: trade_loop.cancel_lockout_seconds,
+            all_approved_at: trade_loop.all_approved_at,
+            loop_tags: trade_loop.loop_tags,
+            recurring: None,
+            successor_loop: None,
+            tokenized_as: None,
+            offered_until_display: 0,
+            display_timezone_offset: 0,
+            deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+            applied_discount_bps: 0,
+            allocated_step_count: moved_step_count,
+            execution_condition: trade_loop.execution_condition,
+            step_hash_history: moved_hash_history,
+            execution_whitelist: trade_loop.execution_whitelist,
+            auto_execute: trade_loop.auto_execute,
+            commitment_mode: trade_loop.commitment_mode,
+            per_step_nft_limits: moved_nft_limits,
+            execution_bond_lamports: trade_loop.execution_bond_lamports,
+            applied_fee_bps: 0,
+        };
+        trade_loop.linked_loop = Some(*new_trade_loop_info.key);
+        
+        // Both halves together must still form a valid cycle
+        verify_trade_loop_cycle(&trade_loop)?;
+        verify_trade_loop_cycle(&new_trade_loop)?;
+        if !trade_loop.verify_linked_loop(&new_trade_loop) {
+            return Err(SwapError::TradeLoopVerificationFailed.into());
+        }
+        
+        // Serialize and store both trade loops
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        serialize_trade_loop(&new_trade_loop, new_trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::TradeLoopCreated {
+            trade_id: new_trade_id,
+            authority: *authority_info.key,
+            expires_at: new_trade_loop.expires_at,
+        })?;
+        
+        msg!("Split trade loop at step {}: {} steps remain, {} moved to {}", 
+             split_at_step, trade_loop.steps.len(), new_trade_loop.steps.len(), new_trade_loop_info.key);
+        
+        Ok(())
+    }
+
+    /// Process ComputeTradeId instruction
+    pub fn process_compute_trade_id(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        participants: Vec<Pubkey>,
+        nonce: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let creator_info = next_account_info(account_info_iter)?;
+        
+        let trade_id = utils::derive_trade_id(creator_info.key, &participants, nonce);
+        let (trade_loop_address, _) = utils::get_trade_loop_address(&trade_id, creator_info.key, program_id);
+        
+        msg!("Trade ID: {:?}", trade_id);
+        msg!("Trade loop address: {}", trade_loop_address);
+        
+        Ok(())
+    }
+
+    /// Process MergeSteps instruction
+    pub fn process_merge_steps(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index_a: u8,
+        step_index_b: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Bonds are held per step index, which merging would shift
+        if trade_loop.execution_bond_lamports > 0 {
+            msg!("Steps of a bonded trade loop cannot be merged");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Ensure both step indexes are valid and distinct
+        if step_index_a == step_index_b
+            || step_index_a as usize >= trade_loop.steps.len()
+            || step_index_b as usize >= trade_loop.steps.len()
+        {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        {
+            let step_a = &trade_loop.steps[step_index_a as usize];
+            let step_b = &trade_loop.steps[step_index_b as usize];
+            
+            // Only the sender of both steps can merge them
+            if step_a.from != *from_info.key || step_b.from != *from_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            // Both steps must go to the same recipient
+            if step_a.to != step_b.to {
+                msg!("Steps {} and {} have different recipients", step_index_a, step_index_b);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            // Only unapproved steps can be merged
+            if step_a.status != StepStatus::Created || step_b.status != StepStatus::Created {
+                msg!("Only steps that have not been approved can be merged");
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            // Cap the merged step at the per-step NFT limit the account was sized for
+            if step_a.nft_mints.len() + step_b.nft_mints.len() > MAX_NFTS_PER_STEP as usize {
+                msg!("Merged step would hold {} NFTs, the maximum is {}", 
+                     step_a.nft_mints.len() + step_b.nft_mints.len(), MAX_NFTS_PER_STEP);
+                return Err(SwapError::TooManyNftsInStep.into());
+            }
+            
+            // And the merged fungible legs at the per-step leg limit
+            if step_a.spl_token_legs.len() + step_b.spl_token_legs.len() > MAX_SPL_LEGS_PER_STEP as usize {
+                msg!("Merged step would have {} fungible legs, the maximum is {}", 
+                     step_a.spl_token_legs.len() + step_b.spl_token_legs.len(), MAX_SPL_LEGS_PER_STEP);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            // Check for duplicate NFTs across the two steps
+            if let Some(duplicate) = step_b.nft_mints.iter().find(|mint| step_a.nft_mints.contains(mint)) {
+                msg!("Duplicate NFT mint found: {}", duplicate);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        // Move step B's NFTs (with their escrow flags and values) into step A
+        let step_b = trade_loop.steps.remove(step_index_b as usize);
+        trade_loop.allocated_step_count = trade_loop.allocated_step_count.saturating_sub(1);
+        let merged_index = if step_index_a > step_index_b { step_index_a - 1 } else { step_index_a };
+        let step_a = &mut trade_loop.steps[merged_index as usize];
+        step_a.nft_mints.extend(step_b.nft_mints);
+        step_a.escrowed.extend(step_b.escrowed);
+        step_a.nft_values_lamports.extend(step_b.nft_values_lamports);
+        step_a.spl_token_legs.extend(step_b.spl_token_legs);
+        
+        // Step B's history goes with it; later steps shift down by one
+        let history_start = step_index_b as usize * STEP_HASH_HISTORY_DEPTH;
+        if trade_loop.step_hash_history.len() > history_start {
+            let history_end = (history_start + STEP_HASH_HISTORY_DEPTH).min(trade_loop.step_hash_history.len());
+            trade_loop.step_hash_history.drain(history_start..history_end);
+        }
+        trade_loop.record_step_hash(merged_index);
+        
+        // The merged step may hold both steps' NFTs, up to the packed layout's limit
+        if (step_index_b as usize) < trade_loop.per_step_nft_limits.len() {
+            let limit_b = trade_loop.per_step_nft_limits.remove(step_index_b as usize);
+            if let Some(limit_a) = trade_loop.per_step_nft_limits.get_mut(merged_index as usize) {
+                *limit_a = std::cmp::min(limit_a.saturating_add(limit_b), MAX_NFTS_PER_STEP);
+            }
+        }
+        
+        // If we have all expected steps, verify the loop still forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Merged step {} into step {} (now step {})", step_index_b, step_index_a, merged_index);
+        
+        Ok(())
+    }
+
+    /// Process SetGovernanceTokenMint instruction
+    pub fn process_set_governance_token_mint(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        governance_token_mint: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the config account is owned by this program
+        utils::verify_account_owner(config_info, program_id)?;
+        
+        // Verify that the provided config account matches the expected PDA
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the config data
+        let mut config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+        
+        // Ensure the config is initialized
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the authority is authorized to update the config
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.governance_token_mint = governance_token_mint;
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Governance token mint set to {:?}", governance_token_mint);
+        
+        Ok(())
+    }
+
+    /// Process ProposeGovernanceChange instruction
+    pub fn process_propose_governance_change(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        proposal_id: [u8; 16],
+        proposed_change: GovernedChange,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let proposer_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let proposer_token_account_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !proposer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Load the program config
+        let config = load_program_config(program_id, config_info)?;
+        
+        // Reject changes the config would not accept, so a passed proposal can always be applied
+        verify_governed_change(&proposed_change)?;
+        
+        // Only governance token holders can propose changes
+        if governance_token_balance(&config, proposer_info.key, proposer_token_account_info)? == 0 {
+            msg!("Proposer {} holds no governance tokens", proposer_info.key);
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // Verify the proposal account is the correct PDA and does not exist yet
+        let (expected_proposal_key, bump_seed) = utils::get_proposal_address(&proposal_id, program_id);
+        if proposal_info.key != &expected_proposal_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if proposal_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the proposal account as a PDA
+        let space = GovernanceProposal::get_space();
+        let seeds = &[b"proposal".as_ref(), proposal_id.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                proposer_info.key,
+                proposal_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                proposer_info.clone(),
+                proposal_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        let clock = Clock::get()?;
+        let deadline = clock.unix_timestamp
+            .checked_add(GOVERNANCE_VOTING_PERIOD_SECONDS)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        let proposal = GovernanceProposal {
+            is_initialized: true,
+            proposal_id,
+            proposed_change,
+            proposer: *proposer_info.key,
+            votes_for: 0,
+            votes_against: 0,
+            deadline,
+            finalized: false,
+        };
+        
+        // Serialize and store the proposal data
+        proposal.serialize(&mut *proposal_info.data.borrow_mut())?;
+        
+        msg!("Governance proposal {:?} created: {:?}, voting closes at {}", 
+             proposal_id, proposal.proposed_change, deadline);
+        
+        Ok(())
+    }
+
+    /// Process VoteOnProposal instruction
+    pub fn process_vote_on_proposal(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        proposal_id: [u8; 16],
+        in_favor: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let voter_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let vote_record_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let voter_token_account_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let vote_escrow_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !voter_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Load the proposal
+        let mut proposal = load_governance_proposal(program_id, proposal_info, &proposal_id)?;
+        
+        // Votes are only accepted until the deadline
+        let clock = Clock::get()?;
+        if proposal.finalized || clock.unix_timestamp >= proposal.deadline {
+            return Err(SwapError::GovernanceVotingClosed.into());
+        }
+        
+        // The vote weight is the voter's governance token balance
+        let config = load_program_config(program_id, config_info)?;
+        let weight = governance_token_balance(&config, voter_info.key, voter_token_account_info)?;
+        if weight == 0 {
+            msg!("Voter {} holds no governance tokens", voter_info.key);
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // Each voter can vote once; the vote record PDA can only be created once
+        let (expected_vote_record_key, bump_seed) = utils::get_vote_record_address(proposal_info.key, voter_info.key, program_id);
+        if vote_record_info.key != &expected_vote_record_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if vote_record_info.data_len() > 0 {
+            return Err(SwapError::AlreadyVoted.into());
+        }
+        
+        let space = VoteRecord::get_space();
+        let seeds = &[b"vote".as_ref(), proposal_info.key.as_ref(), voter_info.key.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                voter_info.key,
+                vote_record_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                voter_info.clone(),
+                vote_record_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        let vote_record = VoteRecord {
+            is_initialized: true,
+            in_favor,
+            weight,
+        };
+        vote_record.serialize(&mut *vote_record_info.data.borrow_mut())?;
+        
+        // Lock the voting tokens until the proposal is finalized
+        if config.governance_token_mint != Some(*mint_info.key) {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(vote_escrow_info, vote_record_info.key, mint_info.key)?;
+        utils::create_associated_token_account_if_needed(
+            voter_info,
+            vote_record_info,
+            mint_info,
+            vote_escrow_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_fungible_tokens(
+            voter_token_account_info,
+            vote_escrow_info,
+            voter_info,
+            token_program_info,
+            weight,
+        )?;
+        
+        // Tally the vote
+        if in_favor {
+            proposal.votes_for = proposal.votes_for.saturating_add(weight);
+        } else {
+            proposal.votes_against = proposal.votes_against.saturating_add(weight);
+        }
+        
+        // Serialize and store the updated proposal data
+        proposal.serialize(&mut *proposal_info.data.borrow_mut())?;
+        
+        msg!("Vote {} with weight {} recorded on proposal {:?} (for: {}, against: {})", 
+             if in_favor { "for" } else { "against" }, weight, proposal_id, 
+             proposal.votes_for, proposal.votes_against);
+        
+        Ok(())
+    }
+
+    /// Process WithdrawVoteTokens instruction
+    pub fn process_withdraw_vote_tokens(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        proposal_id: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let voter_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let vote_record_info = next_account_info(account_info_iter)?;
+        let vote_escrow_info = next_account_info(account_info_iter)?;
+        let voter_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !voter_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Tokens stay locked while the vote can still affect the outcome
+        let proposal = load_governance_proposal(program_id, proposal_info, &proposal_id)?;
+        if !proposal.finalized {
+            msg!("Proposal {:?} must be finalized before vote tokens are withdrawn", proposal_id);
+            return Err(SwapError::GovernanceVotingOpen.into());
+        }
+        
+        // Verify the vote record is the voter's record for this proposal
+        utils::verify_account_owner(vote_record_info, program_id)?;
+        let (expected_vote_record_key, bump_seed) = utils::get_vote_record_address(proposal_info.key, voter_info.key, program_id);
+        if vote_record_info.key != &expected_vote_record_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let vote_record = VoteRecord::try_from_slice(&vote_record_info.data.borrow())?;
+        if !vote_record.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the escrow belongs to the vote record and the destination to the voter
+        utils::verify_token_account_owner(vote_escrow_info)?;
+        let vote_escrow = spl_token::state::Account::unpack(&vote_escrow_info.data.borrow())?;
+        utils::verify_token_account_address(vote_escrow_info, vote_record_info.key, &vote_escrow.mint)?;
+        utils::verify_token_account_owner(voter_token_account_info)?;
+        let voter_token_account = spl_token::state::Account::unpack(&voter_token_account_info.data.borrow())?;
+        if voter_token_account.owner != *voter_info.key || voter_token_account.mint != vote_escrow.mint {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Return the tokens and close the escrow, then the vote record
+        utils::transfer_amount_and_close_escrow(
+            vote_escrow_info,
+            voter_token_account_info,
+            voter_info,
+            vote_record_info,
+            token_program_info,
+            vote_escrow.amount,
+            &[b"vote", proposal_info.key.as_ref(), voter_info.key.as_ref(), &[bump_seed]],
+        )?;
+        close_program_account(vote_record_info, voter_info)?;
+        
+        msg!("Returned {} governance tokens to {} from proposal {:?}", vote_escrow.amount, voter_info.key, proposal_id);
+        
+        Ok(())
+    }
+
+    /// Process FinalizeProposal instruction
+    pub fn process_finalize_proposal(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        proposal_id: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let caller_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !caller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Load the proposal
+        let mut proposal = load_governance_proposal(program_id, proposal_info, &proposal_id)?;
+        
+        if proposal.finalized {
+            return Err(SwapError::ProposalAlreadyFinalized.into());
+        }
+        
+        // Changes are only applied after the voting period
+        let clock = Clock::get()?;
+        if clock.unix_timestamp < proposal.deadline {
+            msg!("Voting on proposal {:?} closes at {}", proposal_id, proposal.deadline);
+            return Err(SwapError::GovernanceVotingOpen.into());
+        }
+        
+        proposal.finalized = true;
+        
+        if proposal.votes_for <= proposal.votes_against {
+            msg!("Governance proposal {:?} rejected ({} for, {} against)", 
+                 proposal_id, proposal.votes_for, proposal.votes_against);
+        } else if verify_governed_change(&proposal.proposed_change).is_err() {
+            // Proposals created before proposals were validated can hold an invalid change;
+            // rejecting it still finalizes the proposal so voters can reclaim their tokens
+            msg!("Governance proposal {:?} passed but its change is invalid and was rejected: {:?}", 
+                 proposal_id, proposal.proposed_change);
+        } else {
+            let mut config = load_program_config(program_id, config_info)?;
+            
+            match proposal.proposed_change {
+                GovernedChange::UpdateAuthority(new_authority) => {
+                    config.upgrade_authority = new_authority;
+                    config.pending_upgrade_authority = None;
+                    config.authority_transfer_expiry = 0;
+                }
+                GovernedChange::SetMaxParticipants(max_participants) => {
+                    config.max_participants = max_participants;
+                }
+                GovernedChange::UpdateFeeConfig(fee_basis_points, fee_recipient) => {
+                    config.fee_basis_points = fee_basis_points;
+                    config.fee_recipient = fee_recipient;
+                }
+                GovernedChange::EmergencyPause => {
+                    config.paused = true;
+                    config.paused_reason = Some(PauseReason::GovernanceOrder);
+                }
+            }
+            
+            // Serialize and store the updated config data
+            config.serialize(&mut *config_info.data.borrow_mut())?;
+            
+            msg!("Governance proposal {:?} passed ({} for, {} against) and was applied: {:?}", 
+                 proposal_id, proposal.votes_for, proposal.votes_against, proposal.proposed_change);
+        }
+        
+        // Serialize and store the updated proposal data
+        proposal.serialize(&mut *proposal_info.data.borrow_mut())?;
+        
+        Ok(())
+    }
+
+    /// Process ProposeCounterOffer instruction
+    pub fn process_propose_counter_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        alternative_nft_mints: Vec<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let proposer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let counter_offer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !proposer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // Ensure the counter-offer has a valid number of NFTs
+        if alternative_nft_mints.is_empty() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if alternative_nft_mints.len() > trade_loop.step_nft_limit(step_index) as usize {
+            msg!("Counter-offer has {} NFTs, maximum for step {} is {}", alternative_nft_mints.len(), step_index, trade_loop.step_nft_limit(step_index));
+            return Err(SwapError::TooManyNftsInStep.into());
+        }
+        
+        // Only the recipient of the step can counter the sender's offer
+        let step = &trade_loop.steps[step_index as usize];
+        if step.to != *proposer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Verify the counter-offer account is the correct PDA and no counter-offer is pending
+        let (expected_counter_offer_key, bump_seed) = utils::get_counter_offer_address(trade_loop_info.key, step_index, program_id);
+        if counter_offer_info.key != &expected_counter_offer_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if counter_offer_info.data_len() > 0 {
+            msg!("Step {} already has a pending counter-offer", step_index);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the counter-offer account as a PDA
+        let space = CounterOffer::get_space(alternative_nft_mints.len());
+        let step_index_bytes = step_index.to_le_bytes();
+        let seeds = &[b"counter".as_ref(), trade_loop_info.key.as_ref(), step_index_bytes.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                proposer_info.key,
+                counter_offer_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                proposer_info.clone(),
+                counter_offer_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        // The counter-offer lives as long as the trade loop itself
+        let counter_offer = CounterOffer {
+            is_initialized: true,
+            proposer: *proposer_info.key,
+            nft_mints: alternative_nft_mints,
+            expires_at: trade_loop.expires_at as i64,
+        };
+        
+        // Serialize and store the counter-offer data
+        counter_offer.serialize(&mut *counter_offer_info.data.borrow_mut())?;
+        
+        msg!("Counter-offer of {} NFTs proposed for step {} by {}", 
+             counter_offer.nft_mints.len(), step_index, proposer_info.key);
+        
+        Ok(())
+    }
+
+    /// Process AcceptCounterOffer instruction
+    pub fn process_accept_counter_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let counter_offer_info = next_account_info(account_info_iter)?;
+        let proposer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let guards = StepGuards::load(program_id, account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        {
+            let step = &trade_loop.steps[step_index as usize];
+            
+            // Ensure the signer is the sender of this step
+            if step.from != *from_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            // Refuse to replace NFTs that are still held in escrow
+            if step.escrowed.iter().any(|escrowed| *escrowed) {
+                msg!("Step {} has escrowed NFTs and cannot be amended", step_index);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Or whose token accounts are still frozen
+            if step.nfts_locked {
+                msg!("Step {} has locked NFTs and cannot be amended", step_index);
+                return Err(SwapError::StepNftsLocked.into());
+            }
+        }
+        
+        // Load the pending counter-offer
+        let counter_offer = load_counter_offer(program_id, trade_loop_info.key, counter_offer_info, step_index)?;
+        
+        if counter_offer.proposer != *proposer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        let clock = Clock::get()?;
+        if clock.unix_timestamp >= counter_offer.expires_at {
+            return Err(SwapError::CounterOfferExpired.into());
+        }
+        
+        // Verify that the sender owns all the NFTs the counter-offer asks for
+        verify_sender_owns_nfts(account_info_iter, from_info.key, &counter_offer.nft_mints)?;
+        verify_mints_not_blocked(guards.mint_blocklist.as_ref(), &counter_offer.nft_mints)?;
+        verify_nft_collections_on_chain(guards.collection_whitelist.as_ref(), accounts, &counter_offer.nft_mints)?;
+        verify_nft_floor_prices(guards.config.as_ref(), guards.collection_whitelist.as_ref(), accounts, &counter_offer.nft_mints)?;
+        
+        // Replace the NFTs, resetting the per-NFT escrow flags and agreed values
+        let step = &mut trade_loop.steps[step_index as usize];
+        step.escrowed = vec![false; counter_offer.nft_mints.len()];
+        step.nft_values_lamports = vec![0; counter_offer.nft_mints.len()];
+        step.nft_mints = counter_offer.nft_mints;
+        trade_loop.record_step_hash(step_index);
+        
+        // The trade terms changed, so every existing approval is invalidated
+        invalidate_approvals(&mut trade_loop, "counter-offer");
+        
+        // If we have all expected steps, verify the loop still forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        // The counter-offer has been applied, return its rent to the proposer
+        close_program_account(counter_offer_info, proposer_info)?;
+        
+        msg!("Counter-offer for step {} accepted by {}", step_index, from_info.key);
+        
+        Ok(())
+    }
+
+    /// Process RejectCounterOffer instruction
+    pub fn process_reject_counter_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let counter_offer_info = next_account_info(account_info_iter)?;
+        let proposer_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Ensure the signer is the sender of this step
+        if trade_loop.steps[step_index as usize].from != *from_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Load the pending counter-offer
+        let counter_offer = load_counter_offer(program_id, trade_loop_info.key, counter_offer_info, step_index)?;
+        
+        if counter_offer.proposer != *proposer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Delete the counter-offer, returning its rent to the proposer
+        close_program_account(counter_offer_info, proposer_info)?;
+        
+        msg!("Counter-offer for step {} rejected by {}", step_index, from_info.key);
+        
+        Ok(())
+    }
+
+    /// Process ProposeStepReplacement instruction
+    pub fn process_propose_step_replacement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        replacement_nft_mints: Vec<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let proposer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !proposer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // Ensure the proposal has a valid number of NFTs
+        if replacement_nft_mints.is_empty() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if replacement_nft_mints.len() > trade_loop.step_nft_limit(step_index) as usize {
+            msg!("Replacement has {} NFTs, maximum for step {} is {}", replacement_nft_mints.len(), step_index, trade_loop.step_nft_limit(step_index));
+            return Err(SwapError::TooManyNftsInStep.into());
+        }
+        
+        // The proposer must be an outside party
+        if trade_loop.steps.iter().any(|step| step.from == *proposer_info.key || step.to == *proposer_info.key) {
+            msg!("{} already participates in this trade loop", proposer_info.key);
+            return Err(SwapError::DuplicateParticipantAddress.into());
+        }
+        
+        // Blocked wallets cannot join the loop
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(proposer_info.key) {
+                msg!("Wallet {} is blocked from joining trade loops", proposer_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // Verify the proposal account is the correct PDA and no proposal is pending
+        let (expected_proposal_key, bump_seed) = utils::get_step_replacement_address(trade_loop_info.key, step_index, program_id);
+        if proposal_info.key != &expected_proposal_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if proposal_info.data_len() > 0 {
+            msg!("Step {} already has a pending replacement proposal", step_index);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the proposal account as a PDA
+        let space = StepReplacementProposal::get_space(replacement_nft_mints.len());
+        let step_index_bytes = step_index.to_le_bytes();
+        let seeds = &[b"step_replace".as_ref(), trade_loop_info.key.as_ref(), step_index_bytes.as_ref(), &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                proposer_info.key,
+                proposal_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                proposer_info.clone(),
+                proposal_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        // The proposal lives as long as the trade loop itself
+        let proposal = StepReplacementProposal {
+            is_initialized: true,
+            proposer: *proposer_info.key,
+            replacement_nft_mints,
+            expires_at: trade_loop.expires_at as i64,
+        };
+        
+        // Serialize and store the proposal data
+        proposal.serialize(&mut *proposal_info.data.borrow_mut())?;
+        
+        msg!("Replacement of step {} with {} NFTs proposed by {}", 
+             step_index, proposal.replacement_nft_mints.len(), proposer_info.key);
+        
+        Ok(())
+    }
+
+    /// Process AcceptStepReplacement instruction
+    pub fn process_accept_step_replacement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let old_owner_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let proposer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let guards = StepGuards::load(program_id, account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !old_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Terms can no longer change once any part of the loop has executed
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        {
+            let step = &trade_loop.steps[step_index as usize];
+            
+            // Ensure the signer is the sender of this step
+            if step.from != *old_owner_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            // Escrowed NFTs belong to the old owner and would go with the position
+            if step.escrowed.iter().any(|escrowed| *escrowed) {
+                msg!("Step {} has escrowed NFTs and cannot be replaced", step_index);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Or whose token accounts are still frozen
+            if step.nfts_locked {
+                msg!("Step {} has locked NFTs and cannot be replaced", step_index);
+                return Err(SwapError::StepNftsLocked.into());
+            }
+        }
+        
+        // Load the pending proposal
+        let proposal = load_step_replacement_proposal(program_id, trade_loop_info.key, proposal_info, step_index)?;
+        
+        if proposal.proposer != *proposer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        let clock = Clock::get()?;
+        if clock.unix_timestamp >= proposal.expires_at {
+            msg!("Replacement proposal for step {} expired at {}", step_index, proposal.expires_at);
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // The proposer may have joined the loop since proposing
+        let new_owner = proposal.proposer;
+        if trade_loop.steps.iter().any(|step| step.from == new_owner || step.to == new_owner) {
+            msg!("{} already participates in this trade loop", new_owner);
+            return Err(SwapError::DuplicateParticipantAddress.into());
+        }
+        
+        // Or been blocked
+        if let Some(blocklist) = &guards.blocklist {
+            if blocklist.is_blocked(&new_owner) {
+                msg!("Wallet {} is blocked from joining trade loops", new_owner);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // Or lack a valid KYC entry while KYC is required
+        verify_participant_identity(program_id, guards.config.as_ref(), accounts, &[&new_owner])?;
+        
+        // Verify that the proposer holds all the NFTs it offered
+        verify_sender_owns_nfts(account_info_iter, &new_owner, &proposal.replacement_nft_mints)?;
+        verify_mints_not_blocked(guards.mint_blocklist.as_ref(), &proposal.replacement_nft_mints)?;
+        verify_nft_collections_on_chain(guards.collection_whitelist.as_ref(), accounts, &proposal.replacement_nft_mints)?;
+        verify_nft_floor_prices(guards.config.as_ref(), guards.collection_whitelist.as_ref(), accounts, &proposal.replacement_nft_mints)?;
+        
+        // Replace the NFTs, resetting the per-NFT escrow flags and agreed values
+        let step = &mut trade_loop.steps[step_index as usize];
+        step.escrowed = vec![false; proposal.replacement_nft_mints.len()];
+        step.nft_values_lamports = vec![0; proposal.replacement_nft_mints.len()];
+        step.nft_mints = proposal.replacement_nft_mints;
+        // The proposer only offered NFTs, the old sender's fungible legs go with them
+        step.spl_token_legs.clear();
+        
+        // The proposer takes over both sending this step and receiving from the previous one
+        let old_owner = *old_owner_info.key;
+        for step in trade_loop.steps.iter_mut() {
+            if step.from == old_owner {
+                step.from = new_owner;
+                // The delegate was chosen by the old owner
+                step.delegate_approval = None;
+            }
+            if step.to == old_owner {
+                step.to = new_owner;
+            }
+        }
+        for index in 0..trade_loop.steps.len() as u8 {
+            let step = &trade_loop.steps[index as usize];
+            if step.from == new_owner || step.to == new_owner {
+                trade_loop.record_step_hash(index);
+            }
+        }
+        
+        // The trade terms changed, so every existing approval is invalidated
+        invalidate_approvals(&mut trade_loop, "step replacement");
+        
+        // If we have all expected steps, verify the loop still forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        // The proposal has been applied, return its rent to the proposer
+        close_program_account(proposal_info, proposer_info)?;
+        
+        utils::emit_event(&SwapEvent::StepOwnershipTransferred {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            old_owner,
+            new_owner,
+        })?;
+        
+        msg!("Step {} replaced: {} handed over to {}", step_index, old_owner, new_owner);
+        
+        Ok(())
+    }
+
+    /// Process RejectStepReplacement instruction
+    pub fn process_reject_step_replacement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let signer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let proposal_info = next_account_info(account_info_iter)?;
+        let proposer_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !signer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Load the pending proposal
+        let proposal = load_step_replacement_proposal(program_id, trade_loop_info.key, proposal_info, step_index)?;
+        
+        if proposal.proposer != *proposer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // The step's sender rejects the proposal, or the proposer withdraws it
+        if trade_loop.steps[step_index as usize].from != *signer_info.key && proposal.proposer != *signer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Delete the proposal, returning its rent to the proposer
+        close_program_account(proposal_info, proposer_info)?;
+        
+        msg!("Replacement proposal for step {} closed by {}", step_index, signer_info.key);
+        
+        Ok(())
+    }
+
+    /// Process BlockParticipant and UnblockParticipant instructions
+    pub fn process_set_participant_blocked(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        wallet: Pubkey,
+        blocked: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to manage the blocklist
+        let config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        // Verify that the provided blocklist account matches the expected PDA
+        let (expected_blocklist_key, bump_seed) = utils::get_blocklist_address(program_id);
+        if blocklist_info.key != &expected_blocklist_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let rent = Rent::get()?;
+        
+        // Load the blocklist, creating it on first use
+        let mut blocklist = if blocklist_info.data_len() == 0 {
+            if !blocked {
+                msg!("Wallet {} is not blocked", wallet);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            let space = BlockedAccounts::get_space(1);
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    blocklist_info.key,
+                    rent.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    blocklist_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"blocklist", &[bump_seed]]],
+            )?;
+            
+            BlockedAccounts {
+                is_initialized: true,
+                accounts: Vec::new(),
+            }
+        } else {
+            utils::verify_account_owner(blocklist_info, program_id)?;
+            BlockedAccounts::deserialize(&mut &blocklist_info.data.borrow()[..])?
+        };
+        
+        if blocked {
+            if blocklist.is_blocked(&wallet) {
+                msg!("Wallet {} is already blocked", wallet);
+                return Ok(());
+            }
+            blocklist.accounts.push(wallet);
+            
+            // Grow the account to fit the new entry
+            utils::reallocate_account(
+                blocklist_info,
+                authority_info,
+                system_program_info,
+                &rent,
+                BlockedAccounts::get_space(blocklist.accounts.len()),
+            )?;
+        } else {
+            if !blocklist.is_blocked(&wallet) {
+                msg!("Wallet {} is not blocked", wallet);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            blocklist.accounts.retain(|blocked_wallet| *blocked_wallet != wallet);
+            
+            // Shrink the account so the data stays an exact Borsh encoding
+            blocklist_info.realloc(BlockedAccounts::get_space(blocklist.accounts.len()), false)?;
+        }
+        
+        // Serialize and store the updated blocklist
+        blocklist.serialize(&mut *blocklist_info.data.borrow_mut())?;
+        
+        msg!("Wallet {} {} ({} wallets blocked)", wallet, 
+             if blocked { "blocked" } else { "unblocked" }, blocklist.accounts.len());
+        
+        Ok(())
+    }
+
+    /// Process BlockNftMint and UnblockNftMint instructions
+    pub fn process_set_nft_mint_blocked(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint: Pubkey,
+        blocked: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let mint_blocklist_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Only the upgrade authority can manage the mint blocklist
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Verify that the provided mint blocklist account matches the expected PDA
+        let (expected_mint_blocklist_key, bump_seed) = utils::get_mint_blocklist_address(program_id);
+        if mint_blocklist_info.key != &expected_mint_blocklist_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let rent = Rent::get()?;
+        
+        // Load the mint blocklist, creating it on first use
+        let mut mint_blocklist = if mint_blocklist_info.data_len() == 0 {
+            if !blocked {
+                msg!("Mint {} is not blocked", mint);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            let space = MintBlocklist::get_space(1);
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    mint_blocklist_info.key,
+                    rent.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    mint_blocklist_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"mint_blocklist", &[bump_seed]]],
+            )?;
+            
+            MintBlocklist {
+                is_initialized: true,
+                mints: Vec::new(),
+            }
+        } else {
+            utils::verify_account_owner(mint_blocklist_info, program_id)?;
+            MintBlocklist::deserialize(&mut &mint_blocklist_info.data.borrow()[..])?
+        };
+        
+        if blocked {
+            if mint_blocklist.is_blocked(&mint) {
+                msg!("Mint {} is already blocked", mint);
+                return Ok(());
+            }
+            mint_blocklist.mints.push(mint);
+            
+            // Grow the account to fit the new entry
+            utils::reallocate_account(
+                mint_blocklist_info,
+                authority_info,
+                system_program_info,
+                &rent,
+                MintBlocklist::get_space(mint_blocklist.mints.len()),
+            )?;
+        } else {
+            if !mint_blocklist.is_blocked(&mint) {
+                msg!("Mint {} is not blocked", mint);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            mint_blocklist.mints.retain(|blocked_mint| *blocked_mint != mint);
+            
+            // Shrink the account so the data stays an exact Borsh encoding
+            mint_blocklist_info.realloc(MintBlocklist::get_space(mint_blocklist.mints.len()), false)?;
+        }
+        
+        // Serialize and store the updated mint blocklist
+        mint_blocklist.serialize(&mut *mint_blocklist_info.data.borrow_mut())?;
+        
+        msg!("Mint {} {} ({} mints blocked)", mint, 
+             if blocked { "blocked" } else { "unblocked" }, mint_blocklist.mints.len());
+        
+        Ok(())
+    }
+
+    /// Process WhitelistCollection and UnwhitelistCollection instructions
+    pub fn process_set_collection_whitelisted(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        collection: Pubkey,
+        whitelisted: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let collection_whitelist_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Only the upgrade authority can manage the collection whitelist
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Verify that the provided collection whitelist account matches the expected PDA
+        let (expected_collection_whitelist_key, bump_seed) = utils::get_collection_whitelist_address(program_id);
+        if collection_whitelist_info.key != &expected_collection_whitelist_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let rent = Rent::get()?;
+        
+        // Load the collection whitelist, creating it on first use
+        let mut collection_whitelist = if collection_whitelist_info.data_len() == 0 {
+            if !whitelisted {
+                msg!("Collection {} is not whitelisted", collection);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            let space = CollectionWhitelist::get_space(1, 0);
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    collection_whitelist_info.key,
+                    rent.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    collection_whitelist_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"collection_whitelist", &[bump_seed]]],
+            )?;
+            
+            CollectionWhitelist {
+                is_initialized: true,
+                collections: Vec::new(),
+                price_feeds: Vec::new(),
+            }
+        } else {
+            utils::verify_account_owner(collection_whitelist_info, program_id)?;
+            CollectionWhitelist::deserialize(&mut &collection_whitelist_info.data.borrow()[..])?
+        };
+        
+        if whitelisted {
+            if collection_whitelist.is_whitelisted(&collection) {
+                msg!("Collection {} is already whitelisted", collection);
+                return Ok(());
+            }
+            collection_whitelist.collections.push(collection);
+            
+            // Grow the account to fit the new entry
+            utils::reallocate_account(
+                collection_whitelist_info,
+                authority_info,
+                system_program_info,
+                &rent,
+                CollectionWhitelist::get_space(collection_whitelist.collections.len(), collection_whitelist.price_feeds.len()),
+            )?;
+        } else {
+            if !collection_whitelist.is_whitelisted(&collection) {
+                msg!("Collection {} is not whitelisted", collection);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            collection_whitelist.collections.retain(|whitelisted_collection| *whitelisted_collection != collection);
+            collection_whitelist.price_feeds.retain(|(feed_collection, _)| *feed_collection != collection);
+            
+            // Shrink the account so the data stays an exact Borsh encoding
+            collection_whitelist_info.realloc(
+                CollectionWhitelist::get_space(collection_whitelist.collections.len(), collection_whitelist.price_feeds.len()),
+                false,
+            )?;
+        }
+        
+        // Serialize and store the updated collection whitelist
+        collection_whitelist.serialize(&mut *collection_whitelist_info.data.borrow_mut())?;
+        
+        msg!("Collection {} {} ({} collections whitelisted)", collection, 
+             if whitelisted { "whitelisted" } else { "removed from the whitelist" }, collection_whitelist.collections.len());
+        
+        Ok(())
+    }
+
+    /// Process SetStepMetadata and ClearStepMetadata instructions
+    pub fn process_set_step_metadata(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        metadata_uri: Option<[u8; 128]>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Ensure the signer is the sender of this step
+        if step.from != *from_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Once the step is approved its terms are fixed unless governance co-signs
+        if step.status != StepStatus::Created {
+            let governance_signed = find_program_config(program_id, accounts)?
+                .and_then(|config| config.governance)
+                .map(|governance| accounts.iter().any(|account| account.key == &governance && account.is_signer))
+                .unwrap_or(false);
+            
+            if !governance_signed {
+                msg!("Step {} metadata can only change after approval with a governance signature", step_index);
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+        }
+        
+        step.metadata_uri = metadata_uri;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        match metadata_uri {
+            Some(metadata_uri) => {
+                utils::emit_event(&SwapEvent::StepMetadataSet {
+                    trade_id: trade_loop.trade_id,
+                    step_index,
+                    metadata_uri,
+                })?;
+                msg!("Metadata set for trade step {}", step_index);
+            }
+            None => {
+                utils::emit_event(&SwapEvent::StepMetadataCleared {
+                    trade_id: trade_loop.trade_id,
+                    step_index,
+                })?;
+                msg!("Metadata cleared for trade step {}", step_index);
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Process VerifyAllNfts instruction
+    pub fn process_verify_all_nfts(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check every NFT instead of stopping at the first failure so clients can
+        // report all problems at once
+        let mut failure_mask: u64 = 0;
+        
+        for (step_index, step) in trade_loop.steps.iter().enumerate() {
+            for (nft_index, nft_mint) in step.nft_mints.iter().enumerate() {
+                let mint_info = next_account_info(account_info_iter)?;
+                let source_token_account_info = next_account_info(account_info_iter)?;
+                let metadata_info = next_account_info(account_info_iter)?;
+                
+                // Escrowed NFTs are expected in the escrow PDA's token account
+                let expected_owner = if step.escrowed.get(nft_index).copied().unwrap_or(false) {
+                    utils::get_escrow_address(trade_loop_info.key, nft_mint, program_id).0
+                } else {
+                    step.from
+                };
+                
+                if let Err(err) = verify_nft_for_step(nft_mint, &expected_owner, mint_info, source_token_account_info, metadata_info) {
+                    msg!("STEP {} NFT {} ({}) failed verification: {:?}", step_index, nft_index, nft_mint, err);
+                    
+                    // NFTs beyond the per-step limit share the step's last bit
+                    let bit = step_index * MAX_NFTS_PER_STEP as usize
+                        + std::cmp::min(nft_index, MAX_NFTS_PER_STEP as usize - 1);
+                    failure_mask |= 1u64 << bit;
+                }
+            }
+        }
+        
+        if failure_mask != 0 {
+            sol_log_data(&[&failure_mask.to_le_bytes()]);
+            return Err(SwapError::BulkVerificationFailed(failure_mask).into());
+        }
+        
+        msg!("All NFTs in trade loop {:?} verified", trade_loop.trade_id);
+        
+        Ok(())
+    }
+
+    /// Process ReopenCancelledLoop instruction
+    pub fn process_reopen_cancelled_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        trade_id: [u8; 32],
+        step_count: u8,
+        timeout_seconds: u64,
+    ) -> ProgramResult {
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Validate the step count and timeout against program and operator limits
+        validate_trade_loop_parameters(program_id, accounts, step_count, timeout_seconds)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // The account must be the creator's PDA for this trade_id
+        let (expected_trade_loop_address, _bump) = utils::get_trade_loop_address(
+            &trade_id,
+            authority_info.key,
+            program_id,
+        );
+        
+        if trade_loop_info.key != &expected_trade_loop_address {
+            msg!("Trade loop account address mismatch. Expected: {}, Got: {}", 
+                 expected_trade_loop_address, trade_loop_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Cancelled trade loops are zeroed out; anything else is still live
+        if !trade_loop_info.data.borrow().iter().all(|b| *b == 0) {
+            msg!("Trade loop {} has not been cancelled", trade_loop_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Blocked wallets cannot start new trade loops
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(authority_info.key) {
+                msg!("Wallet {} is blocked from creating trade loops", authority_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // The existing account must have the packed size and stay rent exempt
+        if trade_loop_info.data_len() != TradeLoop::LEN {
+            msg!("Trade loop account holds {} bytes, expected {}. Migrate it first", 
+                 trade_loop_info.data_len(), TradeLoop::LEN);
+            return Err(SwapError::AccountTooSmall.into());
+        }
+        
+        if !Rent::get()?.is_exempt(trade_loop_info.lamports(), trade_loop_info.data_len()) {
+            return Err(SwapError::NotRentExempt.into());
+        }
+        
+        // Get current timestamp
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp as u64;
+        
+        // Calculate expiration time with overflow protection
+        let expires_at = current_time.checked_add(timeout_seconds)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Re-initialize the trade loop in place; the nonce is derived from the new
+        // creation time so approvals for the cancelled loop cannot be replayed
+        let trade_loop = TradeLoop {
+            is_initialized: true,
+            trade_id,
+            created_at: current_time,
+            expires_at,
+            steps: Vec::with_capacity(step_count as usize),
+            authority: *authority_info.key,
+            is_paused: false,
+            nonce: TradeLoop::initial_nonce(&trade_id, current_time, authority_info.key),
+            witness: None,
+            authority_transfer_request: None,
+            linked_loop: None,
+            cancel_lockout_seconds: 0,
+            all_approved_at: None,
+            loop_tags: [None; MAX_LOOP_TAGS],
+            recurring: None,
+            successor_loop: None,
+            tokenized_as: None,
+            offered_until_display: 0,
+            display_timezone_offset: 0,
+            deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+            applied_discount_bps: 0,
+            allocated_step_count: step_count,
+            execution_condition: None,
+            step_hash_history: Vec::new(),
+            execution_whitelist: None,
+            auto_execute: false,
+            commitment_mode: false,
+            per_step_nft_limits: Vec::new(),
+            execution_bond_lamports: 0,
+            applied_fee_bps: 0,
+        };
+        
+        // Serialize and store the trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::TradeLoopCreated {
+            trade_id,
+            authority: *authority_info.key,
+            expires_at,
+        })?;
+        
+        msg!("Reopened cancelled trade loop with ID {:?}", trade_id);
+        
+        Ok(())
+    }
+
+    /// Process DelegateApproval and RevokeDelegateApproval instructions
+    pub fn process_set_delegate_approval(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        delegate_approval: Option<(Pubkey, i64)>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Only the sender of the step can delegate or revoke its approval
+        if step.from != *from_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if let Some((delegate, expiry)) = delegate_approval {
+            // Delegating only makes sense while the step still needs approval
+            if step.status != StepStatus::Created {
+                msg!("Step {} cannot be delegated. Status: {:?}", step_index, step.status);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            let clock = Clock::get()?;
+            if expiry <= clock.unix_timestamp {
+                msg!("Delegation expiry {} is not in the future", expiry);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            
+            msg!("Step {} approval delegated to {} until {}", step_index, delegate, expiry);
+        } else {
+            msg!("Step {} approval delegation revoked", step_index);
+        }
+        
+        step.delegate_approval = delegate_approval;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        Ok(())
+    }
+
+    /// Process BatchAddSteps instruction
+    pub fn process_batch_add_steps(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        steps: Vec<BatchStepData>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let guards = StepGuards::load(program_id, account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Keep the batch within the compute budget
+        if steps.is_empty() || steps.len() > MAX_BATCH_ADD_STEPS {
+            msg!("Batch must contain between 1 and {} steps. Got: {}", MAX_BATCH_ADD_STEPS, steps.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // The same NFT cannot be committed by more than one step of the batch
+        let mut unique_nfts = std::collections::HashSet::new();
+        for step in &steps {
+            for nft_mint in &step.nft_mints {
+                if !unique_nfts.insert(*nft_mint) {
+                    msg!("BATCH_FAILED: step {} repeats NFT mint {}", step.step_index, nft_mint);
+                    return Err(SwapError::InvalidInstructionData.into());
+                }
+            }
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Validate and add each step, stopping at the first failure
+        let added_steps: Vec<(u8, Pubkey)> = steps.iter().map(|step| (step.step_index, step.to)).collect();
+        for step in steps {
+            let step_index = step.step_index;
+            add_step_to_loop(program_id, accounts, &guards, &mut trade_loop, from_info.key, step_index, step.to, step.nft_mints, |nft_mints| {
+                verify_sender_owns_nfts(account_info_iter, from_info.key, nft_mints)
+            })
+                .map_err(|err| {
+                    msg!("BATCH_FAILED: step {} rejected: {:?}", step_index, err);
+                    err
+                })?;
+            deposit_execution_bond(program_id, accounts, &trade_loop, trade_loop_info.key, from_info, step_index)?;
+        }
+        
+        // If we have added all expected steps, verify the loop forms a valid cycle
+        if trade_loop.all_steps_added() {
+            verify_trade_loop_cycle(&trade_loop)?;
+            msg!("All steps added, trade loop forms a valid cycle");
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        for (step_index, to) in added_steps {
+            utils::emit_event(&SwapEvent::StepAdded {
+                trade_id: trade_loop.trade_id,
+                step_index,
+                from: *from_info.key,
+                to,
+            })?;
+            
+            msg!("Added trade step {} from {} to {}", step_index, from_info.key, to);
+        }
+        
+        Ok(())
+    }
+
+    /// Process SnapshotTradeLoop instruction
+    pub fn process_snapshot_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        snapshot_id: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let participant_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let snapshot_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !participant_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the authority and step participants can take snapshots
+        let is_participant = trade_loop.authority == *participant_info.key
+            || trade_loop.steps.iter().any(|step| step.from == *participant_info.key || step.to == *participant_info.key);
+        if !is_participant {
+            msg!("{} is not a participant in this trade loop", participant_info.key);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Verify the snapshot account is the correct PDA and has not been used yet
+        let (expected_snapshot_key, bump_seed) = utils::get_snapshot_address(trade_loop_info.key, snapshot_id, program_id);
+        if snapshot_info.key != &expected_snapshot_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if snapshot_info.data_len() > 0 {
+            msg!("Snapshot {} already exists", snapshot_id);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the snapshot account as a PDA
+        let space = TradeSnapshot::get_space();
+        let seeds = &[b"snapshot".as_ref(), trade_loop_info.key.as_ref(), &[snapshot_id], &[bump_seed]];
+        
+        invoke_signed(
+            &system_instruction::create_account(
+                participant_info.key,
+                snapshot_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                participant_info.clone(),
+                snapshot_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[seeds],
+        )?;
+        
+        let snapshot = TradeSnapshot {
+            is_initialized: true,
+            taken_at: Clock::get()?.unix_timestamp,
+            state_hash: hash(&trade_loop.try_to_vec()?).to_bytes(),
+            snapshot_id,
+        };
+        
+        // Serialize and store the snapshot data
+        snapshot.serialize(&mut *snapshot_info.data.borrow_mut())?;
+        
+        sol_log_data(&[&snapshot.state_hash]);
+        
+        msg!("Snapshot {} of trade loop {:?} taken by {}", snapshot_id, trade_loop.trade_id, participant_info.key);
+        
+        Ok(())
+    }
+
+    /// Process VerifySnapshot instruction
+    pub fn process_verify_snapshot(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        snapshot_id: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let snapshot_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop and snapshot accounts are owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        utils::verify_account_owner(snapshot_info, program_id)?;
+        
+        // Verify that the provided snapshot account matches the expected PDA
+        let (expected_snapshot_key, _) = utils::get_snapshot_address(trade_loop_info.key, snapshot_id, program_id);
+        if snapshot_info.key != &expected_snapshot_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Deserialize the trade loop and snapshot data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        let snapshot = TradeSnapshot::try_from_slice(&snapshot_info.data.borrow())?;
+        
+        // Ensure both are initialized
+        if !trade_loop.is_initialized || !snapshot.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let state_hash = hash(&trade_loop.try_to_vec()?).to_bytes();
+        if state_hash != snapshot.state_hash {
+            msg!("Trade loop state changed since snapshot {} taken at {}", snapshot_id, snapshot.taken_at);
+            return Err(SwapError::SnapshotMismatch.into());
+        }
+        
+        msg!("Trade loop state matches snapshot {} taken at {}", snapshot_id, snapshot.taken_at);
+        
+        Ok(())
+    }
+
+    /// Process AddRelayer and RemoveRelayer instructions
+    pub fn process_set_relayer_authorized(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        relayer: Pubkey,
+        authorized: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to manage relayers
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        if authorized {
+            if config.is_authorized_relayer(&relayer) {
+                msg!("Relayer {} is already authorized", relayer);
+                return Ok(());
+            }
+            if config.authorized_relayers.len() >= MAX_AUTHORIZED_RELAYERS {
+                msg!("Cannot authorize more than {} relayers", MAX_AUTHORIZED_RELAYERS);
+                return Err(SwapError::TooManyRelayers.into());
+            }
+            config.authorized_relayers.push(relayer);
+            
+            // Config accounts created before relayers existed are too small to hold the list
+            if config_info.data_len() < ProgramConfig::get_space() {
+                utils::reallocate_account(
+                    config_info,
+                    authority_info,
+                    system_program_info,
+                    &Rent::get()?,
+                    ProgramConfig::get_space(),
+                )?;
+            }
+        } else {
+            if !config.is_authorized_relayer(&relayer) {
+                msg!("Relayer {} is not authorized", relayer);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            config.authorized_relayers.retain(|authorized_relayer| *authorized_relayer != relayer);
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Relayer {} {} ({} relayers authorized)", relayer, 
+             if authorized { "authorized" } else { "removed" }, config.authorized_relayers.len());
+        
+        Ok(())
+    }
+
+    /// Process FundFeeVault instruction
+    pub fn process_fund_fee_vault(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let funder_info = next_account_info(account_info_iter)?;
+        let fee_vault_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !funder_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify that the provided fee vault account matches the expected PDA
+        let (expected_fee_vault_key, _) = utils::get_fee_vault_address(program_id);
+        if fee_vault_info.key != &expected_fee_vault_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if amount == 0 {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        invoke(
+            &system_instruction::transfer(funder_info.key, fee_vault_info.key, amount),
+            &[
+                funder_info.clone(),
+                fee_vault_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        
+        msg!("Deposited {} lamports into the fee vault (balance: {})", amount, fee_vault_info.lamports());
+        
+        Ok(())
+    }
+
+    /// Process InitializeFeeVault instruction
+    pub fn process_initialize_fee_vault(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let fee_vault_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify that the provided fee vault account matches the expected PDA
+        let (expected_fee_vault_key, _) = utils::get_fee_vault_address(program_id);
+        if fee_vault_info.key != &expected_fee_vault_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let required_lamports = Rent::get()?.minimum_balance(0).saturating_sub(fee_vault_info.lamports());
+        if required_lamports == 0 {
+            msg!("Fee vault is already initialized (balance: {})", fee_vault_info.lamports());
+            return Ok(());
+        }
+        
+        invoke(
+            &system_instruction::transfer(payer_info.key, fee_vault_info.key, required_lamports),
+            &[
+                payer_info.clone(),
+                fee_vault_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        
+        msg!("Fee vault initialized with {} lamports", required_lamports);
+        
+        Ok(())
+    }
+
+    /// Process DrainFeeVault instruction
+    pub fn process_drain_fee_vault(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount_lamports: u64,
+        recipient: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let fee_vault_info = next_account_info(account_info_iter)?;
+        let recipient_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Only the upgrade authority can withdraw from the fee vault
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Verify that the provided fee vault account matches the expected PDA
+        let (expected_fee_vault_key, bump_seed) = utils::get_fee_vault_address(program_id);
+        if fee_vault_info.key != &expected_fee_vault_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if recipient_info.key != &recipient {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Keep the vault rent exempt so it is never purged
+        let available = fee_vault_info.lamports().saturating_sub(Rent::get()?.minimum_balance(0));
+        let amount = amount_lamports.min(available);
+        if amount == 0 {
+            msg!("Fee vault holds {} spendable lamports", available);
+            return Err(SwapError::FeeVaultInsufficient.into());
+        }
+        
+        invoke_signed(
+            &system_instruction::transfer(fee_vault_info.key, recipient_info.key, amount),
+            &[
+                fee_vault_info.clone(),
+                recipient_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"fee_vault", &[bump_seed]]],
+        )?;
+        
+        msg!("Withdrew {} lamports from the fee vault to {} (balance: {})", amount, recipient, fee_vault_info.lamports());
+        
+        Ok(())
+    }
+
+    /// Process QueryFeeVaultBalance instruction
+    pub fn process_query_fee_vault_balance(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let fee_vault_info = next_account_info(account_info_iter)?;
+        
+        // Verify that the provided fee vault account matches the expected PDA
+        let (expected_fee_vault_key, _) = utils::get_fee_vault_address(program_id);
+        if fee_vault_info.key != &expected_fee_vault_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let balance = fee_vault_info.lamports();
+        msg!("Fee vault balance: {} lamports ({} spendable)", 
+             balance, balance.saturating_sub(Rent::get()?.minimum_balance(0)));
+        
+        Ok(())
+    }
+
+    /// Process SetLoopTags and ClearLoopTags instructions
+    pub fn process_set_loop_tags(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        tags: Vec<[u8; 32]>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer is the trade loop authority
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Tags are fixed once every step is approved
+        if !trade_loop.steps.is_empty() && trade_loop.is_ready_for_execution() {
+            msg!("Tags cannot change after the trade loop is fully approved");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if tags.len() > MAX_LOOP_TAGS {
+            msg!("Cannot attach more than {} tags. Provided: {}", MAX_LOOP_TAGS, tags.len());
+            return Err(SwapError::TooManyTags.into());
+        }
+        
+        trade_loop.loop_tags = [None; MAX_LOOP_TAGS];
+        for (slot, tag) in trade_loop.loop_tags.iter_mut().zip(tags.iter()) {
+            *slot = Some(*tag);
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop now has {} tags", tags.len());
+        
+        utils::emit_event(&SwapEvent::LoopTagsUpdated {
+            trade_id: trade_loop.trade_id,
+            tags,
+        })?;
+        
+        Ok(())
+    }
+
+    /// Process SetDeadlineMessage instruction
+    pub fn process_set_deadline_message(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        message: [u8; DEADLINE_MESSAGE_SIZE],
+        display_deadline: i64,
+        timezone_offset: i16,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer is the trade loop authority
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // The advisory deadline cannot promise more time than the loop actually has
+        if display_deadline < 0 || display_deadline as u64 > trade_loop.expires_at {
+            msg!("Display deadline {} must be between 0 and the loop expiry {}", display_deadline, trade_loop.expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if timezone_offset.unsigned_abs() > MAX_TIMEZONE_OFFSET_MINUTES as u16 {
+            msg!("Timezone offset {} exceeds {} minutes from UTC", timezone_offset, MAX_TIMEZONE_OFFSET_MINUTES);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        trade_loop.offered_until_display = display_deadline;
+        trade_loop.display_timezone_offset = timezone_offset;
+        trade_loop.deadline_message = message;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Display deadline set to {} (UTC{:+} minutes)", display_deadline, timezone_offset);
+        
+        utils::emit_event(&SwapEvent::DeadlineMessageSet {
+            trade_id: trade_loop.trade_id,
+            display_deadline,
+            timezone_offset,
+            message,
+        })?;
+        
+        Ok(())
+    }
+
+    /// Process VerifyLoopIntegrity instruction
+    pub fn process_verify_loop_integrity(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        if let Err(integrity_error) = trade_loop.verify_loop_detailed() {
+            msg!("Trade loop {} is not a valid cycle: {}", trade_loop_info.key, integrity_error);
+            sol_log_data(&[&integrity_error.try_to_vec()?]);
+            return Err(SwapError::TradeLoopVerificationFailed.into());
+        }
+        
+        msg!("Trade loop {} forms a valid cycle", trade_loop_info.key);
+        
+        Ok(())
+    }
+
+    /// Process ReorderSteps instruction
+    pub fn process_reorder_steps(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_order: Vec<u8>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer is the trade loop authority
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Bonds are held per step index, which reordering would shift
+        if trade_loop.execution_bond_lamports > 0 {
+            msg!("Steps of a bonded trade loop cannot be reordered");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Ensure the new order is a permutation of the current step indexes
+        let step_count = trade_loop.steps.len();
+        if new_order.len() != step_count {
+            msg!("Expected {} step indexes, got {}", step_count, new_order.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        let mut seen = vec![false; step_count];
+        for &index in &new_order {
+            if index as usize >= step_count || seen[index as usize] {
+                msg!("Step order is not a permutation of 0..{}", step_count);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            seen[index as usize] = true;
+        }
+        
+        // Approvals are given against the current order, so it is fixed once any exist
+        if trade_loop.steps.iter().any(|step| matches!(step.status, StepStatus::Approved | StepStatus::Executed)) {
+            msg!("Steps can only be reordered before any step is approved");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        trade_loop.steps = new_order.iter()
+            .map(|&index| trade_loop.steps[index as usize].clone())
+            .collect();
+        
+        // Step hash histories are keyed by step index, so they move with their steps
+        if !trade_loop.step_hash_history.is_empty() {
+            trade_loop.step_hash_history.resize(step_count * STEP_HASH_HISTORY_DEPTH, [0; 8]);
+            let histories: Vec<&[[u8; 8]]> = trade_loop.step_hash_history.chunks(STEP_HASH_HISTORY_DEPTH).collect();
+            trade_loop.step_hash_history = new_order.iter()
+                .flat_map(|&index| histories[index as usize].iter().copied())
+                .collect();
+        }
+        
+        // Per-step NFT limits are keyed by step index, so they move with their steps
+        if !trade_loop.per_step_nft_limits.is_empty() {
+            let nft_limits: Vec<u8> = (0..step_count as u8).map(|index| trade_loop.step_nft_limit(index)).collect();
+            trade_loop.per_step_nft_limits = new_order.iter().map(|&index| nft_limits[index as usize]).collect();
+        }
+        
+        // The reordered steps must still form a valid cycle
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepsReordered {
+            trade_id: trade_loop.trade_id,
+            new_order,
+        })?;
+        
+        msg!("Reordered {} steps", step_count);
+        
+        Ok(())
+    }
+
+    /// Process AtomicSwapTwoParty instruction
+    pub fn process_atomic_swap_two_party(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        alice_nft_mint: Pubkey,
+        bob_nft_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let alice_info = next_account_info(account_info_iter)?;
+        let bob_info = next_account_info(account_info_iter)?;
+        let alice_side = TwoPartySwapSide {
+            mint_info: next_account_info(account_info_iter)?,
+            source_token_account_info: next_account_info(account_info_iter)?,
+            escrow_authority_info: next_account_info(account_info_iter)?,
+            escrow_token_account_info: next_account_info(account_info_iter)?,
+            destination_token_account_info: next_account_info(account_info_iter)?,
+        };
+        let bob_side = TwoPartySwapSide {
+            mint_info: next_account_info(account_info_iter)?,
+            source_token_account_info: next_account_info(account_info_iter)?,
+            escrow_authority_info: next_account_info(account_info_iter)?,
+            escrow_token_account_info: next_account_info(account_info_iter)?,
+            destination_token_account_info: next_account_info(account_info_iter)?,
+        };
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !alice_info.is_signer || !bob_info.is_signer {
+            return Err(SwapError::BothPartiesMustSign.into());
+        }
+        
+        // A wallet cannot swap with itself, and each side must give a different NFT
+        if alice_info.key == bob_info.key || alice_nft_mint == bob_nft_mint {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            for wallet in [alice_info.key, bob_info.key] {
+                if blocklist.is_blocked(wallet) {
+                    msg!("Wallet {} is blocked from trading", wallet);
+                    return Err(SwapError::ParticipantBlocked.into());
+                }
+            }
+        }
+        
+        // Both parties must pass KYC while it is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[alice_info.key, bob_info.key])?;
+        
+        let sides = [
+            (alice_info, bob_info, &alice_nft_mint, &alice_side),
+            (bob_info, alice_info, &bob_nft_mint, &bob_side),
+        ];
+        
+        // Verify both sides before moving anything
+        for (depositor_info, recipient_info, nft_mint, side) in sides {
+            side.verify(program_id, depositor_info.key, recipient_info.key, nft_mint)?;
+        }
+        
+        // Move both NFTs into escrow
+        for (depositor_info, _, _, side) in sides {
+            utils::create_associated_token_account_if_needed(
+                depositor_info,
+                side.escrow_authority_info,
+                side.mint_info,
+                side.escrow_token_account_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::transfer_nft(
+                side.source_token_account_info,
+                side.escrow_token_account_info,
+                depositor_info,
+                token_program_info,
+            )?;
+        }
+        
+        // Forward each NFT to the other party and return the escrow rent to the depositor
+        for (depositor_info, recipient_info, nft_mint, side) in sides {
+            utils::create_associated_token_account_if_needed(
+                recipient_info,
+                recipient_info,
+                side.mint_info,
+                side.destination_token_account_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::release_swap_escrow(
+                side.escrow_token_account_info,
+                side.destination_token_account_info,
+                depositor_info,
+                side.escrow_authority_info,
+                token_program_info,
+                depositor_info.key,
+                nft_mint,
+                program_id,
+            )?;
+        }
+        
+        utils::emit_event(&SwapEvent::TwoPartySwapExecuted {
+            alice: *alice_info.key,
+            bob: *bob_info.key,
+            alice_nft_mint,
+            bob_nft_mint,
+        })?;
+        
+        msg!("Swapped NFT {} from {} for NFT {} from {}", 
+             alice_nft_mint, alice_info.key, bob_nft_mint, bob_info.key);
+        
+        Ok(())
+    }
+
+    /// Process CreateOpenOffer instruction
+    pub fn process_create_open_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+        offered_nft_mint: Pubkey,
+        wanted_collection: Pubkey,
+        timeout_seconds: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Validate timeout to prevent excessively long offers
+        if timeout_seconds == 0 || timeout_seconds > MAX_TIMEOUT_SECONDS {
+            msg!("Offer timeout must be between 1 and {} seconds. Requested: {}", MAX_TIMEOUT_SECONDS, timeout_seconds);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(offerer_info.key) {
+                msg!("Wallet {} is blocked from trading", offerer_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // The offerer must pass KYC while it is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[offerer_info.key])?;
+        
+        // Verify that the provided offer account matches the expected PDA
+        let (expected_offer_key, bump_seed) = utils::get_open_offer_address(&offer_id, program_id);
+        if offer_info.key != &expected_offer_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the offer account already exists
+        if offer_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the mint account matches the expected mint
+        if mint_info.key != &offered_nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(mint_info)?;
+        
+        // Verify the source token account is the offerer's ATA and holds the NFT
+        utils::verify_token_account_owner(source_token_account_info)?;
+        utils::verify_token_account_address(source_token_account_info, offerer_info.key, mint_info.key)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+        
+        if source_token_account.owner != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // The offer account itself owns the escrow token account
+        utils::verify_token_account_address(escrow_token_account_info, offer_info.key, mint_info.key)?;
+        
+        // Create the offer account
+        let space = OpenOffer::get_space();
+        invoke_signed(
+            &system_instruction::create_account(
+                offerer_info.key,
+                offer_info.key,
+                Rent::from_account_info(rent_info)?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                offerer_info.clone(),
+                offer_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"open_offer", &offer_id, &[bump_seed]]],
+        )?;
+        
+        let clock = Clock::get()?;
+        let expires_at = clock.unix_timestamp.checked_add(timeout_seconds as i64)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        let offer = OpenOffer {
+            is_initialized: true,
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nft_mint,
+            wanted_collection,
+            expires_at,
+        };
+        offer.serialize(&mut *offer_info.data.borrow_mut())?;
+        
+        // Move the NFT into escrow
+        utils::create_associated_token_account_if_needed(
+            offerer_info,
+            offer_info,
+            mint_info,
+            escrow_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_nft(
+            source_token_account_info,
+            escrow_token_account_info,
+            offerer_info,
+            token_program_info,
+        )?;
+        
+        utils::emit_event(&SwapEvent::OpenOfferCreated {
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nft_mint,
+            wanted_collection,
+            expires_at,
+        })?;
+        
+        msg!("Open offer {:?} created for NFT {} against collection {}", offer_id, offered_nft_mint, wanted_collection);
+        
+        Ok(())
+    }
+
+    /// Process FulfillOpenOffer instruction
+    pub fn process_fulfill_open_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+        fulfiller_nft_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let fulfiller_info = next_account_info(account_info_iter)?;
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let offered_mint_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let fulfiller_destination_info = next_account_info(account_info_iter)?;
+        let fulfiller_mint_info = next_account_info(account_info_iter)?;
+        let fulfiller_metadata_info = next_account_info(account_info_iter)?;
+        let fulfiller_source_info = next_account_info(account_info_iter)?;
+        let offerer_destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !fulfiller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let (offer, bump_seed) = load_open_offer(program_id, offer_info, &offer_id)?;
+        
+        // Ensure the offer is still open
+        let clock = Clock::get()?;
+        if clock.unix_timestamp > offer.expires_at {
+            return Err(SwapError::OpenOfferExpired.into());
+        }
+        
+        if offer.offerer != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if offer.offerer == *fulfiller_info.key {
+            msg!("The offerer cannot fulfill their own offer");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(fulfiller_info.key) {
+                msg!("Wallet {} is blocked from trading", fulfiller_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // Both parties must hold a valid KYC entry at fulfillment while KYC is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[fulfiller_info.key, offerer_info.key])?;
+        
+        // Verify the escrowed NFT accounts
+        if offered_mint_info.key != &offer.offered_nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(escrow_token_account_info, offer_info.key, offered_mint_info.key)?;
+        utils::verify_token_account_address(fulfiller_destination_info, fulfiller_info.key, offered_mint_info.key)?;
+        
+        // Verify the fulfiller's NFT belongs to the wanted collection
+        if fulfiller_mint_info.key != &fulfiller_nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_nft_metadata(fulfiller_mint_info)?;
+        utils::verify_collection_membership(fulfiller_mint_info, fulfiller_metadata_info, &offer.wanted_collection)?;
+        
+        // Verify the fulfiller holds their NFT
+        utils::verify_token_account_owner(fulfiller_source_info)?;
+        utils::verify_token_account_address(fulfiller_source_info, fulfiller_info.key, fulfiller_mint_info.key)?;
+        
+        let fulfiller_source = spl_token::state::Account::unpack(&fulfiller_source_info.data.borrow())?;
+        
+        if fulfiller_source.owner != *fulfiller_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if fulfiller_source.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        utils::verify_token_account_address(offerer_destination_info, offerer_info.key, fulfiller_mint_info.key)?;
+        
+        // Send the fulfiller's NFT to the offerer
+        utils::create_associated_token_account_if_needed(
+            fulfiller_info,
+            offerer_info,
+            fulfiller_mint_info,
+            offerer_destination_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_nft(
+            fulfiller_source_info,
+            offerer_destination_info,
+            fulfiller_info,
+            token_program_info,
+        )?;
+        
+        // Release the escrowed NFT to the fulfiller
+        utils::create_associated_token_account_if_needed(
+            fulfiller_info,
+            fulfiller_info,
+            offered_mint_info,
+            fulfiller_destination_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_and_close_escrow(
+            escrow_token_account_info,
+            fulfiller_destination_info,
+            offerer_info,
+            offer_info,
+            token_program_info,
+            &[b"open_offer", &offer_id, &[bump_seed]],
+        )?;
+        
+        // Close the offer account, returning its rent to the offerer
+        close_program_account(offer_info, offerer_info)?;
+        
+        utils::emit_event(&SwapEvent::OpenOfferFulfilled {
+            offer_id,
+            fulfiller: *fulfiller_info.key,
+            fulfiller_nft_mint,
+        })?;
+        
+        msg!("Open offer {:?} fulfilled by {} with NFT {}", offer_id, fulfiller_info.key, fulfiller_nft_mint);
+        
+        Ok(())
+    }
+
+    /// Process CancelOpenOffer instruction
+    pub fn process_cancel_open_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let destination_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let (offer, bump_seed) = load_open_offer(program_id, offer_info, &offer_id)?;
+        
+        // Only the offerer can cancel
+        if offer.offerer != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Verify the escrowed NFT accounts
+        if mint_info.key != &offer.offered_nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(escrow_token_account_info, offer_info.key, mint_info.key)?;
+        utils::verify_token_account_address(destination_token_account_info, offerer_info.key, mint_info.key)?;
+        
+        // Return the NFT to the offerer
+        utils::create_associated_token_account_if_needed(
+            offerer_info,
+            offerer_info,
+            mint_info,
+            destination_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_and_close_escrow(
+            escrow_token_account_info,
+            destination_token_account_info,
+            offerer_info,
+            offer_info,
+            token_program_info,
+            &[b"open_offer", &offer_id, &[bump_seed]],
+        )?;
+        
+        // Close the offer account, returning its rent to the offerer
+        close_program_account(offer_info, offerer_info)?;
+        
+        utils::emit_event(&SwapEvent::OpenOfferCancelled { offer_id })?;
+        
+        msg!("Open offer {:?} cancelled", offer_id);
+        
+        Ok(())
+    }
+
+    /// Process CreatePartialFillOffer instruction
+    pub fn process_create_partial_fill_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+        offered_nfts: Vec<Pubkey>,
+        wanted_nfts_per_unit: Vec<Pubkey>,
+        minimum_fill: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Validate the bundle
+        if offered_nfts.is_empty() || offered_nfts.len() > MAX_PARTIAL_FILL_OFFER_NFTS {
+            msg!("A partial fill offer must escrow between 1 and {} NFTs. Requested: {}", MAX_PARTIAL_FILL_OFFER_NFTS, offered_nfts.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if offered_nfts.iter().enumerate().any(|(index, nft)| offered_nfts[..index].contains(nft)) {
+            msg!("Offered NFTs must be unique");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if wanted_nfts_per_unit.is_empty() || wanted_nfts_per_unit.len() > MAX_NFTS_PER_STEP as usize {
+            msg!("Between 1 and {} NFTs must be wanted per unit. Requested: {}", MAX_NFTS_PER_STEP, wanted_nfts_per_unit.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if minimum_fill == 0 || minimum_fill as usize > offered_nfts.len() {
+            msg!("Minimum fill must be between 1 and {}. Requested: {}", offered_nfts.len(), minimum_fill);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(offerer_info.key) {
+                msg!("Wallet {} is blocked from trading", offerer_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // Verify that the provided offer account matches the expected PDA
+        let (expected_offer_key, bump_seed) = utils::get_partial_fill_offer_address(offerer_info.key, &offer_id, program_id);
+        if offer_info.key != &expected_offer_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the offer account already exists
+        if offer_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the offer account
+        let space = PartialFillOffer::get_space(offered_nfts.len(), wanted_nfts_per_unit.len());
+        invoke_signed(
+            &system_instruction::create_account(
+                offerer_info.key,
+                offer_info.key,
+                Rent::from_account_info(rent_info)?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                offerer_info.clone(),
+                offer_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"partial_fill_offer", offerer_info.key.as_ref(), &offer_id, &[bump_seed]]],
+        )?;
+        
+        // Move each offered NFT into escrow
+        for nft_mint in &offered_nfts {
+            let mint_info = next_account_info(account_info_iter)?;
+            let source_token_account_info = next_account_info(account_info_iter)?;
+            let escrow_token_account_info = next_account_info(account_info_iter)?;
+            
+            // Verify the mint account matches the expected mint
+            if mint_info.key != nft_mint {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            // Verify this is actually an NFT (metadata check)
+            utils::verify_nft_metadata(mint_info)?;
+            
+            // Verify the source token account is the offerer's ATA and holds the NFT
+            utils::verify_token_account_owner(source_token_account_info)?;
+            utils::verify_token_account_address(source_token_account_info, offerer_info.key, mint_info.key)?;
+            
+            let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+            
+            if source_token_account.owner != *offerer_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            if source_token_account.amount < 1 {
+                return Err(SwapError::InsufficientFunds.into());
+            }
+            
+            // The offer account itself owns the escrow token accounts
+            utils::verify_token_account_address(escrow_token_account_info, offer_info.key, mint_info.key)?;
+            
+            utils::create_associated_token_account_if_needed(
+                offerer_info,
+                offer_info,
+                mint_info,
+                escrow_token_account_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::transfer_nft(
+                source_token_account_info,
+                escrow_token_account_info,
+                offerer_info,
+                token_program_info,
+            )?;
+        }
+        
+        let offer = PartialFillOffer {
+            is_initialized: true,
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nfts: offered_nfts.clone(),
+            wanted_nfts_per_unit: wanted_nfts_per_unit.clone(),
+            minimum_fill,
+        };
+        offer.serialize(&mut *offer_info.data.borrow_mut())?;
+        
+        utils::emit_event(&SwapEvent::PartialFillOfferCreated {
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nfts,
+            wanted_nfts_per_unit,
+            minimum_fill,
+        })?;
+        
+        msg!("Partial fill offer {:?} created with {} NFTs, minimum fill {}", offer_id, offer.offered_nfts.len(), minimum_fill);
+        
+        Ok(())
+    }
+
+    /// Process FillPartialOffer instruction
+    pub fn process_fill_partial_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+        take_indices: Vec<u8>,
+        give_nfts: Vec<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let filler_info = next_account_info(account_info_iter)?;
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !filler_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let (mut offer, bump_seed) = load_partial_fill_offer(program_id, offer_info, offerer_info.key, &offer_id)?;
+        
+        if offer.offerer != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if offer.offerer == *filler_info.key {
+            msg!("The offerer cannot fill their own offer");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(filler_info.key) {
+                msg!("Wallet {} is blocked from trading", filler_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // Both parties must hold a valid KYC entry at the fill while KYC is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[filler_info.key, offerer_info.key])?;
+        
+        // Validate the taken subset
+        if take_indices.len() < offer.minimum_fill as usize {
+            msg!("Fill takes {} NFTs, the offer's minimum fill is {}", take_indices.len(), offer.minimum_fill);
+            return Err(SwapError::BelowMinimumFill.into());
+        }
+        
+        for (position, index) in take_indices.iter().enumerate() {
+            if *index as usize >= offer.offered_nfts.len() {
+                msg!("NFT index {} out of range. The offer holds {} NFTs", index, offer.offered_nfts.len());
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+            if take_indices[..position].contains(index) {
+                msg!("NFT index {} is taken more than once", index);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        // The filler owes one NFT per wanted collection for each NFT taken
+        let unit_size = offer.wanted_nfts_per_unit.len();
+        if give_nfts.len() != take_indices.len() * unit_size {
+            msg!("Taking {} NFTs requires {} NFTs in return. Provided: {}", 
+                take_indices.len(), take_indices.len() * unit_size, give_nfts.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let seeds: &[&[u8]] = &[b"partial_fill_offer", offerer_info.key.as_ref(), &offer_id, &[bump_seed]];
+        
+        // Release the taken NFTs from escrow to the filler
+        let taken_nfts: Vec<Pubkey> = take_indices.iter().map(|index| offer.offered_nfts[*index as usize]).collect();
+        for nft_mint in &taken_nfts {
+            let mint_info = next_account_info(account_info_iter)?;
+            let escrow_token_account_info = next_account_info(account_info_iter)?;
+            let filler_destination_info = next_account_info(account_info_iter)?;
+            
+            // Verify the escrowed NFT accounts
+            if mint_info.key != nft_mint {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            utils::verify_token_account_address(escrow_token_account_info, offer_info.key, mint_info.key)?;
+            utils::verify_token_account_address(filler_destination_info, filler_info.key, mint_info.key)?;
+            
+            utils::create_associated_token_account_if_needed(
+                filler_info,
+                filler_info,
+                mint_info,
+                filler_destination_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::transfer_and_close_escrow(
+                escrow_token_account_info,
+                filler_destination_info,
+                offerer_info,
+                offer_info,
+                token_program_info,
+                seeds,
+            )?;
+        }
+        
+        // Send the filler's NFTs to the offerer
+        for (give_index, nft_mint) in give_nfts.iter().enumerate() {
+            let mint_info = next_account_info(account_info_iter)?;
+            let metadata_info = next_account_info(account_info_iter)?;
+            let filler_source_info = next_account_info(account_info_iter)?;
+            let offerer_destination_info = next_account_info(account_info_iter)?;
+            
+            // Verify the NFT belongs to the collection wanted at its position in the unit
+            if mint_info.key != nft_mint {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            utils::verify_nft_metadata(mint_info)?;
+            utils::verify_collection_membership(mint_info, metadata_info, &offer.wanted_nfts_per_unit[give_index % unit_size])?;
+            
+            // Verify the filler holds the NFT
+            utils::verify_token_account_owner(filler_source_info)?;
+            utils::verify_token_account_address(filler_source_info, filler_info.key, mint_info.key)?;
+            
+            let filler_source = spl_token::state::Account::unpack(&filler_source_info.data.borrow())?;
+            
+            if filler_source.owner != *filler_info.key {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            
+            if filler_source.amount < 1 {
+                return Err(SwapError::InsufficientFunds.into());
+            }
+            
+            utils::verify_token_account_address(offerer_destination_info, offerer_info.key, mint_info.key)?;
+            
+            utils::create_associated_token_account_if_needed(
+                filler_info,
+                offerer_info,
+                mint_info,
+                offerer_destination_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::transfer_nft(
+                filler_source_info,
+                offerer_destination_info,
+                filler_info,
+                token_program_info,
+            )?;
+        }
+        
+        // Drop the taken NFTs from the offer, closing it once nothing is left
+        offer.offered_nfts.retain(|nft_mint| !taken_nfts.contains(nft_mint));
+        let remaining = offer.offered_nfts.len() as u8;
+        if offer.offered_nfts.is_empty() {
+            close_program_account(offer_info, offerer_info)?;
+        } else {
+            offer_info.realloc(PartialFillOffer::get_space(offer.offered_nfts.len(), unit_size), false)?;
+            offer.serialize(&mut *offer_info.data.borrow_mut())?;
+        }
+        
+        utils::emit_event(&SwapEvent::PartialFillOfferFilled {
+            offer_id,
+            filler: *filler_info.key,
+            taken_nfts,
+            given_nfts: give_nfts,
+            remaining,
+        })?;
+        
+        msg!("Partial fill offer {:?}: {} NFTs taken by {}, {} remaining", offer_id, take_indices.len(), filler_info.key, remaining);
+        
+        Ok(())
+    }
+
+    /// Process CancelPartialFillOffer instruction
+    pub fn process_cancel_partial_fill_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let offer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let (offer, bump_seed) = load_partial_fill_offer(program_id, offer_info, offerer_info.key, &offer_id)?;
+        
+        // Only the offerer can cancel
+        if offer.offerer != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Return the remaining NFTs to the offerer
+        for nft_mint in &offer.offered_nfts {
+            let mint_info = next_account_info(account_info_iter)?;
+            let escrow_token_account_info = next_account_info(account_info_iter)?;
+            let destination_token_account_info = next_account_info(account_info_iter)?;
+            
+            // Verify the escrowed NFT accounts
+            if mint_info.key != nft_mint {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            utils::verify_token_account_address(escrow_token_account_info, offer_info.key, mint_info.key)?;
+            utils::verify_token_account_address(destination_token_account_info, offerer_info.key, mint_info.key)?;
+            
+            utils::create_associated_token_account_if_needed(
+                offerer_info,
+                offerer_info,
+                mint_info,
+                destination_token_account_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+            utils::transfer_and_close_escrow(
+                escrow_token_account_info,
+                destination_token_account_info,
+                offerer_info,
+                offer_info,
+                token_program_info,
+                &[b"partial_fill_offer", offerer_info.key.as_ref(), &offer_id, &[bump_seed]],
+            )?;
+        }
+        
+        // Close the offer account, returning its rent to the offerer
+        close_program_account(offer_info, offerer_info)?;
+        
+        utils::emit_event(&SwapEvent::PartialFillOfferCancelled { offer_id })?;
+        
+        msg!("Partial fill offer {:?} cancelled", offer_id);
+        
+        Ok(())
+    }
+
+    /// Process ListNftOffer instruction
+    pub fn process_list_nft_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+        offered_nft: Pubkey,
+        wanted_collection: Pubkey,
+        floor_premium_bps: i16,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let book_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        if !(-10_000..=10_000).contains(&floor_premium_bps) {
+            msg!("Floor premium must be between -10000 and 10000 basis points. Requested: {}", floor_premium_bps);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Blocked wallets cannot trade
+        if let Some(blocklist) = load_blocklist(program_id, blocklist_info)? {
+            if blocklist.is_blocked(offerer_info.key) {
+                msg!("Wallet {} is blocked from trading", offerer_info.key);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+        
+        // The offerer must pass KYC while it is required
+        let config = load_optional_program_config(program_id, config_info)?;
+        verify_participant_identity(program_id, config.as_ref(), accounts, &[offerer_info.key])?;
+        
+        // Verify that the provided offer book account matches the expected PDA
+        let (expected_book_key, bump_seed) = utils::get_offer_book_address(&wanted_collection, program_id);
+        if book_info.key != &expected_book_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the mint account matches the expected mint
+        if mint_info.key != &offered_nft {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(mint_info)?;
+        
+        // Verify the source token account is the offerer's ATA and holds the NFT
+        utils::verify_token_account_owner(source_token_account_info)?;
+        utils::verify_token_account_address(source_token_account_info, offerer_info.key, mint_info.key)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+        
+        if source_token_account.owner != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // The offer book itself owns the escrow token account
+        utils::verify_token_account_address(escrow_token_account_info, book_info.key, mint_info.key)?;
+        
+        let rent = Rent::from_account_info(rent_info)?;
+        
+        // Load the offer book, creating it on first use
+        let mut book = if book_info.data_len() == 0 {
+            let space = OfferBook::get_space(0);
+            invoke_signed(
+                &system_instruction::create_account(
+                    offerer_info.key,
+                    book_info.key,
+                    rent.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    offerer_info.clone(),
+                    book_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"offer_book", wanted_collection.as_ref(), &[bump_seed]]],
+            )?;
+            
+            OfferBook {
+                is_initialized: true,
+                ..OfferBook::default()
+            }
+        } else {
+            utils::verify_account_owner(book_info, program_id)?;
+            OfferBook::try_from_slice(&book_info.data.borrow())?
+        };
+        
+        if book.position(&offer_id).is_some() {
+            msg!("Offer {:?} is already listed", offer_id);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if book.entries.len() >= MAX_OFFER_BOOK_ENTRIES {
+            msg!("Offer book of collection {} is full ({} offers)", wanted_collection, MAX_OFFER_BOOK_ENTRIES);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let clock = Clock::get()?;
+        book.insert(NftOfferEntry {
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nft,
+            wanted_collection,
+            floor_premium_bps,
+            created_at: clock.unix_timestamp,
+        });
+        book.last_updated_slot = clock.slot;
+        
+        // Grow the account to fit the new entry
+        utils::reallocate_account(
+            book_info,
+            offerer_info,
+            system_program_info,
+            &rent,
+            OfferBook::get_space(book.entries.len()),
+        )?;
+        
+        // Serialize and store the updated offer book
+        book.serialize(&mut *book_info.data.borrow_mut())?;
+        
+        // Move the NFT into escrow
+        utils::create_associated_token_account_if_needed(
+            offerer_info,
+            book_info,
+            mint_info,
+            escrow_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_nft(
+            source_token_account_info,
+            escrow_token_account_info,
+            offerer_info,
+            token_program_info,
+        )?;
+        
+        utils::emit_event(&SwapEvent::NftOfferListed {
+            offer_id,
+            offerer: *offerer_info.key,
+            offered_nft,
+            wanted_collection,
+            floor_premium_bps,
+        })?;
+        
+        msg!("Offer {:?} listed NFT {} against collection {} at {} bps ({} offers in book)",
+             offer_id, offered_nft, wanted_collection, floor_premium_bps, book.entries.len());
+        
+        Ok(())
+    }
+
+    /// Process DelistNftOffer instruction
+    pub fn process_delist_nft_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let offerer_info = next_account_info(account_info_iter)?;
+        let book_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let destination_token_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !offerer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        let (mut book, position, bump_seed) = load_offer_book_entry(program_id, book_info, &offer_id)?;
+        let entry = book.entries.remove(position);
+        
+        // Only the offerer can delist
+        if entry.offerer != *offerer_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Verify the escrowed NFT accounts
+        if mint_info.key != &entry.offered_nft {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(escrow_token_account_info, book_info.key, mint_info.key)?;
+        utils::verify_token_account_address(destination_token_account_info, offerer_info.key, mint_info.key)?;
+        
+        // Shrink the account so the data stays an exact Borsh encoding
+        book.last_updated_slot = Clock::get()?.slot;
+        book_info.realloc(OfferBook::get_space(book.entries.len()), false)?;
+        book.serialize(&mut *book_info.data.borrow_mut())?;
+        
+        // Return the NFT to the offerer
+        utils::create_associated_token_account_if_needed(
+            offerer_info,
+            offerer_info,
+            mint_info,
+            destination_token_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_and_close_escrow(
+            escrow_token_account_info,
+            destination_token_account_info,
+            offerer_info,
+            book_info,
+            token_program_info,
+            &[b"offer_book", entry.wanted_collection.as_ref(), &[bump_seed]],
+        )?;
+        
+        utils::emit_event(&SwapEvent::NftOfferDelisted { offer_id })?;
+        
+        msg!("Offer {:?} delisted", offer_id);
+        
+        Ok(())
+    }
+
+    /// Process MatchOffers instruction
+    pub fn process_match_nft_offers(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_id_a: [u8; 16],
+        offer_id_b: [u8; 16],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let matcher_info = next_account_info(account_info_iter)?;
+        let book_a_info = next_account_info(account_info_iter)?;
+        let book_b_info = next_account_info(account_info_iter)?;
+        let offerer_a_info = next_account_info(account_info_iter)?;
+        let offerer_b_info = next_account_info(account_info_iter)?;
+        let mint_a_info = next_account_info(account_info_iter)?;
+        let metadata_a_info = next_account_info(account_info_iter)?;
+        let escrow_a_info = next_account_info(account_info_iter)?;
+        let destination_a_info = next_account_info(account_info_iter)?;
+        let mint_b_info = next_account_info(account_info_iter)?;
+        let metadata_b_info = next_account_info(account_info_iter)?;
+        let escrow_b_info = next_account_info(account_info_iter)?;
+        let destination_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program is paused
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !matcher_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the associated token program is actually the associated token program
+        if associated_token_program_info.key != &spl_associated_token_account::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Complementary offers want different collections, so they live in different books
+        if book_a_info.key == book_b_info.key {
+            msg!("Offers listed in the same book want the same collection");
+            return Err(SwapError::OffersNotMatchable.into());
+        }
+        
+        let (mut book_a, position_a, bump_seed_a) = load_offer_book_entry(program_id, book_a_info, &offer_id_a)?;
+        let (mut book_b, position_b, bump_seed_b) = load_offer_book_entry(program_id, book_b_info, &offer_id_b)?;
+        let offer_a = book_a.entries.remove(position_a);
+        let offer_b = book_b.entries.remove(position_b);
+        
+        if offer_a.offerer != *offerer_a_info.key || offer_b.offerer != *offerer_b_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if offer_a.offerer == offer_b.offerer {
+            msg!("An offerer cannot match their own offers");
+            return Err(SwapError::OffersNotMatchable.into());
+        }
+        
+        // The two asks must be compatible: one side's premium is covered by the other's discount
+        if offer_a.floor_premium_bps as i32 + offer_b.floor_premium_bps as i32 > 0 {
+            msg!("Floor premiums {} and {} bps do not cross", offer_a.floor_premium_bps, offer_b.floor_premium_bps);
+            return Err(SwapError::OffersNotMatchable.into());
+        }
+        
+        // Each offered NFT must belong to the collection the other offer wants
+        if mint_a_info.key != &offer_a.offered_nft || mint_b_info.key != &offer_b.offered_nft {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_collection_membership(mint_a_info, metadata_a_info, &offer_b.wanted_collection)?;
+        utils::verify_collection_membership(mint_b_info, metadata_b_info, &offer_a.wanted_collection)?;
+        
+        // Verify the escrow and destination token accounts
+        utils::verify_token_account_address(escrow_a_info, book_a_info.key, mint_a_info.key)?;
+        utils::verify_token_account_address(destination_a_info, offerer_b_info.key, mint_a_info.key)?;
+        utils::verify_token_account_address(escrow_b_info, book_b_info.key, mint_b_info.key)?;
+        utils::verify_token_account_address(destination_b_info, offerer_a_info.key, mint_b_info.key)?;
+        
+        // Remove both offers before any transfer
+        let slot = Clock::get()?.slot;
+        for (book, book_info) in [(&mut book_a, book_a_info), (&mut book_b, book_b_info)] {
+            book.last_updated_slot = slot;
+            book_info.realloc(OfferBook::get_space(book.entries.len()), false)?;
+            book.serialize(&mut *book_info.data.borrow_mut())?;
+        }
+        
+        // Swap the escrowed NFTs, returning each escrow's rent to its offerer
+        utils::create_associated_token_account_if_needed(
+            matcher_info,
+            offerer_b_info,
+            mint_a_info,
+            destination_a_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_and_close_escrow(
+            escrow_a_info,
+            destination_a_info,
+            offerer_a_info,
+            book_a_info,
+            token_program_info,
+            &[b"offer_book", offer_a.wanted_collection.as_ref(), &[bump_seed_a]],
+        )?;
+        
+        utils::create_associated_token_account_if_needed(
+            matcher_info,
+            offerer_a_info,
+            mint_b_info,
+            destination_b_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        utils::transfer_and_close_escrow(
+            escrow_b_info,
+            destination_b_info,
+            offerer_b_info,
+            book_b_info,
+            token_program_info,
+            &[b"offer_book", offer_b.wanted_collection.as_ref(), &[bump_seed_b]],
+        )?;
+        
+        utils::emit_event(&SwapEvent::NftOffersMatched { offer_id_a, offer_id_b })?;
+        
+        msg!("Offers {:?} and {:?} matched: NFT {} swapped for NFT {}", offer_id_a, offer_id_b, offer_a.offered_nft, offer_b.offered_nft);
+        
+        Ok(())
+    }
+
+    /// Process SetStepSolAmount instruction
+    pub fn process_set_step_sol_amount(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        sol_amount: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let from_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Enforce the operator's per-step SOL limit
+        let config = load_program_config(program_id, config_info)?;
+        if sol_amount > config.max_sol_per_step {
+            msg!("SOL amount exceeds the configured maximum ({}). Requested: {}", 
+                 config.max_sol_per_step, sol_amount);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        // Ensure the signer is the sender of this step
+        if step.from != *from_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // The terms are fixed once the step is approved
+        if step.status != StepStatus::Created {
+            msg!("SOL amount can only change before step {} is approved", step_index);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        step.sol_amount = sol_amount;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Step {} now pays {} lamports alongside its NFTs", step_index, sol_amount);
+        
+        Ok(())
+    }
+
+    /// Process SetMaxSolPerStep instruction
+    pub fn process_set_max_sol_per_step(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_sol_per_step: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.max_sol_per_step = max_sol_per_step;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Maximum SOL per step set to {} lamports", max_sol_per_step);
+        
+        Ok(())
+    }
+
+    /// Process BulkInitializeLoops instruction
+    /// Entries whose trade loop account already exists are skipped; any other
+    /// invalid entry fails the whole instruction
+    pub fn process_bulk_initialize_loops(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        loops: Vec<BulkLoopParams>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Keep the batch within the compute budget
+        if loops.is_empty() || loops.len() > MAX_BULK_INITIALIZE_LOOPS {
+            msg!("Bulk initialization must contain between 1 and {} loops. Got: {}", 
+                 MAX_BULK_INITIALIZE_LOOPS, loops.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let mut trade_loop_infos = Vec::with_capacity(loops.len());
+        for _ in &loops {
+            trade_loop_infos.push(next_account_info(account_info_iter)?);
+        }
+        
+        // The payer's cooldown account follows the trade loops
+        let optional_accounts: Vec<AccountInfo> = account_info_iter.cloned().collect();
+        
+        let loop_count = loops.len();
+        let mut failure_mask: u8 = 0;
+        for (index, (params, trade_loop_info)) in loops.into_iter().zip(trade_loop_infos).enumerate() {
+            // A zero trade ID asks for one derived from the payer, participants and entry index
+            let trade_id = if params.trade_id == [0u8; 32] {
+                utils::derive_trade_id(payer_info.key, &params.participants, index as u64)
+            } else {
+                params.trade_id
+            };
+            
+            // Skip entries whose PDA is already in use
+            let (expected_trade_loop_address, _) = utils::get_trade_loop_address(&trade_id, payer_info.key, program_id);
+            if trade_loop_info.key == &expected_trade_loop_address
+                && (trade_loop_info.data_len() > 0 || trade_loop_info.lamports() > 0)
+            {
+                msg!("Loop {} skipped: trade loop account {} already exists", index, trade_loop_info.key);
+                failure_mask |= 1 << index;
+                continue;
+            }
+            
+            let mut entry_accounts = vec![
+                payer_info.clone(),
+                trade_loop_info.clone(),
+                rent_info.clone(),
+                system_program_info.clone(),
+                blocklist_info.clone(),
+                config_info.clone(),
+            ];
+            entry_accounts.extend(optional_accounts.iter().cloned());
+            
+            Self::process_initialize_trade_loop(
+                program_id,
+                &entry_accounts,
+                trade_id,
+                params.participants,
+                params.timeout_seconds,
+                None,
+                0,
+                false,
+                None,
+            )?;
+        }
+        
+        msg!("Bulk initialization failure mask: {:#010b}", failure_mask);
+        sol_log_data(&[&[failure_mask]]);
+        
+        // Only fail the transaction when no loop could be created
+        if failure_mask.count_ones() as usize == loop_count {
+            return Err(SwapError::BulkOperationPartialFailure(failure_mask).into());
+        }
+        
+        Ok(())
+    }
+
+    /// Process InitializeBadgeMint instruction
+    pub fn process_initialize_badge_mint(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        metadata_uri: [u8; 128],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let badge_mint_info = next_account_info(account_info_iter)?;
+        let metadata_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let metadata_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the program accounts
+        if token_program_info.key != &spl_token::id()
+            || metadata_program_info.key != &utils::TOKEN_METADATA_PROGRAM_ID
+            || system_program_info.key != &solana_program::system_program::id()
+        {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        if let Some(badge_mint) = config.badge_mint {
+            msg!("Completion badge mint {} already exists", badge_mint);
+            return Err(SwapError::BadgeMintAlreadyInitialized.into());
+        }
+        
+        // The URI is zero-padded to 128 bytes
+        let uri_len = metadata_uri.iter().position(|&byte| byte == 0).unwrap_or(metadata_uri.len());
+        let uri = std::str::from_utf8(&metadata_uri[..uri_len])
+            .map_err(|_| SwapError::InvalidInstructionData)?;
+        if uri.is_empty() {
+            msg!("Badge metadata URI cannot be empty");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the badge mint and metadata addresses
+        let (expected_badge_mint, badge_mint_bump) = utils::get_badge_mint_address(program_id);
+        if badge_mint_info.key != &expected_badge_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        let (expected_metadata, _) = utils::get_metadata_address(badge_mint_info.key);
+        if metadata_info.key != &expected_metadata {
+            return Err(SwapError::InvalidMetadataAccount.into());
+        }
+        
+        let rent = Rent::from_account_info(rent_info)?;
+        let (_, config_bump) = utils::get_program_config_address(program_id);
+        
+        // Create the badge mint as a PDA owned by the token program
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                badge_mint_info.key,
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            &[
+                authority_info.clone(),
+                badge_mint_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"badge_mint", &[badge_mint_bump]]],
+        )?;
+        
+        // Badges are semi-fungible: no decimals and unlimited supply, minted by the config PDA
+        invoke(
+            &spl_token::instruction::initialize_mint2(
+                &spl_token::id(),
+                badge_mint_info.key,
+                config_info.key,
+                None,
+                0,
+            )?,
+            &[badge_mint_info.clone(), token_program_info.clone()],
+        )?;
+        
+        utils::create_metaplex_metadata(
+            metadata_info,
+            badge_mint_info,
+            config_info,
+            authority_info,
+            system_program_info,
+            metadata_program_info,
+            COMPLETION_BADGE_NAME,
+            COMPLETION_BADGE_SYMBOL,
+            uri,
+            &[b"config", &[config_bump]],
+        )?;
+        
+        config.badge_mint = Some(*badge_mint_info.key);
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &rent,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Completion badge mint {} initialized", badge_mint_info.key);
+        
+        Ok(())
+    }
+
+    /// Process LockStepNfts and UnlockStepNfts instructions
+    pub fn process_set_step_nfts_locked(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        locked: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let participant_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !participant_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer participates in the trade loop
+        if !trade_loop.steps.iter().any(|step| step.from == *participant_info.key || step.to == *participant_info.key) {
+            msg!("{} is not a participant in this trade loop", participant_info.key);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Ensure the step index is valid
+        if step_index as usize >= trade_loop.steps.len() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let expired = trade_loop.is_expired(Clock::get()?.unix_timestamp as u64);
+        let cancellation_eligible = trade_loop.is_cancellation_eligible();
+        let step = &mut trade_loop.steps[step_index as usize];
+        
+        if step.nfts_locked == locked {
+            msg!("Step {} NFTs are already {}", step_index, if locked { "locked" } else { "unlocked" });
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        if locked {
+            // Only committed steps of a live loop are worth protecting
+            if step.status != StepStatus::Approved {
+                return Err(SwapError::MissingApprovals.into());
+            }
+            if expired {
+                return Err(SwapError::TradeTimeoutExceeded.into());
+            }
+        } else if step.status == StepStatus::Approved && !expired && !cancellation_eligible {
+            msg!("Step {} NFTs stay locked until execution, expiry or a failed step", step_index);
+            return Err(SwapError::StepNftsLocked.into());
+        }
+        
+        for (nft_mint, escrowed) in step.nft_mints.iter().zip(step.escrowed.iter()) {
+            // Escrowed NFTs are already out of the sender's reach
+            if *escrowed {
+                continue;
+            }
+            
+            let mint_info = next_account_info(account_info_iter)?;
+            let token_account_info = next_account_info(account_info_iter)?;
+            
+            if mint_info.key != nft_mint {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            
+            utils::verify_token_account_owner(token_account_info)?;
+            utils::verify_token_account_address(token_account_info, &step.from, mint_info.key)?;
+            
+            set_nft_frozen(program_id, config_info, mint_info, token_account_info, token_program_info, locked)?;
+        }
+        
+        step.nfts_locked = locked;
+        
+        // Serialize and store the updated trade loop
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        if locked {
+            utils::emit_event(&SwapEvent::StepNftsLocked { trade_id: trade_loop.trade_id, step_index })?;
+            msg!("Locked NFTs of step {}", step_index);
+        } else {
+            utils::emit_event(&SwapEvent::StepNftsUnlocked { trade_id: trade_loop.trade_id, step_index })?;
+            msg!("Unlocked NFTs of step {}", step_index);
+        }
+        
+        Ok(())
+    }
+
+    /// Process GetProgramStats instruction
+    pub fn process_get_program_stats(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let stats_info = next_account_info(account_info_iter)?;
+        
+        // Verify the stats account is the stats PDA owned by this program
+        utils::verify_account_owner(stats_info, program_id)?;
+        let (expected_stats_key, _) = utils::get_stats_address(program_id);
+        if stats_info.key != &expected_stats_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let stats = ProgramStats::deserialize(&mut &stats_info.data.borrow()[..])
+            .map_err(|_| SwapError::InvalidAccountData)?;
+        if !stats.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        sol_log_data(&[&stats.try_to_vec()?]);
+        
+        msg!("Loops initialized: {}, steps executed: {}, NFTs traded: {}, fees: {} lamports, loops cancelled: {}",
+             stats.total_loops_initialized, stats.total_steps_executed, stats.total_nfts_traded,
+             stats.total_fees_lamports, stats.total_loops_cancelled);
+        
+        Ok(())
+    }
+
+    /// Process ResetProgramStats instruction
+    pub fn process_reset_program_stats(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let stats_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Only the upgrade authority can reset the stats
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Verify the stats account is the stats PDA
+        let (expected_stats_key, bump_seed) = utils::get_stats_address(program_id);
+        if stats_info.key != &expected_stats_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the stats account on first use
+        if stats_info.data_len() == 0 {
+            let space = ProgramStats::get_space();
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    stats_info.key,
+                    Rent::get()?.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    stats_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"stats", &[bump_seed]]],
+            )?;
+        } else {
+            utils::verify_account_owner(stats_info, program_id)?;
+        }
+        
+        let stats = ProgramStats {
+            is_initialized: true,
+            ..ProgramStats::default()
+        };
+        stats.serialize(&mut *stats_info.data.borrow_mut())?;
+        
+        msg!("Program stats reset");
+        
+        Ok(())
+    }
+
+    /// Process EnableRecurring instruction
+    pub fn process_enable_recurring(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_recurrences: u8,
+        interval_seconds: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can make it recur
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if max_recurrences == 0 {
+            msg!("At least one recurrence is required");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Each successor lives for one interval
+        if interval_seconds == 0 || interval_seconds > MAX_TIMEOUT_SECONDS {
+            msg!("Interval must be between 1 and {} seconds. Requested: {}", MAX_TIMEOUT_SECONDS, interval_seconds);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // A split loop only forms a cycle together with its other half
+        if trade_loop.linked_loop.is_some() {
+            msg!("Split trade loops cannot recur");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Renewal happens on execution, so it must be set up beforehand
+        if trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed) {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        trade_loop.recurring = Some(match trade_loop.recurring {
+            Some(recurring) => RecurringConfig {
+                max_recurrences,
+                interval_seconds,
+                ..recurring
+            },
+            None => RecurringConfig {
+                max_recurrences,
+                interval_seconds,
+                recurrence_count: 0,
+                next_loop_seed: trade_loop.nonce,
+            },
+        });
+        
+        // Serialize and store the updated trade loop
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop recurs up to {} times every {} seconds", max_recurrences, interval_seconds);
+        
+        Ok(())
+    }
+
+    /// Process InitializeDedupCache instruction
+    pub fn process_initialize_dedup_cache(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let dedup_cache_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to manage program accounts
+        let config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        // Verify that the provided dedup cache account matches the expected PDA
+        let (expected_dedup_cache_key, bump_seed) = utils::get_dedup_cache_address(program_id);
+        if dedup_cache_info.key != &expected_dedup_cache_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Check if the dedup cache already exists
+        if dedup_cache_info.data_len() > 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let space = ProcessedInstructions::get_space();
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                dedup_cache_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                authority_info.clone(),
+                dedup_cache_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"dedup_cache", &[bump_seed]]],
+        )?;
+        
+        let dedup_cache = ProcessedInstructions {
+            is_initialized: true,
+            hashes: [[0u8; 8]; DEDUP_CACHE_SIZE],
+            head: 0,
+        };
+        dedup_cache.serialize(&mut *dedup_cache_info.data.borrow_mut())?;
+        
+        msg!("Dedup cache initialized with {} entries", DEDUP_CACHE_SIZE);
+        
+        Ok(())
+    }
+
+    /// Process ValidateTopology instruction
+    pub fn process_validate_topology(participants: &[Pubkey]) -> ProgramResult {
+        let participant_count = participants.len();
+        let mut issue_count = 0u32;
+        
+        if participant_count < 2 || participant_count > MAX_PARTICIPANTS_PER_TRANSACTION as usize {
+            msg!("Topology has {} participants, must be between 2 and {}", 
+                 participant_count, MAX_PARTICIPANTS_PER_TRANSACTION);
+            issue_count += 1;
+        }
+        
+        // A wallet sending to itself
+        for (step_index, from) in participants.iter().enumerate() {
+            let to = &participants[(step_index + 1) % participant_count];
+            if from == to {
+                msg!("Step {} sends from {} to itself", step_index, from);
+                issue_count += 1;
+            }
+        }
+        
+        // Each wallet must send and receive exactly once
+        let mut wallets: Vec<Pubkey> = Vec::with_capacity(participant_count);
+        for participant in participants {
+            if !wallets.contains(participant) {
+                wallets.push(*participant);
+            }
+        }
+        for wallet in &wallets {
+            let occurrences = participants.iter().filter(|participant| *participant == wallet).count();
+            if occurrences > 1 {
+                msg!("Wallet {} sends and receives {} times, expected once", wallet, occurrences);
+                issue_count += 1;
+            }
+        }
+        
+        // Every wallet must lie on the single cycle formed by the ring
+        if participant_count <= MAX_PARTICIPANTS_PER_TRANSACTION as usize {
+            let mut successors = vec![Vec::new(); wallets.len()];
+            for (step_index, from) in participants.iter().enumerate() {
+                let to = &participants[(step_index + 1) % participant_count];
+                let from_node = wallets.iter().position(|wallet| wallet == from).unwrap_or_default();
+                let to_node = wallets.iter().position(|wallet| wallet == to).unwrap_or_default();
+                successors[from_node].push(to_node);
+            }
+            
+            let components = utils::strongly_connected_components(&successors);
+            if components.len() > 1 {
+                msg!("Topology splits into {} disconnected groups", components.len());
+                for component in &components {
+                    let members: Vec<&Pubkey> = component.iter().map(|node| &wallets[*node]).collect();
+                    msg!("Group: {:?}", members);
+                }
+                issue_count += 1;
+            }
+        }
+        
+        if issue_count > 0 {
+            msg!("Topology validation found {} issue(s)", issue_count);
+            return Err(SwapError::TradeLoopVerificationFailed.into());
+        }
+        
+        msg!("Topology of {} participants is a valid trade loop", participant_count);
+        
+        Ok(())
+    }
+
+    /// Process HealthCheck instruction
+    pub fn process_health_check(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        expected_version: u32,
+    ) -> ProgramResult {
+        // Deliberately no check_program_not_paused: monitors must see a paused program
+        let (config_pubkey, _) = utils::get_program_config_address(program_id);
+        let config_info = match accounts.first() {
+            Some(config_info) if config_info.owner == program_id && config_info.data_len() > 0 => config_info,
+            _ => {
+                msg!("Warning: program config account {} not provided or not created", config_pubkey);
+                return Ok(());
+            }
+        };
+        if config_info.key != &config_pubkey {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let config = ProgramConfig::unpack_versioned(&config_info.data.borrow())
+            .map_err(|_| SwapError::InvalidAccountData)?;
+        if !config.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let status = HealthStatus {
+            version: config.version,
+            paused: config.paused,
+            clock: Clock::get()?.unix_timestamp,
+            authority: config.upgrade_authority,
+        };
+        sol_log_data(&[&status.try_to_vec()?]);
+        
+        if config.paused {
+            msg!("Warning: program is paused");
+        }
+        
+        if config.version != expected_version {
+            msg!("Program version {} does not match expected version {}", config.version, expected_version);
+            return Err(SwapError::InvalidProgramVersion.into());
+        }
+        
+        msg!("Health check passed: version {}", config.version);
+        Ok(())
+    }
+
+    /// Process MigrateLegacyLoop instruction
+    pub fn process_migrate_legacy_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        original_trade_id: [u8; 32],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let legacy_loop_info = next_account_info(account_info_iter)?;
+        let new_loop_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the legacy trade loop address
+        utils::verify_account_owner(legacy_loop_info, program_id)?;
+        let (legacy_address, _) = utils::get_trade_loop_address_legacy(&original_trade_id, program_id);
+        if legacy_loop_info.key != &legacy_address {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Legacy accounts may predate the packed layout, so read the Borsh data directly
+        let trade_loop = TradeLoop::unpack_from_slice(&legacy_loop_info.data.borrow())?;
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can migrate it
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Executed steps have already moved NFTs; such loops are not migrated
+        if trade_loop.overall_status() != LoopStatus::Pending {
+            msg!("Only trade loops with no executed steps can be migrated");
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // Verify the new creator-scoped address
+        let (new_address, bump_seed) = utils::get_trade_loop_address(&trade_loop.trade_id, authority_info.key, program_id);
+        if new_loop_info.key != &new_address {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the new trade loop account at the packed size
+        let space = TradeLoop::LEN;
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                new_loop_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[
+                authority_info.clone(),
+                new_loop_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"trade_loop".as_ref(), trade_loop.trade_id.as_ref(), authority_info.key.as_ref(), &[bump_seed]]],
+        )?;
+        
+        // Copy the trade loop, then zero the legacy account and return its rent
+        serialize_trade_loop(&trade_loop, new_loop_info)?;
+        close_program_account(legacy_loop_info, authority_info)?;
+        
+        utils::emit_event(&SwapEvent::LoopMigrated {
+            old_address: *legacy_loop_info.key,
+            new_address,
+        })?;
+        
+        msg!("Migrated legacy trade loop {} to {}", legacy_loop_info.key, new_address);
+        
+        Ok(())
+    }
+
+    /// Process BatchApproveSteps instruction
+    pub fn process_batch_approve_steps(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        approvals: Vec<BatchApprovalEntry>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Keep the batch within the compute budget
+        if approvals.is_empty() || approvals.len() > MAX_BATCH_APPROVALS {
+            msg!("Batch approval must contain between 1 and {} entries. Got: {}", 
+                 MAX_BATCH_APPROVALS, approvals.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let mut trade_loop_infos = Vec::with_capacity(approvals.len());
+        for _ in &approvals {
+            trade_loop_infos.push(next_account_info(account_info_iter)?);
+        }
+        
+        // The optional program config account follows the trade loops
+        let optional_accounts: Vec<AccountInfo> = account_info_iter.cloned().collect();
+        
+        // A paused program fails the whole batch rather than every entry
+        check_program_not_paused(program_id, &optional_accounts, None)?;
+        
+        let approval_count = approvals.len();
+        let mut failure_mask: u8 = 0;
+        for (index, (entry, trade_loop_info)) in approvals.iter().zip(trade_loop_infos).enumerate() {
+            if trade_loop_info.key != &entry.trade_loop_key {
+                msg!("Approval {} skipped: expected trade loop {}, got {}", index, entry.trade_loop_key, trade_loop_info.key);
+                failure_mask |= 1 << index;
+                continue;
+            }
+            
+            // Only the step's own sender can approve in a batch
+            let is_sender = trade_loop_info.owner == program_id
+                && TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())
+                    .ok()
+                    .and_then(|trade_loop| trade_loop.steps.get(entry.step_index as usize).map(|step| step.from))
+                    == Some(*sender_info.key);
+            if !is_sender {
+                msg!("Approval {} skipped: {} is not the sender of step {}", index, sender_info.key, entry.step_index);
+                failure_mask |= 1 << index;
+                continue;
+            }
+            
+            let mut entry_accounts = vec![
+                sender_info.clone(),
+                trade_loop_info.clone(),
+                clock_info.clone(),
+            ];
+            entry_accounts.extend(optional_accounts.iter().cloned());
+            
+            // The approval only writes the trade loop once every check has passed
+            if let Err(err) = Self::process_approve_trade_step(program_id, &entry_accounts, entry.step_index) {
+                msg!("Approval {} skipped: {}", index, err);
+                failure_mask |= 1 << index;
+            }
+        }
+        
+        msg!("Batch approval failure mask: {:#010b}", failure_mask);
+        sol_log_data(&[&[failure_mask]]);
+        
+        // Only fail the transaction when no step could be approved
+        if failure_mask.count_ones() as usize == approval_count {
+            return Err(SwapError::BulkOperationPartialFailure(failure_mask).into());
+        }
+        
+        Ok(())
+    }
+
+    /// Process BatchSignedApprovals instruction
+    pub fn process_batch_signed_approvals(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        approvals: Vec<SignedApproval>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let submitter_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let instructions_sysvar_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !submitter_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // At most one approval per step
+        if approvals.is_empty() || approvals.len() > MAX_PARTICIPANTS_PER_TRANSACTION as usize {
+            msg!("Signed approvals must contain between 1 and {} entries. Got: {}", 
+                 MAX_PARTICIPANTS_PER_TRANSACTION, approvals.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        let mut approved_steps = Vec::with_capacity(approvals.len());
+        for approval in &approvals {
+            // Signed approvals come from the sender itself; delegates approve on chain
+            let step = trade_loop.steps.get(approval.step_index as usize)
+                .ok_or(SwapError::InvalidInstructionData)?;
+            if step.from != approval.signer {
+                msg!("{} is not the sender of step {}", approval.signer, approval.step_index);
+                return Err(SwapError::SignerNotStepOwner.into());
+            }
+            
+            let step_hash = step.hash8(approval.step_index);
+            let message = SignedApproval::message(trade_loop_info.key, approval.step_index, trade_loop.nonce, &step_hash);
+            if !utils::find_ed25519_verification(instructions_sysvar_info, &approval.signer, &message, &approval.signature)? {
+                msg!("No Ed25519 instruction verifies the approval of step {} by {}", approval.step_index, approval.signer);
+                return Err(SwapError::SignatureVerificationFailed.into());
+            }
+            
+            if approve_step_in_loop(&mut trade_loop, approval.step_index, &approval.signer, &clock)? {
+                approved_steps.push(approval.step_index);
+            }
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        for step_index in &approved_steps {
+            utils::emit_event(&SwapEvent::StepApproved {
+                trade_id: trade_loop.trade_id,
+                step_index: *step_index,
+            })?;
+        }
+        
+        msg!("FINAL APPROVAL: {} signed approvals applied by {}. These approvals cannot be revoked.", 
+             approved_steps.len(), submitter_info.key);
+        
+        Ok(())
+    }
+
+    /// Process CreateTradeLoopWithSignedApprovals instruction
+    pub fn process_create_trade_loop_with_signed_approvals(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        expires_at: u64,
+        nft_mints: Vec<Vec<Pubkey>>,
+        approvals: Vec<SignedApproval>,
+    ) -> ProgramResult {
+        // The step senders consent to their steps only through the signed approvals
+        if nft_mints.len() != participants.len() || approvals.len() != participants.len() {
+            msg!("Expected NFTs and a signed approval for each of the {} participants", participants.len());
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        let timeout_seconds = expires_at.checked_sub(current_time).filter(|timeout_seconds| *timeout_seconds > 0)
+            .ok_or(SwapError::TradeTimeoutExceeded)?;
+        Self::process_initialize_trade_loop(program_id, accounts, trade_id, participants, timeout_seconds, None, 0, false, None)?;
+        
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts; the first six are those of InitializeTradeLoop
+        let _payer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let _rent_info = next_account_info(account_info_iter)?;
+        let _system_program_info = next_account_info(account_info_iter)?;
+        let blocklist_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let mint_blocklist_info = next_account_info(account_info_iter)?;
+        let collection_whitelist_info = next_account_info(account_info_iter)?;
+        let instructions_sysvar_info = next_account_info(account_info_iter)?;
+        
+        let guards = StepGuards {
+            blocklist: load_blocklist(program_id, blocklist_info)?,
+            mint_blocklist: load_mint_blocklist(program_id, mint_blocklist_info)?,
+            collection_whitelist: load_collection_whitelist(program_id, collection_whitelist_info)?,
+            config: load_optional_program_config(program_id, config_info)?,
+        };
+        
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Fill in each reserved step, with the same checks as AddTradeStep
+        for (step_index, step_nft_mints) in nft_mints.into_iter().enumerate() {
+            let step = &trade_loop.steps[step_index];
+            let (from, to) = (step.from, step.to);
+            add_step_to_loop(program_id, accounts, &guards, &mut trade_loop, &from, step_index as u8, to, step_nft_mints, |nft_mints| {
+                verify_sender_owns_nfts(account_info_iter, &from, nft_mints)
+            })?;
+        }
+        
+        let clock = Clock::get()?;
+        for approval in &approvals {
+            // Signed approvals come from the sender itself
+            let step = trade_loop.steps.get(approval.step_index as usize)
+                .ok_or(SwapError::InvalidInstructionData)?;
+            if step.from != approval.signer {
+                msg!("{} is not the sender of step {}", approval.signer, approval.step_index);
+                return Err(SwapError::SignerNotStepOwner.into());
+            }
+            
+            let step_hash = step.hash8(approval.step_index);
+            let message = SignedApproval::creation_message(trade_loop_info.key, approval.step_index, trade_loop.expires_at, &step_hash);
+            if !utils::find_ed25519_verification(instructions_sysvar_info, &approval.signer, &message, &approval.signature)? {
+                msg!("No Ed25519 instruction verifies the approval of step {} by {}", approval.step_index, approval.signer);
+                return Err(SwapError::SignatureVerificationFailed.into());
+            }
+            
+            // Each step is approved exactly once
+            if !approve_step_in_loop(&mut trade_loop, approval.step_index, &approval.signer, &clock)? {
+                msg!("Step {} is approved more than once", approval.step_index);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        // Every step is added and approved, so the loop must form a valid cycle
+        verify_trade_loop_cycle(&trade_loop)?;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        for (step_index, step) in trade_loop.steps.iter().enumerate() {
+            utils::emit_event(&SwapEvent::StepAdded {
+                trade_id: trade_loop.trade_id,
+                step_index: step_index as u8,
+                from: step.from,
+                to: step.to,
+            })?;
+            utils::emit_event(&SwapEvent::StepApproved {
+                trade_id: trade_loop.trade_id,
+                step_index: step_index as u8,
+            })?;
+        }
+        
+        msg!("FINAL APPROVAL: trade loop created with {} signed approvals. These approvals cannot be revoked.", 
+             trade_loop.steps.len());
+        
+        Ok(())
+    }
+
+    /// Process ApplyOffchainApproval instruction
+    pub fn process_verify_signature_off_chain_approval(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        signature: [u8; 64],
+        message: [u8; OFFCHAIN_APPROVAL_MESSAGE_SIZE],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let submitter_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let instructions_sysvar_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !submitter_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // The message must approve the current terms of this step of this loop, and must not have expired
+        let step = trade_loop.steps.get(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        let step_sender = step.from;
+        let step_hash = step.hash8(step_index);
+        let expires_slot = u64::from_le_bytes(message[33..41].try_into().unwrap());
+        if message != offchain_approval_message(trade_loop_info.key, step_index, expires_slot, trade_loop.nonce, &step_hash) {
+            msg!("Signed message does not approve the current step {} of trade loop {}", step_index, trade_loop_info.key);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        if clock.slot > expires_slot {
+            msg!("Signed approval expired at slot {}, current slot {}", expires_slot, clock.slot);
+            return Err(SwapError::SignatureVerificationFailed.into());
+        }
+        
+        // Off-chain approvals come from the sender itself; delegates approve on chain
+        if !utils::find_ed25519_verification(instructions_sysvar_info, &step_sender, &message, &signature)? {
+            msg!("No Ed25519 instruction verifies the approval of step {} by {}", step_index, step_sender);
+            return Err(SwapError::SignatureVerificationFailed.into());
+        }
+        
+        let newly_approved = approve_step_in_loop(&mut trade_loop, step_index, &step_sender, &clock)?;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        if newly_approved {
+            utils::emit_event(&SwapEvent::StepApproved {
+                trade_id: trade_loop.trade_id,
+                step_index,
+            })?;
+        }
+        
+        msg!("FINAL APPROVAL: Step {} approved off-chain by {} and applied by {}. This approval cannot be revoked.", 
+             step_index, step_sender, submitter_info.key);
+        
+        Ok(())
+    }
+
+    /// Process DebugDumpInstruction instruction
+    #[cfg(feature = "debug")]
+    pub fn process_debug_dump_instruction(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        instruction_bytes: &[u8],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Restricted to the upgrade authority to keep it from being used to spam logs
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        let instruction = SwapInstruction::unpack(instruction_bytes)?;
+        msg!("{:?}", instruction);
+        
+        Ok(())
+    }
+
+    /// Process TokenizeTradeLoop instruction
+    pub fn process_tokenize_trade_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let position_mint_info = next_account_info(account_info_iter)?;
+        let metadata_info = next_account_info(account_info_iter)?;
+        let position_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let associated_token_program_info = next_account_info(account_info_iter)?;
+        let metadata_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the program accounts
+        if token_program_info.key != &spl_token::id()
+            || associated_token_program_info.key != &spl_associated_token_account::id()
+            || metadata_program_info.key != &utils::TOKEN_METADATA_PROGRAM_ID
+            || system_program_info.key != &solana_program::system_program::id()
+        {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Only the trade loop authority can tokenize it
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if let Some(tokenized_as) = trade_loop.tokenized_as {
+            msg!("Trade loop is already tokenized as {}", tokenized_as);
+            return Err(SwapError::AlreadyTokenized.into());
+        }
+        
+        // An executed loop has no position left to trade
+        if trade_loop.overall_status() == LoopStatus::Executed {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        // Verify the config, position mint and metadata addresses
+        let (config_key, config_bump) = utils::get_program_config_address(program_id);
+        if config_info.key != &config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        let (expected_position_mint, position_mint_bump) = utils::get_position_mint_address(trade_loop_info.key, program_id);
+        if position_mint_info.key != &expected_position_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        let (expected_metadata, _) = utils::get_metadata_address(position_mint_info.key);
+        if metadata_info.key != &expected_metadata {
+            return Err(SwapError::InvalidMetadataAccount.into());
+        }
+        utils::verify_token_account_address(position_account_info, authority_info.key, position_mint_info.key)?;
+        
+        let rent = Rent::from_account_info(rent_info)?;
+        
+        // Create the position mint as a PDA owned by the token program
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                position_mint_info.key,
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            &[
+                authority_info.clone(),
+                position_mint_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[b"position_mint", trade_loop_info.key.as_ref(), &[position_mint_bump]]],
+        )?;
+        
+        invoke(
+            &spl_token::instruction::initialize_mint2(
+                &spl_token::id(),
+                position_mint_info.key,
+                &config_key,
+                None,
+                0,
+            )?,
+            &[position_mint_info.clone(), token_program_info.clone()],
+        )?;
+        
+        // The metadata URI records which trade loop the NFT controls
+        utils::create_metaplex_metadata(
+            metadata_info,
+            position_mint_info,
+            config_info,
+            authority_info,
+            system_program_info,
+            metadata_program_info,
+            TRADE_POSITION_NAME,
+            TRADE_POSITION_SYMBOL,
+            &trade_loop_info.key.to_string(),
+            &[b"config", &[config_bump]],
+        )?;
+        
+        utils::create_associated_token_account_if_needed(
+            authority_info,
+            authority_info,
+            position_mint_info,
+            position_account_info,
+            token_program_info,
+            associated_token_program_info,
+            system_program_info,
+            rent_info,
+        )?;
+        
+        invoke_signed(
+            &spl_token::instruction::mint_to(
+                &spl_token::id(),
+                position_mint_info.key,
+                position_account_info.key,
+                &config_key,
+                &[],
+                1,
+            )?,
+            &[
+                position_mint_info.clone(),
+                position_account_info.clone(),
+                config_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[b"config", &[config_bump]]],
+        )?;
+        
+        // Revoke the mint authority so the supply stays at exactly one
+        invoke_signed(
+            &spl_token::instruction::set_authority(
+                &spl_token::id(),
+                position_mint_info.key,
+                None,
+                spl_token::instruction::AuthorityType::MintTokens,
+                &config_key,
+                &[],
+            )?,
+            &[
+                position_mint_info.clone(),
+                config_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[b"config", &[config_bump]]],
+        )?;
+        
+        // The program holds the authority until the NFT is redeemed
+        trade_loop.authority = config_key;
+        trade_loop.tokenized_as = Some(*position_mint_info.key);
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop {} tokenized as {}", trade_loop_info.key, position_mint_info.key);
+        
+        Ok(())
+    }
+
+    /// Process RedeemTokenizedPosition instruction
+    pub fn process_redeem_tokenized_position(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        tokenized_nft_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let holder_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let position_account_info = next_account_info(account_info_iter)?;
+        let position_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !holder_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // The mint must be the one this trade loop was tokenized as
+        if trade_loop.tokenized_as != Some(tokenized_nft_mint) || position_mint_info.key != &tokenized_nft_mint {
+            msg!("{} is not the position NFT of trade loop {}", tokenized_nft_mint, trade_loop_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the holder owns the position NFT
+        utils::verify_token_account_owner(position_account_info)?;
+        let position_account = spl_token::state::Account::unpack(&position_account_info.data.borrow())?;
+        if position_account.mint != tokenized_nft_mint
+            || position_account.owner != *holder_info.key
+            || position_account.amount != 1
+        {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        invoke(
+            &spl_token::instruction::burn(
+                &spl_token::id(),
+                position_account_info.key,
+                position_mint_info.key,
+                holder_info.key,
+                &[],
+                1,
+            )?,
+            &[
+                position_account_info.clone(),
+                position_mint_info.clone(),
+                holder_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        
+        // The holder takes over the trade loop
+        trade_loop.authority = *holder_info.key;
+        trade_loop.tokenized_as = None;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Position NFT {} redeemed, {} is now the trade loop authority", tokenized_nft_mint, holder_info.key);
+        
+        Ok(())
+    }
+
+    /// Process CancelTradeLoopV2 instruction
+    pub fn process_cancel_trade_loop_v2(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let canceller_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !canceller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Verify the canceller may cancel the trade loop in its current state
+        verify_cancellation_allowed(&trade_loop, canceller_info.key)?;
+        
+        let token_program_info = if trade_loop.has_escrowed_nfts() {
+            let token_program_info = next_account_info(account_info_iter)?;
+            
+            // Verify the token program is actually the token program
+            if token_program_info.key != &spl_token::id() {
+                return Err(SwapError::IncorrectProgramId.into());
+            }
+            
+            Some(token_program_info)
+        } else {
+            None
+        };
+        
+        let mut escrowed_step_count = 0;
+        let mut failure_mask: u16 = 0;
+        for (step_index, step) in trade_loop.steps.iter_mut().enumerate() {
+            if let Some(token_program_info) = token_program_info {
+                if step.escrowed.iter().any(|escrowed| *escrowed) {
+                    escrowed_step_count += 1;
+                    
+                    if !release_step_escrow(program_id, trade_loop_info.key, step, token_program_info, account_info_iter)? {
+                        msg!("Step {} escrow could not be fully released", step_index);
+                        failure_mask |= 1 << step_index;
+                    }
+                }
+            }
+            
+            step.status = StepStatus::Cancelled;
+        }
+        
+        if failure_mask != 0 {
+            msg!("Escrow release failure mask: {:#013b}", failure_mask);
+            sol_log_data(&[&failure_mask.to_le_bytes()]);
+            
+            // Fail only when no escrowed step could be released at all
+            if failure_mask.count_ones() == escrowed_step_count {
+                return Err(SwapError::EscrowReleasePartialFailure(failure_mask).into());
+            }
+            
+            // Keep the loop so the remaining escrowed NFTs can be released by a retry
+            serialize_trade_loop(&trade_loop, trade_loop_info)?;
+            msg!("Trade loop partially cancelled, retry to release the remaining escrowed NFTs");
+            return Ok(());
+        }
+        
+        // Every escrowed NFT is back with its sender, so the bonds are returned and the loop state can be wiped
+        refund_execution_bonds(program_id, trade_loop_info.key, &trade_loop, accounts)?;
+        trade_loop_info.data.borrow_mut().fill(0);
+        
+        utils::emit_event(&SwapEvent::TradeLoopCancelled { trade_id: trade_loop.trade_id })?;
+        
+        record_program_stats(program_id, accounts, |stats| {
+            stats.total_loops_cancelled = stats.total_loops_cancelled.saturating_add(1);
+        })?;
+        
+        msg!("Cancelled trade loop");
+        
+        Ok(())
+    }
+
+    /// Process SetMetadataUpdateAuthority instruction
+    pub fn process_set_metadata_update_authority(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint: Pubkey,
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let metadata_info = next_account_info(account_info_iter)?;
+        let metadata_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token metadata program
+        if metadata_program_info.key != &utils::TOKEN_METADATA_PROGRAM_ID {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to act for the config
+        let config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        // Verify the metadata belongs to the mint and is held by the config
+        let (expected_metadata, _) = utils::get_metadata_address(&mint);
+        if metadata_info.key != &expected_metadata || metadata_info.owner != &utils::TOKEN_METADATA_PROGRAM_ID {
+            return Err(SwapError::InvalidMetadataAccount.into());
+        }
+        let metadata = utils::MetaplexMetadata::deserialize(&mut &metadata_info.data.borrow()[..])
+            .map_err(|_| SwapError::InvalidMetadataAccount)?;
+        if metadata.update_authority != *config_info.key {
+            msg!("Metadata of {} is not held by the program config", mint);
+            return Err(SwapError::InvalidMetadataAccount.into());
+        }
+        
+        let (_, config_bump) = utils::get_program_config_address(program_id);
+        utils::set_metadata_update_authority(
+            metadata_info,
+            config_info,
+            metadata_program_info,
+            &new_authority,
+            &[b"config", &[config_bump]],
+        )?;
+        
+        msg!("Metadata update authority of {} set to {}", mint, new_authority);
+        
+        Ok(())
+    }
+
+    /// Process SetAppendProvenanceMetadata instruction
+    pub fn process_set_append_provenance_metadata(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        enabled: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.append_provenance_metadata = enabled;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Appending trade provenance to NFT metadata set to {}", enabled);
+        
+        Ok(())
+    }
+
+    /// Process SetRateLimitingEnabled instruction
+    pub fn process_set_rate_limiting_enabled(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        enabled: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.rate_limiting_enabled = enabled;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Trade loop initialization rate limiting set to {}", enabled);
+        
+        Ok(())
+    }
+
+    /// Process SetKycRequired instruction
+    pub fn process_set_kyc_required(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        required: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.kyc_required = required;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("KYC requirement for step participants set to {}", required);
+        
+        Ok(())
+    }
+
+    /// Process RegisterKycEntry instruction
+    pub fn process_register_kyc_entry(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        wallet: Pubkey,
+        expires_at: i64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let kyc_entry_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the authority is authorized to manage KYC entries
+        let config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        if expires_at <= Clock::get()?.unix_timestamp {
+            msg!("KYC verification of {} must expire in the future. Requested: {}", wallet, expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify that the provided KYC entry account matches the expected PDA
+        let (expected_kyc_entry_key, bump_seed) = utils::get_kyc_entry_address(&wallet, program_id);
+        if kyc_entry_info.key != &expected_kyc_entry_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Create the entry on first registration; later registrations renew it
+        if kyc_entry_info.data_len() == 0 {
+            let space = KycEntry::get_space();
+            invoke_signed(
+                &system_instruction::create_account(
+                    authority_info.key,
+                    kyc_entry_info.key,
+                    Rent::get()?.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    authority_info.clone(),
+                    kyc_entry_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[&[b"kyc", wallet.as_ref(), &[bump_seed]]],
+            )?;
+        } else {
+            utils::verify_account_owner(kyc_entry_info, program_id)?;
+        }
+        
+        let kyc_entry = KycEntry {
+            wallet,
+            verified: true,
+            expires_at,
+        };
+        kyc_entry.serialize(&mut *kyc_entry_info.data.borrow_mut())?;
+        
+        msg!("Wallet {} is KYC verified until {}", wallet, expires_at);
+        
+        Ok(())
+    }
+
+    /// Process RevokeKycEntry instruction
+    pub fn process_revoke_kyc_entry(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        wallet: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let kyc_entry_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to manage KYC entries
+        let config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        // Verify that the provided KYC entry account matches the expected PDA
+        let (expected_kyc_entry_key, _) = utils::get_kyc_entry_address(&wallet, program_id);
+        if kyc_entry_info.key != &expected_kyc_entry_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the KYC entry account is owned by this program
+        utils::verify_account_owner(kyc_entry_info, program_id)?;
+        
+        // Close the entry, returning its rent to the authority
+        close_program_account(kyc_entry_info, authority_info)?;
+        
+        msg!("KYC entry of wallet {} revoked", wallet);
+        
+        Ok(())
+    }
+
+    /// Process SetCouponIssuer instruction
+    pub fn process_set_coupon_issuer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        coupon_issuer: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.coupon_issuer = coupon_issuer;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Coupon issuer set to {:?}", coupon_issuer);
+        
+        Ok(())
+    }
+
+    /// Process ApplyCoupon instruction
+    pub fn process_apply_coupon(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        coupon: DiscountCoupon,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let instructions_sysvar_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // The coupon is bound to a single trade loop
+        if coupon.loop_key != *trade_loop_info.key {
+            msg!("Coupon is for trade loop {}, not {}", coupon.loop_key, trade_loop_info.key);
+            return Err(SwapError::InvalidCouponSignature.into());
+        }
+        
+        if coupon.discount_basis_points > 10_000 {
+            msg!("Discount cannot exceed 10000 basis points. Coupon: {}", coupon.discount_basis_points);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Ensure the signer is the trade loop authority
+        if trade_loop.authority != *authority_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        let clock = Clock::get()?;
+        if coupon.valid_until < clock.unix_timestamp {
+            msg!("Coupon expired at {}", coupon.valid_until);
+            return Err(SwapError::CouponExpired.into());
+        }
+        
+        // The issuer's signature is checked by the Ed25519 program instruction preceding this one
+        let config = load_program_config(program_id, config_info)?;
+        let coupon_issuer = config.coupon_issuer.ok_or_else(|| {
+            msg!("No coupon issuer is configured");
+            ProgramError::from(SwapError::InvalidCouponSignature)
+        })?;
+        let signature_instruction = solana_program::sysvar::instructions::get_instruction_relative(-1, instructions_sysvar_info)
+            .map_err(|_| SwapError::InvalidCouponSignature)?;
+        if !utils::verify_ed25519_instruction(&signature_instruction, &coupon_issuer, &coupon.message(), &coupon.issuer_signature) {
+            msg!("Coupon is not signed by the coupon issuer {}", coupon_issuer);
+            return Err(SwapError::InvalidCouponSignature.into());
+        }
+        
+        trade_loop.applied_discount_bps = coupon.discount_basis_points;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Applied a {} basis point fee discount to trade loop {}", coupon.discount_basis_points, trade_loop_info.key);
+        
+        Ok(())
+    }
+
+    /// Process ProposeGovernanceReplacement instruction
+    pub fn process_propose_governance_replacement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_governance: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let governance_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !governance_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Only the current governance can propose its replacement
+        let mut config = load_program_config(program_id, config_info)?;
+        if config.governance != Some(*governance_info.key) {
+            msg!("Only the current governance can propose a replacement");
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        let clock = Clock::get()?;
+        let finalizable_at = clock.unix_timestamp
+            .checked_add(GOVERNANCE_REPLACEMENT_DELAY_SECONDS)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        config.proposed_governance = Some((new_governance, finalizable_at));
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                governance_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Governance replacement by {} proposed, finalizable at {}", new_governance, finalizable_at);
+        
+        Ok(())
+    }
+
+    /// Process FinalizeGovernanceReplacement instruction
+    pub fn process_finalize_governance_replacement(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let config_info = next_account_info(account_info_iter)?;
+        
+        let mut config = load_program_config(program_id, config_info)?;
+        let (new_governance, finalizable_at) = config.proposed_governance.ok_or_else(|| {
+            msg!("No governance replacement is pending");
+            ProgramError::from(SwapError::InvalidInstructionData)
+        })?;
+        
+        let clock = Clock::get()?;
+        if clock.unix_timestamp < finalizable_at {
+            msg!("Governance replacement can be finalized at {} (now: {})", finalizable_at, clock.unix_timestamp);
+            return Err(SwapError::GovernanceTimelockNotExpired.into());
+        }
+        
+        config.governance = Some(new_governance);
+        config.proposed_governance = None;
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Governance replaced by {}", new_governance);
+        
+        Ok(())
+    }
+
+    /// Process CreateSealedBidLoop instruction
+    pub fn process_create_sealed_bid_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        timeout_seconds: u64,
+    ) -> ProgramResult {
+        Self::process_initialize_trade_loop(program_id, accounts, trade_id, participants, timeout_seconds, None, 0, false, None)?;
+        
+        // The trade loop account follows the payer
+        let trade_loop_info = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        trade_loop.commitment_mode = true;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop is sealed-bid; steps commit to hashed NFTs");
+        
+        Ok(())
+    }
+    
+    /// Process CommitTradeStep instruction
+    pub fn process_commit_trade_step(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        nft_mint_commitments: Vec<[u8; 32]>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        if !trade_loop.commitment_mode {
+            msg!("Trade loop is not sealed-bid");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Check if the trade loop has expired
+        let clock = Clock::get()?;
+        if trade_loop.is_expired(clock.unix_timestamp as u64) {
+            return Err(SwapError::TradeTimeoutExceeded.into());
+        }
+        
+        // Ensure there is at least one NFT, within the per-step limit
+        if nft_mint_commitments.is_empty() {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        if nft_mint_commitments.len() > trade_loop.step_nft_limit(step_index) as usize {
+            msg!("Step has {} NFTs, maximum for step {} is {}", nft_mint_commitments.len(), step_index, trade_loop.step_nft_limit(step_index));
+            return Err(SwapError::TooManyNftsInStep.into());
+        }
+        
+        let step = trade_loop.steps.get_mut(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Only the sender commits, and only once, before approving
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        if step.status != StepStatus::Created {
+            msg!("Step {} has already committed to its NFTs", step_index);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        step.nft_mint_commitments = nft_mint_commitments;
+        step.status = StepStatus::Committed;
+        step.added_at_slot = clock.slot;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Step {} committed to {} NFTs", step_index, trade_loop.steps[step_index as usize].nft_mint_commitments.len());
+        
+        Ok(())
+    }
+    
+    /// Process ExecuteSealedBidLoop instruction
+    pub fn process_execute_sealed_bid_loop(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        expected_nonce: u64,
+        reveals: Vec<StepReveal>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts; the ExecuteFullTradeLoop accounts follow the guard accounts
+        let mint_blocklist_info = next_account_info(account_info_iter)?;
+        let collection_whitelist_info = next_account_info(account_info_iter)?;
+        let execute_accounts = account_info_iter.as_slice();
+        let trade_loop_info = execute_accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let config_info = execute_accounts.get(8).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        if !trade_loop.commitment_mode {
+            msg!("Trade loop is not sealed-bid");
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // The NFTs are only revealed once every participant has approved the commitments
+        if !trade_loop.is_ready_for_execution() {
+            return Err(SwapError::MissingApprovals.into());
+        }
+        
+        if reveals.len() != trade_loop.steps.len() {
+            msg!("Expected revealed NFTs for {} steps, got {}", trade_loop.steps.len(), reveals.len());
+            return Err(SwapError::CommitmentMismatch.into());
+        }
+        
+        // Each mint and nonce must hash to the commitment at the same position
+        for (step_index, (step, reveal)) in trade_loop.steps.iter().zip(&reveals).enumerate() {
+            if reveal.nft_mints.len() != step.nft_mint_commitments.len() || reveal.nonces.len() != reveal.nft_mints.len() {
+                msg!("Expected {} revealed NFTs and nonces for step {}", step.nft_mint_commitments.len(), step_index);
+                return Err(SwapError::CommitmentMismatch.into());
+            }
+            for (nft_index, ((nft_mint, nonce), commitment)) in reveal.nft_mints.iter().zip(&reveal.nonces).zip(&step.nft_mint_commitments).enumerate() {
+                if TradeStep::nft_commitment(nft_mint, nonce) != *commitment {
+                    msg!("NFT {} of step {} does not match its commitment", nft_index, step_index);
+                    return Err(SwapError::CommitmentMismatch.into());
+                }
+            }
+        }
+        
+        // The revealed NFTs must pass the same checks as in AddTradeStep; ownership is
+        // checked by the transfers themselves
+        let mint_blocklist = load_mint_blocklist(program_id, mint_blocklist_info)?;
+        let collection_whitelist = load_collection_whitelist(program_id, collection_whitelist_info)?;
+        let config = load_optional_program_config(program_id, config_info)?;
+        for reveal in &reveals {
+            verify_mints_not_blocked(mint_blocklist.as_ref(), &reveal.nft_mints)?;
+            verify_nft_collections_on_chain(collection_whitelist.as_ref(), accounts, &reveal.nft_mints)?;
+            verify_nft_floor_prices(config.as_ref(), collection_whitelist.as_ref(), accounts, &reveal.nft_mints)?;
+        }
+        
+        for (step_index, reveal) in reveals.into_iter().enumerate() {
+            let step = &mut trade_loop.steps[step_index];
+            step.escrowed = vec![false; reveal.nft_mints.len()];
+            step.nft_values_lamports = vec![0; reveal.nft_mints.len()];
+            step.nft_mints = reveal.nft_mints;
+            step.nft_mint_commitments = Vec::new();
+            trade_loop.record_step_hash(step_index as u8);
+        }
+        
+        // Store the revealed NFTs for the execution below; a failed execution reverts them
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        msg!("Revealed the NFTs of {} sealed-bid steps", trade_loop.steps.len());
+        
+        Self::process_execute_full_trade_loop(program_id, execute_accounts, expected_nonce, false)
+    }
+    
+    /// Process ReleaseFailedStepEscrow instruction
+    pub fn process_release_failed_step_escrow(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let participant_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !participant_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Any participant can return a failed step's NFTs to their sender
+        if !trade_loop.steps.iter().any(|step| step.from == *participant_info.key || step.to == *participant_info.key) {
+            msg!("{} is not a participant in this trade loop", participant_info.key);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        let step = trade_loop.steps.get_mut(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        if !matches!(step.status, StepStatus::Failed { .. }) {
+            msg!("Step {} has not failed", step_index);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        let mut returned_nfts = 0;
+        for (nft_mint, escrowed) in step.nft_mints.iter().zip(step.escrowed.iter_mut()) {
+            if !*escrowed {
+                continue;
+            }
+            
+            let escrow_authority_info = next_account_info(account_info_iter)?;
+            let escrow_token_account_info = next_account_info(account_info_iter)?;
+            let sender_token_account_info = next_account_info(account_info_iter)?;
+            
+            verify_escrow_holds_nft(program_id, trade_loop_info.key, nft_mint, escrow_token_account_info)?;
+            utils::verify_token_account_address(sender_token_account_info, &step.from, nft_mint)?;
+            
+            utils::transfer_nft_from_escrow(
+                escrow_token_account_info,
+                sender_token_account_info,
+                escrow_authority_info,
+                token_program_info,
+                trade_loop_info.key,
+                nft_mint,
+                program_id,
+            )?;
+            
+            *escrowed = false;
+            returned_nfts += 1;
+            msg!("Returned escrowed NFT {} to {}", nft_mint, step.from);
+        }
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Returned {} escrowed NFTs of failed step {}", returned_nfts, step_index);
+        
+        Ok(())
+    }
+    
+    /// Process ClaimExpiredEscrow instruction
+    pub fn process_claim_expired_escrow(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        nft_mint_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let escrow_authority_info = next_account_info(account_info_iter)?;
+        let escrow_token_account_info = next_account_info(account_info_iter)?;
+        let sender_token_account_info = next_account_info(account_info_iter)?;
+        
+        // A loop paused by a lost authority must not strand the NFT, so only the program pause applies
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the token program is actually the token program
+        if token_program_info.key != &spl_token::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // The NFT stays committed until the loop expires
+        let clock = Clock::get()?;
+        if !trade_loop.is_expired(clock.unix_timestamp as u64) {
+            msg!("Trade loop expires at {}", trade_loop.expires_at);
+            return Err(SwapError::TradeLoopNotExpired.into());
+        }
+        
+        let step = trade_loop.steps.get_mut(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Only the sender can reclaim their NFT
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // An executed step's escrowed NFT belongs to its recipient
+        if step.status == StepStatus::Executed {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        let nft_mint = *step.nft_mints.get(nft_mint_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        if !step.escrowed.get(nft_mint_index as usize).copied().unwrap_or(false) {
+            msg!("NFT {} of step {} is not escrowed", nft_mint, step_index);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        verify_escrow_holds_nft(program_id, trade_loop_info.key, &nft_mint, escrow_token_account_info)?;
+        utils::verify_token_account_address(sender_token_account_info, sender_info.key, &nft_mint)?;
+        
+        utils::transfer_nft_from_escrow(
+            escrow_token_account_info,
+            sender_token_account_info,
+            escrow_authority_info,
+            token_program_info,
+            trade_loop_info.key,
+            &nft_mint,
+            program_id,
+        )?;
+        
+        step.escrowed[nft_mint_index as usize] = false;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Returned expired escrowed NFT {} to {}", nft_mint, sender_info.key);
+        
+        Ok(())
+    }
+    
+    /// Process CreateTradeLoopWithGuaranteedExecution instruction
+    pub fn process_create_trade_loop_with_guaranteed_execution(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        timeout_seconds: u64,
+        execution_bond_lamports: u64,
+    ) -> ProgramResult {
+        // Bond PDAs hold no data, so a bond below the rent-exempt minimum could be collected
+        let minimum_bond = Rent::get()?.minimum_balance(0);
+        if execution_bond_lamports < minimum_bond {
+            msg!("Execution bond of {} lamports is below the minimum of {}", execution_bond_lamports, minimum_bond);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        Self::process_initialize_trade_loop(program_id, accounts, trade_id, participants, timeout_seconds, None, 0, false, None)?;
+        
+        // The trade loop account follows the payer
+        let trade_loop_info = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        trade_loop.execution_bond_lamports = execution_bond_lamports;
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Trade loop requires an execution bond of {} lamports per step", execution_bond_lamports);
+        
+        Ok(())
+    }
+    
+    /// Process ClaimExecutionBond instruction
+    pub fn process_claim_execution_bond(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let claimer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let bond_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        
+        // Bonds of a loop paused by its authority must stay claimable, so only the program pause applies
+        check_program_not_paused(program_id, accounts, None)?;
+        
+        // Verify signers
+        if !claimer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program is actually the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        let step = trade_loop.steps.get(step_index as usize)
+            .ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Verify the bond account is the step's bond PDA and still holds the bond
+        let (expected_bond_key, _) = utils::get_execution_bond_address(trade_loop_info.key, step_index, program_id);
+        if bond_info.key != &expected_bond_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        if bond_info.lamports() == 0 {
+            msg!("Step {} holds no execution bond", step_index);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // The execution window comes from the program config, if it is initialized
+        let (expected_config_key, _) = utils::get_program_config_address(program_id);
+        if config_info.key != &expected_config_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        let execution_window = if config_info.data_len() > 0 {
+            load_program_config(program_id, config_info)?.execution_window()
+        } else {
+            DEFAULT_EXECUTION_WINDOW_SECONDS
+        };
+        
+        let current_time = Clock::get()?.unix_timestamp as u64;
+        let executed = trade_loop.steps.iter().any(|step| step.status == StepStatus::Executed);
+        let execute_deadline = trade_loop.all_approved_at
+            .filter(|_| trade_loop.is_ready_for_execution())
+            .map(|all_approved_at| all_approved_at.saturating_add(execution_window));
+        
+        let recipient_info = if !executed && execute_deadline.is_some_and(|deadline| current_time >= deadline) {
+            // The loop missed its execution window, so the bond is forfeited to the claimer
+            msg!("Trade loop missed its execution deadline of {}", execute_deadline.unwrap_or_default());
+            claimer_info
+        } else if executed || trade_loop.is_expired(current_time) {
+            // The loop executed, or expired without missing the window; the bond goes back to its sender
+            let sender_info = next_account_info(account_info_iter)?;
+            if sender_info.key != &step.from {
+                return Err(SwapError::InvalidAccountOwner.into());
+            }
+            sender_info
+        } else {
+            msg!("Execution bond of step {} is locked until the loop executes, expires or misses its deadline", step_index);
+            return Err(SwapError::ExecutionBondLocked.into());
+        };
+        
+        let bond_lamports = pay_out_execution_bond(program_id, trade_loop_info.key, step_index, bond_info, recipient_info, system_program_info)?;
+        
+        msg!("Paid execution bond of step {} ({} lamports) to {}", step_index, bond_lamports, recipient_info.key);
+        
+        Ok(())
+    }
+    
+    /// Process SetExecutionWindow instruction
+    pub fn process_set_execution_window(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        execution_window_seconds: Option<u64>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.execution_window_seconds = execution_window_seconds;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Execution window set to {} seconds", config.execution_window());
+        
+        Ok(())
+    }
+    
+    /// Process UpdateFeeSchedule instruction
+    pub fn process_update_fee_schedule(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_schedule: FeeSchedule,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Fees are set by the upgrade authority alone; governance changes them with UpdateFeeConfig proposals
+        let mut config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        if !new_schedule.is_valid() {
+            msg!("Fee tiers cannot exceed {} basis points. Requested: {:?}", MAX_SCHEDULED_FEE_BASIS_POINTS, new_schedule);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        config.fee_schedule = Some(new_schedule);
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Fee schedule updated: {:?}", new_schedule);
+        
+        Ok(())
+    }
+    
+    /// Process UpdateFeeRoute instruction
+    pub fn process_update_fee_route(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        route: Option<FeeRoute>,
+        fallback_to_sol: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Where fees end up is set by the upgrade authority alone
+        let mut config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        if let Some(route) = &route {
+            if !route.is_valid() {
+                msg!("Fee route needs a route program, at most {} intermediate mints and a minimum output. Requested: {:?}", 
+                     MAX_FEE_ROUTE_HOPS, route);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        
+        config.fee_route = route;
+        config.fee_route_fallback_to_sol = fallback_to_sol;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Fee route updated: {:?} (fallback to SOL: {})", config.fee_route, fallback_to_sol);
+        
+        Ok(())
+    }
+    
+    /// Process PauseForMaintenance instruction
+    pub fn process_pause_for_maintenance(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        reason: PauseReason,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.paused = true;
+        config.paused_reason = Some(reason);
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Program paused ({:?})", reason);
+        
+        Ok(())
+    }
+
+    /// Process SetMinNftFloorPrice instruction
+    pub fn process_set_min_nft_floor_price(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        min_usd_cents: Option<u64>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the authority is authorized to update the config
+        let mut config = load_program_config(program_id, config_info)?;
+        verify_config_authority(&config, authority_info.key)?;
+        
+        config.min_nft_floor_price_usd_cents = min_usd_cents;
+        
+        // Config accounts created before this setting existed are too small to hold it
+        if config_info.data_len() < ProgramConfig::get_space() {
+            utils::reallocate_account(
+                config_info,
+                authority_info,
+                system_program_info,
+                &Rent::get()?,
+                ProgramConfig::get_space(),
+            )?;
+        }
+        
+        // Serialize and store the updated config data
+        config.serialize(&mut *config_info.data.borrow_mut())?;
+        
+        msg!("Minimum NFT floor price set to {:?} cents", min_usd_cents);
+        
+        Ok(())
+    }
+
+    /// Process SetCollectionPriceFeed instruction
+    pub fn process_set_collection_price_feed(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        collection: Pubkey,
+        feed_id: Option<[u8; 32]>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let collection_whitelist_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Only the upgrade authority can manage the collection whitelist
+        let config = load_program_config(program_id, config_info)?;
+        if config.upgrade_authority != *authority_info.key {
+            return Err(SwapError::UpgradeAuthorityMismatch.into());
+        }
+        
+        // Verify that the provided collection whitelist account matches the expected PDA
+        let (expected_collection_whitelist_key, _) = utils::get_collection_whitelist_address(program_id);
+        if collection_whitelist_info.key != &expected_collection_whitelist_key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_account_owner(collection_whitelist_info, program_id)?;
+        
+        let mut collection_whitelist = CollectionWhitelist::deserialize(&mut &collection_whitelist_info.data.borrow()[..])?;
+        if !collection_whitelist.is_whitelisted(&collection) {
+            msg!("Collection {} is not whitelisted", collection);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        collection_whitelist.price_feeds.retain(|(feed_collection, _)| *feed_collection != collection);
+        if let Some(feed_id) = feed_id {
+            collection_whitelist.price_feeds.push((collection, feed_id));
+        }
+        
+        // Resize the account so the data stays an exact Borsh encoding
+        let space = CollectionWhitelist::get_space(collection_whitelist.collections.len(), collection_whitelist.price_feeds.len());
+        if space > collection_whitelist_info.data_len() {
+            utils::reallocate_account(collection_whitelist_info, authority_info, system_program_info, &Rent::get()?, space)?;
+        } else {
+            collection_whitelist_info.realloc(space, false)?;
+        }
+        
+        // Serialize and store the updated collection whitelist
+        collection_whitelist.serialize(&mut *collection_whitelist_info.data.borrow_mut())?;
+        
+        msg!("Floor price feed of collection {} set to {:?}", collection, 
+             feed_id.map(|feed_id| utils::get_pyth_price_feed_address(&feed_id).0));
+        
+        Ok(())
+    }
+
+    /// Process SetStepExpiry instruction
+    pub fn process_set_step_expiry(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        step_expires_at: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // A step cannot outlive its trade loop
+        if step_expires_at > trade_loop.expires_at {
+            msg!("Step expiry {} is after the trade loop expiry {}", step_expires_at, trade_loop.expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Ensure the step index is valid
+        let step = trade_loop.steps.get_mut(step_index as usize).ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Only the step's sender can set its deadline
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if step.status == StepStatus::Executed {
+            return Err(SwapError::StepAlreadyExecuted.into());
+        }
+        
+        step.step_expires_at = Some(step_expires_at);
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Step {} expires at {}", step_index, step_expires_at);
+        
+        Ok(())
+    }
+
+    /// Process SetStepNotBefore instruction
+    pub fn process_set_step_not_before(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        step_index: u8,
+        not_before: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let sender_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        
+        // Check if the program or this trade loop is paused
+        check_program_not_paused(program_id, accounts, Some(trade_loop_info))?;
+        
+        // Verify signers
+        if !sender_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        // Deserialize the trade loop data
+        let mut trade_loop = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // A step that only unlocks once the loop has expired could never execute
+        if not_before >= trade_loop.expires_at {
+            msg!("Step timelock {} is not before the trade loop expiry {}", not_before, trade_loop.expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Ensure the step index is valid
+        let step = trade_loop.steps.get_mut(step_index as usize).ok_or(SwapError::InvalidInstructionData)?;
+        
+        // Only the step's sender can timelock it
+        if step.from != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Only steps that have not been approved yet can be amended
+        if step.status != StepStatus::Created {
+            msg!("Step {} can only be timelocked before approval. Status: {:?}", step_index, step.status);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Nor by the step's own deadline
+        if step.step_expires_at.is_some_and(|step_expires_at| not_before > step_expires_at) {
+            msg!("Step timelock {} is after the step expiry {:?}", not_before, step.step_expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        step.not_before = Some(not_before);
+        
+        // Serialize and store the updated trade loop data
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        utils::emit_event(&SwapEvent::StepTimelockSet {
+            trade_id: trade_loop.trade_id,
+            step_index,
+            not_before,
+        })?;
+        
+        msg!("Step {} cannot execute before {}", step_index, not_before);
+        
+        Ok(())
+    }
+
+    /// Process MigrateTradeLoopAccount instruction
+    pub fn process_migrate_trade_loop_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        
+        // Get accounts
+        let payer_info = next_account_info(account_info_iter)?;
+        let trade_loop_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        
+        // Verify signers
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        
+        // Verify the system program
+        if system_program_info.key != &solana_program::system_program::id() {
+            return Err(SwapError::IncorrectProgramId.into());
+        }
+        
+        // Verify the trade loop account is owned by this program
+        utils::verify_account_owner(trade_loop_info, program_id)?;
+        
+        if trade_loop_info.data_len() == TradeLoop::LEN {
+            msg!("Trade loop {} already uses the packed layout", trade_loop_info.key);
+            return Ok(());
+        }
+        
+        // Read the existing Borsh data, ignoring any padding after it
+        let trade_loop = TradeLoop::unpack_from_slice(&trade_loop_info.data.borrow())?;
+        
+        // Ensure the trade loop is initialized
+        if !trade_loop.is_initialized {
+            return Err(SwapError::UninitializedAccount.into());
+        }
+        
+        // Resize the account to the packed size, topping up rent when it grows
+        if trade_loop_info.data_len() < TradeLoop::LEN {
+            utils::reallocate_account(
+                trade_loop_info,
+                payer_info,
+                system_program_info,
+                &Rent::get()?,
+                TradeLoop::LEN,
+            )?;
+        } else {
+            trade_loop_info.realloc(TradeLoop::LEN, false)?;
+        }
+        
+        // Serialize and store the trade loop data in the packed layout
+        serialize_trade_loop(&trade_loop, trade_loop_info)?;
+        
+        msg!("Migrated trade loop {} to the packed layout ({} bytes)", trade_loop_info.key, TradeLoop::LEN);
+        
+        Ok(())
+    }
+}
+
+/// Process an instruction
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction: SwapInstruction,
+) -> ProgramResult {
+    // Instructions that are not exempt from scheduled maintenance end with the program
+    // config account, so the pause cannot be skipped by leaving the config out
+    let accounts = if PAUSE_EXEMPT_TAGS.contains(&instruction.tag()) {
+        accounts
+    } else {
+        let (config_info, accounts) = accounts.split_last().ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if let Some(PauseReason::ScheduledMaintenance) = load_optional_program_config(program_id, config_info)?.and_then(|config| config.pause_reason()) {
+            msg!("Program is paused for scheduled maintenance");
+            return Err(SwapError::ProgramPaused(PauseReason::ScheduledMaintenance.code()).into());
+        }
+        accounts
+    };
+    
+    match instruction {
+        SwapInstruction::InitializeTradeLoop { trade_id, participants, timeout_seconds, step_signer_counts, cancel_lockout_seconds, auto_execute, per_step_nft_limits } => {
+            Processor::process_initialize_trade_loop(program_id, accounts, trade_id, participants, timeout_seconds, step_signer_counts, cancel_lockout_seconds, auto_execute, per_step_nft_limits)
+        }
+        SwapInstruction::AddTradeStep { step_index, to, nft_mints } => {
+            Processor::process_add_trade_step(program_id, accounts, step_index, to, nft_mints, Vec::new())
+        }
+        SwapInstruction::ApproveTradeStep { step_index } => {
+            Processor::process_approve_trade_step(program_id, accounts, step_index)
+        }
+        SwapInstruction::ExecuteTradeStep { step_index } => {
+            Processor::process_execute_trade_step(program_id, accounts, step_index, false)
+        }
+        SwapInstruction::ExecuteFullTradeLoop { expected_nonce } => {
+            Processor::process_execute_full_trade_loop(program_id, accounts, expected_nonce, false)
+        }
+        SwapInstruction::ExecuteWithFallback { expected_nonce } => {
+            Processor::process_execute_full_trade_loop(program_id, accounts, expected_nonce, true)
+        }
+        SwapInstruction::ReleaseFailedStepEscrow { step_index } => {
+            Processor::process_release_failed_step_escrow(program_id, accounts, step_index)
+        }
+        SwapInstruction::ClaimExpiredEscrow { step_index, nft_mint_index } => {
+            Processor::process_claim_expired_escrow(program_id, accounts, step_index, nft_mint_index)
+        }
+        SwapInstruction::CreateTradeLoopWithGuaranteedExecution { trade_id, participants, timeout_seconds, execution_bond_lamports } => {
+            Processor::process_create_trade_loop_with_guaranteed_execution(program_id, accounts, trade_id, participants, timeout_seconds, execution_bond_lamports)
+        }
+        SwapInstruction::ClaimExecutionBond { step_index } => {
+            Processor::process_claim_execution_bond(program_id, accounts, step_index)
+        }
+        SwapInstruction::SetExecutionWindow { execution_window_seconds } => {
+            Processor::process_set_execution_window(program_id, accounts, execution_window_seconds)
+        }
+        SwapInstruction::ApplyOffchainApproval { step_index, signature, message } => {
+            Processor::process_verify_signature_off_chain_approval(program_id, accounts, step_index, signature, message)
+        }
+        SwapInstruction::GetParticipantStats {} => {
+            Processor::process_get_loop_participant_stats(program_id, accounts)
+        }
+        SwapInstruction::InitializeProgramConfigWithFeeSchedule { governance, fee_schedule } => {
+            Processor::process_initialize_program_config_with_fee_schedule(program_id, accounts, governance, fee_schedule)
+        }
+        SwapInstruction::UpdateFeeSchedule { new_schedule } => {
+            Processor::process_update_fee_schedule(program_id, accounts, new_schedule)
+        }
+        SwapInstruction::BulkCloseEscrows {} => {
+            Processor::process_bulk_close_empty_escrow_accounts(program_id, accounts)
+        }
+        SwapInstruction::UpdateStepRecipient { step_index, new_recipient } => {
+            Processor::process_update_step_recipient(program_id, accounts, step_index, new_recipient)
+        }
+        SwapInstruction::RegisterTransferHook { mint } => {
+            Processor::process_register_transfer_hook(program_id, accounts, mint)
+        }
+        SwapInstruction::DeregisterTransferHook {} => {
+            Processor::process_deregister_transfer_hook(program_id, accounts)
+        }
+        SwapInstruction::ListNftOffer { offer_id, offered_nft, wanted_collection, floor_premium_bps } => {
+            Processor::process_list_nft_offer(program_id, accounts, offer_id, offered_nft, wanted_collection, floor_premium_bps)
+        }
+        SwapInstruction::DelistNftOffer { offer_id } => {
+            Processor::process_delist_nft_offer(program_id, accounts, offer_id)
+        }
+        SwapInstruction::MatchOffers { offer_id_a, offer_id_b } => {
+            Processor::process_match_nft_offers(program_id, accounts, offer_id_a, offer_id_b)
+        }
+        SwapInstruction::CheckRentExemption {} => {
+            Processor::process_check_rent_exemption(program_id, accounts)
+        }
+        SwapInstruction::UpdateFeeRoute { route, fallback_to_sol } => {
+            Processor::process_update_fee_route(program_id, accounts, route, fallback_to_sol)
+        }
+        SwapInstruction::SetStepNotBefore { step_index, not_before } => {
+            Processor::process_set_step_not_before(program_id, accounts, step_index, not_before)
+        }
+        SwapInstruction::SetKycRequired { required } => {
+            Processor::process_set_kyc_required(program_id, accounts, required)
+        }
+        SwapInstruction::RegisterKycEntry { wallet, expires_at } => {
+            Processor::process_register_kyc_entry(program_id, accounts, wallet, expires_at)
+        }
+        SwapInstruction::RevokeKycEntry { wallet } => {
+            Processor::process_revoke_kyc_entry(program_id, accounts, wallet)
+        }
+        SwapInstruction::CreatePartialFillOffer { offer_id, offered_nfts, wanted_nfts_per_unit, minimum_fill } => {
+            Processor::process_create_partial_fill_offer(program_id, accounts, offer_id, offered_nfts, wanted_nfts_per_unit, minimum_fill)
+        }
+        SwapInstruction::FillPartialOffer { offer_id, take_indices, give_nfts } => {
+            Processor::process_fill_partial_offer(program_id, accounts, offer_id, take_indices, give_nfts)
+        }
+        SwapInstruction::CancelPartialFillOffer { offer_id } => {
+            Processor::process_cancel_partial_fill_offer(program_id, accounts, offer_id)
+        }
+        SwapInstruction::WithdrawVoteTokens { proposal_id } => {
+            Processor::process_withdraw_vote_tokens(program_id, accounts, proposal_id)
+        }
+        SwapInstruction::VerifyProgramUpgrade { test_instruction_data } => {
+            Processor::process_verify_program_upgrade(program_id, accounts, test_instruction_data)
+        }
+        SwapInstruction::CreateTradeLoopWithSignedApprovals { trade_id, participants, expires_at, nft_mints, approvals } => {
+            Processor::process_create_trade_loop_with_signed_approvals(program_id, accounts, trade_id, participants, expires_at, nft_mints, approvals)
+        }
+        SwapInstruction::CancelTradeLoop {} => {
+            Processor::process_cancel_trade_loop(program_id, accounts)
+        }
+        SwapInstruction::UpgradeProgram { new_program_version } => {
+            Processor::process_upgrade_program(program_id, accounts, new_program_version)
+        }
+        SwapInstruction::InitializeProgramConfig { governance } => {
+            Processor::process_initialize_program_config(program_id, accounts, governance)
+        }
+        SwapInstruction::UpdateProgramConfig { new_upgrade_authority, new_governance, new_paused_state } => {
+            Processor::process_update_program_config(program_id, accounts, new_upgrade_authority, new_governance, new_paused_state)
+        }
+        SwapInstruction::EscrowNft { step_index, nft_mint } => {
+            Processor::process_escrow_nft(program_id, accounts, step_index, nft_mint)
+        }
+        SwapInstruction::ReleaseEscrow { step_index, nft_mint } => {
+            Processor::process_release_escrow(program_id, accounts, step_index, nft_mint)
+        }
+        SwapInstruction::PauseTradeLoop {} => {
+            Processor::process_set_trade_loop_paused(program_id, accounts, true)
+        }
+        SwapInstruction::ResumeTradeLoop {} => {
+            Processor::process_set_trade_loop_paused(program_id, accounts, false)
+        }
+        SwapInstruction::CreateTemplate { template_id, participant_ring, step_count } => {
+            Processor::process_create_template(program_id, accounts, template_id, participant_ring, step_count)
+        }
+        SwapInstruction::InitializeFromTemplate { template_id, trade_id, timeout_seconds } => {
+            Processor::process_initialize_from_template(program_id, accounts, template_id, trade_id, timeout_seconds)
+        }
+        SwapInstruction::BatchCancelExpired { recipient } => {
+            Processor::process_batch_cancel_expired(program_id, accounts, recipient)
+        }
+        SwapInstruction::EstimateComputeUnits { step_count, nft_counts, ata_creates } => {
+            Processor::process_estimate_compute_units(step_count, nft_counts, ata_creates)
+        }
+        SwapInstruction::ProposeAuthorityTransfer { new_authority } => {
+            Processor::process_propose_authority_transfer(program_id, accounts, new_authority)
+        }
+        SwapInstruction::AcceptAuthorityTransfer {} => {
+            Processor::process_accept_authority_transfer(program_id, accounts)
+        }
+        SwapInstruction::MarkStepFailed { step_index, reason } => {
+            Processor::process_mark_step_failed(program_id, accounts, step_index, reason)
+        }
+        SwapInstruction::SetStepNftValues { step_index, nft_values_lamports } => {
+            Processor::process_set_step_nft_values(program_id, accounts, step_index, nft_values_lamports)
+        }
+        SwapInstruction::UpdateRoyaltyEnforcement { enabled, max_creators } => {
+            Processor::process_update_royalty_enforcement(program_id, accounts, enabled, max_creators)
+        }
+        SwapInstruction::SimulateExecuteFullTradeLoop {} => {
+            Processor::process_simulate_execute_full_trade_loop(program_id, accounts)
+        }
+        SwapInstruction::UpdateStepNfts { step_index, new_nft_mints } => {
+            Processor::process_update_step_nfts(program_id, accounts, step_index, new_nft_mints)
+        }
+        SwapInstruction::InitializeTradeLoopWithEscrow {
+            trade_id,
+            participants,
+            timeout_seconds,
+            initiator_step_index,
+            initiator_to,
+            initiator_nft_mints,
+        } => Processor::process_initialize_trade_loop_with_escrow(
+            program_id,
+            accounts,
+            trade_id,
+            participants,
+            timeout_seconds,
+            initiator_step_index,
+            initiator_to,
+            initiator_nft_mints,
+        ),
+        SwapInstruction::ExecutePartialLoop { from_step, to_step } => {
+            Processor::process_execute_partial_loop(program_id, accounts, from_step, to_step)
+        }
+        SwapInstruction::SetWitness { witness } => {
+            Processor::process_set_witness(program_id, accounts, Some(witness))
+        }
+        SwapInstruction::ClearWitness {} => {
+            Processor::process_set_witness(program_id, accounts, None)
+        }
+        SwapInstruction::ExecuteTradeStepAndCloseEmpty { step_index } => {
+            Processor::process_execute_trade_step(program_id, accounts, step_index, true)
+        }
+        SwapInstruction::SetCloseEmptyTokenAccounts { enabled } => {
+            Processor::process_set_close_empty_token_accounts(program_id, accounts, enabled)
+        }
+        SwapInstruction::InitializeProgramConfigV2 {
+            governance,
+            max_participants,
+            fee_basis_points,
+            fee_recipient,
+            royalty_enforcement_enabled,
+        } => Processor::process_initialize_program_config_v2(
+            program_id,
+            accounts,
+            governance,
+            max_participants,
+            fee_basis_points,
+            fee_recipient,
+            royalty_enforcement_enabled,
+        ),
+        SwapInstruction::DiagnoseTradeLoop {} => {
+            Processor::process_diagnose_trade_loop(program_id, accounts)
+        }
+        SwapInstruction::TransferTradeAuthority { new_authority } => {
+            Processor::process_transfer_trade_authority(program_id, accounts, new_authority)
+        }
+        SwapInstruction::ConfirmTradeAuthorityTransfer {} => {
+            Processor::process_confirm_trade_authority_transfer(program_id, accounts)
+        }
+        SwapInstruction::SplitTradeLoop { split_at_step, new_trade_id } => {
+            Processor::process_split_trade_loop(program_id, accounts, split_at_step, new_trade_id)
+        }
+        SwapInstruction::ComputeTradeId { participants, nonce } => {
+            Processor::process_compute_trade_id(program_id, accounts, participants, nonce)
+        }
+        SwapInstruction::MergeSteps { step_index_a, step_index_b } => {
+            Processor::process_merge_steps(program_id, accounts, step_index_a, step_index_b)
+        }
+        SwapInstruction::SetGovernanceTokenMint { governance_token_mint } => {
+            Processor::process_set_governance_token_mint(program_id, accounts, governance_token_mint)
+        }
+        SwapInstruction::ProposeGovernanceChange { proposal_id, proposed_change } => {
+            Processor::process_propose_governance_change(program_id, accounts, proposal_id, proposed_change)
+        }
+        SwapInstruction::VoteOnProposal { proposal_id, in_favor } => {
+            Processor::process_vote_on_proposal(program_id, accounts, proposal_id, in_favor)
+        }
+        SwapInstruction::FinalizeProposal { proposal_id } => {
+            Processor::process_finalize_proposal(program_id, accounts, proposal_id)
+        }
+        SwapInstruction::ProposeCounterOffer { step_index, alternative_nft_mints } => {
+            Processor::process_propose_counter_offer(program_id, accounts, step_index, alternative_nft_mints)
+        }
+        SwapInstruction::AcceptCounterOffer { step_index } => {
+            Processor::process_accept_counter_offer(program_id, accounts, step_index)
+        }
+        SwapInstruction::RejectCounterOffer { step_index } => {
+            Processor::process_reject_counter_offer(program_id, accounts, step_index)
+        }
+        SwapInstruction::BlockParticipant { wallet } => {
+            Processor::process_set_participant_blocked(program_id, accounts, wallet, true)
+        }
+        SwapInstruction::UnblockParticipant { wallet } => {
+            Processor::process_set_participant_blocked(program_id, accounts, wallet, false)
+        }
+        SwapInstruction::SetStepMetadata { step_index, metadata_uri } => {
+            Processor::process_set_step_metadata(program_id, accounts, step_index, Some(metadata_uri))
+        }
+        SwapInstruction::ClearStepMetadata { step_index } => {
+            Processor::process_set_step_metadata(program_id, accounts, step_index, None)
+        }
+        SwapInstruction::VerifyAllNfts {} => {
+            Processor::process_verify_all_nfts(program_id, accounts)
+        }
+        SwapInstruction::ReopenCancelledLoop { trade_id, step_count, timeout_seconds } => {
+            Processor::process_reopen_cancelled_loop(program_id, accounts, trade_id, step_count, timeout_seconds)
+        }
+        SwapInstruction::DelegateApproval { step_index, delegate, expiry } => {
+            Processor::process_set_delegate_approval(program_id, accounts, step_index, Some((delegate, expiry)))
+        }
+        SwapInstruction::RevokeDelegateApproval { step_index } => {
+            Processor::process_set_delegate_approval(program_id, accounts, step_index, None)
+        }
+        SwapInstruction::BatchAddSteps { steps } => {
+            Processor::process_batch_add_steps(program_id, accounts, steps)
+        }
+        SwapInstruction::SnapshotTradeLoop { snapshot_id } => {
+            Processor::process_snapshot_trade_loop(program_id, accounts, snapshot_id)
+        }
+        SwapInstruction::VerifySnapshot { snapshot_id } => {
+            Processor::process_verify_snapshot(program_id, accounts, snapshot_id)
+        }
+        SwapInstruction::MigrateTradeLoopAccount {} => {
+            Processor::process_migrate_trade_loop_account(program_id, accounts)
+        }
+        SwapInstruction::AddRelayer { relayer } => {
+            Processor::process_set_relayer_authorized(program_id, accounts, relayer, true)
+        }
+        SwapInstruction::RemoveRelayer { relayer } => {
+            Processor::process_set_relayer_authorized(program_id, accounts, relayer, false)
+        }
+        SwapInstruction::FundFeeVault { amount } => {
+            Processor::process_fund_fee_vault(program_id, accounts, amount)
+        }
+        SwapInstruction::SetLoopTags { tags } => {
+            Processor::process_set_loop_tags(program_id, accounts, tags)
+        }
+        SwapInstruction::ClearLoopTags {} => {
+            Processor::process_set_loop_tags(program_id, accounts, Vec::new())
+        }
+        SwapInstruction::VerifyLoopIntegrity {} => {
+            Processor::process_verify_loop_integrity(program_id, accounts)
+        }
+        SwapInstruction::ReorderSteps { new_order } => {
+            Processor::process_reorder_steps(program_id, accounts, new_order)
+        }
+        SwapInstruction::AtomicSwapTwoParty { alice_nft_mint, bob_nft_mint } => {
+            Processor::process_atomic_swap_two_party(program_id, accounts, alice_nft_mint, bob_nft_mint)
+        }
+        SwapInstruction::CreateOpenOffer { offer_id, offered_nft_mint, wanted_collection, timeout_seconds } => {
+            Processor::process_create_open_offer(program_id, accounts, offer_id, offered_nft_mint, wanted_collection, timeout_seconds)
+        }
+        SwapInstruction::FulfillOpenOffer { offer_id, fulfiller_nft_mint } => {
+            Processor::process_fulfill_open_offer(program_id, accounts, offer_id, fulfiller_nft_mint)
+        }
+        SwapInstruction::CancelOpenOffer { offer_id } => {
+            Processor::process_cancel_open_offer(program_id, accounts, offer_id)
+        }
+        SwapInstruction::SetStepSolAmount { step_index, sol_amount } => {
+            Processor::process_set_step_sol_amount(program_id, accounts, step_index, sol_amount)
+        }
+        SwapInstruction::SetMaxSolPerStep { max_sol_per_step } => {
+            Processor::process_set_max_sol_per_step(program_id, accounts, max_sol_per_step)
+        }
+        SwapInstruction::BulkInitializeLoops { loops } => {
+            Processor::process_bulk_initialize_loops(program_id, accounts, loops)
+        }
+        SwapInstruction::InitializeBadgeMint { metadata_uri } => {
+            Processor::process_initialize_badge_mint(program_id, accounts, metadata_uri)
+        }
+        SwapInstruction::LockStepNfts { step_index } => {
+            Processor::process_set_step_nfts_locked(program_id, accounts, step_index, true)
+        }
+        SwapInstruction::UnlockStepNfts { step_index } => {
+            Processor::process_set_step_nfts_locked(program_id, accounts, step_index, false)
+        }
+        SwapInstruction::GetProgramStats {} => {
+            Processor::process_get_program_stats(program_id, accounts)
+        }
+        SwapInstruction::ResetProgramStats {} => {
+            Processor::process_reset_program_stats(program_id, accounts)
+        }
+        SwapInstruction::BlockNftMint { mint } => {
+            Processor::process_set_nft_mint_blocked(program_id, accounts, mint, true)
+        }
+        SwapInstruction::UnblockNftMint { mint } => {
+            Processor::process_set_nft_mint_blocked(program_id, accounts, mint, false)
+        }
+        SwapInstruction::TransferStepOwnership { step_index, new_owner } => {
+            Processor::process_transfer_step_ownership(program_id, accounts, step_index, new_owner)
+        }
+        SwapInstruction::EnableRecurring { max_recurrences, interval_seconds } => {
+            Processor::process_enable_recurring(program_id, accounts, max_recurrences, interval_seconds)
+        }
+        SwapInstruction::InitializeDedupCache {} => {
+            Processor::process_initialize_dedup_cache(program_id, accounts)
+        }
+        SwapInstruction::ValidateTopology { participants } => {
+            Processor::process_validate_topology(&participants)
+        }
+        SwapInstruction::HealthCheck { expected_version } => {
+            Processor::process_health_check(program_id, accounts, expected_version)
+        }
+        SwapInstruction::MigrateLegacyLoop { original_trade_id } => {
+            Processor::process_migrate_legacy_trade_loop(program_id, accounts, original_trade_id)
+        }
+        SwapInstruction::BatchApproveSteps { approvals } => {
+            Processor::process_batch_approve_steps(program_id, accounts, approvals)
+        }
+        #[cfg(feature = "debug")]
+        SwapInstruction::DebugDumpInstruction { instruction_bytes } => {
+            Processor::process_debug_dump_instruction(program_id, accounts, &instruction_bytes)
+        }
+        #[cfg(not(feature = "debug"))]
+        SwapInstruction::DebugDumpInstruction { .. } => {
+            msg!("DebugDumpInstruction requires a build with the debug feature");
+            Err(SwapError::InvalidInstructionData.into())
+        }
+        SwapInstruction::TokenizeTradeLoop {} => {
+            Processor::process_tokenize_trade_loop(program_id, accounts)
+        }
+        SwapInstruction::RedeemTokenizedPosition { tokenized_nft_mint } => {
+            Processor::process_redeem_tokenized_position(program_id, accounts, tokenized_nft_mint)
+        }
+        SwapInstruction::CancelTradeLoopV2 {} => {
+            Processor::process_cancel_trade_loop_v2(program_id, accounts)
+        }
+        SwapInstruction::SetMetadataUpdateAuthority { mint, new_authority } => {
+            Processor::process_set_metadata_update_authority(program_id, accounts, mint, new_authority)
+        }
+        SwapInstruction::SetAppendProvenanceMetadata { enabled } => {
+            Processor::process_set_append_provenance_metadata(program_id, accounts, enabled)
+        }
+        SwapInstruction::SetStepExpiry { step_index, step_expires_at } => {
+            Processor::process_set_step_expiry(program_id, accounts, step_index, step_expires_at)
+        }
+        SwapInstruction::WhitelistCollection { collection } => {
+            Processor::process_set_collection_whitelisted(program_id, accounts, collection, true)
+        }
+        SwapInstruction::UnwhitelistCollection { collection } => {
+            Processor::process_set_collection_whitelisted(program_id, accounts, collection, false)
+        }
+        SwapInstruction::SetDeadlineMessage { message, display_deadline, timezone_offset } => {
+            Processor::process_set_deadline_message(program_id, accounts, message, display_deadline, timezone_offset)
+        }
+        SwapInstruction::SetRateLimitingEnabled { enabled } => {
+            Processor::process_set_rate_limiting_enabled(program_id, accounts, enabled)
+        }
+        SwapInstruction::InitializeRingFromParticipants { trade_id, participants, timeout_seconds } => {
+            Processor::process_initialize_trade_loop(program_id, accounts, trade_id, participants, timeout_seconds, None, 0, false, None)
+        }
+        SwapInstruction::SetCouponIssuer { coupon_issuer } => {
+            Processor::process_set_coupon_issuer(program_id, accounts, coupon_issuer)
+        }
+        SwapInstruction::ApplyCoupon { coupon } => {
+            Processor::process_apply_coupon(program_id, accounts, coupon)
+        }
+        SwapInstruction::ProposeGovernanceReplacement { new_governance } => {
+            Processor::process_propose_governance_replacement(program_id, accounts, new_governance)
+        }
+        SwapInstruction::FinalizeGovernanceReplacement {} => {
+            Processor::process_finalize_governance_replacement(program_id, accounts)
+        }
+        SwapInstruction::SetMinNftFloorPrice { min_usd_cents } => {
+            Processor::process_set_min_nft_floor_price(program_id, accounts, min_usd_cents)
+        }
+        SwapInstruction::SetCollectionPriceFeed { collection, feed_id } => {
+            Processor::process_set_collection_price_feed(program_id, accounts, collection, feed_id)
+        }
+        SwapInstruction::InitializeFeeVault {} => {
+            Processor::process_initialize_fee_vault(program_id, accounts)
+        }
+        SwapInstruction::DrainFeeVault { amount_lamports, recipient } => {
+            Processor::process_drain_fee_vault(program_id, accounts, amount_lamports, recipient)
+        }
+        SwapInstruction::QueryFeeVaultBalance {} => {
+            Processor::process_query_fee_vault_balance(program_id, accounts)
+        }
+        SwapInstruction::BatchSignedApprovals { approvals } => {
+            Processor::process_batch_signed_approvals(program_id, accounts, approvals)
+        }
+        SwapInstruction::ProposeStepReplacement { step_index, replacement_nft_mints } => {
+            Processor::process_propose_step_replacement(program_id, accounts, step_index, replacement_nft_mints)
+        }
+        SwapInstruction::AcceptStepReplacement { step_index } => {
+            Processor::process_accept_step_replacement(program_id, accounts, step_index)
+        }
+        SwapInstruction::RejectStepReplacement { step_index } => {
+            Processor::process_reject_step_replacement(program_id, accounts, step_index)
+        }
+        SwapInstruction::SetExecutionCondition { account, byte_offset, expected_value } => {
+            Processor::process_set_execution_condition(program_id, accounts, ExecutionCondition { account, byte_offset, expected_value })
+        }
+        SwapInstruction::QueryStepHistory { step_index } => {
+            Processor::process_query_step_history(program_id, accounts, step_index)
+        }
+        SwapInstruction::SetExecutionWhitelist { executors } => {
+            Processor::process_set_execution_whitelist(program_id, accounts, Some(executors))
+        }
+        SwapInstruction::ClearExecutionWhitelist {} => {
+            Processor::process_set_execution_whitelist(program_id, accounts, None)
+        }
+        SwapInstruction::AddTradeStepWithFungibles { step_index, to, nft_mints, spl_legs } => {
+            Processor::process_add_trade_step(program_id, accounts, step_index, to, nft_mints, spl_legs)
+        }
+        SwapInstruction::CreateSealedBidLoop { trade_id, participants, timeout_seconds } => {
+            Processor::process_create_sealed_bid_loop(program_id, accounts, trade_id, participants, timeout_seconds)
+        }
+        SwapInstruction::CommitTradeStep { step_index, nft_mint_commitments } => {
+            Processor::process_commit_trade_step(program_id, accounts, step_index, nft_mint_commitments)
+        }
+        SwapInstruction::ExecuteSealedBidLoop { expected_nonce, reveals } => {
+            Processor::process_execute_sealed_bid_loop(program_id, accounts, expected_nonce, reveals)
+        }
+        SwapInstruction::PauseForMaintenance { reason } => {
+            Processor::process_pause_for_maintenance(program_id, accounts, reason)
+        }
+    }
+}
+
+/// Helper function to load the program config if the config PDA is among the accounts
+fn find_program_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<Option<ProgramConfig>, ProgramError> {
+    let (config_pubkey, _) = utils::get_program_config_address(program_id);
+    
+    for account_info in accounts {
+        if account_info.key == &config_pubkey && account_info.owner == program_id && account_info.data_len() > 0 {
+            let config = ProgramConfig::unpack_versioned(&account_info.data.borrow())
+                .map_err(|_| SwapError::InvalidAccountData)?;
+            
+            if config.is_initialized {
+                return Ok(Some(config));
+            }
+        }
+    }
+    
+    Ok(None)
+}
+
+/// Helper function to verify each wallet has a valid KYC entry while the config requires KYC
+/// The wallets' KYC entry PDAs must then be among the accounts
+fn verify_participant_identity(
+    program_id: &Pubkey,
+    config: Option<&ProgramConfig>,
+    accounts: &[AccountInfo],
+    wallets: &[&Pubkey],
+) -> ProgramResult {
+    if !config.is_some_and(|config| config.kyc_required) {
+        return Ok(());
+    }
+    
+    let current_time = Clock::get()?.unix_timestamp;
+    for wallet in wallets {
+        let (kyc_entry_pubkey, _) = utils::get_kyc_entry_address(wallet, program_id);
+        let kyc_entry = accounts.iter()
+            .find(|account_info| account_info.key == &kyc_entry_pubkey && account_info.owner == program_id)
+            .and_then(|account_info| KycEntry::try_from_slice(&account_info.data.borrow()).ok());
+        
+        if !kyc_entry.is_some_and(|kyc_entry| kyc_entry.wallet == **wallet && kyc_entry.is_valid(current_time)) {
+            msg!("Wallet {} has no valid KYC entry; pass its KYC entry account {}", wallet, kyc_entry_pubkey);
+            return Err(SwapError::ParticipantNotKYCVerified.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to enforce the per-wallet initialization cooldown while rate limiting is enabled
+/// The wallet's cooldown PDA must then be among the accounts; it is created on first use
+fn enforce_participant_cooldown<'a>(
+    program_id: &Pubkey,
+    config: Option<&ProgramConfig>,
+    accounts: &[AccountInfo<'a>],
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    if !config.is_some_and(|config| config.rate_limiting_enabled) {
+        return Ok(());
+    }
+    
+    let (cooldown_pubkey, bump_seed) = utils::get_participant_cooldown_address(payer_info.key, program_id);
+    let cooldown_info = accounts.iter().find(|account_info| account_info.key == &cooldown_pubkey).ok_or_else(|| {
+        msg!("Cooldown account {} is required while rate limiting is enabled", cooldown_pubkey);
+        ProgramError::from(SwapError::InvalidAccountData)
+    })?;
+    
+    let clock = Clock::get()?;
+    
+    let mut cooldown = if cooldown_info.data_len() == 0 {
+        let space = ParticipantCooldown::get_space();
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                cooldown_info.key,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            &[payer_info.clone(), cooldown_info.clone(), system_program_info.clone()],
+            &[&[b"cooldown", payer_info.key.as_ref(), &[bump_seed]]],
+        )?;
+        
+        ParticipantCooldown::default()
+    } else {
+        utils::verify_account_owner(cooldown_info, program_id)?;
+        let cooldown = ParticipantCooldown::deserialize(&mut &cooldown_info.data.borrow()[..])?;
+        
+        if clock.slot.saturating_sub(cooldown.last_init_slot) < COOLDOWN_SLOTS {
+            msg!("Wallet {} must wait {} slots between trade loops (last: slot {}, now: slot {})", 
+                 payer_info.key, COOLDOWN_SLOTS, cooldown.last_init_slot, clock.slot);
+            return Err(SwapError::RateLimitExceeded.into());
+        }
+        
+        cooldown
+    };
+    
+    // The per-epoch count restarts with each new epoch
+    if cooldown.loop_count_this_epoch > 0 && EpochSchedule::get()?.get_epoch(cooldown.last_init_slot) != clock.epoch {
+        cooldown.loop_count_this_epoch = 0;
+    }
+    
+    if cooldown.loop_count_this_epoch >= MAX_LOOPS_PER_EPOCH {
+        msg!("Wallet {} already initialized {} trade loops this epoch", payer_info.key, cooldown.loop_count_this_epoch);
+        return Err(SwapError::RateLimitExceeded.into());
+    }
+    
+    cooldown.last_init_slot = clock.slot;
+    cooldown.loop_count_this_epoch += 1;
+    cooldown.serialize(&mut *cooldown_info.data.borrow_mut())?;
+    
+    Ok(())
+}
+
+/// Helper function to validate the step count and timeout of a new trade loop
+fn validate_trade_loop_parameters(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    step_count: u8,
+    timeout_seconds: u64,
+) -> ProgramResult {
+    // Enforce the maximum step count limit
+    if step_count == 0 {
+        msg!("Trade loop must have at least 1 step");
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    if step_count > MAX_PARTICIPANTS_PER_TRANSACTION {
+        msg!("Trade loop exceeds the maximum allowed steps ({}). Requested: {}", 
+             MAX_PARTICIPANTS_PER_TRANSACTION, step_count);
+        return Err(SwapError::TooManyParticipants.into());
+    }
+    
+    // Validate timeout to prevent excessively long timeouts
+    if timeout_seconds > MAX_TIMEOUT_SECONDS {
+        msg!("Timeout exceeds maximum allowed ({}). Requested: {}", 
+             MAX_TIMEOUT_SECONDS, timeout_seconds);
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Operators can lower the step limit in the program config
+    if let Some(config) = find_program_config(program_id, accounts)? {
+        if step_count > config.max_participants {
+            msg!("Trade loop exceeds the configured maximum steps ({}). Requested: {}", 
+                 config.max_participants, step_count);
+            return Err(SwapError::TooManyParticipants.into());
+        }
+    }
+    
+    // Reject loops that cannot execute within the compute budget even in the
+    // simplest case (one NFT per step with a new recipient token account)
+    let estimated_compute_units = utils::estimate_compute_units(
+        step_count,
+        &vec![1; step_count as usize],
+        &vec![true; step_count as usize],
+    );
+    if estimated_compute_units > MAX_COMPUTE_UNITS_PER_LOOP {
+        msg!("Estimated compute units ({}) exceed the per-loop limit ({})", 
+             estimated_compute_units, MAX_COMPUTE_UNITS_PER_LOOP);
+        return Err(SwapError::EstimatedComputeExceedsLimit.into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to load the blocklist from its account, which instructions admitting
+/// wallets take at a fixed position; until the blocklist is created no wallet is blocked
+fn load_blocklist(program_id: &Pubkey, blocklist_info: &AccountInfo) -> Result<Option<BlockedAccounts>, ProgramError> {
+    let (blocklist_pubkey, _) = utils::get_blocklist_address(program_id);
+    if blocklist_info.key != &blocklist_pubkey {
+        msg!("Expected the blocklist account {}, got {}", blocklist_pubkey, blocklist_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if blocklist_info.data_len() == 0 {
+        return Ok(None);
+    }
+    
+    utils::verify_account_owner(blocklist_info, program_id)?;
+    let blocklist = BlockedAccounts::deserialize(&mut &blocklist_info.data.borrow()[..])
+        .map_err(|_| SwapError::InvalidAccountData)?;
+    
+    Ok(Some(blocklist).filter(|blocklist| blocklist.is_initialized))
+}
+
+/// Helper function to update the program stats if the stats PDA is among the accounts
+fn record_program_stats(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    update: impl FnOnce(&mut ProgramStats),
+) -> ProgramResult {
+    let (stats_pubkey, _) = utils::get_stats_address(program_id);
+    
+    for account_info in accounts {
+        if account_info.key == &stats_pubkey && account_info.owner == program_id && account_info.data_len() > 0 {
+            let mut stats = ProgramStats::deserialize(&mut &account_info.data.borrow()[..])
+                .map_err(|_| SwapError::InvalidAccountData)?;
+            
+            if stats.is_initialized {
+                update(&mut stats);
+                stats.serialize(&mut *account_info.data.borrow_mut())?;
+            }
+            
+            return Ok(());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to load the mint blocklist from its account, which instructions adding
+/// NFTs to a trade take at a fixed position; until the list is created no mint is blocked
+fn load_mint_blocklist(program_id: &Pubkey, mint_blocklist_info: &AccountInfo) -> Result<Option<MintBlocklist>, ProgramError> {
+    let (mint_blocklist_pubkey, _) = utils::get_mint_blocklist_address(program_id);
+    if mint_blocklist_info.key != &mint_blocklist_pubkey {
+        msg!("Expected the mint blocklist account {}, got {}", mint_blocklist_pubkey, mint_blocklist_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if mint_blocklist_info.data_len() == 0 {
+        return Ok(None);
+    }
+    
+    utils::verify_account_owner(mint_blocklist_info, program_id)?;
+    let mint_blocklist = MintBlocklist::deserialize(&mut &mint_blocklist_info.data.borrow()[..])
+        .map_err(|_| SwapError::InvalidAccountData)?;
+    
+    Ok(Some(mint_blocklist).filter(|mint_blocklist| mint_blocklist.is_initialized))
+}
+
+/// Helper function to reject NFT mints on the mint blocklist
+fn verify_mints_not_blocked(mint_blocklist: Option<&MintBlocklist>, nft_mints: &[Pubkey]) -> ProgramResult {
+    if let Some(mint) = mint_blocklist.and_then(|mint_blocklist| nft_mints.iter().find(|mint| mint_blocklist.is_blocked(mint))) {
+        msg!("NFT mint {} is blocked from trading", mint);
+        return Err(SwapError::NftMintBlocked.into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to load the collection whitelist from its account, which instructions adding
+/// NFTs to a trade take at a fixed position; until the whitelist is created any collection is allowed
+fn load_collection_whitelist(program_id: &Pubkey, collection_whitelist_info: &AccountInfo) -> Result<Option<CollectionWhitelist>, ProgramError> {
+    let (collection_whitelist_pubkey, _) = utils::get_collection_whitelist_address(program_id);
+    if collection_whitelist_info.key != &collection_whitelist_pubkey {
+        msg!("Expected the collection whitelist account {}, got {}", collection_whitelist_pubkey, collection_whitelist_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if collection_whitelist_info.data_len() == 0 {
+        return Ok(None);
+    }
+    
+    utils::verify_account_owner(collection_whitelist_info, program_id)?;
+    let collection_whitelist = CollectionWhitelist::deserialize(&mut &collection_whitelist_info.data.borrow()[..])
+        .map_err(|_| SwapError::InvalidAccountData)?;
+    
+    Ok(Some(collection_whitelist).filter(|collection_whitelist| collection_whitelist.is_initialized))
+}
+
+/// Helper function to find the metadata account of an NFT among the accounts
+fn find_nft_metadata<'a, 'b>(accounts: &'b [AccountInfo<'a>], nft_mint: &Pubkey, error: SwapError) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    let (metadata_key, _) = utils::get_metadata_address(nft_mint);
+    accounts.iter().find(|account_info| account_info.key == &metadata_key).ok_or_else(|| {
+        msg!("Metadata account of {} is required to verify its collection", nft_mint);
+        ProgramError::from(error)
+    })
+}
+
+/// Helper function to verify each NFT is a verified member of a whitelisted collection
+/// Only applies when the collection whitelist is not empty; the metadata account of
+/// every NFT must then be among the accounts
+fn verify_nft_collections_on_chain(collection_whitelist: Option<&CollectionWhitelist>, accounts: &[AccountInfo], nft_mints: &[Pubkey]) -> ProgramResult {
+    let collection_whitelist = match collection_whitelist {
+        Some(collection_whitelist) if !collection_whitelist.collections.is_empty() => collection_whitelist,
+        _ => return Ok(()),
+    };
+    
+    for nft_mint in nft_mints {
+        let metadata_info = find_nft_metadata(accounts, nft_mint, SwapError::UnverifiedCollectionMember)?;
+        utils::verify_verified_collection_member(nft_mint, metadata_info, &collection_whitelist.collections)?;
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify each NFT's collection floor price meets the configured minimum
+/// Only applies when the program config sets a minimum floor price; each NFT
+/// must then belong to a verified collection with a Pyth price feed in the collection whitelist,
+/// and its metadata and price feed accounts must be among the accounts
+fn verify_nft_floor_prices(
+    config: Option<&ProgramConfig>,
+    collection_whitelist: Option<&CollectionWhitelist>,
+    accounts: &[AccountInfo],
+    nft_mints: &[Pubkey],
+) -> ProgramResult {
+    let min_usd_cents = match config.and_then(|config| config.min_nft_floor_price_usd_cents) {
+        Some(min_usd_cents) => min_usd_cents,
+        None => return Ok(()),
+    };
+    
+    let collection_whitelist = collection_whitelist.ok_or_else(|| {
+        msg!("Collection whitelist is required to look up floor price feeds");
+        ProgramError::from(SwapError::NftBelowFloorPrice)
+    })?;
+    let current_time = Clock::get()?.unix_timestamp;
+    
+    for nft_mint in nft_mints {
+        let metadata_info = find_nft_metadata(accounts, nft_mint, SwapError::NftBelowFloorPrice)?;
+        let feed_id = utils::read_verified_collection(nft_mint, metadata_info)?
+            .and_then(|collection| collection_whitelist.price_feed(&collection))
+            .ok_or_else(|| {
+                msg!("NFT {} has no collection with a floor price feed", nft_mint);
+                ProgramError::from(SwapError::NftBelowFloorPrice)
+            })?;
+        
+        let (price_feed_key, _) = utils::get_pyth_price_feed_address(&feed_id);
+        let price_info = accounts.iter().find(|account_info| account_info.key == &price_feed_key).ok_or_else(|| {
+            msg!("Pyth price feed account {} is required for NFT {}", price_feed_key, nft_mint);
+            ProgramError::from(SwapError::InvalidAccountData)
+        })?;
+        let price = utils::read_pyth_price(price_info, &feed_id)?;
+        
+        if current_time.saturating_sub(price.publish_time) > MAX_ORACLE_PRICE_AGE_SECONDS {
+            msg!("Floor price of NFT {} was published at {}, more than {} seconds ago", 
+                 nft_mint, price.publish_time, MAX_ORACLE_PRICE_AGE_SECONDS);
+            return Err(SwapError::OraclePriceStale.into());
+        }
+        
+        let floor_usd_cents = price.to_usd_cents().unwrap_or(0);
+        if floor_usd_cents < min_usd_cents {
+            msg!("Floor price of NFT {} is {} cents, below the minimum of {} cents", nft_mint, floor_usd_cents, min_usd_cents);
+            return Err(SwapError::NftBelowFloorPrice.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to record an execution in the dedup cache, which ExecuteFullTradeLoop
+/// takes at a fixed position; nothing is recorded until the cache is created
+fn record_execution(
+    program_id: &Pubkey,
+    dedup_cache_info: &AccountInfo,
+    trade_loop: &Pubkey,
+    slot: u64,
+    executor: &Pubkey,
+) -> ProgramResult {
+    let (dedup_cache_pubkey, _) = utils::get_dedup_cache_address(program_id);
+    if dedup_cache_info.key != &dedup_cache_pubkey {
+        msg!("Expected the dedup cache account {}, got {}", dedup_cache_pubkey, dedup_cache_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if dedup_cache_info.data_len() == 0 {
+        return Ok(());
+    }
+    
+    utils::verify_account_owner(dedup_cache_info, program_id)?;
+    let mut dedup_cache = ProcessedInstructions::deserialize(&mut &dedup_cache_info.data.borrow()[..])
+        .map_err(|_| SwapError::InvalidAccountData)?;
+    
+    if dedup_cache.is_initialized {
+        if !dedup_cache.insert(ProcessedInstructions::execution_hash(trade_loop, slot, executor)) {
+            msg!("Execution of {} by {} was already processed in slot {}", trade_loop, executor, slot);
+            return Err(SwapError::DuplicateInstruction.into());
+        }
+        dedup_cache.serialize(&mut *dedup_cache_info.data.borrow_mut())?;
+    }
+    
+    Ok(())
+}
+
+/// Helper function to load the program config from an account an instruction takes at a fixed
+/// position, so the checks it enables cannot be skipped; before the config is created the defaults apply
+fn load_optional_program_config(program_id: &Pubkey, config_info: &AccountInfo) -> Result<Option<ProgramConfig>, ProgramError> {
+    let (config_pubkey, _) = utils::get_program_config_address(program_id);
+    if config_info.key != &config_pubkey {
+        msg!("Expected the program config account {}, got {}", config_pubkey, config_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if config_info.data_len() == 0 {
+        return Ok(None);
+    }
+    
+    load_program_config(program_id, config_info).map(Some)
+}
+
+/// Helper function to load and validate the program config account
+fn load_program_config(program_id: &Pubkey, config_info: &AccountInfo) -> Result<ProgramConfig, ProgramError> {
+    // Verify the config account is owned by this program
+    utils::verify_account_owner(config_info, program_id)?;
+    
+    // Verify that the provided config account matches the expected PDA
+    let (expected_config_key, _) = utils::get_program_config_address(program_id);
+    if config_info.key != &expected_config_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let config = ProgramConfig::unpack_versioned(&config_info.data.borrow())?;
+    
+    // Ensure the config is initialized
+    if !config.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok(config)
+}
+
+/// Helper function to verify a governed change is within the limits the config accepts
+fn verify_governed_change(change: &GovernedChange) -> ProgramResult {
+    match *change {
+        GovernedChange::SetMaxParticipants(max_participants) => {
+            if !(2..=MAX_PARTICIPANTS_PER_TRANSACTION).contains(&max_participants) {
+                msg!("Max participants must be between 2 and {}. Proposed: {}", 
+                     MAX_PARTICIPANTS_PER_TRANSACTION, max_participants);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        GovernedChange::UpdateFeeConfig(fee_basis_points, _) => {
+            if fee_basis_points > 10_000 {
+                msg!("Fee cannot exceed 10000 basis points. Proposed: {}", fee_basis_points);
+                return Err(SwapError::InvalidInstructionData.into());
+            }
+        }
+        GovernedChange::UpdateAuthority(_) | GovernedChange::EmergencyPause => {}
+    }
+    
+    Ok(())
+}
+
+/// Helper function to load and validate a governance proposal account
+fn load_governance_proposal(
+    program_id: &Pubkey,
+    proposal_info: &AccountInfo,
+    proposal_id: &[u8; 16],
+) -> Result<GovernanceProposal, ProgramError> {
+    // Verify the proposal account is owned by this program
+    utils::verify_account_owner(proposal_info, program_id)?;
+    
+    // Verify that the provided proposal account matches the expected PDA
+    let (expected_proposal_key, _) = utils::get_proposal_address(proposal_id, program_id);
+    if proposal_info.key != &expected_proposal_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let proposal = GovernanceProposal::try_from_slice(&proposal_info.data.borrow())?;
+    
+    // Ensure the proposal is initialized
+    if !proposal.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok(proposal)
+}
+
+/// Helper function to read a holder's governance token balance from their token account
+fn governance_token_balance(
+    config: &ProgramConfig,
+    holder: &Pubkey,
+    token_account_info: &AccountInfo,
+) -> Result<u64, ProgramError> {
+    let governance_token_mint = config.governance_token_mint.ok_or_else(|| {
+        msg!("No governance token mint is configured");
+        SwapError::InvalidAccountData
+    })?;
+    
+    // Verify the token account is owned by the token program
+    utils::verify_token_account_owner(token_account_info)?;
+    
+    let token_account = spl_token::state::Account::unpack(&token_account_info.data.borrow())?;
+    
+    if token_account.owner != *holder {
+        return Err(SwapError::InvalidAccountOwner.into());
+    }
+    
+    if token_account.mint != governance_token_mint {
+        msg!("Token account {} is not for the governance token mint {}", token_account_info.key, governance_token_mint);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    Ok(token_account.amount)
+}
+
+/// Helper function to load and validate the counter-offer account for a step
+fn load_counter_offer(
+    program_id: &Pubkey,
+    trade_loop_key: &Pubkey,
+    counter_offer_info: &AccountInfo,
+    step_index: u8,
+) -> Result<CounterOffer, ProgramError> {
+    // Verify the counter-offer account is owned by this program
+    utils::verify_account_owner(counter_offer_info, program_id)?;
+    
+    // Verify that the provided counter-offer account matches the expected PDA
+    let (expected_counter_offer_key, _) = utils::get_counter_offer_address(trade_loop_key, step_index, program_id);
+    if counter_offer_info.key != &expected_counter_offer_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let counter_offer = CounterOffer::try_from_slice(&counter_offer_info.data.borrow())?;
+    
+    // Ensure the counter-offer is initialized
+    if !counter_offer.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok(counter_offer)
+}
+
+/// Helper function to load and validate a step replacement proposal account
+fn load_step_replacement_proposal(
+    program_id: &Pubkey,
+    trade_loop_key: &Pubkey,
+    proposal_info: &AccountInfo,
+    step_index: u8,
+) -> Result<StepReplacementProposal, ProgramError> {
+    // Verify the proposal account is owned by this program
+    utils::verify_account_owner(proposal_info, program_id)?;
+    
+    // Verify that the provided proposal account matches the expected PDA
+    let (expected_proposal_key, _) = utils::get_step_replacement_address(trade_loop_key, step_index, program_id);
+    if proposal_info.key != &expected_proposal_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let proposal = StepReplacementProposal::try_from_slice(&proposal_info.data.borrow())?;
+    
+    // Ensure the proposal is initialized
+    if !proposal.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok(proposal)
+}
+
+/// Helper function to load and validate an open offer account, returning it with its PDA bump seed
+fn load_open_offer(
+    program_id: &Pubkey,
+    offer_info: &AccountInfo,
+    offer_id: &[u8; 16],
+) -> Result<(OpenOffer, u8), ProgramError> {
+    // Verify the offer account is owned by this program
+    utils::verify_account_owner(offer_info, program_id)?;
+    
+    // Verify that the provided offer account matches the expected PDA
+    let (expected_offer_key, bump_seed) = utils::get_open_offer_address(offer_id, program_id);
+    if offer_info.key != &expected_offer_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let offer = OpenOffer::try_from_slice(&offer_info.data.borrow())?;
+    
+    // Ensure the offer is initialized
+    if !offer.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok((offer, bump_seed))
+}
+
+/// Helper function to load and validate a partial fill offer, returning it with its PDA bump seed
+fn load_partial_fill_offer(
+    program_id: &Pubkey,
+    offer_info: &AccountInfo,
+    offerer: &Pubkey,
+    offer_id: &[u8; 16],
+) -> Result<(PartialFillOffer, u8), ProgramError> {
+    // Verify the offer account is owned by this program
+    utils::verify_account_owner(offer_info, program_id)?;
+    
+    // Verify that the provided offer account matches the expected PDA
+    let (expected_offer_key, bump_seed) = utils::get_partial_fill_offer_address(offerer, offer_id, program_id);
+    if offer_info.key != &expected_offer_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let offer = PartialFillOffer::try_from_slice(&offer_info.data.borrow())?;
+    
+    // Ensure the offer is initialized
+    if !offer.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    Ok((offer, bump_seed))
+}
+
+/// Helper function to load an offer book and find one of its offers, returning the book,
+/// the offer's position and the book's PDA bump seed
+fn load_offer_book_entry(
+    program_id: &Pubkey,
+    book_info: &AccountInfo,
+    offer_id: &[u8; 16],
+) -> Result<(OfferBook, usize, u8), ProgramError> {
+    // Verify the offer book account is owned by this program
+    utils::verify_account_owner(book_info, program_id)?;
+    
+    let book = OfferBook::try_from_slice(&book_info.data.borrow())?;
+    
+    // Ensure the offer book is initialized
+    if !book.is_initialized {
+        return Err(SwapError::UninitializedAccount.into());
+    }
+    
+    let position = book.position(offer_id).ok_or_else(|| {
+        msg!("Offer {:?} is not listed in offer book {}", offer_id, book_info.key);
+        SwapError::InvalidInstructionData
+    })?;
+    
+    // Verify the book is the PDA of the collection the offer wants
+    let (expected_book_key, bump_seed) = utils::get_offer_book_address(&book.entries[position].wanted_collection, program_id);
+    if book_info.key != &expected_book_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    Ok((book, position, bump_seed))
+}
+
+/// Helper function to verify the canceller may cancel the trade loop
+fn verify_cancellation_allowed(trade_loop: &TradeLoop, canceller: &Pubkey) -> ProgramResult {
+    // Check if the canceller is a participant
+    let user_step_index = trade_loop.steps.iter().position(|step| step.from == *canceller);
+    
+    if user_step_index.is_none() {
+        msg!("Canceller is not a participant in this trade loop");
+        return Err(SwapError::InvalidAccountOwner.into());
+    }
+    
+    // Nobody can cancel during the lockout after full approval, even if a step
+    // has since been marked failed
+    if let Some(all_approved_at) = trade_loop.all_approved_at {
+        let lockout_ends_at = all_approved_at.saturating_add(trade_loop.cancel_lockout_seconds);
+        if (Clock::get()?.unix_timestamp as u64) < lockout_ends_at {
+            msg!("Cancellation is locked until {}", lockout_ends_at);
+            return Err(SwapError::CancellationTimelocked.into());
+        }
+    }
+    
+    if trade_loop.is_cancellation_eligible() {
+        // A failed step means the loop can never execute, so approvals no longer bind anyone
+        msg!("Trade loop has a failed or cancelled step, cancellation allowed for any participant");
+    } else {
+        // Get the user's step 
+        let user_step = &trade_loop.steps[user_step_index.unwrap()];
+        
+        // CRITICAL: Only allow cancellation if the user's step is not yet approved
+        // This prevents users from backing out after committing
+        if user_step.status != StepStatus::Created {
+            msg!("Cannot cancel trade after approving. Your step status: {:?}", user_step.status);
+            return Err(SwapError::CancellationDenied.into());
+        }
+        
+        // Check if any other steps are already approved
+        let any_approved_steps = trade_loop.steps.iter()
+            .any(|step| step.status == StepStatus::Approved);
+        
+        if any_approved_steps {
+            msg!("Cannot cancel trade when other participants have already approved");
+            return Err(SwapError::CancellationDenied.into());
+        }
+    }
+    
+    // Frozen token accounts would be stranded once the loop state is wiped
+    if trade_loop.has_locked_nfts() {
+        msg!("Locked step NFTs must be unlocked before cancelling");
+        return Err(SwapError::StepNftsLocked.into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to return a step's escrowed NFTs to its sender
+/// Consumes an (escrow authority, escrow token account, sender token account) triple per
+/// escrowed NFT. NFTs whose transfer would fail are skipped instead, since a failed
+/// transfer would abort the whole transaction; returns whether every NFT was released.
+fn release_step_escrow<'a, 'b>(
+    program_id: &Pubkey,
+    trade_loop: &Pubkey,
+    step: &mut TradeStep,
+    token_program_info: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> Result<bool, ProgramError> {
+    let mut all_released = true;
+    
+    for (nft_mint, escrowed) in step.nft_mints.iter().zip(step.escrowed.iter_mut()) {
+        if !*escrowed {
+            continue;
+        }
+        
+        let (escrow_authority_info, escrow_token_account_info, sender_token_account_info) =
+            match (account_info_iter.next(), account_info_iter.next(), account_info_iter.next()) {
+                (Some(authority), Some(escrow), Some(sender)) => (authority, escrow, sender),
+                _ => {
+                    msg!("Accounts for escrowed NFT {} not provided", nft_mint);
+                    all_released = false;
+                    continue;
+                }
+            };
+        
+        let (expected_escrow_authority, _) = utils::get_escrow_address(trade_loop, nft_mint, program_id);
+        let transferable = |token_account_info: &AccountInfo| {
+            token_account_info.owner == &spl_token::id()
+                && spl_token::state::Account::unpack(&token_account_info.data.borrow())
+                    .is_ok_and(|account| account.mint == *nft_mint && !account.is_frozen())
+        };
+        let releasable = escrow_authority_info.key == &expected_escrow_authority
+            && verify_escrow_holds_nft(program_id, trade_loop, nft_mint, escrow_token_account_info).is_ok()
+            && transferable(escrow_token_account_info)
+            && utils::verify_token_account_address(sender_token_account_info, &step.from, nft_mint).is_ok()
+            && transferable(sender_token_account_info);
+        if !releasable {
+            msg!("Escrowed NFT {} cannot be returned to {}", nft_mint, step.from);
+            all_released = false;
+            continue;
+        }
+        
+        utils::transfer_nft_from_escrow(
+            escrow_token_account_info,
+            sender_token_account_info,
+            escrow_authority_info,
+            token_program_info,
+            trade_loop,
+            nft_mint,
+            program_id,
+        )?;
+        *escrowed = false;
+        
+        msg!("Returned escrowed NFT {} to {}", nft_mint, step.from);
+    }
+    
+    Ok(all_released)
+}
+
+/// Helper function to close a program-owned account, moving its lamports to the recipient
+fn close_program_account(account_info: &AccountInfo, recipient_info: &AccountInfo) -> ProgramResult {
+    account_info.data.borrow_mut().fill(0);
+    
+    let reclaimed_lamports = account_info.lamports();
+    **recipient_info.lamports.borrow_mut() = recipient_info.lamports()
+        .checked_add(reclaimed_lamports)
+        .ok_or(SwapError::InvalidAccountData)?;
+    **account_info.lamports.borrow_mut() = 0;
+    
+    Ok(())
+}
+
+/// Helper function to verify the signer is the upgrade authority or the governance account
+fn verify_config_authority(config: &ProgramConfig, signer: &Pubkey) -> ProgramResult {
+    if config.upgrade_authority == *signer || config.governance == Some(*signer) {
+        return Ok(());
+    }
+    
+    Err(SwapError::UpgradeAuthorityMismatch.into())
+}
+
+/// Helper function to pay Metaplex creator royalties for an NFT out of the sender's lamports
+/// Creator accounts are consumed from the iterator, one per verified creator in metadata order
+fn pay_creator_royalties<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    mint_info: &AccountInfo<'a>,
+    metadata_info: &AccountInfo<'a>,
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    nft_value_lamports: u64,
+    royalty_enforcement: &RoyaltyEnforcement,
+) -> ProgramResult {
+    // A zero value would let the sender skip the royalty entirely
+    if nft_value_lamports == 0 {
+        msg!("NFT {} has no agreed value", mint_info.key);
+        return Err(SwapError::NftValueNotAgreed.into());
+    }
+    
+    let metadata = utils::read_metaplex_metadata(mint_info, metadata_info)?;
+    
+    let royalty_lamports = (nft_value_lamports as u128)
+        .checked_mul(metadata.seller_fee_basis_points as u128)
+        .ok_or(SwapError::RoyaltyTransferFailed)?
+        / 10_000;
+    
+    if royalty_lamports == 0 {
+        msg!("No royalty due for NFT {}", mint_info.key);
+        return Ok(());
+    }
+    
+    let creators = metadata.creators.unwrap_or_default();
+    if creators.len() > royalty_enforcement.max_creators as usize {
+        msg!("NFT {} lists {} creators, more than the allowed {}", 
+             mint_info.key, creators.len(), royalty_enforcement.max_creators);
+        return Err(SwapError::InvalidMetadataAccount.into());
+    }
+    
+    // Only verified creators are paid, so spoofed creator entries receive nothing
+    let verified_creators: Vec<_> = creators.iter().filter(|creator| creator.verified).collect();
+    if verified_creators.is_empty() {
+        msg!("NFT {} has no verified creators to receive royalties", mint_info.key);
+        return Err(SwapError::MetadataCreatorNotVerified.into());
+    }
+    
+    if (payer_info.lamports() as u128) < royalty_lamports {
+        msg!("Sender cannot cover the royalty of {} lamports", royalty_lamports);
+        return Err(SwapError::InsufficientFunds.into());
+    }
+    
+    for creator in verified_creators {
+        let creator_info = next_account_info(account_info_iter)?;
+        
+        if creator_info.key != &creator.address {
+            msg!("Creator account mismatch. Expected: {}, Found: {}", creator.address, creator_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        let creator_royalty = (royalty_lamports * creator.share as u128 / 100) as u64;
+        if creator_royalty == 0 {
+            continue;
+        }
+        
+        invoke(
+            &system_instruction::transfer(payer_info.key, creator_info.key, creator_royalty),
+            &[payer_info.clone(), creator_info.clone(), system_program_info.clone()],
+        ).map_err(|err| {
+            msg!("Royalty transfer to {} failed: {}", creator_info.key, err);
+            SwapError::RoyaltyTransferFailed
+        })?;
+        
+        msg!("Paid {} lamports royalty to creator {}", creator_royalty, creator_info.key);
+    }
+    
+    Ok(())
+}
+
+/// Program-wide lists checked when a step is added, loaded from the accounts
+/// every step-adding instruction takes right after its fixed accounts
+struct StepGuards {
+    blocklist: Option<BlockedAccounts>,
+    mint_blocklist: Option<MintBlocklist>,
+    collection_whitelist: Option<CollectionWhitelist>,
+    config: Option<ProgramConfig>,
+}
+
+impl StepGuards {
+    fn load<'a, 'b>(program_id: &Pubkey, account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>) -> Result<Self, ProgramError> {
+        Ok(Self {
+            blocklist: load_blocklist(program_id, next_account_info(account_info_iter)?)?,
+            mint_blocklist: load_mint_blocklist(program_id, next_account_info(account_info_iter)?)?,
+            collection_whitelist: load_collection_whitelist(program_id, next_account_info(account_info_iter)?)?,
+            config: load_optional_program_config(program_id, next_account_info(account_info_iter)?)?,
+        })
+    }
+}
+
+/// Helper function to validate a new trade step and add it to the trade loop (or replace the step at its index)
+/// `verify_ownership` checks that the sender holds every NFT in the step
+#[allow(clippy::too_many_arguments)]
+fn add_step_to_loop(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    guards: &StepGuards,
+    trade_loop: &mut TradeLoop,
+    from: &Pubkey,
+    step_index: u8,
+    to: Pubkey,
+    nft_mints: Vec<Pubkey>,
+    verify_ownership: impl FnOnce(&[Pubkey]) -> ProgramResult,
+) -> ProgramResult {
+    // Sealed-bid steps commit to their NFTs with CommitTradeStep instead
+    if trade_loop.commitment_mode {
+        msg!("Steps of a sealed-bid trade loop are added with CommitTradeStep");
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Ensure the step index is valid; new steps are appended without gaps
+    if step_index as usize > trade_loop.steps.len() || step_index >= MAX_PARTICIPANTS_PER_TRANSACTION {
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Adding a step beyond the allocated ones grows the loop, which its authority must approve
+    if step_index >= trade_loop.allocated_step_count {
+        if !accounts.iter().any(|account_info| account_info.key == &trade_loop.authority && account_info.is_signer) {
+            msg!("Step {} is beyond the {} allocated steps; the trade loop authority must sign", 
+                 step_index, trade_loop.allocated_step_count);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        trade_loop.allocated_step_count = step_index + 1;
+        msg!("Trade loop grown to {} steps", trade_loop.allocated_step_count);
+    }
+    
+    // Ensure there is at least one NFT to transfer
+    if nft_mints.is_empty() {
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Each step holds at most its NFT limit (MAX_NFTS_PER_STEP unless set at initialization)
+    if nft_mints.len() > trade_loop.step_nft_limit(step_index) as usize {
+        msg!("Step has {} NFTs, maximum for step {} is {}", nft_mints.len(), step_index, trade_loop.step_nft_limit(step_index));
+        return Err(SwapError::TooManyNftsInStep.into());
+    }
+    
+    // Blocked wallets cannot join the loop, but wallets that already participate
+    // can still add the steps needed to complete it
+    if let Some(blocklist) = &guards.blocklist {
+        for wallet in [from, &to] {
+            let already_participant = trade_loop.steps.iter()
+                .any(|step| step.from == *wallet || step.to == *wallet);
+            if blocklist.is_blocked(wallet) && !already_participant {
+                msg!("Wallet {} is blocked from joining trade loops", wallet);
+                return Err(SwapError::ParticipantBlocked.into());
+            }
+        }
+    }
+    
+    // Regulated deployments only admit KYC-verified participants
+    verify_participant_identity(program_id, guards.config.as_ref(), accounts, &[from, &to])?;
+    
+    // Verify that the sender owns all the NFTs they're committing to trade
+    verify_ownership(&nft_mints)?;
+    verify_mints_not_blocked(guards.mint_blocklist.as_ref(), &nft_mints)?;
+    verify_nft_collections_on_chain(guards.collection_whitelist.as_ref(), accounts, &nft_mints)?;
+    verify_nft_floor_prices(guards.config.as_ref(), guards.collection_whitelist.as_ref(), accounts, &nft_mints)?;
+    
+    if let Some(existing_step) = trade_loop.steps.get(step_index as usize) {
+        // Refuse to overwrite a step whose NFTs are still held in escrow
+        if existing_step.escrowed.iter().any(|escrowed| *escrowed) {
+            msg!("Step {} has escrowed NFTs and cannot be replaced", step_index);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Or whose token accounts are still frozen
+        if existing_step.nfts_locked {
+            msg!("Step {} has locked NFTs and cannot be replaced", step_index);
+            return Err(SwapError::StepNftsLocked.into());
+        }
+        
+        // A step's bond is refunded to its sender, so only that sender may replace it
+        if trade_loop.execution_bond_lamports > 0 && !existing_step.nft_mints.is_empty() && existing_step.from != *from {
+            msg!("Step {} is bonded by {} and cannot be replaced", step_index, existing_step.from);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        // Steps pre-populated from a template must keep their topology
+        if existing_step.nft_mints.is_empty()
+            && (existing_step.from != *from || existing_step.to != to)
+        {
+            msg!("Step {} is reserved for {} -> {}", step_index, existing_step.from, existing_step.to);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+    }
+    
+    // A sender replacing their own step keeps its timelock, which must leave time to execute
+    let not_before = trade_loop.steps.get(step_index as usize)
+        .filter(|existing_step| existing_step.from == *from)
+        .and_then(|existing_step| existing_step.not_before);
+    if let Some(not_before) = not_before {
+        if not_before >= trade_loop.expires_at {
+            msg!("Step {} timelock {} is not before the trade loop expiry {}", step_index, not_before, trade_loop.expires_at);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+    }
+    
+    // Create the new trade step
+    let escrowed = vec![false; nft_mints.len()];
+    let nft_values_lamports = vec![0; nft_mints.len()];
+    let new_step = TradeStep {
+        from: *from,
+        to,
+        nft_mints,
+        status: StepStatus::Created,
+        escrowed,
+        nft_values_lamports,
+        metadata_uri: None,
+        delegate_approval: None,
+        sol_amount: 0,
+        nfts_locked: false,
+        step_expires_at: None,
+        spl_token_legs: Vec::new(),
+        nft_mint_commitments: Vec::new(),
+        added_at_slot: Clock::get()?.slot,
+        approved_at_slot: None,
+        executed_at_slot: None,
+        not_before,
+    };
+    
+    // Add or replace the step at the specified index
+    if step_index as usize >= trade_loop.steps.len() {
+        trade_loop.steps.push(new_step);
+    } else {
+        trade_loop.steps[step_index as usize] = new_step;
+    }
+    trade_loop.record_step_hash(step_index);
+    
+    Ok(())
+}
+
+/// Helper function to top up the execution bond of a step in a bonded trade loop
+/// The step's bond PDA and the system program are found among the accounts
+fn deposit_execution_bond<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    trade_loop: &TradeLoop,
+    trade_loop_key: &Pubkey,
+    sender_info: &AccountInfo<'a>,
+    step_index: u8,
+) -> ProgramResult {
+    if trade_loop.execution_bond_lamports == 0 {
+        return Ok(());
+    }
+    
+    let (bond_key, _) = utils::get_execution_bond_address(trade_loop_key, step_index, program_id);
+    let bond_info = accounts.iter().find(|account_info| account_info.key == &bond_key);
+    let system_program_info = accounts.iter().find(|account_info| account_info.key == &solana_program::system_program::id());
+    let (bond_info, system_program_info) = match (bond_info, system_program_info) {
+        (Some(bond_info), Some(system_program_info)) => (bond_info, system_program_info),
+        _ => {
+            msg!("Step {} requires an execution bond; pass its bond PDA {} and the system program", step_index, bond_key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+    };
+    
+    // A replaced step keeps the bond its sender already deposited
+    let shortfall = trade_loop.execution_bond_lamports.saturating_sub(bond_info.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(sender_info.key, bond_info.key, shortfall),
+            &[sender_info.clone(), bond_info.clone(), system_program_info.clone()],
+        )?;
+    }
+    
+    msg!("Step {} execution bond of {} lamports deposited", step_index, trade_loop.execution_bond_lamports);
+    
+    Ok(())
+}
+
+/// Helper function to move all lamports of a step's bond PDA to the recipient
+/// Returns the lamports paid out
+fn pay_out_execution_bond<'a>(
+    program_id: &Pubkey,
+    trade_loop_key: &Pubkey,
+    step_index: u8,
+    bond_info: &AccountInfo<'a>,
+    recipient_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+    let (bond_key, bump_seed) = utils::get_execution_bond_address(trade_loop_key, step_index, program_id);
+    if bond_info.key != &bond_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    let bond_lamports = bond_info.lamports();
+    if bond_lamports == 0 {
+        return Ok(0);
+    }
+    
+    invoke_signed(
+        &system_instruction::transfer(bond_info.key, recipient_info.key, bond_lamports),
+        &[bond_info.clone(), recipient_info.clone(), system_program_info.clone()],
+        &[&[b"bond", trade_loop_key.as_ref(), &step_index.to_le_bytes(), &[bump_seed]]],
+    )?;
+    
+    Ok(bond_lamports)
+}
+
+/// Helper function to refund every step's execution bond of a bonded trade loop to its sender
+/// The bond PDAs, the senders (writable) and the system program are found among the accounts
+fn refund_execution_bonds(
+    program_id: &Pubkey,
+    trade_loop_key: &Pubkey,
+    trade_loop: &TradeLoop,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if trade_loop.execution_bond_lamports == 0 {
+        return Ok(());
+    }
+    
+    let system_program_info = accounts.iter()
+        .find(|account_info| account_info.key == &solana_program::system_program::id())
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    
+    for (step_index, step) in trade_loop.steps.iter().enumerate() {
+        let (bond_key, _) = utils::get_execution_bond_address(trade_loop_key, step_index as u8, program_id);
+        let bond_info = accounts.iter().find(|account_info| account_info.key == &bond_key);
+        let sender_info = accounts.iter().find(|account_info| account_info.key == &step.from && account_info.is_writable);
+        let (bond_info, sender_info) = match (bond_info, sender_info) {
+            (Some(bond_info), Some(sender_info)) => (bond_info, sender_info),
+            _ => {
+                msg!("Refunding step {} needs its bond PDA {} and writable sender {}", step_index, bond_key, step.from);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+        };
+        
+        let bond_lamports = pay_out_execution_bond(program_id, trade_loop_key, step_index as u8, bond_info, sender_info, system_program_info)?;
+        if bond_lamports > 0 {
+            msg!("Refunded execution bond of {} lamports to {}", bond_lamports, step.from);
+        }
+    }
+    
+    Ok(())
+}
+
+/// Consumes a (mint, source token account) pair from the iterator per NFT
+fn verify_sender_owns_nfts<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    sender: &Pubkey,
+    nft_mints: &[Pubkey],
+) -> ProgramResult {
+    // Check for duplicate NFTs in the list
+    let mut unique_nfts = std::collections::HashSet::new();
+    for nft_mint in nft_mints {
+        if !unique_nfts.insert(*nft_mint) {
+            msg!("Duplicate NFT mint found: {}", nft_mint);
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+    }
+    
+    for nft_mint in nft_mints {
+        // Get accounts for this specific NFT
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        
+        // Verify the mint account matches the expected mint
+        if mint_info.key != nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(mint_info)?;
+        
+        // Verify the token account is owned by the mint's token program (SPL Token or Token-2022)
+        utils::verify_account_owner(source_token_account_info, mint_info.owner)?;
+        
+        // Verify the token account is the expected ATA for this wallet/mint
+        utils::verify_token_account_address_for_program(source_token_account_info, sender, mint_info.key, mint_info.owner)?;
+        
+        // Verify the token account belongs to the sender and contains the NFT
+        let source_token_account = utils::unpack_token_account(source_token_account_info)?;
+        
+        if source_token_account.owner != *sender {
+            msg!("Token account {} is not owned by sender {}", source_token_account_info.key, sender);
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.mint != *mint_info.key {
+            msg!("Token account {} does not match mint {}", source_token_account_info.key, mint_info.key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the sender has the NFT (amount should be 1 for NFTs)
+        if source_token_account.amount < 1 {
+            msg!("Token account {} has insufficient balance for NFT {}", source_token_account_info.key, mint_info.key);
+            return Err(SwapError::InsufficientFunds.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to fully verify one NFT of a step: strict metadata checks and current ownership
+fn verify_nft_for_step<'a>(
+    nft_mint: &Pubkey,
+    expected_owner: &Pubkey,
+    mint_info: &AccountInfo<'a>,
+    token_account_info: &AccountInfo<'a>,
+    metadata_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    // Verify the mint account matches the expected mint
+    if mint_info.key != nft_mint {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    utils::verify_nft_metadata_with_mode(mint_info, Some(metadata_info), NftVerificationMode::Strict)?;
+    
+    // Verify the token account is owned by the token program
+    utils::verify_token_account_owner(token_account_info)?;
+    
+    let token_account = spl_token::state::Account::unpack(&token_account_info.data.borrow())?;
+    
+    if token_account.mint != *nft_mint {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if token_account.owner != *expected_owner {
+        return Err(SwapError::InvalidAccountOwner.into());
+    }
+    
+    if token_account.amount < 1 {
+        return Err(SwapError::InsufficientFunds.into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to transfer the NFTs of a step whose status was already set to Executed
+/// Consumes the step's participant accounts followed by a (mint, source, destination) triple per NFT
+/// The lock authority (program config) is only needed when the step's NFTs were locked
+/// With royalty enforcement, each NFT's metadata and creator accounts are consumed from
+/// `royalty_account_iter`, which callers keep separate so the step accounts stay fixed-size
+#[allow(clippy::too_many_arguments)]
+fn execute_step_transfers<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    royalty_account_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    accounts: &'b [AccountInfo<'a>],
+    program_id: &Pubkey,
+    step: &TradeStep,
+    trade_loop_info: &AccountInfo<'a>,
+    executor_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    associated_token_program_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent_info: &AccountInfo<'a>,
+    lock_authority_info: Option<&AccountInfo<'a>>,
+    royalty_enforcement: Option<&RoyaltyEnforcement>,
+) -> ProgramResult {
+    // Get participant accounts for this step
+    let sender_info = next_account_info(account_info_iter)?;
+    let recipient_info = next_account_info(account_info_iter)?;
+    
+    // Verify the participants match the expected step
+    if step.from != *sender_info.key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if step.to != *recipient_info.key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    // Pay the step's SOL leg before its NFTs
+    transfer_step_sol(step, sender_info, recipient_info, system_program_info, &Rent::from_account_info(rent_info)?)?;
+    
+    // Process each NFT in this step
+    for (nft_index, nft_mint) in step.nft_mints.iter().enumerate() {
+        // Get accounts for this specific NFT
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        let destination_token_account_info = next_account_info(account_info_iter)?;
+        
+        // Verify that the mint account matches the expected mint
+        if mint_info.key != nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(mint_info)?;
+        
+        // Pay creator royalties before completing the transfer
+        if let Some(royalty_enforcement) = royalty_enforcement {
+            let metadata_info = next_account_info(royalty_account_iter)?;
+            pay_creator_royalties(
+                royalty_account_iter,
+                mint_info,
+                metadata_info,
+                sender_info,
+                system_program_info,
+                step.nft_values_lamports.get(nft_index).copied().unwrap_or(0),
+                royalty_enforcement,
+            )?;
+        }
+        
+        // Escrowed NFTs stay with the escrow PDA until released to the recipient
+        if step.escrowed.get(nft_index).copied().unwrap_or(false) {
+            verify_escrow_holds_nft(program_id, trade_loop_info.key, mint_info.key, source_token_account_info)?;
+            msg!("NFT {} is held in escrow, release it to {} with ReleaseEscrow", mint_info.key, recipient_info.key);
+            continue;
+        }
+        
+        // Token-2022 NFTs move through the Token-2022 program, which must be included, once
+        // any transfer hook this program holds on the mint is released
+        let nft_token_program_info = if mint_info.owner == &utils::TOKEN_2022_PROGRAM_ID {
+            let token_2022_program_info = accounts.iter()
+                .find(|account_info| account_info.key == &utils::TOKEN_2022_PROGRAM_ID)
+                .ok_or_else(|| {
+                    msg!("The Token-2022 program is required to transfer NFT {}", mint_info.key);
+                    SwapError::IncorrectProgramId
+                })?;
+            release_transfer_hook_for_execution(program_id, accounts, trade_loop_info.key, mint_info, token_2022_program_info)?;
+            token_2022_program_info
+        } else {
+            token_program_info
+        };
+        
+        // Verify the token accounts are owned by the mint's token program
+        utils::verify_account_owner(source_token_account_info, nft_token_program_info.key)?;
+        
+        // Verify the source token account is the expected ATA for this wallet/mint
+        utils::verify_token_account_address_for_program(source_token_account_info, sender_info.key, mint_info.key, nft_token_program_info.key)?;
+        
+        // Locked NFTs stay frozen until execution
+        if let Some(lock_authority_info) = lock_authority_info {
+            set_nft_frozen(program_id, lock_authority_info, mint_info, source_token_account_info, nft_token_program_info, false)?;
+        }
+        
+        // For destination, we only verify if it exists
+        if destination_token_account_info.data_len() > 0 {
+            utils::verify_token_account_address_for_program(destination_token_account_info, recipient_info.key, mint_info.key, nft_token_program_info.key)?;
+        }
+        
+        // Create the destination token account if it doesn't exist
+        if destination_token_account_info.data_len() == 0 {
+            msg!("Creating token account for recipient");
+            utils::create_associated_token_account_if_needed(
+                executor_info,
+                recipient_info,
+                mint_info,
+                destination_token_account_info,
+                nft_token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+        }
+        
+        // Verify the token accounts are correctly associated with the sender and recipient
+        let source_token_account = utils::unpack_token_account(source_token_account_info)?;
+        
+        if source_token_account.owner != *sender_info.key {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.mint != *mint_info.key {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the sender has the NFT (amount should be 1 for NFTs)
+        if source_token_account.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // Transfer the NFT to the recipient
+        msg!("Transferring NFT {} from {} to {}", mint_info.key, sender_info.key, recipient_info.key);
+        if nft_token_program_info.key == &utils::TOKEN_2022_PROGRAM_ID {
+            utils::transfer_token_2022_nft(
+                source_token_account_info,
+                mint_info,
+                destination_token_account_info,
+                sender_info,
+                nft_token_program_info,
+            )?;
+        } else {
+            utils::transfer_nft(
+                source_token_account_info,
+                destination_token_account_info,
+                sender_info,
+                token_program_info,
+            )?;
+        }
+    }
+    
+    // Pay the step's fungible token legs after its NFTs
+    transfer_step_spl_legs(
+        account_info_iter,
+        step,
+        sender_info,
+        recipient_info,
+        executor_info,
+        token_program_info,
+        associated_token_program_info,
+        system_program_info,
+        rent_info,
+    )
+}
+
+/// Helper function to verify the linked half of a split trade loop is ready for execution
+/// The linked loop's steps may already be executed when both halves run in the same transaction
+fn verify_linked_loop_ready(
+    program_id: &Pubkey,
+    trade_loop: &TradeLoop,
+    trade_loop_key: &Pubkey,
+    accounts: &[AccountInfo],
+    current_time: u64,
+) -> ProgramResult {
+    let linked_loop_key = match trade_loop.linked_loop {
+        Some(linked_loop_key) => linked_loop_key,
+        None => return Ok(()),
+    };
+    
+    let linked_loop_info = accounts.iter()
+        .find(|account_info| account_info.key == &linked_loop_key)
+        .ok_or_else(|| {
+            msg!("Linked trade loop {} must be included", linked_loop_key);
+            SwapError::LinkedLoopNotReady
+        })?;
+    
+    utils::verify_account_owner(linked_loop_info, program_id)?;
+    
+    let linked_loop = TradeLoop::unpack_unchecked(&linked_loop_info.data.borrow())
+        .map_err(|_| SwapError::LinkedLoopNotReady)?;
+    
+    if !linked_loop.is_initialized || linked_loop.linked_loop != Some(*trade_loop_key) {
+        msg!("Trade loop {} is not linked back to {}", linked_loop_key, trade_loop_key);
+        return Err(SwapError::LinkedLoopNotReady.into());
+    }
+    
+    if linked_loop.is_expired(current_time) {
+        return Err(SwapError::LinkedLoopExpired.into());
+    }
+    
+    if !linked_loop.steps.iter().all(|step| matches!(step.status, StepStatus::Approved | StepStatus::Executed)) {
+        msg!("Linked trade loop {} is not fully approved", linked_loop_key);
+        return Err(SwapError::LinkedLoopNotReady.into());
+    }
+    
+    if !trade_loop.verify_linked_loop(&linked_loop) {
+        return Err(SwapError::TradeLoopVerificationFailed.into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify the trade loop witness, if any, signed the transaction
+fn verify_witness_signature(trade_loop: &TradeLoop, accounts: &[AccountInfo]) -> ProgramResult {
+    if let Some(witness) = trade_loop.witness {
+        let witness_signed = accounts.iter()
+            .any(|account_info| account_info.key == &witness && account_info.is_signer);
+        
+        if !witness_signed {
+            msg!("Trade loop requires a signature from witness {}", witness);
+            return Err(SwapError::WitnessSignatureRequired.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify the executor is in the trade loop's execution whitelist, if any
+fn verify_executor_whitelisted(trade_loop: &TradeLoop, executor: &Pubkey) -> ProgramResult {
+    if let Some(whitelist) = trade_loop.execution_whitelist {
+        if *executor == Pubkey::default() || !whitelist.contains(executor) {
+            msg!("Executor {} is not in the trade loop's execution whitelist", executor);
+            return Err(SwapError::UnauthorizedExecutor.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify the trade loop's execution condition, if any, holds
+fn verify_execution_condition(trade_loop: &TradeLoop, accounts: &[AccountInfo]) -> ProgramResult {
+    if let Some(condition) = trade_loop.execution_condition {
+        let condition_info = accounts.iter()
+            .find(|account_info| account_info.key == &condition.account)
+            .ok_or_else(|| {
+                msg!("Execution condition account {} is required", condition.account);
+                ProgramError::from(SwapError::InvalidAccountData)
+            })?;
+        
+        let actual_value = condition_info.data.borrow().get(condition.byte_offset as usize).copied();
+        if actual_value != Some(condition.expected_value) {
+            msg!("Execution condition not met: byte {} of {} is {:?}, expected {}", 
+                 condition.byte_offset, condition.account, actual_value, condition.expected_value);
+            return Err(SwapError::ExecutionConditionNotMet.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Accounts for one party's NFT in an AtomicSwapTwoParty instruction
+struct TwoPartySwapSide<'a, 'b> {
+    mint_info: &'b AccountInfo<'a>,
+    source_token_account_info: &'b AccountInfo<'a>,
+    escrow_authority_info: &'b AccountInfo<'a>,
+    escrow_token_account_info: &'b AccountInfo<'a>,
+    destination_token_account_info: &'b AccountInfo<'a>,
+}
+
+impl TwoPartySwapSide<'_, '_> {
+    /// Verify the depositor holds the NFT and the escrow and destination accounts are the expected ones
+    fn verify(&self, program_id: &Pubkey, depositor: &Pubkey, recipient: &Pubkey, nft_mint: &Pubkey) -> ProgramResult {
+        // Verify the mint account matches the expected mint
+        if self.mint_info.key != nft_mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify this is actually an NFT (metadata check)
+        utils::verify_nft_metadata(self.mint_info)?;
+        
+        // Verify the source token account is the depositor's ATA and holds the NFT
+        utils::verify_token_account_owner(self.source_token_account_info)?;
+        utils::verify_token_account_address(self.source_token_account_info, depositor, nft_mint)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&self.source_token_account_info.data.borrow())?;
+        
+        if source_token_account.owner != *depositor {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.amount < 1 {
+            return Err(SwapError::InsufficientFunds.into());
+        }
+        
+        // Verify the escrow authority is the expected PDA and the escrow account is its ATA
+        let (expected_escrow_authority, _) = utils::get_swap_escrow_address(depositor, nft_mint, program_id);
+        if self.escrow_authority_info.key != &expected_escrow_authority {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        utils::verify_token_account_address(self.escrow_token_account_info, &expected_escrow_authority, nft_mint)?;
+        
+        // The NFT must land in the recipient's ATA
+        utils::verify_token_account_address(self.destination_token_account_info, recipient, nft_mint)
+    }
+}
+
+/// Helper function to pay a step's SOL leg from its sender to its recipient
+/// The sender must keep enough lamports to stay rent exempt
+fn transfer_step_sol<'a>(
+    step: &TradeStep,
+    sender_info: &AccountInfo<'a>,
+    recipient_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent: &Rent,
+) -> ProgramResult {
+    if step.sol_amount == 0 {
+        return Ok(());
+    }
+    
+    let required_lamports = step.sol_amount
+        .checked_add(rent.minimum_balance(sender_info.data_len()))
+        .ok_or(SwapError::InsufficientFunds)?;
+    if sender_info.lamports() < required_lamports {
+        msg!("Sender {} cannot cover the SOL leg of {} lamports", sender_info.key, step.sol_amount);
+        return Err(SwapError::InsufficientFunds.into());
+    }
+    
+    msg!("Transferring {} lamports from {} to {}", step.sol_amount, sender_info.key, recipient_info.key);
+    invoke(
+        &system_instruction::transfer(sender_info.key, recipient_info.key, step.sol_amount),
+        &[sender_info.clone(), recipient_info.clone(), system_program_info.clone()],
+    )
+}
+
+/// Helper function to verify a sender's token accounts cover each fungible leg of a step
+/// Consumes a (mint, source token account) pair from the iterator per leg
+fn verify_sender_holds_spl_legs<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    sender: &Pubkey,
+    spl_legs: &[SplTokenLeg],
+) -> ProgramResult {
+    if spl_legs.len() > MAX_SPL_LEGS_PER_STEP as usize {
+        msg!("Step has {} fungible legs, maximum is {}", spl_legs.len(), MAX_SPL_LEGS_PER_STEP);
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    for spl_leg in spl_legs {
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        
+        if mint_info.key != &spl_leg.mint || spl_leg.amount == 0 {
+            return Err(SwapError::InvalidInstructionData.into());
+        }
+        
+        // Verify the token account is owned by the token program
+        utils::verify_token_account_owner(source_token_account_info)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+        
+        if source_token_account.owner != *sender {
+            return Err(SwapError::InvalidAccountOwner.into());
+        }
+        
+        if source_token_account.mint != spl_leg.mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        if source_token_account.amount < spl_leg.amount {
+            msg!("Sender {} holds {} of {}, the step sends {}", 
+                 sender, source_token_account.amount, spl_leg.mint, spl_leg.amount);
+            return Err(SwapError::InsufficientTokenBalance.into());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to pay a step's fungible token legs from its sender to its recipient
+/// Consumes a (mint, source, destination) triple from the iterator per leg
+#[allow(clippy::too_many_arguments)]
+fn transfer_step_spl_legs<'a, 'b>(
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    step: &TradeStep,
+    sender_info: &AccountInfo<'a>,
+    recipient_info: &AccountInfo<'a>,
+    executor_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    associated_token_program_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    for spl_leg in &step.spl_token_legs {
+        let mint_info = next_account_info(account_info_iter)?;
+        let source_token_account_info = next_account_info(account_info_iter)?;
+        let destination_token_account_info = next_account_info(account_info_iter)?;
+        
+        // Verify that the mint account matches the expected mint
+        if mint_info.key != &spl_leg.mint {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+        
+        // Verify the source token account is the sender's ATA and covers the amount
+        utils::verify_token_account_owner(source_token_account_info)?;
+        utils::verify_token_account_address(source_token_account_info, sender_info.key, mint_info.key)?;
+        
+        let source_token_account = spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+        if source_token_account.amount < spl_leg.amount {
+            msg!("Sender {} holds {} of {}, the step sends {}", 
+                 sender_info.key, source_token_account.amount, spl_leg.mint, spl_leg.amount);
+            return Err(SwapError::InsufficientTokenBalance.into());
+        }
+        
+        // Create the recipient's token account if it doesn't exist
+        if destination_token_account_info.data_len() > 0 {
+            utils::verify_token_account_address(destination_token_account_info, recipient_info.key, mint_info.key)?;
+        } else {
+            utils::create_associated_token_account_if_needed(
+                executor_info,
+                recipient_info,
+                mint_info,
+                destination_token_account_info,
+                token_program_info,
+                associated_token_program_info,
+                system_program_info,
+                rent_info,
+            )?;
+        }
+        
+        msg!("Transferring {} of {} from {} to {}", spl_leg.amount, spl_leg.mint, sender_info.key, recipient_info.key);
+        utils::transfer_fungible_tokens(
+            source_token_account_info,
+            destination_token_account_info,
+            sender_info,
+            token_program_info,
+            spl_leg.amount,
+        )?;
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify that a trade loop forms a valid cycle, logging why not
+fn verify_trade_loop_cycle(trade_loop: &TradeLoop) -> ProgramResult {
+    trade_loop.verify_loop_detailed().map_err(|integrity_error| {
+        msg!("Trade loop validation failed - {}", integrity_error);
+        SwapError::TradeLoopVerificationFailed.into()
+    })
+}
+
+/// Helper function to pay a relayer's transaction fee out of the fee vault
+/// Execution has already succeeded, so an absent or underfunded vault skips the
+/// reimbursement with a warning rather than failing the trade
+fn reimburse_relayer<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    relayer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent: &Rent,
+) -> ProgramResult {
+    let (fee_vault_key, bump_seed) = utils::get_fee_vault_address(program_id);
+    let fee_vault_info = match accounts.iter().find(|account_info| account_info.key == &fee_vault_key) {
+        Some(fee_vault_info) => fee_vault_info,
+        None => {
+            msg!("Warning: fee vault not provided, relayer {} not reimbursed", relayer_info.key);
+            return Ok(());
+        }
+    };
+    
+    // Keep the vault rent exempt so it is never purged
+    let available = fee_vault_info.lamports().saturating_sub(rent.minimum_balance(0));
+    if available < RELAYER_REIMBURSEMENT_LAMPORTS {
+        msg!("Warning: fee vault holds {} spendable lamports, relayer {} not reimbursed", 
+             available, relayer_info.key);
+        return Ok(());
+    }
+    
+    invoke_signed(
+        &system_instruction::transfer(fee_vault_info.key, relayer_info.key, RELAYER_REIMBURSEMENT_LAMPORTS),
+        &[
+            fee_vault_info.clone(),
+            relayer_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[&[b"fee_vault", &[bump_seed]]],
+    )?;
+    
+    msg!("Reimbursed relayer {} with {} lamports", relayer_info.key, RELAYER_REIMBURSEMENT_LAMPORTS);
+    
+    Ok(())
+}
+
+/// Helper function to mint a completion badge to each participant of an executed trade loop
+/// Badges are best-effort: a failed CPI would abort the trade itself, so anything the
+/// token program would reject is skipped with a warning instead
+fn mint_completion_badges<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    trade_loop: &TradeLoop,
+    token_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let badge_mint = match find_program_config(program_id, accounts)?.and_then(|config| config.badge_mint) {
+        Some(badge_mint) => badge_mint,
+        None => return Ok(()),
+    };
+    
+    let (config_key, config_bump) = utils::get_program_config_address(program_id);
+    let config_info = accounts.iter().find(|account_info| account_info.key == &config_key);
+    let badge_mint_info = accounts.iter().find(|account_info| account_info.key == &badge_mint);
+    let (config_info, badge_mint_info) = match (config_info, badge_mint_info) {
+        (Some(config_info), Some(badge_mint_info)) => (config_info, badge_mint_info),
+        _ => {
+            msg!("Warning: badge mint {} not provided, no completion badges minted", badge_mint);
+            return Ok(());
+        }
+    };
+    
+    let mint_authority = spl_token::state::Mint::unpack(&badge_mint_info.data.borrow())
+        .ok()
+        .and_then(|mint| mint.mint_authority.into());
+    if badge_mint_info.owner != &spl_token::id() || mint_authority != Some(config_key) {
+        msg!("Warning: badge mint {} is not mintable by the program config, no completion badges minted", badge_mint);
+        return Ok(());
+    }
+    
+    for step in &trade_loop.steps {
+        let badge_account_key = spl_associated_token_account::get_associated_token_address(&step.from, &badge_mint);
+        let badge_account_info = match accounts.iter().find(|account_info| account_info.key == &badge_account_key) {
+            Some(badge_account_info) => badge_account_info,
+            None => {
+                msg!("Warning: badge token account of {} not provided, no completion badge minted", step.from);
+                continue;
+            }
+        };
+        
+        let mintable = badge_account_info.owner == &spl_token::id()
+            && spl_token::state::Account::unpack(&badge_account_info.data.borrow())
+                .is_ok_and(|account| account.mint == badge_mint && !account.is_frozen());
+        if !mintable {
+            msg!("Warning: badge token account of {} cannot receive badges, no completion badge minted", step.from);
+            continue;
+        }
+        
+        invoke_signed(
+            &spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &badge_mint,
+                badge_account_info.key,
+                &config_key,
+                &[],
+                1,
+            )?,
+            &[
+                badge_mint_info.clone(),
+                badge_account_info.clone(),
+                config_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[b"config", &[config_bump]]],
+        )?;
+        
+        msg!("Minted completion badge to {}", step.from);
+    }
+    
+    Ok(())
+}
+
+/// Helper function to record trade provenance in the metadata of every NFT in an executed trade loop
+/// Only runs when enabled in the config; missing or non-updatable metadata is skipped with a warning
+fn append_trade_provenance<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    trade_loop: &TradeLoop,
+    slot: u64,
+) -> ProgramResult {
+    if !find_program_config(program_id, accounts)?.is_some_and(|config| config.append_provenance_metadata) {
+        return Ok(());
+    }
+    
+    let (config_key, config_bump) = utils::get_program_config_address(program_id);
+    let config_info = accounts.iter().find(|account_info| account_info.key == &config_key);
+    let metadata_program_info = accounts.iter().find(|account_info| account_info.key == &utils::TOKEN_METADATA_PROGRAM_ID);
+    let (config_info, metadata_program_info) = match (config_info, metadata_program_info) {
+        (Some(config_info), Some(metadata_program_info)) => (config_info, metadata_program_info),
+        _ => {
+            msg!("Warning: token metadata program not provided, no trade provenance recorded");
+            return Ok(());
+        }
+    };
+    
+    for nft_mint in trade_loop.steps.iter().flat_map(|step| step.nft_mints.iter()) {
+        let (metadata_key, _) = utils::get_metadata_address(nft_mint);
+        let metadata_info = match accounts.iter().find(|account_info| account_info.key == &metadata_key) {
+            Some(metadata_info) => metadata_info,
+            None => {
+                msg!("Warning: metadata of {} not provided, no trade provenance recorded", nft_mint);
+                continue;
+            }
+        };
+        
+        if utils::append_metadata_provenance(
+            nft_mint,
+            metadata_info,
+            config_info,
+            metadata_program_info,
+            slot,
+            &[b"config", &[config_bump]],
+        )? {
+            msg!("Recorded trade provenance for {}", nft_mint);
+        }
+    }
+    
+    Ok(())
+}
+
+/// Helper function to hand a registered mint's transfer hook back to its original program
+/// and authority, marking the registry released
+fn release_transfer_hook<'a>(
+    program_id: &Pubkey,
+    mint_info: &AccountInfo<'a>,
+    registry_info: &AccountInfo<'a>,
+    hook_authority_info: &AccountInfo<'a>,
+    token_2022_program_info: &AccountInfo<'a>,
+    registry: &mut HookRegistry,
+) -> ProgramResult {
+    let (hook_authority_key, hook_authority_bump) = utils::get_hook_authority_address(program_id);
+    if hook_authority_info.key != &hook_authority_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if token_2022_program_info.key != &utils::TOKEN_2022_PROGRAM_ID {
+        return Err(SwapError::IncorrectProgramId.into());
+    }
+    
+    // Restore the original hook program, then the original hook authority
+    let hook_authority_seeds: &[&[u8]] = &[b"hook_authority", &[hook_authority_bump]];
+    invoke_signed(
+        &utils::token_2022_update_transfer_hook(mint_info.key, &hook_authority_key, registry.original_hook_program),
+        &[mint_info.clone(), hook_authority_info.clone(), token_2022_program_info.clone()],
+        &[hook_authority_seeds],
+    )?;
+    invoke_signed(
+        &utils::token_2022_set_transfer_hook_authority(mint_info.key, &hook_authority_key, &registry.original_authority),
+        &[mint_info.clone(), hook_authority_info.clone(), token_2022_program_info.clone()],
+        &[hook_authority_seeds],
+    )?;
+    
+    registry.active_loop = Pubkey::default();
+    registry.serialize(&mut *registry_info.data.borrow_mut())?;
+    
+    msg!("Released transfer hook of mint {}", mint_info.key);
+    
+    Ok(())
+}
+
+/// Helper function to release the transfer hook of a Token-2022 NFT before the program moves it,
+/// since Token-2022 would otherwise invoke the hook on this program again during the transfer
+/// The registry (writable) and hook authority are found among the accounts
+fn release_transfer_hook_for_execution<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    trade_loop_key: &Pubkey,
+    mint_info: &AccountInfo<'a>,
+    token_2022_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let hooked = utils::read_token_2022_transfer_hook(mint_info).ok()
+        .is_some_and(|transfer_hook| transfer_hook.program_id == Some(*program_id));
+    if !hooked {
+        return Ok(());
+    }
+    
+    let (registry_key, _) = utils::get_hook_registry_address(mint_info.key, program_id);
+    let (hook_authority_key, _) = utils::get_hook_authority_address(program_id);
+    let find_account = |key: &Pubkey| accounts.iter().find(|account_info| account_info.key == key).ok_or_else(|| {
+        msg!("Account {} is required to release the transfer hook of mint {}", key, mint_info.key);
+        ProgramError::from(SwapError::TransferNotAuthorized)
+    });
+    let registry_info = find_account(&registry_key)?;
+    let hook_authority_info = find_account(&hook_authority_key)?;
+    
+    utils::verify_account_owner(registry_info, program_id)?;
+    let mut registry = HookRegistry::try_from_slice(&registry_info.data.borrow())?;
+    if !registry.is_initialized || registry.active_loop != *trade_loop_key {
+        msg!("Mint {} is hooked for another trade loop", mint_info.key);
+        return Err(SwapError::TransferNotAuthorized.into());
+    }
+    
+    release_transfer_hook(program_id, mint_info, registry_info, hook_authority_info, token_2022_program_info, &mut registry)
+}
+
+/// Helper function to find the program config account, which thaws locked NFTs
+fn find_lock_authority<'a, 'b>(
+    program_id: &Pubkey,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    let (config_key, _) = utils::get_program_config_address(program_id);
+    
+    accounts.iter().find(|account_info| account_info.key == &config_key).ok_or_else(|| {
+        msg!("The program config account is required to thaw locked NFTs");
+        SwapError::StepNftsLocked.into()
+    })
+}
+
+/// Helper function to freeze or thaw an NFT token account with the program config as freeze authority
+/// Accounts already in the requested state are left untouched
+fn set_nft_frozen<'a>(
+    program_id: &Pubkey,
+    config_info: &AccountInfo<'a>,
+    mint_info: &AccountInfo<'a>,
+    token_account_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    frozen: bool,
+) -> ProgramResult {
+    let (config_key, config_bump) = utils::get_program_config_address(program_id);
+    if config_info.key != &config_key {
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    // The program can only freeze mints whose freeze authority it holds
+    let mint = spl_token::state::Mint::unpack(&mint_info.data.borrow())?;
+    if mint.freeze_authority != COption::Some(config_key) {
+        msg!("Freeze authority of {} is not the program config", mint_info.key);
+        return Err(SwapError::InvalidAccountData.into());
+    }
+    
+    if spl_token::state::Account::unpack(&token_account_info.data.borrow())?.is_frozen() == frozen {
+        return Ok(());
+    }
+    
+    let instruction = if frozen {
+        spl_token::instruction::freeze_account(&spl_token::id(), token_account_info.key, mint_info.key, &config_key, &[])?
+    } else {
+        spl_token::instruction::thaw_account(&spl_token::id(), token_account_info.key, mint_info.key, &config_key, &[])?
+    };
+    
+    invoke_signed(
+        &instruction,
+        &[
+            token_account_info.clone(),
+            mint_info.clone(),
+            config_info.clone(),
+            token_program_info.clone(),
+        ],
+        &[&[b"config", &[config_bump]]],
+    )
+}
+
+/// Helper function to create the successor of an executed recurring trade loop
+/// Returns whether a successor was created and recorded in `trade_loop`
+#[allow(clippy::too_many_arguments)]
+fn create_successor_loop<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    trade_loop: &mut TradeLoop,
+    trade_loop_info: &AccountInfo<'a>,
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    rent: &Rent,
+) -> Result<bool, ProgramError> {
+    let recurring = match trade_loop.recurring {
+        Some(recurring) if recurring.recurrence_count < recurring.max_recurrences => recurring,
+        _ => return Ok(false),
+    };
+    
+    // The successor keeps the participant ring and the loop's authority
+    let participants: Vec<Pubkey> = trade_loop.steps.iter().map(|step| step.from).collect();
+    let trade_id = utils::derive_trade_id(&trade_loop.authority, &participants, recurring.next_loop_seed);
+    let (successor_key, bump_seed) = utils::get_trade_loop_address(&trade_id, &trade_loop.authority, program_id);
+    
+    let successor_info = match accounts.iter().find(|account_info| account_info.key == &successor_key) {
+        Some(successor_info) => successor_info,
+        None => {
+            msg!("Recurring trade loop requires its successor account {}", successor_key);
+            return Err(SwapError::InvalidAccountData.into());
+        }
+    };
+    
+    let space = TradeLoop::LEN;
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            successor_info.key,
+            rent.minimum_balance(space),
+            space as u64,
+            program_id,
+        ),
+        &[
+            payer_info.clone(),
+            successor_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[&[b"trade_loop".as_ref(), trade_id.as_ref(), trade_loop.authority.as_ref(), &[bump_seed]]],
+    )?;
+    
+    let current_time = Clock::get()?.unix_timestamp as u64;
+    let expires_at = current_time.checked_add(recurring.interval_seconds)
+        .ok_or(SwapError::InvalidInstructionData)?;
+    
+    let successor = TradeLoop {
+        is_initialized: true,
+        trade_id,
+        created_at: current_time,
+        expires_at,
+        steps: TradeStep::build_ring(&participants),
+        authority: trade_loop.authority,
+        is_paused: false,
+        nonce: TradeLoop::initial_nonce(&trade_id, current_time, &trade_loop.authority),
+        witness: trade_loop.witness,
+        authority_transfer_request: None,
+        linked_loop: None,
+        cancel_lockout_seconds: trade_loop.cancel_lockout_seconds,
+        all_approved_at: None,
+        loop_tags: trade_loop.loop_tags,
+        recurring: Some(RecurringConfig {
+            recurrence_count: recurring.recurrence_count + 1,
+            next_loop_seed: recurring.next_loop_seed.wrapping_add(1),
+            ..recurring
+        }),
+        successor_loop: None,
+        tokenized_as: None,
+        offered_until_display: 0,
+        display_timezone_offset: 0,
+        deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+        applied_discount_bps: 0,
+        allocated_step_count: trade_loop.allocated_step_count,
+        execution_condition: None,
+        step_hash_history: Vec::new(),
+        execution_whitelist: trade_loop.execution_whitelist,
+        auto_execute: trade_loop.auto_execute,
+        commitment_mode: trade_loop.commitment_mode,
+        per_step_nft_limits: trade_loop.per_step_nft_limits.clone(),
+        execution_bond_lamports: trade_loop.execution_bond_lamports,
+        applied_fee_bps: 0,
+    };
+    serialize_trade_loop(&successor, successor_info)?;
+    
+    trade_loop.successor_loop = Some(successor_key);
+    
+    utils::emit_event(&SwapEvent::TradeLoopCreated {
+        trade_id,
+        authority: trade_loop.authority,
+        expires_at,
+    })?;
+    
+    msg!("Recurring trade loop {} renewed as {} ({} of {})", trade_loop_info.key, successor_key,
+         recurring.recurrence_count + 1, recurring.max_recurrences);
+    
+    Ok(true)
+}
+
+/// Helper function to close a source token account emptied by a transfer
+/// A CPI failure would abort the whole transaction, so accounts the token program
+/// would refuse to close are skipped with a warning instead
+fn close_empty_token_account<'a>(
+    token_account_info: &AccountInfo<'a>,
+    rent_destination_info: &AccountInfo<'a>,
+    owner_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+) -> ProgramResult {
+    let token_account = spl_token::state::Account::unpack(&token_account_info.data.borrow())?;
+    
+    if token_account.amount != 0 || token_account.is_native() {
+        msg!("WARNING: Token account {} is not empty and was not closed", token_account_info.key);
+        return Ok(());
+    }
+    
+    let close_authority = token_account.close_authority.unwrap_or(token_account.owner);
+    if close_authority != *owner_info.key {
+        msg!("WARNING: Token account {} has a different close authority and was not closed", token_account_info.key);
+        return Ok(());
+    }
+    
+    utils::close_token_account(token_account_info, rent_destination_info, owner_info, token_program_info)?;
+    
+    msg!("Closed empty token account {} and returned rent to {}", token_account_info.key, rent_destination_info.key);
+    
+    Ok(())
+}
+
+/// Helper function to store a trade loop in its fixed-size packed account
+fn serialize_trade_loop(trade_loop: &TradeLoop, trade_loop_info: &AccountInfo) -> ProgramResult {
+    // Accounts created before the packed layout must be migrated first
+    if trade_loop_info.data_len() != TradeLoop::LEN {
+        msg!("Trade loop account holds {} bytes, expected {}. Migrate it first", 
+             trade_loop_info.data_len(), TradeLoop::LEN);
+        return Err(SwapError::AccountTooSmall.into());
+    }
+    
+    trade_loop
+        .pack_into_slice(&mut trade_loop_info.data.borrow_mut())
+        .map_err(|_| {
+            msg!("Trade loop does not fit in {} bytes", TradeLoop::LEN);
+            SwapError::AccountTooSmall.into()
+        })
+}
+
+/// Number of accounts a step takes in ExecuteFullTradeLoop: sender, recipient, and a
+/// (mint, source, destination) triple per NFT and per fungible leg
+fn step_execution_account_count(step: &TradeStep) -> usize {
+    2 + 3 * (step.nft_mints.len() + step.spl_token_legs.len())
+}
+
+/// Helper function to find the steps whose transfers would hit a frozen token account,
+/// given the step accounts of ExecuteFullTradeLoop. Escrowed NFTs are not transferred at
+/// execution, and locked NFTs are thawed by the program first, so their sources are ignored
+fn find_steps_with_frozen_accounts(trade_loop: &TradeLoop, step_accounts: &[AccountInfo]) -> Result<Vec<bool>, ProgramError> {
+    let mut offset = 0;
+    trade_loop.steps.iter().map(|step| {
+        let account_count = step_execution_account_count(step);
+        let accounts = step_accounts.get(offset..offset + account_count)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        offset += account_count;
+        
+        Ok(accounts[2..].chunks(3).enumerate().any(|(transfer_index, transfer_accounts)| {
+            let is_nft = transfer_index < step.nft_mints.len();
+            if is_nft && step.escrowed.get(transfer_index).copied().unwrap_or(false) {
+                return false;
+            }
+            let source_thawed_by_program = is_nft && step.nfts_locked;
+            (!source_thawed_by_program && is_token_account_frozen(&transfer_accounts[1]))
+                || is_token_account_frozen(&transfer_accounts[2])
+        }))
+    }).collect()
+}
+
+/// Helper function to check whether an existing token account is frozen
+fn is_token_account_frozen(token_account_info: &AccountInfo) -> bool {
+    utils::unpack_token_account(token_account_info).is_ok_and(|account| account.is_frozen())
+}
+
+/// Execute a fully approved auto-execute loop by invoking ExecuteFullTradeLoop on this program,
+/// with the approver as executor. The trade loop must already be persisted, since the inner
+/// instruction reads and rewrites it
+fn auto_execute_trade_loop<'a>(
+    program_id: &Pubkey,
+    approver_info: &AccountInfo<'a>,
+    trade_loop_info: &AccountInfo<'a>,
+    trade_loop: &TradeLoop,
+    remaining_accounts: &[AccountInfo<'a>],
+) -> ProgramResult {
+    let (program_info, execute_accounts) = remaining_accounts.split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if program_info.key != program_id {
+        msg!("Auto execution requires this program's account after the clock sysvar");
+        return Err(SwapError::IncorrectProgramId.into());
+    }
+    
+    let mut account_infos = vec![approver_info.clone(), trade_loop_info.clone()];
+    account_infos.extend(execute_accounts.iter().cloned());
+    let account_metas = account_infos.iter()
+        .map(|info| AccountMeta { pubkey: *info.key, is_signer: info.is_signer, is_writable: info.is_writable })
+        .collect();
+    account_infos.push(program_info.clone());
+    
+    let instruction = Instruction {
+        program_id: *program_id,
+        accounts: account_metas,
+        data: SwapInstruction::ExecuteFullTradeLoop { expected_nonce: trade_loop.nonce }.pack_versioned(),
+    };
+    
+    // A failed invocation aborts the whole transaction, approval included
+    msg!("All steps approved, auto executing trade loop");
+    invoke(&instruction, &account_infos)
+}
+
+/// Helper function to reset every approved step after the trade terms change
+/// Advances the nonce, so approvals signed for the old terms cannot be replayed
+fn invalidate_approvals(trade_loop: &mut TradeLoop, reason: &str) {
+    for (index, step) in trade_loop.steps.iter_mut().enumerate() {
+        if step.status == StepStatus::Approved {
+            step.status = StepStatus::Created;
+            step.approved_at_slot = None;
+            msg!("Approval for step {} by {} invalidated by {}", index, step.from, reason);
+        }
+    }
+    trade_loop.all_approved_at = None;
+    trade_loop.nonce = trade_loop.nonce.wrapping_add(1);
+}
+
+/// Helper function to approve a step on behalf of its sender or approval delegate
+/// Returns false without changes if the step is already approved
+fn approve_step_in_loop(trade_loop: &mut TradeLoop, step_index: u8, approver: &Pubkey, clock: &Clock) -> Result<bool, ProgramError> {
+    // Ensure the step index is valid
+    if step_index as usize >= trade_loop.steps.len() {
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Sealed-bid steps are approved once their NFTs are committed
+    let commitment_mode = trade_loop.commitment_mode;
+    
+    // Get the step
+    let step = &mut trade_loop.steps[step_index as usize];
+    
+    if commitment_mode && step.status == StepStatus::Created {
+        msg!("Step {} must commit to its NFTs before it can be approved", step_index);
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // The step's own deadline applies on top of the loop's
+    if step.is_expired(clock.unix_timestamp as u64) {
+        return Err(SwapError::StepExpired.into());
+    }
+    
+    // Ensure the approver is the owner of this step or its approval delegate
+    let is_delegated_approval = step.from != *approver;
+    if is_delegated_approval {
+        match step.delegate_approval {
+            Some((delegate, expiry)) if delegate == *approver => {
+                if clock.unix_timestamp >= expiry {
+                    msg!("Approval delegation for step {} expired at {}", step_index, expiry);
+                    return Err(SwapError::DelegateExpired.into());
+                }
+            }
+            Some(_) => return Err(SwapError::UnauthorizedDelegate.into()),
+            None => return Err(SwapError::InvalidAccountOwner.into()),
+        }
+    }
+    
+    // If already approved, there is nothing to do (idempotent)
+    if step.status == StepStatus::Approved {
+        return Ok(false);
+    }
+    
+    // Verify the step isn't already executed
+    if step.status == StepStatus::Executed {
+        return Err(SwapError::StepAlreadyExecuted.into());
+    }
+    
+    // Failed steps can only be cancelled
+    if let StepStatus::Failed { reason } = step.status {
+        msg!("Step {} has failed ({:?}) and cannot be approved", step_index, reason);
+        return Err(SwapError::StepFailed.into());
+    }
+    
+    // Cancelled steps belong to a loop that is being wound down
+    if step.status == StepStatus::Cancelled {
+        msg!("Step {} has been cancelled and cannot be approved", step_index);
+        return Err(SwapError::CancellationDenied.into());
+    }
+    
+    // Steps pre-populated from a template need their NFTs added first; committed
+    // sealed-bid steps reveal theirs at execution
+    if step.nft_mints.is_empty() && step.nft_mint_commitments.is_empty() {
+        msg!("Step {} has no NFTs to approve", step_index);
+        return Err(SwapError::InvalidInstructionData.into());
+    }
+    
+    // Update the step status to Approved
+    step.status = StepStatus::Approved;
+    step.approved_at_slot = Some(clock.slot);
+    
+    // A delegation is good for a single approval
+    if is_delegated_approval {
+        step.delegate_approval = None;
+        msg!("Step {} approved by delegate {} on behalf of {}", step_index, approver, step.from);
+    }
+    
+    // Start the cancellation lockout once every step is approved
+    if trade_loop.all_approved_at.is_none() && trade_loop.is_ready_for_execution() {
+        trade_loop.all_approved_at = Some(clock.unix_timestamp as u64);
+        msg!("All steps approved, cancellation locked for {} seconds", trade_loop.cancel_lockout_seconds);
+    }
+    
+    Ok(true)
+}
+
+/// Helper function to grow a trade loop account smaller than the packed size
+/// The trade loop authority (as a signer) pays the extra rent; it and the system program
+/// are found among the accounts
+fn grow_trade_loop_account<'a>(
+    trade_loop: &TradeLoop,
+    trade_loop_info: &AccountInfo<'a>,
+    accounts: &[AccountInfo<'a>],
+) -> ProgramResult {
+    if trade_loop_info.data_len() >= TradeLoop::LEN {
+        return Ok(());
+    }
+    
+    let payer_info = accounts.iter().find(|account_info| account_info.key == &trade_loop.authority && account_info.is_signer);
+    let system_program_info = accounts.iter().find(|account_info| account_info.key == &solana_program::system_program::id());
+    let (payer_info, system_program_info) = match (payer_info, system_program_info) {
+        (Some(payer_info), Some(system_program_info)) => (payer_info, system_program_info),
+        _ => {
+            msg!("Trade loop account holds {} bytes; the authority must sign and the system program be passed to grow it to {}", 
+                 trade_loop_info.data_len(), TradeLoop::LEN);
+            return Err(SwapError::InsufficientAccountSpace.into());
+        }
+    };
+    
+    utils::reallocate_account(trade_loop_info, payer_info, system_program_info, &Rent::get()?, TradeLoop::LEN)
+        .map_err(|err| {
+            msg!("Failed to grow trade loop account {}: {:?}", trade_loop_info.key, err);
+            SwapError::InsufficientAccountSpace
+        })?;
+    
+    msg!("Grew trade loop account {} to {} bytes", trade_loop_info.key, TradeLoop::LEN);
+    
+    Ok(())
+}
+
+/// Helper function to check if the program is paused
+/// When a trade loop account is provided, its per-loop pause flag is checked as well
+fn check_program_not_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    trade_loop_info: Option<&AccountInfo>,
+) -> ProgramResult {
+    // The per-loop pause does not depend on the config PDA being present
+    if let Some(trade_loop_info) = trade_loop_info {
+        if trade_loop_info.owner == program_id && trade_loop_info.data_len() > 0 {
+            if let Ok(trade_loop) = TradeLoop::unpack_unchecked(&trade_loop_info.data.borrow()) {
+                if trade_loop.is_initialized && trade_loop.is_paused {
+                    msg!("Trade loop {} is currently paused", trade_loop_info.key);
+                    return Err(SwapError::TradeLoopPaused.into());
+                }
+            }
+        }
+    }
+    
+    // Get the program configuration PDA
+    let (config_pubkey, _) = utils::get_program_config_address(program_id);
+    
+    // Try to find the config account
+    let mut config_found = false;
+    
+    for account_info in accounts {
+        if account_info.key == &config_pubkey {
+            // Verify the account is owned by this program
+            if account_info.owner != program_id {
+                msg!("Config account found but has incorrect owner");
+                continue;
+            }
+            
+            // Verify the account has data
+            if account_info.data_len() == 0 {
+                msg!("Config account found but has no data");
+                continue;
+            }
+            
+            config_found = true;
+            
+            // Try to deserialize - if it fails, the config might be corrupted
+            match ProgramConfig::unpack_versioned(&account_info.data.borrow()) {
+                Ok(config) => {
+                    if let Some(reason) = config.pause_reason() {
+                        msg!("Program is currently paused ({:?})", reason);
+                        return Err(SwapError::ProgramPaused(reason.code()).into());
+                    }
+                },
+                Err(err) => {
+                    msg!("Error deserializing config account: {}", err);
+                    return Err(SwapError::InvalidAccountData.into());
+                }
+            }
+            
+            // Found valid config, stop searching
+            break;
+        }
+    }
+    
+    // If config account was not found, that's ok - it might not be initialized yet
+    if !config_found {
+        msg!("Config account not found, assuming program is not paused");
+    }
+    
+    Ok(())
+}
+
+/// Helper function to verify an escrow token account belongs to the escrow PDA and holds the NFT
+fn verify_escrow_holds_nft(
+    program_id: &Pubkey,
+    trade_loop: &Pubkey,
+    nft_mint: &Pubkey,
+    escrow_token_account_info: &AccountInfo,
+) -> ProgramResult {
+    let (escrow_authority, _) = utils::get_escrow_address(trade_loop, nft_mint, program_id);
+    
+    utils::verify_token_account_owner(escrow_token_account_info)?;
+    utils::verify_token_account_address(escrow_token_account_info, &escrow_authority, nft_mint)?;
+    
+    let escrow_token_account = spl_token::state::Account::unpack(&escrow_token_account_info.data.borrow())?;
+    if escrow_token_account.amount < 1 {
+        msg!("Escrow token account {} does not hold NFT {}", escrow_token_account_info.key, nft_mint);
+        return Err(SwapError::InsufficientFunds.into());
+    }
+    
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
+    hash::hashv,
     program_error::ProgramError,
-    program_pack::{IsInitialized, Pack, Sealed},
+    program_pack::{IsInitialized, Sealed},
     pubkey::Pubkey,
 };
 use std::collections::HashSet;
@@ -16,9 +17,113 @@ pub const MAX_PARTICIPANTS_PER_TRANSACTION: u8 = 11;
 /// Maximum number of NFTs allowed per step
 pub const MAX_NFTS_PER_STEP: u8 = 4;
 
+/// Maximum number of fungible token legs allowed per step
+pub const MAX_SPL_LEGS_PER_STEP: u8 = 4;
+
 /// Maximum timeout for trade loops (30 days in seconds)
 pub const MAX_TIMEOUT_SECONDS: u64 = 30 * 24 * 60 * 60;
 
+/// Time window for a proposed upgrade authority to accept the transfer (7 days in seconds)
+pub const AUTHORITY_TRANSFER_GRACE_PERIOD_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+/// Voting period for governance proposals (3 days in seconds)
+pub const GOVERNANCE_VOTING_PERIOD_SECONDS: i64 = 3 * 24 * 60 * 60;
+
+/// Delay before a proposed governance key replaces the current one (48 hours in seconds)
+pub const GOVERNANCE_REPLACEMENT_DELAY_SECONDS: i64 = 48 * 60 * 60;
+
+/// Delay before a trade loop authority transfer can be confirmed (24 hours in seconds)
+pub const TRADE_AUTHORITY_TRANSFER_DELAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// Maximum compute units a single trade loop execution may be estimated to need
+/// This matches the per-transaction compute budget ceiling
+pub const MAX_COMPUTE_UNITS_PER_LOOP: u64 = 1_400_000;
+
+/// Maximum number of expired trade loops that can be closed in a single batch
+pub const MAX_BATCH_CANCEL_ACCOUNTS: usize = 10;
+
+/// Maximum number of steps that can be added in a single BatchAddSteps call (compute budget)
+pub const MAX_BATCH_ADD_STEPS: usize = 4;
+
+/// Maximum number of trade loops created in a single BulkInitializeLoops call (compute budget)
+pub const MAX_BULK_INITIALIZE_LOOPS: usize = 5;
+
+/// Maximum number of steps approved in a single BatchApproveSteps call
+pub const MAX_BATCH_APPROVALS: usize = 8;
+
+/// Maximum number of searchable tags attached to a trade loop
+pub const MAX_LOOP_TAGS: usize = 8;
+
+/// Maximum number of offers listed in a single collection's offer book (account size)
+pub const MAX_OFFER_BOOK_ENTRIES: usize = 64;
+
+/// Maximum number of NFTs escrowed by a single partial fill offer (compute budget)
+pub const MAX_PARTIAL_FILL_OFFER_NFTS: usize = 8;
+
+/// Minimum number of slots between two trade loops initialized by the same wallet (when rate limiting is enabled)
+pub const COOLDOWN_SLOTS: u64 = 32;
+
+/// Maximum number of trade loops a wallet can initialize per epoch (when rate limiting is enabled)
+pub const MAX_LOOPS_PER_EPOCH: u32 = 100;
+
+/// Maximum age of an oracle price used for floor price checks, in seconds
+pub const MAX_ORACLE_PRICE_AGE_SECONDS: i64 = 60;
+
+/// Maximum size of a trade loop's deadline message in bytes
+pub const DEADLINE_MESSAGE_SIZE: usize = 64;
+
+/// Number of step hashes kept per step in a trade loop's amendment history
+pub const STEP_HASH_HISTORY_DEPTH: usize = 8;
+
+/// Largest timezone offset from UTC in minutes (UTC+14:00)
+pub const MAX_TIMEZONE_OFFSET_MINUTES: i16 = 840;
+
+/// Maximum number of relayers that can be authorized to execute trade loops
+pub const MAX_AUTHORIZED_RELAYERS: usize = 5;
+
+/// Maximum number of executors in a trade loop's execution whitelist
+pub const MAX_WHITELISTED_EXECUTORS: usize = 5;
+
+/// Estimated transaction fee reimbursed to a relayer for executing a trade loop
+/// (one signature at the base fee of 5000 lamports)
+pub const RELAYER_REIMBURSEMENT_LAMPORTS: u64 = 5000;
+
+/// Metaplex name of the completion badge
+pub const COMPLETION_BADGE_NAME: &str = "SWAPS Trade Badge";
+
+/// Metaplex symbol of the completion badge
+pub const COMPLETION_BADGE_SYMBOL: &str = "SWAPBADGE";
+
+/// Metaplex name of a tokenized trade loop position
+pub const TRADE_POSITION_NAME: &str = "SWAPS Trade Position";
+
+/// Metaplex symbol of a tokenized trade loop position
+pub const TRADE_POSITION_SYMBOL: &str = "SWAPPOS";
+
+/// Number of recent execution hashes kept by the dedup cache
+pub const DEDUP_CACHE_SIZE: usize = 256;
+
+/// Maximum protocol fee of each tier of a fee schedule (5%)
+pub const MAX_SCHEDULED_FEE_BASIS_POINTS: u16 = 500;
+
+/// Maximum number of intermediate mints a fee route may swap through
+pub const MAX_FEE_ROUTE_HOPS: usize = 3;
+
+/// Seconds a fully approved bonded trade loop has to execute before its bonds can be claimed,
+/// unless the program config sets another window
+pub const DEFAULT_EXECUTION_WINDOW_SECONDS: u64 = 3600;
+
+/// Reason a trade step could not be executed
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum FailReason {
+    /// An NFT in the step was frozen by its freeze authority
+    NftFrozen,
+    /// An NFT in the step left the sender's wallet
+    NftTransferred,
+    /// An NFT's metadata is no longer valid
+    MetadataInvalid,
+}
+
 /// Current status of a trade step
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub enum StepStatus {
@@ -28,6 +133,73 @@ pub enum StepStatus {
     Approved,
     /// Step has been executed (NFTs transferred)
     Executed,
+    /// Step can no longer be executed (makes the loop eligible for cancellation)
+    Failed {
+        /// Why the step failed
+        reason: FailReason,
+    },
+    /// Step was cancelled by CancelTradeLoopV2 while its escrowed NFTs are returned
+    Cancelled,
+    /// Step of a sealed-bid loop whose NFTs are committed as hashes; follows Created
+    /// and precedes Approved
+    Committed,
+}
+
+/// Overall execution status of a trade loop, derived from its steps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopStatus {
+    /// No step has been executed yet
+    Pending,
+    /// Some, but not all, steps have been executed via partial execution
+    PartiallyExecuted,
+    /// Every step has been executed
+    Executed,
+}
+
+/// Reason a trade loop does not form a valid cycle
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LoopIntegrityError {
+    /// The trade loop has no steps
+    EmptyLoop,
+    /// The last recipient is not the first sender
+    LoopNotClosed {
+        /// The first step's sender
+        expected: Pubkey,
+        /// The last step's recipient
+        found: Pubkey,
+    },
+    /// A step's sender is not the previous step's recipient
+    ChainBrokenAt {
+        /// Index of the step whose sender breaks the chain
+        step: u8,
+        /// The previous step's recipient
+        expected: Pubkey,
+        /// The step's sender
+        found: Pubkey,
+    },
+    /// A wallet sends in more than one step
+    DuplicateParticipant {
+        /// The repeated sender
+        pubkey: Pubkey,
+    },
+    /// Fewer than two distinct wallets take part in the loop
+    TooFewParticipants,
+}
+
+impl std::fmt::Display for LoopIntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyLoop => write!(f, "trade loop has no steps"),
+            Self::LoopNotClosed { expected, found } => {
+                write!(f, "loop not closed: last recipient {} is not first sender {}", found, expected)
+            }
+            Self::ChainBrokenAt { step, expected, found } => {
+                write!(f, "chain broken at step {}: sender {} is not previous recipient {}", step, found, expected)
+            }
+            Self::DuplicateParticipant { pubkey } => write!(f, "{} sends in more than one step", pubkey),
+            Self::TooFewParticipants => write!(f, "fewer than 2 distinct participants"),
+        }
+    }
 }
 
 /// Trade step in a trade loop
@@ -41,6 +213,113 @@ pub struct TradeStep {
     pub nft_mints: Vec<Pubkey>,
     /// Current status of this step
     pub status: StepStatus,
+    /// Whether each NFT in `nft_mints` (same order) is held in the escrow PDA
+    pub escrowed: Vec<bool>,
+    /// Agreed value of each NFT in `nft_mints` (same order), used as the royalty baseline
+    pub nft_values_lamports: Vec<u64>,
+    /// Optional URI of an off-chain description or terms document, zero-padded
+    pub metadata_uri: Option<[u8; 128]>,
+    /// Wallet allowed to approve this step on the sender's behalf, and when that permission expires
+    pub delegate_approval: Option<(Pubkey, i64)>,
+    /// Lamports the sender pays the recipient alongside the NFTs ("top-up" deals)
+    pub sol_amount: u64,
+    /// Whether the sender's token accounts for this step are frozen until execution
+    pub nfts_locked: bool,
+    /// Unix timestamp after which this step can no longer be approved or executed
+    pub step_expires_at: Option<u64>,
+    /// Fungible token amounts the sender pays the recipient alongside the NFTs
+    pub spl_token_legs: Vec<SplTokenLeg>,
+    /// Hashes of the NFTs a sealed-bid step commits to, cleared when ExecuteSealedBidLoop fills `nft_mints`
+    pub nft_mint_commitments: Vec<[u8; 32]>,
+    /// Slot at which the step was added (or committed), 0 for steps not yet added
+    pub added_at_slot: u64,
+    /// Slot of the step's current approval, cleared when the approval is invalidated
+    pub approved_at_slot: Option<u64>,
+    /// Slot at which the step was executed
+    pub executed_at_slot: Option<u64>,
+    /// Unix timestamp before which the step's NFTs cannot be transferred
+    pub not_before: Option<u64>,
+}
+
+/// Amount of a fungible SPL token sent alongside a step's NFTs (e.g. USDC to balance values)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SplTokenLeg {
+    /// The token mint
+    pub mint: Pubkey,
+    /// Amount in the mint's base units
+    pub amount: u64,
+}
+
+impl TradeStep {
+    /// Build one step per participant, each sending to the next one in the ring,
+    /// with no NFTs committed yet (AddTradeStep fills them in)
+    pub fn build_ring(participants: &[Pubkey]) -> Vec<TradeStep> {
+        let participant_count = participants.len();
+        
+        participants.iter().enumerate().map(|(i, from)| TradeStep {
+            from: *from,
+            to: participants[(i + 1) % participant_count],
+            nft_mints: Vec::new(),
+            status: StepStatus::Created,
+            escrowed: Vec::new(),
+            nft_values_lamports: Vec::new(),
+            metadata_uri: None,
+            delegate_approval: None,
+            sol_amount: 0,
+            nfts_locked: false,
+            step_expires_at: None,
+            spl_token_legs: Vec::new(),
+            nft_mint_commitments: Vec::new(),
+            added_at_slot: 0,
+            approved_at_slot: None,
+            executed_at_slot: None,
+            not_before: None,
+        }).collect()
+    }
+    
+    /// Commitment to an NFT of a sealed-bid step: SHA-256 of mint || nonce
+    pub fn nft_commitment(nft_mint: &Pubkey, nonce: &[u8; 16]) -> [u8; 32] {
+        hashv(&[nft_mint.as_ref(), nonce.as_ref()]).to_bytes()
+    }
+    
+    /// Check if the step's own deadline has passed
+    pub fn is_expired(&self, current_time: u64) -> bool {
+        self.step_expires_at.is_some_and(|expires_at| current_time > expires_at)
+    }
+    
+    /// Check if the step's timelock still prevents its execution
+    pub fn is_timelocked(&self, current_time: u64) -> bool {
+        self.not_before.is_some_and(|not_before| current_time < not_before)
+    }
+    
+    /// Short hash of the step's terms: the first 8 bytes of hash(step_index || from || to || nft_mints)
+    pub fn hash8(&self, step_index: u8) -> [u8; 8] {
+        let step_index_bytes = [step_index];
+        let mut fields: Vec<&[u8]> = vec![&step_index_bytes, self.from.as_ref(), self.to.as_ref()];
+        fields.extend(self.nft_mints.iter().map(|nft_mint| nft_mint.as_ref()));
+        
+        let mut hash8 = [0u8; 8];
+        hash8.copy_from_slice(&hashv(&fields).to_bytes()[..8]);
+        hash8
+    }
+    
+    /// Calculate space needed for a step holding `nft_count` NFTs
+    pub const fn get_space(nft_count: u8) -> usize {
+        // from(32) + to(32) + status(1 + 1 fail reason)
+        // + vector headers for nft_mints(4), escrowed(4) and nft_values_lamports(4)
+        // + metadata_uri(1 + 128) + delegate_approval(1 + 32 + 8) + sol_amount(8) + nfts_locked(1)
+        // + step_expires_at(1 + 8) + spl_token_legs(4 + MAX_SPL_LEGS_PER_STEP * (mint(32) + amount(8)))
+        // + vector header for nft_mint_commitments(4) + added_at_slot(8) + approved_at_slot(1 + 8)
+        // + executed_at_slot(1 + 8) + not_before(1 + 8)
+        let step_base_size = 32 + 32 + 2 + 4 + 4 + 4 + 129 + 41 + 8 + 1 + 9
+            + 4 + MAX_SPL_LEGS_PER_STEP as usize * 40 + 4 + 8 + 9 + 9 + 9;
+        
+        // Each NFT: mint(32) + escrowed flag(1) + value in lamports(8); a step holds either
+        // mints or commitments (32 each), so the NFT size also covers a commitment
+        let nft_mint_size = 32 + 1 + 8;
+        
+        step_base_size + nft_count as usize * nft_mint_size
+    }
 }
 
 /// Trade loop state
@@ -58,10 +337,119 @@ pub struct TradeLoop {
     pub steps: Vec<TradeStep>,
     /// Authority that can cancel this trade loop (usually the creator)
     pub authority: Pubkey,
+    /// Whether this trade loop has been paused by its authority
+    pub is_paused: bool,
+    /// Replay protection nonce expected by ExecuteFullTradeLoop, incremented after each execution
+    pub nonce: u64,
+    /// Third party that must co-sign execution (for regulated deployments)
+    pub witness: Option<Pubkey>,
+    /// Pending authority transfer: new authority and the earliest time it can be confirmed
+    pub authority_transfer_request: Option<(Pubkey, i64)>,
+    /// The other half of a split trade loop; both halves together form the cycle
+    pub linked_loop: Option<Pubkey>,
+    /// Seconds after every step is approved during which the loop cannot be cancelled
+    pub cancel_lockout_seconds: u64,
+    /// Unix timestamp when every step first became approved
+    pub all_approved_at: Option<u64>,
+    /// Searchable tags (raw UTF-8, zero-padded) used by marketplaces for discovery
+    pub loop_tags: [Option<[u8; 32]>; MAX_LOOP_TAGS],
+    /// Automatic renewal settings; a fresh loop is created after each full execution
+    pub recurring: Option<RecurringConfig>,
+    /// The loop created when this recurring loop executed
+    pub successor_loop: Option<Pubkey>,
+    /// Mint of the NFT representing this loop's authority while it is tokenized
+    pub tokenized_as: Option<Pubkey>,
+    /// Advisory deadline shown to participants (Unix timestamp, 0 if unset); never enforced
+    pub offered_until_display: i64,
+    /// Timezone to show `offered_until_display` in, as minutes from UTC
+    pub display_timezone_offset: i16,
+    /// Free-text deadline note from the authority (raw UTF-8, zero-padded)
+    pub deadline_message: [u8; DEADLINE_MESSAGE_SIZE],
+    /// Protocol fee discount from an applied coupon, in basis points of the fee
+    pub applied_discount_bps: u16,
+    /// Number of steps the loop is expected to have; steps below this index may not be added yet
+    pub allocated_step_count: u8,
+    /// External account state that must hold for the loop to execute
+    pub execution_condition: Option<ExecutionCondition>,
+    /// Last STEP_HASH_HISTORY_DEPTH hashes of each step, oldest first and zero if unused,
+    /// flattened by step index (step i uses entries i * DEPTH .. (i + 1) * DEPTH)
+    pub step_hash_history: Vec<[u8; 8]>,
+    /// Only these wallets may execute the loop when set (unused slots are the zero pubkey);
+    /// an all-zero whitelist blocks execution entirely
+    pub execution_whitelist: Option<[Pubkey; MAX_WHITELISTED_EXECUTORS]>,
+    /// Execute the loop in the same instruction as its last approval, when that approval
+    /// is an ApproveTradeStep; batch and signed approvals never execute the loop
+    pub auto_execute: bool,
+    /// Sealed-bid loop: steps commit to hashed NFTs and reveal them before execution
+    pub commitment_mode: bool,
+    /// Maximum NFTs of each step, by step index; steps without an entry may hold MAX_NFTS_PER_STEP
+    pub per_step_nft_limits: Vec<u8>,
+    /// Lamports each sender deposits in its step's bond PDA when adding the step (0 if unbonded)
+    pub execution_bond_lamports: u64,
+    /// Protocol fee in basis points charged when the loop executed, after any coupon discount
+    pub applied_fee_bps: u16,
+}
+
+/// Automatic renewal settings of a recurring trade loop
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RecurringConfig {
+    /// Number of successor loops that can be created in total
+    pub max_recurrences: u8,
+    /// Lifetime of each successor loop in seconds
+    pub interval_seconds: u64,
+    /// Number of successor loops created so far
+    pub recurrence_count: u8,
+    /// Seed mixed into the next successor's trade ID
+    pub next_loop_seed: u64,
+}
+
+/// Byte of an external account that must hold an expected value for a trade loop to execute
+/// (e.g. a DAO proposal's "passed" flag or an auction's "ended" flag)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ExecutionCondition {
+    /// The account holding the condition
+    pub account: Pubkey,
+    /// Offset of the byte within the account data
+    pub byte_offset: u32,
+    /// Value the byte must have
+    pub expected_value: u8,
 }
 
 impl Sealed for TradeLoop {}
 
+impl TradeLoop {
+    /// Every trade loop account is allocated at the maximum size, so steps and
+    /// NFTs can be added or replaced without reallocating
+    pub const LEN: usize = TradeLoop::get_space(MAX_PARTICIPANTS_PER_TRANSACTION, MAX_NFTS_PER_STEP);
+    
+    /// Write the Borsh encoding to the start of `dst` and zero the rest,
+    /// failing instead of truncating when it does not fit
+    pub fn pack_into_slice(&self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let data = self.try_to_vec()?;
+        if data.len() > dst.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        
+        let (encoded, padding) = dst.split_at_mut(data.len());
+        encoded.copy_from_slice(&data);
+        padding.fill(0);
+        Ok(())
+    }
+    
+    pub fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        // Deserialize the Borsh encoding, ignoring the zero padding after it
+        Self::deserialize(&mut &src[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+    
+    /// Unpack an account in the packed layout without checking `is_initialized`
+    pub fn unpack_unchecked(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::unpack_from_slice(src)
+    }
+}
+
 impl IsInitialized for TradeLoop {
     fn is_initialized(&self) -> bool {
         self.is_initialized
@@ -69,72 +457,365 @@ impl IsInitialized for TradeLoop {
 }
 
 impl TradeLoop {
+    /// Check if every allocated step has been added
+    pub fn all_steps_added(&self) -> bool {
+        self.steps.len() >= self.allocated_step_count as usize
+    }
+    
+    /// Maximum number of NFTs the step at `step_index` may hold
+    pub fn step_nft_limit(&self, step_index: u8) -> u8 {
+        self.per_step_nft_limits.get(step_index as usize).copied().unwrap_or(MAX_NFTS_PER_STEP)
+    }
+    
+    /// Append the current hash of a step to its history, dropping the oldest entry when full
+    pub fn record_step_hash(&mut self, step_index: u8) {
+        let hash8 = match self.steps.get(step_index as usize) {
+            Some(step) => step.hash8(step_index),
+            None => return,
+        };
+        
+        let start = step_index as usize * STEP_HASH_HISTORY_DEPTH;
+        let end = start + STEP_HASH_HISTORY_DEPTH;
+        if self.step_hash_history.len() < end {
+            self.step_hash_history.resize(end, [0; 8]);
+        }
+        
+        let history = &mut self.step_hash_history[start..end];
+        history.rotate_left(1);
+        history[STEP_HASH_HISTORY_DEPTH - 1] = hash8;
+    }
+    
+    /// Recorded hashes of a step, oldest first
+    pub fn step_hashes(&self, step_index: u8) -> Vec<[u8; 8]> {
+        let start = step_index as usize * STEP_HASH_HISTORY_DEPTH;
+        self.step_hash_history.iter()
+            .skip(start)
+            .take(STEP_HASH_HISTORY_DEPTH)
+            .filter(|hash8| **hash8 != [0; 8])
+            .copied()
+            .collect()
+    }
+    
+    /// Protocol fee in basis points after the loop's coupon discount
+    pub fn discounted_fee_basis_points(&self, fee_basis_points: u16) -> u16 {
+        let discount = (fee_basis_points as u32 * self.applied_discount_bps.min(10_000) as u32) / 10_000;
+        fee_basis_points - discount as u16
+    }
+    
+    /// Derive the initial nonce from hash(trade_id || created_at || authority)
+    /// A trade loop recreated at the same address gets a different nonce, so old
+    /// signed execution transactions cannot be replayed against it
+    pub fn initial_nonce(trade_id: &[u8; 32], created_at: u64, authority: &Pubkey) -> u64 {
+        let hash = hashv(&[trade_id, &created_at.to_le_bytes(), authority.as_ref()]);
+        let mut nonce_bytes = [0u8; 8];
+        nonce_bytes.copy_from_slice(&hash.to_bytes()[..8]);
+        u64::from_le_bytes(nonce_bytes)
+    }
+    
     /// Calculate space needed for this trade loop
-    pub fn get_space(step_count: u8, max_nfts_per_step: u8) -> usize {
-        // Base size: is_initialized(1) + trade_id(32) + created_at(8) + expires_at(8) + authority(32)
-        let base_size = 1 + 32 + 8 + 8 + 32;
+    /// The size depends only on the step count, so reordering steps never changes it
+    pub const fn get_space(step_count: u8, max_nfts_per_step: u8) -> usize {
+        // Base size: is_initialized(1) + trade_id(32) + created_at(8) + expires_at(8) + authority(32) + is_paused(1) + nonce(8) + witness(33)
+        // + authority_transfer_request(1 + 32 + 8) + linked_loop(33) + cancel_lockout_seconds(8) + all_approved_at(9)
+        // + loop_tags(MAX_LOOP_TAGS * (1 + 32)) + recurring(1 + 1 + 8 + 1 + 8) + successor_loop(33) + tokenized_as(33)
+        // + offered_until_display(8) + display_timezone_offset(2) + deadline_message(DEADLINE_MESSAGE_SIZE) + applied_discount_bps(2)
+        // + allocated_step_count(1) + execution_condition(1 + 32 + 4 + 1) + step_hash_history header(4)
+        // + execution_whitelist(1 + MAX_WHITELISTED_EXECUTORS * 32) + auto_execute(1) + commitment_mode(1)
+        // + per_step_nft_limits(4 + MAX_PARTICIPANTS_PER_TRANSACTION) + execution_bond_lamports(8) + applied_fee_bps(2)
+        let base_size = 1 + 32 + 8 + 8 + 32 + 1 + 8 + 33 + 41 + 33 + 8 + 9 + MAX_LOOP_TAGS * 33 + 19 + 33 + 33
+            + 8 + 2 + DEADLINE_MESSAGE_SIZE + 2 + 1 + 38 + 4 + 1 + MAX_WHITELISTED_EXECUTORS * 32 + 1 + 1
+            + 4 + MAX_PARTICIPANTS_PER_TRANSACTION as usize + 8 + 2;
         
         // Vector header for steps: 4 bytes
         let steps_header_size = 4;
         
-        // Each step: from(32) + to(32) + status(1) + vector header for nft_mints(4)
-        let step_base_size = 32 + 32 + 1 + 4;
-        
-        // Each NFT mint: 32 bytes
-        let nft_mint_size = 32;
-        
         // Ensure we don't exceed the maximum participants
-        let actual_step_count = std::cmp::min(step_count, MAX_PARTICIPANTS_PER_TRANSACTION);
+        let actual_step_count = if step_count < MAX_PARTICIPANTS_PER_TRANSACTION {
+            step_count
+        } else {
+            MAX_PARTICIPANTS_PER_TRANSACTION
+        };
+        
+        // Ensure we don't exceed the maximum NFTs per step (merged steps are capped at the same limit)
+        let actual_max_nfts = if max_nfts_per_step < MAX_NFTS_PER_STEP {
+            max_nfts_per_step
+        } else {
+            MAX_NFTS_PER_STEP
+        };
         
-        // Ensure we don't exceed the maximum NFTs per step
-        let actual_max_nfts = std::cmp::min(max_nfts_per_step, MAX_NFTS_PER_STEP);
+        // Each step's hash history: STEP_HASH_HISTORY_DEPTH entries of 8 bytes
+        let step_hash_history_size = 8 * STEP_HASH_HISTORY_DEPTH;
         
         // Total size
-        base_size + steps_header_size + (actual_step_count as usize * (step_base_size + (actual_max_nfts as usize * nft_mint_size)))
+        base_size + steps_header_size
+            + (actual_step_count as usize * (TradeStep::get_space(actual_max_nfts) + step_hash_history_size))
     }
     
-    /// Verify that the trade loop forms a valid cycle
-    pub fn verify_loop(&self) -> bool {
-        if self.steps.is_empty() {
-            return false;
-        }
+    /// Calculate space needed for this trade loop including per-step multisig approval configs
+    /// `approval_config_sizes` holds the serialized approval config size of each step
+    pub fn get_space_v2(step_count: u8, max_nfts_per_step: u8, approval_config_sizes: &[usize]) -> usize {
+        let approval_configs_size: usize = approval_config_sizes.iter()
+            .take(std::cmp::min(step_count, MAX_PARTICIPANTS_PER_TRANSACTION) as usize)
+            .sum();
         
-        // Check that the loop closes - last recipient must be first sender
-        if self.steps.last().unwrap().to != self.steps.first().unwrap().from {
-            return false;
+        Self::get_space(step_count, max_nfts_per_step) + approval_configs_size
+    }
+    
+    /// Calculate space needed for a step's multisig approval config with the given number of signers
+    pub fn approval_config_space(signer_count: u8) -> usize {
+        if signer_count == 0 {
+            return 0;
         }
         
+        // threshold(1) + signers vector header(4) + signers(32 each) + approvals vector header(4) + approvals(1 each)
+        1 + 4 + (signer_count as usize * 32) + 4 + signer_count as usize
+    }
+    
+    /// Verify that the trade loop forms a valid cycle, reporting the first problem found
+    pub fn verify_loop_detailed(&self) -> Result<(), LoopIntegrityError> {
+        let (first, last) = match (self.steps.first(), self.steps.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(LoopIntegrityError::EmptyLoop),
+        };
+        
         // Check that each step's recipient is the next step's sender
-        for i in 0..self.steps.len() - 1 {
-            if self.steps[i].to != self.steps[i + 1].from {
-                return false;
+        for (i, pair) in self.steps.windows(2).enumerate() {
+            if pair[0].to != pair[1].from {
+                return Err(LoopIntegrityError::ChainBrokenAt {
+                    step: (i + 1) as u8,
+                    expected: pair[0].to,
+                    found: pair[1].from,
+                });
             }
         }
         
+        // Half of a split loop is a chain; the cycle is closed across both halves
+        // and checked with verify_linked_loop
+        if self.linked_loop.is_some() {
+            return Ok(());
+        }
+        
+        // Check that the loop closes - last recipient must be first sender
+        if last.to != first.from {
+            return Err(LoopIntegrityError::LoopNotClosed {
+                expected: first.from,
+                found: last.to,
+            });
+        }
+        
         // Check that all participants in the loop are unique
         let mut unique_participants = HashSet::new();
         for step in &self.steps {
-            unique_participants.insert(step.from);
+            if !unique_participants.insert(step.from) {
+                return Err(LoopIntegrityError::DuplicateParticipant { pubkey: step.from });
+            }
         }
         
         // At least 2 unique participants required for a valid loop
-        unique_participants.len() >= 2
+        if unique_participants.len() < 2 {
+            return Err(LoopIntegrityError::TooFewParticipants);
+        }
+        
+        Ok(())
+    }
+    
+    /// Check that this loop and its linked half form a single valid cycle
+    pub fn verify_linked_loop(&self, linked: &TradeLoop) -> bool {
+        if self.steps.is_empty() || linked.steps.is_empty() {
+            return false;
+        }
+        
+        // Each half must hand over to the other at both ends
+        let first_hands_over = self.steps.last().unwrap().to == linked.steps.first().unwrap().from;
+        let second_hands_back = linked.steps.last().unwrap().to == self.steps.first().unwrap().from;
+        if !first_hands_over || !second_hands_back {
+            return false;
+        }
+        
+        // Every participant sends exactly once across the whole cycle
+        let mut unique_participants = HashSet::new();
+        self.steps.iter().chain(linked.steps.iter()).all(|step| unique_participants.insert(step.from))
     }
     
     /// Check if all steps are approved and ready for execution
+    /// A sealed-bid loop still reveals its NFTs when it executes
     pub fn is_ready_for_execution(&self) -> bool {
         self.steps.iter().all(|step| step.status == StepStatus::Approved)
     }
     
+    /// Check if any sealed-bid step has not revealed its NFTs yet
+    pub fn has_unrevealed_commitments(&self) -> bool {
+        self.steps.iter().any(|step| !step.nft_mint_commitments.is_empty())
+    }
+    
+    /// Check if any step has failed or was cancelled, which allows any participant to cancel the loop
+    pub fn is_cancellation_eligible(&self) -> bool {
+        self.steps.iter().any(|step| matches!(step.status, StepStatus::Failed { .. } | StepStatus::Cancelled))
+    }
+    
+    /// Check if any NFT in the trade loop is currently held in escrow
+    pub fn has_escrowed_nfts(&self) -> bool {
+        self.steps.iter().any(|step| step.escrowed.iter().any(|escrowed| *escrowed))
+    }
+    
+    /// Check if any step's NFTs are currently locked in their sender's wallet
+    pub fn has_locked_nfts(&self) -> bool {
+        self.steps.iter().any(|step| step.nfts_locked)
+    }
+    
     /// Check if the trade loop has expired
     pub fn is_expired(&self, current_time: u64) -> bool {
         current_time >= self.expires_at
     }
+    
+    /// Get the overall execution status of the trade loop
+    pub fn overall_status(&self) -> LoopStatus {
+        let executed_steps = self.steps.iter()
+            .filter(|step| step.status == StepStatus::Executed)
+            .count();
+        
+        if executed_steps == 0 {
+            LoopStatus::Pending
+        } else if executed_steps == self.steps.len() {
+            LoopStatus::Executed
+        } else {
+            LoopStatus::PartiallyExecuted
+        }
+    }
+}
+
+/// Reusable participant topology for trade loops that are run repeatedly
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct TradeLoopTemplate {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Unique identifier for this template among its creator's templates
+    pub template_id: [u8; 16],
+    /// Account that created the template
+    pub creator: Pubkey,
+    /// Participants in ring order (participant i sends to participant i + 1)
+    pub participant_ring: Vec<Pubkey>,
+    /// Number of steps in trade loops spawned from this template
+    pub step_count: u8,
+    /// Number of trade loops spawned from this template
+    pub usage_count: u64,
+}
+
+impl Sealed for TradeLoopTemplate {}
+
+impl IsInitialized for TradeLoopTemplate {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl TradeLoopTemplate {
+    /// Calculate space needed for a template with the given number of participants
+    pub fn get_space(participant_count: u8) -> usize {
+        // is_initialized(1) + template_id(16) + creator(32) + participant_ring header(4) + step_count(1) + usage_count(8)
+        let base_size = 1 + 16 + 32 + 4 + 1 + 8;
+        
+        // Ensure we don't exceed the maximum participants
+        let actual_participant_count = std::cmp::min(participant_count, MAX_PARTICIPANTS_PER_TRANSACTION);
+        
+        base_size + actual_participant_count as usize * 32
+    }
+    
+    /// Build the trade steps described by this template with no NFTs committed yet
+    pub fn build_steps(&self) -> Vec<TradeStep> {
+        TradeStep::build_ring(&self.participant_ring)
+    }
+}
+
+/// Maximum number of creators a Metaplex metadata account can list
+pub const MAX_METADATA_CREATORS: u8 = 5;
+
+/// Creator royalty enforcement settings
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoyaltyEnforcement {
+    /// Whether royalties are paid to creators when NFTs are transferred
+    pub enabled: bool,
+    /// Maximum number of creators paid per NFT (bounds compute usage)
+    pub max_creators: u8,
+}
+
+/// Protocol fee tiers by trade loop size
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    /// Fee of loops with at most 2 steps
+    pub two_party_bps: u16,
+    /// Fee of loops with at most `small_threshold` steps
+    pub small_loop_bps: u16,
+    /// Fee of larger loops
+    pub large_loop_bps: u16,
+    /// Largest step count charged `small_loop_bps`
+    pub small_threshold: u8,
+}
+
+impl FeeSchedule {
+    /// Fee in basis points of a loop with `step_count` steps
+    pub fn fee_basis_points(&self, step_count: usize) -> u16 {
+        if step_count <= 2 {
+            self.two_party_bps
+        } else if step_count <= self.small_threshold as usize {
+            self.small_loop_bps
+        } else {
+            self.large_loop_bps
+        }
+    }
+    
+    /// Check that no tier exceeds MAX_SCHEDULED_FEE_BASIS_POINTS
+    pub fn is_valid(&self) -> bool {
+        [self.two_party_bps, self.small_loop_bps, self.large_loop_bps]
+            .iter()
+            .all(|bps| *bps <= MAX_SCHEDULED_FEE_BASIS_POINTS)
+    }
+}
+
+/// Swap route converting protocol fees from SOL to the treasury's token
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct FeeRoute {
+    /// Swap program the route goes through (e.g. Orca Whirlpools)
+    pub route_program: Pubkey,
+    /// Mints swapped through between SOL and the output token, at most MAX_FEE_ROUTE_HOPS
+    pub intermediate_mints: Vec<Pubkey>,
+    /// Minimum amount of the output token accepted for one conversion
+    pub min_out_amount: u64,
+}
+
+impl FeeRoute {
+    /// Check that the route names a program, has at most MAX_FEE_ROUTE_HOPS hops and a minimum output
+    pub fn is_valid(&self) -> bool {
+        self.route_program != Pubkey::default()
+            && self.intermediate_mints.len() <= MAX_FEE_ROUTE_HOPS
+            && self.min_out_amount > 0
+    }
+}
+
+/// Layout version of the program config account, stored at byte 0
+/// Version 1 accounts start with `is_initialized`, which reads as 1
+pub const PROGRAM_CONFIG_VERSION: u8 = 2;
+
+/// Original program config layout (config version 1)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct ProgramConfigV1 {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Current program version
+    pub version: u32,
+    /// Upgrade authority (can deploy new versions)
+    pub upgrade_authority: Pubkey,
+    /// Optional: A multi-sig governance account for decentralized upgrades
+    pub governance: Option<Pubkey>,
+    /// Whether the program is currently paused (emergency stop)
+    pub paused: bool,
 }
 
 /// Program upgrade authority configuration
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
 pub struct ProgramConfig {
+    /// Layout version of this account (PROGRAM_CONFIG_VERSION)
+    pub config_version: u8,
     /// Is initialized
     pub is_initialized: bool,
     /// Current program version
@@ -145,6 +826,73 @@ pub struct ProgramConfig {
     pub governance: Option<Pubkey>,
     /// Whether the program is currently paused (emergency stop)
     pub paused: bool,
+    /// Upgrade authority proposed by the current authority, pending acceptance
+    pub pending_upgrade_authority: Option<Pubkey>,
+    /// Unix timestamp after which the pending authority transfer lapses
+    pub authority_transfer_expiry: i64,
+    /// Creator royalty enforcement settings
+    pub royalty_enforcement: RoyaltyEnforcement,
+    /// Whether ExecuteTradeStepAndCloseEmpty closes emptied source token accounts
+    pub close_empty_token_accounts: bool,
+    /// Maximum number of steps allowed in a trade loop
+    pub max_participants: u8,
+    /// Protocol fee in basis points, charged to every loop while no fee schedule is set
+    pub fee_basis_points: u16,
+    /// Account receiving protocol fees
+    pub fee_recipient: Pubkey,
+    /// Mint of the token whose holders vote on governance proposals
+    pub governance_token_mint: Option<Pubkey>,
+    /// Relayers allowed to execute trade loops on behalf of participants
+    pub authorized_relayers: Vec<Pubkey>,
+    /// Maximum lamports a step may pay alongside its NFTs (0 disables SOL legs)
+    pub max_sol_per_step: u64,
+    /// Whether the self-test of the last upgrade passed
+    pub last_upgrade_test_passed: bool,
+    /// Unix timestamp of the last upgrade
+    pub last_upgrade_timestamp: i64,
+    /// Mint of the completion badge given to participants of executed trade loops
+    pub badge_mint: Option<Pubkey>,
+    /// Whether executed trade loops record provenance in the metadata of NFTs the config can update
+    pub append_provenance_metadata: bool,
+    /// Whether wallets must respect a cooldown between trade loop initializations
+    pub rate_limiting_enabled: bool,
+    /// Key that signs fee discount coupons (coupons are rejected if unset)
+    pub coupon_issuer: Option<Pubkey>,
+    /// Pending governance replacement: new governance and the earliest time it can be finalized
+    pub proposed_governance: Option<(Pubkey, i64)>,
+    /// Minimum collection floor price (in USD cents) of NFTs added to trade steps
+    pub min_nft_floor_price_usd_cents: Option<u64>,
+    /// Why the program is paused; a paused config without a reason is an emergency pause
+    pub paused_reason: Option<PauseReason>,
+    /// Seconds a fully approved bonded trade loop has to execute before its bonds can be
+    /// claimed; None uses DEFAULT_EXECUTION_WINDOW_SECONDS
+    pub execution_window_seconds: Option<u64>,
+    /// Protocol fee tiers by loop size; replaces `fee_basis_points` when set
+    pub fee_schedule: Option<FeeSchedule>,
+    /// Swap route converting protocol fees before they reach the treasury; None keeps SOL
+    pub fee_route: Option<FeeRoute>,
+    /// Deposit fees as SOL when the fee route cannot be used
+    pub fee_route_fallback_to_sol: bool,
+    /// Whether step participants need a valid KYC entry
+    pub kyc_required: bool,
+}
+
+/// Reason the program is paused
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PauseReason {
+    /// Emergency stop; instructions that check the pause are rejected
+    Emergency,
+    /// Scheduled maintenance; every instruction outside `PAUSE_EXEMPT_TAGS` is rejected
+    ScheduledMaintenance,
+    /// Paused by a passed governance proposal
+    GovernanceOrder,
+}
+
+impl PauseReason {
+    /// Code of the reason reported in `SwapError::ProgramPaused`
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
 }
 
 impl Sealed for ProgramConfig {}
@@ -153,4 +901,703 @@ impl IsInitialized for ProgramConfig {
     fn is_initialized(&self) -> bool {
         self.is_initialized
     }
-} 
\ No newline at end of file
+}
+
+impl ProgramConfig {
+    /// Calculate space needed for the program config account
+    pub fn get_space() -> usize {
+        // config_version(1) + is_initialized(1) + version(4) + upgrade_authority(32) + governance(1 + 32) + paused(1)
+        let base_size = 1 + 1 + 4 + 32 + 33 + 1;
+        
+        // pending_upgrade_authority(1 + 32) + authority_transfer_expiry(8)
+        let authority_transfer_size = 33 + 8;
+        
+        // royalty_enforcement: enabled(1) + max_creators(1)
+        let royalty_enforcement_size = 1 + 1;
+        
+        // close_empty_token_accounts(1)
+        let close_empty_token_accounts_size = 1;
+        
+        // max_participants(1) + fee_basis_points(2) + fee_recipient(32)
+        let operator_settings_size = 1 + 2 + 32;
+        
+        // governance_token_mint(1 + 32)
+        let governance_token_mint_size = 33;
+        
+        // authorized_relayers: length prefix(4) + MAX_AUTHORIZED_RELAYERS * 32
+        let authorized_relayers_size = 4 + MAX_AUTHORIZED_RELAYERS * 32;
+        
+        // max_sol_per_step(8)
+        let max_sol_per_step_size = 8;
+        
+        // last_upgrade_test_passed(1) + last_upgrade_timestamp(8)
+        let upgrade_audit_size = 1 + 8;
+        
+        // badge_mint(1 + 32)
+        let badge_mint_size = 33;
+        
+        // append_provenance_metadata(1)
+        let append_provenance_metadata_size = 1;
+        
+        // rate_limiting_enabled(1)
+        let rate_limiting_enabled_size = 1;
+        
+        // coupon_issuer(1 + 32)
+        let coupon_issuer_size = 33;
+        
+        // proposed_governance(1 + 32 + 8)
+        let proposed_governance_size = 1 + 32 + 8;
+        
+        // min_nft_floor_price_usd_cents(1 + 8)
+        let min_nft_floor_price_size = 1 + 8;
+        
+        // paused_reason(1 + 1)
+        let paused_reason_size = 1 + 1;
+        
+        // execution_window_seconds(1 + 8)
+        let execution_window_size = 1 + 8;
+        
+        // fee_schedule(1 + 2 + 2 + 2 + 1)
+        let fee_schedule_size = 1 + 7;
+        
+        // fee_route: (1 + route_program(32) + intermediate_mints(4 + MAX_FEE_ROUTE_HOPS * 32) + min_out_amount(8))
+        // + fee_route_fallback_to_sol(1)
+        let fee_route_size = 1 + 32 + 4 + MAX_FEE_ROUTE_HOPS * 32 + 8 + 1;
+        
+        // kyc_required(1)
+        let kyc_required_size = 1;
+        
+        base_size + authority_transfer_size + royalty_enforcement_size + close_empty_token_accounts_size
+            + operator_settings_size + governance_token_mint_size + authorized_relayers_size + max_sol_per_step_size
+            + upgrade_audit_size + badge_mint_size + append_provenance_metadata_size + rate_limiting_enabled_size
+            + coupon_issuer_size + proposed_governance_size + min_nft_floor_price_size + paused_reason_size
+            + execution_window_size + fee_schedule_size + fee_route_size + kyc_required_size
+    }
+    
+    /// Why the program is paused, or None if it is not
+    pub fn pause_reason(&self) -> Option<PauseReason> {
+        self.paused.then(|| self.paused_reason.unwrap_or(PauseReason::Emergency))
+    }
+    
+    /// Seconds a fully approved bonded trade loop has to execute before its bonds can be claimed
+    pub fn execution_window(&self) -> u64 {
+        self.execution_window_seconds.unwrap_or(DEFAULT_EXECUTION_WINDOW_SECONDS)
+    }
+    
+    /// Protocol fee in basis points of a loop with `step_count` steps, before any coupon discount
+    pub fn fee_basis_points_for(&self, step_count: usize) -> u16 {
+        self.fee_schedule.map_or(self.fee_basis_points, |schedule| schedule.fee_basis_points(step_count))
+    }
+    
+    /// Check if a wallet is an authorized relayer
+    pub fn is_authorized_relayer(&self, wallet: &Pubkey) -> bool {
+        self.authorized_relayers.contains(wallet)
+    }
+    
+    /// Deserialize a program config account of any layout version
+    /// Version 1 accounts are upgraded in memory with default values for the newer fields
+    pub fn unpack_versioned(data: &[u8]) -> Result<Self, std::io::Error> {
+        match data.first() {
+            Some(1) => ProgramConfigV1::deserialize(&mut &data[..]).map(Self::from),
+            // Fields appended to the current layout after an account was created are read
+            // as zero (None, empty or 0) until the account is grown to `get_space`
+            Some(0) | Some(&PROGRAM_CONFIG_VERSION) if data.len() < Self::get_space() => {
+                let mut padded = data.to_vec();
+                padded.resize(Self::get_space(), 0);
+                Self::deserialize(&mut &padded[..])
+            }
+            Some(0) | Some(&PROGRAM_CONFIG_VERSION) => Self::deserialize(&mut &data[..]),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Unknown program config version",
+            )),
+        }
+    }
+}
+
+impl From<ProgramConfigV1> for ProgramConfig {
+    fn from(config: ProgramConfigV1) -> Self {
+        Self {
+            config_version: PROGRAM_CONFIG_VERSION,
+            is_initialized: config.is_initialized,
+            version: config.version,
+            upgrade_authority: config.upgrade_authority,
+            governance: config.governance,
+            paused: config.paused,
+            pending_upgrade_authority: None,
+            authority_transfer_expiry: 0,
+            royalty_enforcement: RoyaltyEnforcement::default(),
+            close_empty_token_accounts: false,
+            max_participants: MAX_PARTICIPANTS_PER_TRANSACTION,
+            fee_basis_points: 0,
+            fee_recipient: config.upgrade_authority,
+            governance_token_mint: None,
+            authorized_relayers: Vec::new(),
+            max_sol_per_step: 0,
+            last_upgrade_test_passed: false,
+            last_upgrade_timestamp: 0,
+            badge_mint: None,
+            append_provenance_metadata: false,
+            rate_limiting_enabled: false,
+            coupon_issuer: None,
+            proposed_governance: None,
+            min_nft_floor_price_usd_cents: None,
+            paused_reason: None,
+            execution_window_seconds: None,
+            fee_schedule: None,
+            fee_route: None,
+            fee_route_fallback_to_sol: false,
+            kyc_required: false,
+        }
+    }
+} 
+/// Configuration change that can be applied through a governance vote
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub enum GovernedChange {
+    /// Replace the upgrade authority
+    UpdateAuthority(Pubkey),
+    /// Change the maximum number of steps in a trade loop
+    SetMaxParticipants(u8),
+    /// Change the protocol fee (basis points) and its recipient
+    UpdateFeeConfig(u16, Pubkey),
+    /// Pause the program
+    EmergencyPause,
+}
+
+/// Governance proposal voted on by governance token holders
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct GovernanceProposal {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Unique identifier for this proposal
+    pub proposal_id: [u8; 16],
+    /// The change applied if the proposal passes
+    pub proposed_change: GovernedChange,
+    /// Account that created the proposal
+    pub proposer: Pubkey,
+    /// Governance token weight voting in favor
+    pub votes_for: u64,
+    /// Governance token weight voting against
+    pub votes_against: u64,
+    /// Unix timestamp when voting closes
+    pub deadline: i64,
+    /// Whether the proposal has been finalized (applied or rejected)
+    pub finalized: bool,
+}
+
+impl Sealed for GovernanceProposal {}
+
+impl IsInitialized for GovernanceProposal {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl GovernanceProposal {
+    /// Calculate space needed for a governance proposal account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + proposal_id(16) + proposed_change(1 + largest variant 2 + 32)
+        // + proposer(32) + votes_for(8) + votes_against(8) + deadline(8) + finalized(1)
+        1 + 16 + (1 + 2 + 32) + 32 + 8 + 8 + 8 + 1
+    }
+}
+
+/// Record of a vote cast on a governance proposal, preventing double voting
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct VoteRecord {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Whether the vote was in favor
+    pub in_favor: bool,
+    /// Governance token weight of the vote, held in the vote record's token account until withdrawn
+    pub weight: u64,
+}
+
+impl VoteRecord {
+    /// Calculate space needed for a vote record account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + in_favor(1) + weight(8)
+        1 + 1 + 8
+    }
+}
+
+impl Sealed for VoteRecord {}
+
+impl IsInitialized for VoteRecord {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+/// Alternative NFT list proposed by a step's recipient in place of the sender's offer
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct CounterOffer {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Account that proposed the counter-offer (the step's recipient)
+    pub proposer: Pubkey,
+    /// NFTs the step's sender is asked to send instead
+    pub nft_mints: Vec<Pubkey>,
+    /// Unix timestamp when the counter-offer expires
+    pub expires_at: i64,
+}
+
+impl Sealed for CounterOffer {}
+
+impl IsInitialized for CounterOffer {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl CounterOffer {
+    /// Calculate space needed for a counter-offer account
+    pub fn get_space(nft_count: usize) -> usize {
+        // is_initialized(1) + proposer(32) + nft_mints(4 + 32 * count) + expires_at(8)
+        1 + 32 + 4 + 32 * nft_count + 8
+    }
+}
+
+/// Offer by an outside wallet to take over a step, sending its own NFTs instead
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct StepReplacementProposal {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Wallet offering to take the step's place in the loop
+    pub proposer: Pubkey,
+    /// NFTs the proposer would send in place of the step's NFTs
+    pub replacement_nft_mints: Vec<Pubkey>,
+    /// Unix timestamp when the proposal expires
+    pub expires_at: i64,
+}
+
+impl Sealed for StepReplacementProposal {}
+
+impl IsInitialized for StepReplacementProposal {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl StepReplacementProposal {
+    /// Calculate space needed for a step replacement proposal account
+    pub fn get_space(nft_count: usize) -> usize {
+        // is_initialized(1) + proposer(32) + replacement_nft_mints(4 + 32 * count) + expires_at(8)
+        1 + 32 + 4 + 32 * nft_count + 8
+    }
+}
+
+/// Wallets blocked from joining new trade loops
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct BlockedAccounts {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// The blocked wallets
+    pub accounts: Vec<Pubkey>,
+}
+
+impl Sealed for BlockedAccounts {}
+
+impl IsInitialized for BlockedAccounts {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl BlockedAccounts {
+    /// Calculate space needed for a blocklist holding the given number of wallets
+    pub fn get_space(account_count: usize) -> usize {
+        // is_initialized(1) + accounts(4 + 32 * count)
+        1 + 4 + 32 * account_count
+    }
+    
+    /// Check if a wallet is blocked
+    pub fn is_blocked(&self, wallet: &Pubkey) -> bool {
+        self.accounts.contains(wallet)
+    }
+}
+
+/// Trade loop initialization history of one wallet, used for rate limiting
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct ParticipantCooldown {
+    /// Slot of the wallet's last trade loop initialization
+    pub last_init_slot: u64,
+    /// Trade loops initialized during the epoch of `last_init_slot`
+    pub loop_count_this_epoch: u32,
+}
+
+impl ParticipantCooldown {
+    /// Calculate space needed for the participant cooldown account
+    pub fn get_space() -> usize {
+        // last_init_slot(8) + loop_count_this_epoch(4)
+        8 + 4
+    }
+}
+
+/// KYC status of one wallet, registered by the config authority
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct KycEntry {
+    /// The verified wallet
+    pub wallet: Pubkey,
+    /// Whether the wallet passed KYC
+    pub verified: bool,
+    /// Unix timestamp when the verification lapses
+    pub expires_at: i64,
+}
+
+impl KycEntry {
+    /// Calculate space needed for a KYC entry account
+    pub fn get_space() -> usize {
+        // wallet(32) + verified(1) + expires_at(8)
+        32 + 1 + 8
+    }
+    
+    /// Check if the wallet is verified and the verification has not lapsed
+    pub fn is_valid(&self, current_time: i64) -> bool {
+        self.verified && current_time < self.expires_at
+    }
+}
+
+/// NFT mints that cannot be added to trade steps
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct MintBlocklist {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// The blocked mints
+    pub mints: Vec<Pubkey>,
+}
+
+impl Sealed for MintBlocklist {}
+
+impl IsInitialized for MintBlocklist {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl MintBlocklist {
+    /// Calculate space needed for a mint blocklist holding the given number of mints
+    pub fn get_space(mint_count: usize) -> usize {
+        // is_initialized(1) + mints(4 + 32 * count)
+        1 + 4 + 32 * mint_count
+    }
+    
+    /// Check if a mint is blocked
+    pub fn is_blocked(&self, mint: &Pubkey) -> bool {
+        self.mints.contains(mint)
+    }
+}
+
+/// Verified Metaplex collections whose NFTs may be added to trade steps
+/// An empty whitelist places no collection requirement on NFTs
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct CollectionWhitelist {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// The whitelisted collection mints
+    pub collections: Vec<Pubkey>,
+    /// Pyth feed ID of the floor price of whitelisted collections that have one
+    pub price_feeds: Vec<(Pubkey, [u8; 32])>,
+}
+
+impl Sealed for CollectionWhitelist {}
+
+impl IsInitialized for CollectionWhitelist {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl CollectionWhitelist {
+    /// Calculate space needed for a collection whitelist holding the given number of collections and price feeds
+    pub fn get_space(collection_count: usize, price_feed_count: usize) -> usize {
+        // is_initialized(1) + collections(4 + 32 * count) + price_feeds(4 + (32 + 32) * count)
+        1 + 4 + 32 * collection_count + 4 + 64 * price_feed_count
+    }
+    
+    /// Check if a collection is whitelisted
+    pub fn is_whitelisted(&self, collection: &Pubkey) -> bool {
+        self.collections.contains(collection)
+    }
+    
+    /// Get the Pyth feed ID of a collection's floor price, if it has one
+    pub fn price_feed(&self, collection: &Pubkey) -> Option<[u8; 32]> {
+        self.price_feeds.iter().find(|(feed_collection, _)| feed_collection == collection).map(|(_, feed_id)| *feed_id)
+    }
+}
+
+/// Ring buffer of recent ExecuteFullTradeLoop hashes, rejecting resubmitted executions
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct ProcessedInstructions {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Truncated hashes of (trade loop, slot, executor), oldest overwritten first
+    pub hashes: [[u8; 8]; DEDUP_CACHE_SIZE],
+    /// Index the next hash is written to (wraps at DEDUP_CACHE_SIZE)
+    pub head: u8,
+}
+
+impl Sealed for ProcessedInstructions {}
+
+impl IsInitialized for ProcessedInstructions {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl ProcessedInstructions {
+    /// Calculate space needed for the dedup cache account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + hashes(DEDUP_CACHE_SIZE * 8) + head(1)
+        1 + DEDUP_CACHE_SIZE * 8 + 1
+    }
+    
+    /// Truncated hash identifying one execution of a trade loop
+    pub fn execution_hash(trade_loop: &Pubkey, slot: u64, executor: &Pubkey) -> [u8; 8] {
+        let hash = hashv(&[trade_loop.as_ref(), &slot.to_le_bytes(), executor.as_ref()]);
+        let mut truncated = [0u8; 8];
+        truncated.copy_from_slice(&hash.to_bytes()[..8]);
+        truncated
+    }
+    
+    /// Record a hash, returning false if it is already in the ring
+    pub fn insert(&mut self, hash: [u8; 8]) -> bool {
+        if self.hashes.contains(&hash) {
+            return false;
+        }
+        
+        self.hashes[self.head as usize] = hash;
+        self.head = self.head.wrapping_add(1);
+        true
+    }
+}
+
+/// Aggregate program activity counters, kept for operator dashboards
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct ProgramStats {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Trade loops created
+    pub total_loops_initialized: u64,
+    /// Trade steps executed
+    pub total_steps_executed: u64,
+    /// NFTs transferred (or released from escrow) by executed steps
+    pub total_nfts_traded: u64,
+    /// Protocol fees collected, in lamports
+    pub total_fees_lamports: u64,
+    /// Trade loops cancelled by a participant
+    pub total_loops_cancelled: u64,
+}
+
+impl Sealed for ProgramStats {}
+
+impl IsInitialized for ProgramStats {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl ProgramStats {
+    /// Calculate space needed for the program stats account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + five u64 counters(8 each)
+        1 + 5 * 8
+    }
+    
+    /// Count executed steps and the NFTs they traded
+    pub fn record_steps_executed<'a>(&mut self, steps: impl IntoIterator<Item = &'a TradeStep>) {
+        for step in steps {
+            self.total_steps_executed = self.total_steps_executed.saturating_add(1);
+            self.total_nfts_traded = self.total_nfts_traded.saturating_add(step.nft_mints.len() as u64);
+        }
+    }
+}
+
+/// Hash of a trade loop's state at a point in time, kept for dispute resolution
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct TradeSnapshot {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Unix timestamp when the snapshot was taken
+    pub taken_at: i64,
+    /// SHA-256 hash of the Borsh-serialized trade loop
+    pub state_hash: [u8; 32],
+    /// Identifier of the snapshot within its trade loop
+    pub snapshot_id: u8,
+}
+
+impl Sealed for TradeSnapshot {}
+
+impl IsInitialized for TradeSnapshot {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl TradeSnapshot {
+    /// Calculate space needed for a trade snapshot account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + taken_at(8) + state_hash(32) + snapshot_id(1)
+        1 + 8 + 32 + 1
+    }
+}
+
+/// Escrowed NFT that anyone holding an NFT from the wanted collection can swap for
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct OpenOffer {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Unique identifier for the offer
+    pub offer_id: [u8; 16],
+    /// Account that created the offer and receives the fulfiller's NFT
+    pub offerer: Pubkey,
+    /// NFT held in escrow by the offer account
+    pub offered_nft_mint: Pubkey,
+    /// Verified Metaplex collection the fulfiller's NFT must belong to
+    pub wanted_collection: Pubkey,
+    /// Unix timestamp when the offer expires
+    pub expires_at: i64,
+}
+
+impl Sealed for OpenOffer {}
+
+impl IsInitialized for OpenOffer {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl OpenOffer {
+    /// Calculate space needed for an open offer account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + offer_id(16) + offerer(32) + offered_nft_mint(32) + wanted_collection(32) + expires_at(8)
+        1 + 16 + 32 + 32 + 32 + 8
+    }
+}
+
+/// Escrowed bundle of NFTs that fillers can take in any subset, paying per NFT taken
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct PartialFillOffer {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// Unique identifier for the offer
+    pub offer_id: [u8; 16],
+    /// Account that created the offer and receives the fillers' NFTs
+    pub offerer: Pubkey,
+    /// NFTs still held in escrow by the offer account
+    pub offered_nfts: Vec<Pubkey>,
+    /// Verified Metaplex collections of the NFTs owed for each offered NFT taken, one NFT per collection
+    pub wanted_nfts_per_unit: Vec<Pubkey>,
+    /// Minimum number of offered NFTs a single fill must take
+    pub minimum_fill: u8,
+}
+
+impl Sealed for PartialFillOffer {}
+
+impl IsInitialized for PartialFillOffer {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl PartialFillOffer {
+    /// Calculate space needed for a partial fill offer account
+    pub fn get_space(offered_count: usize, wanted_count: usize) -> usize {
+        // is_initialized(1) + offer_id(16) + offerer(32) + offered_nfts(4 + count * 32)
+        // + wanted_nfts_per_unit(4 + count * 32) + minimum_fill(1)
+        1 + 16 + 32 + 4 + offered_count * 32 + 4 + wanted_count * 32 + 1
+    }
+}
+
+/// Escrowed NFT listed in an offer book against any NFT from the book's collection
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct NftOfferEntry {
+    /// Unique identifier for the offer within its book
+    pub offer_id: [u8; 16],
+    /// Account that listed the offer and receives the matched NFT
+    pub offerer: Pubkey,
+    /// NFT held in escrow by the offer book
+    pub offered_nft: Pubkey,
+    /// Verified Metaplex collection wanted in return (the book's collection)
+    pub wanted_collection: Pubkey,
+    /// Premium over the wanted collection's floor the offerer asks for, in basis points
+    /// (negative values concede a discount)
+    pub floor_premium_bps: i16,
+    /// Unix timestamp when the offer was listed
+    pub created_at: i64,
+}
+
+/// Open NFT offers wanting one collection, sorted by ascending floor premium
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default)]
+pub struct OfferBook {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// The listed offers, cheapest first
+    pub entries: Vec<NftOfferEntry>,
+    /// Slot of the book's last listing, delisting or match
+    pub last_updated_slot: u64,
+}
+
+impl Sealed for OfferBook {}
+
+impl IsInitialized for OfferBook {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl OfferBook {
+    /// Calculate space needed for an offer book holding the given number of offers
+    pub fn get_space(entry_count: usize) -> usize {
+        // is_initialized(1) + entries(4 + count * (offer_id(16) + offerer(32) + offered_nft(32)
+        // + wanted_collection(32) + floor_premium_bps(2) + created_at(8))) + last_updated_slot(8)
+        1 + 4 + entry_count * (16 + 32 + 32 + 32 + 2 + 8) + 8
+    }
+    
+    /// Find the position of an offer in the book
+    pub fn position(&self, offer_id: &[u8; 16]) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.offer_id == *offer_id)
+    }
+    
+    /// Insert an offer, keeping the entries sorted by floor premium and then by listing order
+    pub fn insert(&mut self, entry: NftOfferEntry) {
+        let index = self.entries.partition_point(|existing| existing.floor_premium_bps <= entry.floor_premium_bps);
+        self.entries.insert(index, entry);
+    }
+}
+
+/// Token-2022 mint whose transfer hook points at this program, restricting its transfers to a trade loop
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug)]
+pub struct HookRegistry {
+    /// Is initialized
+    pub is_initialized: bool,
+    /// The hooked mint
+    pub mint: Pubkey,
+    /// Trade loop whose approved steps may transfer the mint (the default pubkey once released)
+    pub active_loop: Pubkey,
+    /// Transfer hook authority of the mint before registration, restored on deregistration
+    pub original_authority: Pubkey,
+    /// Transfer hook program of the mint before registration, restored on deregistration
+    pub original_hook_program: Option<Pubkey>,
+}
+
+impl Sealed for HookRegistry {}
+
+impl IsInitialized for HookRegistry {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl HookRegistry {
+    /// Calculate space needed for a hook registry account
+    pub fn get_space() -> usize {
+        // is_initialized(1) + mint(32) + active_loop(32) + original_authority(32) + original_hook_program(1 + 32)
+        1 + 32 + 32 + 32 + 33
+    }
+    
+    /// Whether the hook was handed back to its original program and authority
+    pub fn is_released(&self) -> bool {
+        self.active_loop == Pubkey::default()
+    }
+}
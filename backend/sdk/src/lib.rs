@@ -0,0 +1,317 @@
+//! Native client SDK for the NFT Swap program
+//!
+//! `SwapsClient` builds instructions and unsigned transactions with their accounts
+//! in the order the program expects. Instruction data uses the versioned format.
+//! `TradeLoopSummary` gives a fixed-size view of trade loop accounts, and `SwapError`
+//! decodes the program's custom error codes.
+
+use solana_nft_swap::{
+    instruction::{SwapInstruction, PAUSE_EXEMPT_TAGS},
+    state::TradeLoop,
+    utils,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+    sysvar,
+    transaction::Transaction,
+};
+use spl_associated_token_account::get_associated_token_address;
+
+pub use solana_nft_swap as program;
+pub use solana_nft_swap::error::SwapError;
+pub use summary::TradeLoopSummary;
+
+mod summary;
+
+/// Instruction and transaction builder for one deployment of the NFT Swap program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapsClient {
+    /// The deployed program's ID
+    pub program_id: Pubkey,
+}
+
+impl SwapsClient {
+    /// Create a client for the program deployed at `program_id`
+    pub fn new(program_id: Pubkey) -> Self {
+        Self { program_id }
+    }
+    
+    /// Calculate the trade loop PDA for a trade ID and creator
+    pub fn compute_trade_loop_pda(&self, trade_id: &[u8; 32], creator: &Pubkey) -> (Pubkey, u8) {
+        utils::get_trade_loop_address(trade_id, creator, &self.program_id)
+    }
+    
+    /// Build an InitializeTradeLoop instruction; the payer becomes the loop's creator
+    ///
+    /// While rate limiting is enabled, insert the payer's cooldown account (writable) before the
+    /// trailing program config account of the result.
+    pub fn initialize_trade_loop_instruction(
+        &self,
+        payer: &Pubkey,
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        timeout_seconds: u64,
+    ) -> Instruction {
+        let (trade_loop, _) = self.compute_trade_loop_pda(&trade_id, payer);
+        let (blocklist, _) = utils::get_blocklist_address(&self.program_id);
+        let (config, _) = utils::get_program_config_address(&self.program_id);
+        
+        self.instruction(
+            SwapInstruction::InitializeTradeLoop {
+                trade_id,
+                participants,
+                timeout_seconds,
+                step_signer_counts: None,
+                cancel_lockout_seconds: 0,
+                auto_execute: false,
+                per_step_nft_limits: None,
+            },
+            vec![
+                AccountMeta::new(*payer, true),
+                AccountMeta::new(trade_loop, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(blocklist, false),
+                AccountMeta::new_readonly(config, false),
+            ],
+        )
+    }
+    
+    /// Build an AddTradeStep instruction, passing the sender's token account for each NFT
+    pub fn add_trade_step_instruction(
+        &self,
+        from: &Pubkey,
+        trade_loop: &Pubkey,
+        step_index: u8,
+        to: Pubkey,
+        nft_mints: Vec<Pubkey>,
+    ) -> Instruction {
+        let (blocklist, _) = utils::get_blocklist_address(&self.program_id);
+        let (mint_blocklist, _) = utils::get_mint_blocklist_address(&self.program_id);
+        let (collection_whitelist, _) = utils::get_collection_whitelist_address(&self.program_id);
+        let (config, _) = utils::get_program_config_address(&self.program_id);
+        let mut accounts = vec![
+            AccountMeta::new_readonly(*from, true),
+            AccountMeta::new(*trade_loop, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(blocklist, false),
+            AccountMeta::new_readonly(mint_blocklist, false),
+            AccountMeta::new_readonly(collection_whitelist, false),
+            AccountMeta::new_readonly(config, false),
+        ];
+        for nft_mint in &nft_mints {
+            accounts.push(AccountMeta::new_readonly(*nft_mint, false));
+            accounts.push(AccountMeta::new_readonly(get_associated_token_address(from, nft_mint), false));
+        }
+        
+        self.instruction(SwapInstruction::AddTradeStep { step_index, to, nft_mints }, accounts)
+    }
+    
+    /// Build an ApproveTradeStep instruction signed by the sender or its approval delegate
+    pub fn approve_trade_step_instruction(&self, approver: &Pubkey, trade_loop: &Pubkey, step_index: u8) -> Instruction {
+        self.instruction(
+            SwapInstruction::ApproveTradeStep { step_index },
+            vec![
+                AccountMeta::new_readonly(*approver, true),
+                AccountMeta::new(*trade_loop, false),
+                AccountMeta::new_readonly(sysvar::clock::id(), false),
+            ],
+        )
+    }
+    
+    /// Build the last ApproveTradeStep instruction of an auto-execute loop
+    ///
+    /// `trade_loop` is the loop's state before this approval. The approver becomes the
+    /// executor of the ExecuteFullTradeLoop the program invokes, so every other sender
+    /// must still sign the transaction.
+    pub fn approve_and_execute_trade_step_instruction(
+        &self,
+        approver: &Pubkey,
+        trade_loop_address: &Pubkey,
+        trade_loop: &TradeLoop,
+        step_index: u8,
+    ) -> Instruction {
+        let execute = self.execute_full_trade_loop_instruction(approver, trade_loop_address, trade_loop);
+        
+        let mut accounts = vec![
+            AccountMeta::new(*approver, true),
+            AccountMeta::new(*trade_loop_address, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(self.program_id, false),
+        ];
+        // The approval's own trailing config account replaces the execution's
+        let execute_accounts = &execute.accounts[2..execute.accounts.len() - 1];
+        accounts.extend_from_slice(execute_accounts);
+        
+        self.instruction(SwapInstruction::ApproveTradeStep { step_index }, accounts)
+    }
+    
+    /// Build an ExecuteFullTradeLoop instruction from the loop's current on-chain state
+    ///
+    /// Every sender signs, since the program transfers their NFTs with their authority.
+    /// Escrowed NFTs are sourced from their escrow token account. The witness (as a
+    /// signer), execution condition account, linked loop and execution bond PDAs are
+    /// appended when the loop has them; other optional accounts (fee vault, stats, ...) can be
+    /// inserted before the trailing program config account of the result.
+    pub fn execute_full_trade_loop_instruction(
+        &self,
+        executor: &Pubkey,
+        trade_loop_address: &Pubkey,
+        trade_loop: &TradeLoop,
+    ) -> Instruction {
+        self.execute_full_trade_loop_with_royalties_instruction(executor, trade_loop_address, trade_loop, Vec::new())
+    }
+    
+    /// Build an ExecuteFullTradeLoop instruction for a program with royalty enforcement enabled
+    ///
+    /// `royalty_accounts` holds each NFT's metadata account followed by its verified
+    /// creators (writable), in step and metadata order. They are placed right after the
+    /// step accounts, and senders are made writable to pay the royalties.
+    pub fn execute_full_trade_loop_with_royalties_instruction(
+        &self,
+        executor: &Pubkey,
+        trade_loop_address: &Pubkey,
+        trade_loop: &TradeLoop,
+        royalty_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let (dedup_cache, _) = utils::get_dedup_cache_address(&self.program_id);
+        let (config, _) = utils::get_program_config_address(&self.program_id);
+        let mut accounts = vec![
+            AccountMeta::new(*executor, true),
+            AccountMeta::new(*trade_loop_address, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new(dedup_cache, false),
+            AccountMeta::new_readonly(config, false),
+        ];
+        
+        for step in &trade_loop.steps {
+            // SOL legs debit the sender and credit the recipient; bonds are refunded to the sender
+            let pays_sol = step.sol_amount > 0;
+            let is_bonded = trade_loop.execution_bond_lamports > 0;
+            let pays_royalties = !royalty_accounts.is_empty();
+            accounts.push(AccountMeta { pubkey: step.from, is_signer: true, is_writable: pays_sol || is_bonded || pays_royalties });
+            accounts.push(AccountMeta { pubkey: step.to, is_signer: false, is_writable: pays_sol });
+            
+            for (nft_index, nft_mint) in step.nft_mints.iter().enumerate() {
+                let source = if step.escrowed.get(nft_index).copied().unwrap_or(false) {
+                    let (escrow_authority, _) = utils::get_escrow_address(trade_loop_address, nft_mint, &self.program_id);
+                    get_associated_token_address(&escrow_authority, nft_mint)
+                } else {
+                    get_associated_token_address(&step.from, nft_mint)
+                };
+                
+                accounts.push(AccountMeta::new_readonly(*nft_mint, false));
+                accounts.push(AccountMeta::new(source, false));
+                accounts.push(AccountMeta::new(get_associated_token_address(&step.to, nft_mint), false));
+            }
+            
+            for spl_leg in &step.spl_token_legs {
+                accounts.push(AccountMeta::new_readonly(spl_leg.mint, false));
+                accounts.push(AccountMeta::new(get_associated_token_address(&step.from, &spl_leg.mint), false));
+                accounts.push(AccountMeta::new(get_associated_token_address(&step.to, &spl_leg.mint), false));
+            }
+        }
+        
+        accounts.extend(royalty_accounts);
+        
+        if let Some(witness) = trade_loop.witness {
+            accounts.push(AccountMeta::new_readonly(witness, true));
+        }
+        if let Some(condition) = trade_loop.execution_condition {
+            accounts.push(AccountMeta::new_readonly(condition.account, false));
+        }
+        if let Some(linked_loop) = trade_loop.linked_loop {
+            accounts.push(AccountMeta::new_readonly(linked_loop, false));
+        }
+        if trade_loop.execution_bond_lamports > 0 {
+            for step_index in 0..trade_loop.steps.len() as u8 {
+                let (bond, _) = utils::get_execution_bond_address(trade_loop_address, step_index, &self.program_id);
+                accounts.push(AccountMeta::new(bond, false));
+            }
+        }
+        
+        self.instruction(SwapInstruction::ExecuteFullTradeLoop { expected_nonce: trade_loop.nonce }, accounts)
+    }
+    
+    /// Build a CancelTradeLoop instruction for a loop without escrowed NFTs
+    pub fn cancel_trade_loop_instruction(&self, canceller: &Pubkey, trade_loop: &Pubkey) -> Instruction {
+        self.instruction(
+            SwapInstruction::CancelTradeLoop {},
+            vec![
+                AccountMeta::new_readonly(*canceller, true),
+                AccountMeta::new(*trade_loop, false),
+            ],
+        )
+    }
+    
+    /// Build an unsigned InitializeTradeLoop transaction paid by `payer`
+    pub fn build_initialize_trade_loop_tx(
+        &self,
+        payer: &Pubkey,
+        trade_id: [u8; 32],
+        participants: Vec<Pubkey>,
+        timeout_seconds: u64,
+    ) -> Transaction {
+        let instruction = self.initialize_trade_loop_instruction(payer, trade_id, participants, timeout_seconds);
+        Transaction::new_with_payer(&[instruction], Some(payer))
+    }
+    
+    /// Build an unsigned AddTradeStep transaction paid by the sender
+    pub fn build_add_trade_step_tx(
+        &self,
+        from: &Pubkey,
+        trade_loop: &Pubkey,
+        step_index: u8,
+        to: Pubkey,
+        nft_mints: Vec<Pubkey>,
+    ) -> Transaction {
+        let instruction = self.add_trade_step_instruction(from, trade_loop, step_index, to, nft_mints);
+        Transaction::new_with_payer(&[instruction], Some(from))
+    }
+    
+    /// Build an unsigned ApproveTradeStep transaction paid by the approver
+    pub fn build_approve_trade_step_tx(&self, approver: &Pubkey, trade_loop: &Pubkey, step_index: u8) -> Transaction {
+        let instruction = self.approve_trade_step_instruction(approver, trade_loop, step_index);
+        Transaction::new_with_payer(&[instruction], Some(approver))
+    }
+    
+    /// Build an unsigned ExecuteFullTradeLoop transaction paid by the executor
+    pub fn build_execute_full_trade_loop_tx(
+        &self,
+        executor: &Pubkey,
+        trade_loop_address: &Pubkey,
+        trade_loop: &TradeLoop,
+    ) -> Transaction {
+        let instruction = self.execute_full_trade_loop_instruction(executor, trade_loop_address, trade_loop);
+        Transaction::new_with_payer(&[instruction], Some(executor))
+    }
+    
+    /// Build an unsigned CancelTradeLoop transaction paid by the canceller
+    pub fn build_cancel_trade_loop_tx(&self, canceller: &Pubkey, trade_loop: &Pubkey) -> Transaction {
+        let instruction = self.cancel_trade_loop_instruction(canceller, trade_loop);
+        Transaction::new_with_payer(&[instruction], Some(canceller))
+    }
+    
+    /// Pack a program instruction with its accounts
+    ///
+    /// Instructions outside `PAUSE_EXEMPT_TAGS` get the program config account appended,
+    /// which the program reads to enforce scheduled maintenance.
+    fn instruction(&self, instruction: SwapInstruction, mut accounts: Vec<AccountMeta>) -> Instruction {
+        if !PAUSE_EXEMPT_TAGS.contains(&instruction.tag()) {
+            let (config, _) = utils::get_program_config_address(&self.program_id);
+            accounts.push(AccountMeta::new_readonly(config, false));
+        }
+        
+        Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: instruction.pack_versioned(),
+        }
+    }
+}
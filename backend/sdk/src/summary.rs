@@ -0,0 +1,59 @@
+//! Fixed-size view of a trade loop account for wallets and web apps
+
+use solana_nft_swap::state::{StepStatus, TradeLoop, MAX_PARTICIPANTS_PER_TRANSACTION};
+use solana_sdk::{program_error::ProgramError, pubkey::Pubkey};
+
+/// Maximum number of steps a trade loop can have
+pub const MAX_STEPS: usize = MAX_PARTICIPANTS_PER_TRANSACTION as usize;
+
+/// Summary of a trade loop using only fixed-size fields
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeLoopSummary {
+    /// Unique identifier of the trade loop
+    pub trade_id: [u8; 32],
+    /// Authority that can cancel the trade loop
+    pub authority: Pubkey,
+    /// Unix timestamp when the trade loop expires
+    pub expires_at: u64,
+    /// Number of steps in the trade loop
+    pub step_count: u8,
+    /// Status of each step; entries past `step_count` are `Created`
+    pub statuses: [StepStatus; MAX_STEPS],
+    /// Number of NFTs committed across all steps
+    pub total_nft_count: u8,
+}
+
+impl TradeLoopSummary {
+    /// Decode a trade loop account and keep only its summary
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        let trade_loop = TradeLoop::unpack_from_slice(data)?;
+        if !trade_loop.is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        
+        Ok(trade_loop.into())
+    }
+    
+    /// Statuses of the trade loop's steps
+    pub fn step_statuses(&self) -> &[StepStatus] {
+        &self.statuses[..self.step_count as usize]
+    }
+}
+
+impl From<TradeLoop> for TradeLoopSummary {
+    fn from(trade_loop: TradeLoop) -> Self {
+        let mut statuses: [StepStatus; MAX_STEPS] = std::array::from_fn(|_| StepStatus::Created);
+        for (slot, step) in statuses.iter_mut().zip(trade_loop.steps.iter()) {
+            *slot = step.status.clone();
+        }
+        
+        Self {
+            trade_id: trade_loop.trade_id,
+            authority: trade_loop.authority,
+            expires_at: trade_loop.expires_at,
+            step_count: trade_loop.steps.len() as u8,
+            statuses,
+            total_nft_count: trade_loop.steps.iter().map(|step| step.nft_mints.len() as u8).sum(),
+        }
+    }
+}
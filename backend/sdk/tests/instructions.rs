@@ -0,0 +1,377 @@
+use solana_nft_swap::{
+    instruction::{SwapInstruction, PAUSE_EXEMPT_TAGS},
+    state::{FeeSchedule, PauseReason, StepStatus, TradeLoop, TradeStep, DEADLINE_MESSAGE_SIZE, MAX_LOOP_TAGS},
+    utils,
+};
+use solana_nft_swap_sdk::{SwapError, SwapsClient, TradeLoopSummary};
+use solana_sdk::{instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey, system_program, sysvar, transaction::Transaction};
+use spl_associated_token_account::get_associated_token_address;
+
+fn client() -> SwapsClient {
+    SwapsClient::new(Pubkey::new_unique())
+}
+
+/// Unpack the single instruction of a built transaction
+fn unpack_single(client: &SwapsClient, transaction: &Transaction) -> SwapInstruction {
+    assert_eq!(transaction.message.instructions.len(), 1);
+    let compiled = &transaction.message.instructions[0];
+    assert_eq!(transaction.message.account_keys[compiled.program_id_index as usize], client.program_id);
+    SwapInstruction::unpack(&compiled.data).unwrap()
+}
+
+fn test_trade_loop(participants: &[Pubkey], nft_mints: &[Pubkey]) -> TradeLoop {
+    let mut steps = TradeStep::build_ring(participants);
+    for (step, nft_mint) in steps.iter_mut().zip(nft_mints) {
+        step.nft_mints = vec![*nft_mint];
+        step.escrowed = vec![false];
+        step.nft_values_lamports = vec![0];
+        step.status = StepStatus::Approved;
+    }
+    
+    TradeLoop {
+        is_initialized: true,
+        trade_id: [7; 32],
+        created_at: 0,
+        expires_at: 3600,
+        steps,
+        authority: participants[0],
+        is_paused: false,
+        nonce: 42,
+        witness: None,
+        authority_transfer_request: None,
+        linked_loop: None,
+        cancel_lockout_seconds: 0,
+        all_approved_at: None,
+        loop_tags: [None; MAX_LOOP_TAGS],
+        recurring: None,
+        successor_loop: None,
+        tokenized_as: None,
+        offered_until_display: 0,
+        display_timezone_offset: 0,
+        deadline_message: [0; DEADLINE_MESSAGE_SIZE],
+        applied_discount_bps: 0,
+        allocated_step_count: participants.len() as u8,
+        execution_condition: None,
+        step_hash_history: Vec::new(),
+        execution_whitelist: None,
+        auto_execute: false,
+        commitment_mode: false,
+        per_step_nft_limits: Vec::new(),
+        execution_bond_lamports: 0,
+        applied_fee_bps: 0,
+    }
+}
+
+#[test]
+fn compute_trade_loop_pda_matches_program() {
+    let client = client();
+    let creator = Pubkey::new_unique();
+    let trade_id = [1; 32];
+    
+    assert_eq!(
+        client.compute_trade_loop_pda(&trade_id, &creator),
+        utils::get_trade_loop_address(&trade_id, &creator, &client.program_id),
+    );
+}
+
+#[test]
+fn initialize_trade_loop_round_trip() {
+    let client = client();
+    let payer = Pubkey::new_unique();
+    let participants = vec![payer, Pubkey::new_unique(), Pubkey::new_unique()];
+    let trade_id = [3; 32];
+    
+    let transaction = client.build_initialize_trade_loop_tx(&payer, trade_id, participants.clone(), 600);
+    assert_eq!(
+        unpack_single(&client, &transaction),
+        SwapInstruction::InitializeTradeLoop {
+            trade_id,
+            participants,
+            timeout_seconds: 600,
+            step_signer_counts: None,
+            cancel_lockout_seconds: 0,
+            auto_execute: false,
+            per_step_nft_limits: None,
+        }
+    );
+    
+    let instruction = client.initialize_trade_loop_instruction(&payer, trade_id, vec![], 600);
+    let keys: Vec<Pubkey> = instruction.accounts.iter().map(|meta| meta.pubkey).collect();
+    let (trade_loop, _) = client.compute_trade_loop_pda(&trade_id, &payer);
+    let (blocklist, _) = utils::get_blocklist_address(&client.program_id);
+    let (config, _) = utils::get_program_config_address(&client.program_id);
+    assert_eq!(keys, vec![payer, trade_loop, sysvar::rent::id(), system_program::id(), blocklist, config, config]);
+    assert!(instruction.accounts[0].is_signer && instruction.accounts[1].is_writable);
+}
+
+#[test]
+fn initialize_trade_loop_legacy_packing_keeps_nft_limits() {
+    let instruction = SwapInstruction::InitializeTradeLoop {
+        trade_id: [5; 32],
+        participants: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        timeout_seconds: 600,
+        step_signer_counts: None,
+        cancel_lockout_seconds: 0,
+        auto_execute: false,
+        per_step_nft_limits: Some(vec![1, 3]),
+    };
+    assert_eq!(SwapInstruction::unpack(&instruction.pack_legacy()).unwrap(), instruction);
+}
+
+#[test]
+fn add_trade_step_round_trip() {
+    let client = client();
+    let from = Pubkey::new_unique();
+    let to = Pubkey::new_unique();
+    let trade_loop = Pubkey::new_unique();
+    let nft_mints = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    
+    let transaction = client.build_add_trade_step_tx(&from, &trade_loop, 1, to, nft_mints.clone());
+    assert_eq!(
+        unpack_single(&client, &transaction),
+        SwapInstruction::AddTradeStep { step_index: 1, to, nft_mints: nft_mints.clone() }
+    );
+    
+    let instruction = client.add_trade_step_instruction(&from, &trade_loop, 1, to, nft_mints.clone());
+    assert_eq!(instruction.accounts.len(), 7 + 2 * nft_mints.len() + 1);
+    assert_eq!(instruction.accounts[3].pubkey, utils::get_blocklist_address(&client.program_id).0);
+    assert_eq!(instruction.accounts[4].pubkey, utils::get_mint_blocklist_address(&client.program_id).0);
+    assert_eq!(instruction.accounts[5].pubkey, utils::get_collection_whitelist_address(&client.program_id).0);
+    assert_eq!(instruction.accounts[6].pubkey, utils::get_program_config_address(&client.program_id).0);
+    assert_eq!(instruction.accounts[7].pubkey, nft_mints[0]);
+    assert_eq!(instruction.accounts[8].pubkey, get_associated_token_address(&from, &nft_mints[0]));
+}
+
+#[test]
+fn approve_trade_step_round_trip() {
+    let client = client();
+    let approver = Pubkey::new_unique();
+    let trade_loop = Pubkey::new_unique();
+    
+    let transaction = client.build_approve_trade_step_tx(&approver, &trade_loop, 2);
+    assert_eq!(unpack_single(&client, &transaction), SwapInstruction::ApproveTradeStep { step_index: 2 });
+}
+
+#[test]
+fn execute_full_trade_loop_round_trip() {
+    let client = client();
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut trade_loop = test_trade_loop(&participants, &nft_mints);
+    trade_loop.steps[1].escrowed = vec![true];
+    let trade_loop_address = Pubkey::new_unique();
+    let executor = participants[0];
+    
+    let transaction = client.build_execute_full_trade_loop_tx(&executor, &trade_loop_address, &trade_loop);
+    assert_eq!(
+        unpack_single(&client, &transaction),
+        SwapInstruction::ExecuteFullTradeLoop { expected_nonce: trade_loop.nonce }
+    );
+    assert_eq!(transaction.message.header.num_required_signatures, 2);
+    
+    let instruction = client.execute_full_trade_loop_instruction(&executor, &trade_loop_address, &trade_loop);
+    // 9 base accounts, then per step: sender, recipient and one (mint, source, destination) triple,
+    // then the trailing program config account
+    assert_eq!(instruction.accounts.len(), 9 + 2 * 5 + 1);
+    assert_eq!(instruction.accounts[7].pubkey, utils::get_dedup_cache_address(&client.program_id).0);
+    assert_eq!(instruction.accounts[8].pubkey, utils::get_program_config_address(&client.program_id).0);
+    
+    let (escrow_authority, _) = utils::get_escrow_address(&trade_loop_address, &nft_mints[1], &client.program_id);
+    let second_step = &instruction.accounts[14..19];
+    assert_eq!(second_step[0].pubkey, participants[1]);
+    assert!(second_step[0].is_signer);
+    assert_eq!(second_step[3].pubkey, get_associated_token_address(&escrow_authority, &nft_mints[1]));
+    assert_eq!(second_step[4].pubkey, get_associated_token_address(&participants[0], &nft_mints[1]));
+}
+
+#[test]
+fn execute_bonded_trade_loop_refunds_bonds() {
+    let client = client();
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut trade_loop = test_trade_loop(&participants, &nft_mints);
+    trade_loop.execution_bond_lamports = 1_000_000;
+    let trade_loop_address = Pubkey::new_unique();
+    
+    let instruction = client.execute_full_trade_loop_instruction(&participants[0], &trade_loop_address, &trade_loop);
+    // Each step's bond PDA follows the step accounts
+    assert_eq!(instruction.accounts.len(), 9 + 2 * 5 + 2 + 1);
+    for (step_index, bond) in instruction.accounts[19..21].iter().enumerate() {
+        let (expected_bond, _) = utils::get_execution_bond_address(&trade_loop_address, step_index as u8, &client.program_id);
+        assert_eq!(bond.pubkey, expected_bond);
+        assert!(bond.is_writable);
+    }
+    
+    // Senders receive their bonds back
+    assert!(instruction.accounts[9].is_writable);
+    assert!(instruction.accounts[14].is_writable);
+}
+
+#[test]
+fn execute_trade_loop_with_royalties_follows_step_accounts() {
+    let client = client();
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let trade_loop = test_trade_loop(&participants, &nft_mints);
+    let trade_loop_address = Pubkey::new_unique();
+    let royalty_accounts = vec![
+        AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        AccountMeta::new(Pubkey::new_unique(), false),
+    ];
+    
+    let instruction = client.execute_full_trade_loop_with_royalties_instruction(
+        &participants[0], &trade_loop_address, &trade_loop, royalty_accounts.clone(),
+    );
+    assert_eq!(instruction.accounts[19..21], royalty_accounts[..]);
+    
+    // Senders pay the royalties
+    assert!(instruction.accounts[9].is_writable);
+    assert!(instruction.accounts[14].is_writable);
+}
+
+#[test]
+fn approve_and_execute_trade_step_round_trip() {
+    let client = client();
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut trade_loop = test_trade_loop(&participants, &nft_mints);
+    trade_loop.auto_execute = true;
+    let trade_loop_address = Pubkey::new_unique();
+    let approver = participants[1];
+    
+    let instruction = client.approve_and_execute_trade_step_instruction(&approver, &trade_loop_address, &trade_loop, 1);
+    assert_eq!(SwapInstruction::unpack(&instruction.data).unwrap(), SwapInstruction::ApproveTradeStep { step_index: 1 });
+    
+    // The approve accounts and this program, then the execute accounts after the trade loop
+    let execute = client.execute_full_trade_loop_instruction(&approver, &trade_loop_address, &trade_loop);
+    assert_eq!(instruction.accounts.len(), 4 + execute.accounts.len() - 2);
+    assert_eq!(instruction.accounts[3].pubkey, client.program_id);
+    assert_eq!(instruction.accounts[4..], execute.accounts[2..]);
+    assert!(instruction.accounts[0].is_signer && instruction.accounts[0].is_writable);
+}
+
+#[test]
+fn cancel_trade_loop_round_trip() {
+    let client = client();
+    let canceller = Pubkey::new_unique();
+    let trade_loop = Pubkey::new_unique();
+    
+    let transaction = client.build_cancel_trade_loop_tx(&canceller, &trade_loop);
+    assert_eq!(unpack_single(&client, &transaction), SwapInstruction::CancelTradeLoop {});
+    
+    // Instructions outside PAUSE_EXEMPT_TAGS end with the program config account
+    let instruction = client.cancel_trade_loop_instruction(&canceller, &trade_loop);
+    let config = instruction.accounts.last().unwrap();
+    assert_eq!(config.pubkey, utils::get_program_config_address(&client.program_id).0);
+    assert!(!config.is_writable);
+}
+
+#[test]
+fn trade_loop_summary_from_account_data() {
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let trade_loop = test_trade_loop(&participants, &nft_mints);
+    
+    let mut data = vec![0; TradeLoop::LEN];
+    trade_loop.pack_into_slice(&mut data).unwrap();
+    let summary = TradeLoopSummary::from_account_data(&data).unwrap();
+    
+    assert_eq!(summary.trade_id, trade_loop.trade_id);
+    assert_eq!(summary.authority, participants[0]);
+    assert_eq!(summary.expires_at, 3600);
+    assert_eq!(summary.total_nft_count, 2);
+    assert_eq!(summary.step_statuses(), &[StepStatus::Approved, StepStatus::Approved, StepStatus::Created]);
+    assert!(TradeLoopSummary::from_account_data(&vec![0; TradeLoop::LEN]).is_err());
+}
+
+#[test]
+fn trade_loop_migrates_from_unpadded_account_data() {
+    let participants = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let nft_mints = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let trade_loop = test_trade_loop(&participants, &nft_mints);
+    
+    // Accounts created before the packed layout were sized for their own steps and NFTs
+    let legacy_len = TradeLoop::get_space(participants.len() as u8, 1);
+    let mut legacy = vec![0; legacy_len];
+    trade_loop.pack_into_slice(&mut legacy).unwrap();
+    assert_eq!(trade_loop.pack_into_slice(&mut legacy[..64]), Err(ProgramError::AccountDataTooSmall));
+    assert_eq!(TradeLoop::unpack_unchecked(&legacy).unwrap_err(), ProgramError::InvalidAccountData);
+    
+    // MigrateTradeLoop reads the old data and repacks it into a reallocated account
+    let mut migrated = vec![0xff; TradeLoop::LEN];
+    TradeLoop::unpack_from_slice(&legacy).unwrap().pack_into_slice(&mut migrated).unwrap();
+    assert_eq!(&migrated[..legacy_len], &legacy[..]);
+    assert!(migrated[legacy_len..].iter().all(|byte| *byte == 0));
+    
+    let unpacked = TradeLoop::unpack_unchecked(&migrated).unwrap();
+    assert_eq!(unpacked.trade_id, trade_loop.trade_id);
+    assert_eq!(unpacked.nonce, trade_loop.nonce);
+    assert_eq!(unpacked.steps.len(), participants.len());
+    assert_eq!(unpacked.steps[2].nft_mints, vec![nft_mints[2]]);
+}
+
+#[test]
+fn swap_error_decodes_program_errors() {
+    // Every known code decodes to the variant that encodes to it
+    let mut code = 0;
+    while let Ok(error) = SwapError::try_from(code) {
+        assert_eq!(error.code(), code);
+        code += 1;
+    }
+    assert!(code > SwapError::ExecutionConditionNotMet.code());
+    
+    let known = ProgramError::from(SwapError::ExecutionConditionNotMet);
+    assert_eq!(SwapError::from_program_error(&known), Some(SwapError::ExecutionConditionNotMet));
+    
+    let unknown = ProgramError::Custom(9_999);
+    assert_eq!(SwapError::from_program_error(&unknown).unwrap_or(SwapError::Unknown(9_999)), SwapError::Unknown(9_999));
+    assert_eq!(ProgramError::from(SwapError::Unknown(9_999)), unknown);
+    assert_eq!(SwapError::from_program_error(&ProgramError::InvalidArgument), None);
+}
+
+#[test]
+fn fee_schedule_selects_tier_by_loop_size() {
+    let schedule = FeeSchedule { two_party_bps: 10, small_loop_bps: 25, large_loop_bps: 50, small_threshold: 5 };
+    assert!(schedule.is_valid());
+    
+    let tiers: Vec<u16> = [2, 3, 5, 6, 11].iter().map(|step_count| schedule.fee_basis_points(*step_count)).collect();
+    assert_eq!(tiers, [10, 25, 25, 50, 50]);
+    
+    assert!(!FeeSchedule { large_loop_bps: 501, ..schedule }.is_valid());
+}
+
+#[test]
+fn transfer_hook_extra_account_metas_follow_interface_layout() {
+    let execute_hash = solana_sdk::hash::hash(b"spl-transfer-hook-interface:execute");
+    assert_eq!(utils::TRANSFER_HOOK_EXECUTE_DISCRIMINATOR, execute_hash.to_bytes()[..8]);
+    
+    let active_loop = Pubkey::new_unique();
+    let data = utils::transfer_hook_extra_account_metas(&active_loop);
+    // discriminator(8) + length(4) + count(4) + 2 metas of 35 bytes
+    assert_eq!(data.len(), 86);
+    assert_eq!(data[8..16], [74, 0, 0, 0, 2, 0, 0, 0]);
+    
+    // Registry: PDA of the hook program seeded by "hook_registry" and the mint (account 1)
+    assert_eq!(data[16], 1);
+    assert_eq!(data[17..32], *b"\x01\x0dhook_registry");
+    assert_eq!(data[32..34], [3, 1]);
+    // Trade loop: fixed address
+    assert_eq!(data[51], 0);
+    assert_eq!(data[52..84], active_loop.to_bytes());
+}
+
+#[test]
+fn pause_exempt_tags_match_instructions() {
+    let exempt = [
+        SwapInstruction::UpdateProgramConfig { new_upgrade_authority: None, new_governance: None, new_paused_state: None },
+        SwapInstruction::DiagnoseTradeLoop {},
+        SwapInstruction::GetProgramStats {},
+        SwapInstruction::HealthCheck { expected_version: 0 },
+        SwapInstruction::PauseForMaintenance { reason: PauseReason::ScheduledMaintenance },
+        SwapInstruction::GetParticipantStats {},
+        SwapInstruction::CheckRentExemption {},
+    ];
+    let tags: Vec<u8> = exempt.iter().map(SwapInstruction::tag).collect();
+    assert_eq!(tags, PAUSE_EXEMPT_TAGS);
+    assert!(!PAUSE_EXEMPT_TAGS.contains(&SwapInstruction::CancelTradeLoop {}.tag()));
+}
@@ -0,0 +1,102 @@
+use solana_nft_swap::state::{StepStatus, TradeLoop};
+use solana_nft_swap_sdk::SwapsClient;
+use solana_program_test::{processor, BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+fn program_test(program_id: Pubkey) -> ProgramTest {
+    ProgramTest::new("solana_nft_swap", program_id, processor!(solana_nft_swap::process_instruction))
+}
+
+fn add_wallet(program_test: &mut ProgramTest) -> Keypair {
+    let wallet = Keypair::new();
+    program_test.add_account(wallet.pubkey(), Account::new(10_000_000_000, 0, &solana_sdk::system_program::id()));
+    wallet
+}
+
+fn add_packed_account<T: Pack>(program_test: &mut ProgramTest, address: Pubkey, state: T) {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    program_test.add_account(address, Account { lamports: 1_000_000_000, data, owner: spl_token::id(), executable: false, rent_epoch: 0 });
+}
+
+/// Mint an NFT (supply 1, no mint authority) into the owner's associated token account
+fn add_nft(program_test: &mut ProgramTest, owner: &Pubkey) -> Pubkey {
+    let mint = Pubkey::new_unique();
+    add_packed_account(program_test, mint, Mint {
+        mint_authority: COption::None,
+        supply: 1,
+        decimals: 0,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    });
+    add_packed_account(program_test, get_associated_token_address(owner, &mint), TokenAccount {
+        mint,
+        owner: *owner,
+        amount: 1,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    });
+    mint
+}
+
+async fn send(banks_client: &mut BanksClient, mut transaction: Transaction, signers: &[&Keypair]) {
+    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    transaction.sign(signers, recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+}
+
+async fn load_trade_loop(banks_client: &mut BanksClient, address: Pubkey) -> TradeLoop {
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    TradeLoop::unpack_unchecked(&account.data).unwrap()
+}
+
+async fn token_balance(banks_client: &mut BanksClient, owner: &Pubkey, mint: &Pubkey) -> u64 {
+    let account = banks_client.get_account(get_associated_token_address(owner, mint)).await.unwrap().unwrap();
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+#[tokio::test]
+async fn trade_loop_executes_after_every_step_is_added_and_approved() {
+    let client = SwapsClient::new(Pubkey::new_unique());
+    let mut program_test = program_test(client.program_id);
+    let alice = add_wallet(&mut program_test);
+    let bob = add_wallet(&mut program_test);
+    let alice_nft = add_nft(&mut program_test, &alice.pubkey());
+    let bob_nft = add_nft(&mut program_test, &bob.pubkey());
+    let (mut banks_client, _payer, _recent_blockhash) = program_test.start().await;
+
+    let trade_id = [1; 32];
+    let (trade_loop_address, _) = client.compute_trade_loop_pda(&trade_id, &alice.pubkey());
+    let transaction = client.build_initialize_trade_loop_tx(&alice.pubkey(), trade_id, vec![alice.pubkey(), bob.pubkey()], 3600);
+    send(&mut banks_client, transaction, &[&alice]).await;
+
+    let transaction = client.build_add_trade_step_tx(&alice.pubkey(), &trade_loop_address, 0, bob.pubkey(), vec![alice_nft]);
+    send(&mut banks_client, transaction, &[&alice]).await;
+    let transaction = client.build_add_trade_step_tx(&bob.pubkey(), &trade_loop_address, 1, alice.pubkey(), vec![bob_nft]);
+    send(&mut banks_client, transaction, &[&bob]).await;
+
+    let transaction = client.build_approve_trade_step_tx(&alice.pubkey(), &trade_loop_address, 0);
+    send(&mut banks_client, transaction, &[&alice]).await;
+    let transaction = client.build_approve_trade_step_tx(&bob.pubkey(), &trade_loop_address, 1);
+    send(&mut banks_client, transaction, &[&bob]).await;
+
+    let trade_loop = load_trade_loop(&mut banks_client, trade_loop_address).await;
+    assert!(trade_loop.steps.iter().all(|step| step.status == StepStatus::Approved));
+
+    let transaction = client.build_execute_full_trade_loop_tx(&alice.pubkey(), &trade_loop_address, &trade_loop);
+    send(&mut banks_client, transaction, &[&alice, &bob]).await;
+
+    assert_eq!(token_balance(&mut banks_client, &bob.pubkey(), &alice_nft).await, 1);
+    assert_eq!(token_balance(&mut banks_client, &alice.pubkey(), &bob_nft).await, 1);
+    assert_eq!(token_balance(&mut banks_client, &alice.pubkey(), &alice_nft).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &bob.pubkey(), &bob_nft).await, 0);
+}